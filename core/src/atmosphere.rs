@@ -0,0 +1,31 @@
+pub const STANDARD_PRESSURE_PA: f32 = 101325.0; // Sea level pressure of the standard atmosphere
+const BAROMETRIC_EXPONENT: f32 = 1.0 / 5.255; // Of the international barometric formula
+const BAROMETRIC_SCALE_M: f32 = 44330.0; // Of the international barometric formula
+
+
+/// Altitude in meters for 'pressure_pa' given the pressure at sea level
+pub fn pressure_altitude(pressure_pa: f32, sea_level_pa: f32) -> f32 {
+  BAROMETRIC_SCALE_M * (1.0 - libm::powf(pressure_pa / sea_level_pa, BAROMETRIC_EXPONENT))
+}
+
+
+/// Sea level pressure that puts 'pressure_pa' at 'altitude_m'
+pub fn sea_level_pressure(pressure_pa: f32, altitude_m: f32) -> f32 {
+  pressure_pa / libm::powf(1.0 - altitude_m / BAROMETRIC_SCALE_M, 1.0 / BAROMETRIC_EXPONENT)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_altitude() {
+    assert!(pressure_altitude(STANDARD_PRESSURE_PA, STANDARD_PRESSURE_PA).abs() < 1e-3);
+    let altitude = pressure_altitude(89874.6, STANDARD_PRESSURE_PA); // ~1000 m in the standard atmosphere
+    assert!((altitude - 1000.0).abs() < 1.0);
+    let sea_level = sea_level_pressure(89874.6, 1000.0);
+    assert!((sea_level - STANDARD_PRESSURE_PA).abs() < 15.0);
+  }
+}
@@ -0,0 +1,144 @@
+use crate::detect::verdict::DetectorScore;
+use crate::ring::FixedRing;
+
+pub const MAX_OFFSET_S: f64 = 1.0; // Default largest gps to host clock offset allowed
+pub const STEP_TOLERANCE_S: f64 = 0.2; // Default largest change in the offset between two fixes
+pub const SLEW_TOLERANCE_PPM: f64 = 100.0; // Default largest steady drift of the offset
+pub const SLEW_WINDOW_S: f64 = 600.0; // Default time the drift is fitted over
+const OFFSETS_KEPT: usize = 4096; // Offsets kept for fitting the drift (over an hour at 1 Hz)
+const MIN_SLEW_SPAN: f64 = 0.25; // Fraction of the window the offsets must cover before the drift is scored
+
+
+/// Thresholds for comparing the gps time with a disciplined host clock
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockTolerances {
+  pub max_offset_s: f64,
+  pub step_s: f64,
+  pub slew_ppm: f64,
+  pub slew_window_s: f64,
+}
+
+/// Follows the offset between the gps time and the host's wall clock (kept by NTP or a
+/// battery backed rtc). The receiver's time being pulled away from the host's, either in
+/// one step or slowly enough to get past a step check, is how a timing receiver is spoofed.
+#[derive(Clone, Debug)]
+pub struct ClockCheck {
+  tolerances: ClockTolerances,
+  offsets: FixedRing<f64, OFFSETS_KEPT>, // gps minus host time, at each fix's host time
+}
+
+/// Result of checking a single fix's time
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClockStep {
+  pub offset_s: f64,          // gps minus host time
+  pub step_s: f64,            // change in the offset since the last fix
+  pub slew_ppm: Option<f64>,  // drift of the offset over the window, once it's covered
+  pub score: DetectorScore,
+}
+
+
+/// ClockCheck implementations
+impl ClockCheck {
+  pub fn new(tolerances: ClockTolerances) -> ClockCheck {
+    ClockCheck { tolerances, offsets: FixedRing::new() }
+  }
+
+  /// Scores a fix at 'gps_s' on the gps clock, which the host clock read as 'host_s'
+  /// (both seconds since the Unix epoch). The score is the worst of the offset, its
+  /// step since the last fix and its drift, each as a fraction of its tolerance.
+  pub fn check(&mut self, gps_s: f64, host_s: f64) -> ClockStep {
+    let tolerances = &self.tolerances;
+    let offset_s = gps_s - host_s;
+    let step_s = self.offsets.latest().map_or(0.0, |(_, last)| offset_s - last);
+    if !self.offsets.push(host_s, offset_s) {
+      self.offsets.clear(); // the host clock went backwards, start over
+      self.offsets.push(host_s, offset_s);
+    }
+    self.offsets.discard_before(host_s - tolerances.slew_window_s);
+    let slew_ppm = self.slew().map(|slope| slope * 1e6);
+
+    let score = [
+      offset_s.abs() / tolerances.max_offset_s,
+      step_s.abs() / tolerances.step_s,
+      slew_ppm.map_or(0.0, |slew| slew.abs() / tolerances.slew_ppm),
+    ].into_iter().fold(0.0, f64::max);
+    ClockStep { offset_s, step_s, slew_ppm, score: DetectorScore::new("clock", score as f32) }
+  }
+
+  /// Forgets the offsets, eg. after the host clock is set
+  pub fn reset(&mut self) {
+    self.offsets.clear();
+  }
+
+  /// Least squares slope of the offset against host time, if the offsets cover enough of the window
+  fn slew(&self) -> Option<f64> {
+    let (first, last) = (self.offsets.first_t()?, self.offsets.last_t()?);
+    if last - first < self.tolerances.slew_window_s * MIN_SLEW_SPAN {
+      return None;
+    }
+    let n = self.offsets.len() as f64;
+    let (mean_t, mean_offset) = self.offsets.iter()
+      .fold((0.0, 0.0), |(t_sum, o_sum), (t, offset)| (t_sum + (t - first) / n, o_sum + offset / n));
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for (t, offset) in self.offsets.iter() {
+      let dt = t - first - mean_t;
+      covariance += dt * (offset - mean_offset);
+      variance += dt * dt;
+    }
+    (variance > 0.0).then(|| covariance / variance)
+  }
+}
+
+impl Default for ClockTolerances {
+  fn default() -> Self {
+    ClockTolerances {
+      max_offset_s: MAX_OFFSET_S,
+      step_s: STEP_TOLERANCE_S,
+      slew_ppm: SLEW_TOLERANCE_PPM,
+      slew_window_s: SLEW_WINDOW_S,
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const START: f64 = 1_700_000_000.0;
+
+  #[test]
+  fn test_steady_offset_is_nominal() {
+    let mut check = ClockCheck::new(ClockTolerances::default());
+    for i in 0..600 {
+      let host = START + i as f64;
+      let jitter = if i % 2 == 0 { 0.01 } else { -0.01 };
+      let step = check.check(host + 0.3 + jitter, host); // rmc latency and jitter
+      assert!(step.score.score() < 1.0, "fix {i}: {step:?}");
+    }
+  }
+
+  #[test]
+  fn test_step_is_flagged() {
+    let mut check = ClockCheck::new(ClockTolerances::default());
+    check.check(START, START);
+    let step = check.check(START + 1.5, START + 1.0); // 0.5 s ahead of the host
+    assert!((step.step_s - 0.5).abs() < 1e-6);
+    assert!(step.score.score() > 1.0);
+  }
+
+  #[test]
+  fn test_slew_is_flagged() {
+    let mut check = ClockCheck::new(ClockTolerances::default());
+    let mut last = None;
+    for i in 0..600 {
+      let host = START + i as f64;
+      last = Some(check.check(host + i as f64 * 500e-6, host)); // pulled away at 500 ppm
+    }
+    let last = last.unwrap();
+    assert!((last.slew_ppm.unwrap() - 500.0).abs() < 1.0);
+    assert!(last.step_s < STEP_TOLERANCE_S); // too slow for the step check
+    assert!(last.score.score() > 1.0);
+  }
+}
@@ -0,0 +1,115 @@
+use crate::detect::position::position_residual;
+use crate::detect::verdict::{DetectorScore, Verdict};
+use crate::geo::GpsCoord;
+use crate::nav::{calc_new_pos, calc_new_vel, RawPoint};
+
+
+/// Runs detection fix by fix: predicts where the next fix should be from the
+/// acceleration since the last one, then scores the fix against the prediction.
+/// Used both live and when replaying recorded sessions.
+pub struct Engine {
+  gps_accuracy: f32,
+  suspect_ratio: f32,
+  x0: Option<GpsCoord>, // last fix
+  v0: RawPoint,         // velocity at the last fix
+}
+
+/// Result of checking a single fix
+#[derive(Clone, Debug)]
+pub struct Step {
+  pub predicted: GpsCoord,
+  pub velocity: RawPoint,
+  pub residual_m: f32,
+  pub verdict: Verdict,
+}
+
+
+/// Engine implementations
+impl Engine {
+  /// 'gps_accuracy' is the gps accuracy in meters at an hdop of 1
+  pub fn new(gps_accuracy: f32, suspect_ratio: f32) -> Engine {
+    Engine { gps_accuracy, suspect_ratio, x0: None, v0: RawPoint::new(0.0, 0.0, 0.0) }
+  }
+
+  /// Sets the starting position without checking it
+  pub fn reset(&mut self, fix: &GpsCoord) {
+    self.x0 = Some(*fix);
+    self.v0 = RawPoint::new(0.0, 0.0, 0.0);
+  }
+
+  /// Zero velocity update: the imu was still up to the last fix, so whatever velocity the
+  /// accelerometer's bias has built up is dropped and the next prediction starts from rest
+  pub fn zero_velocity_update(&mut self) {
+    self.v0 = RawPoint::new(0.0, 0.0, 0.0);
+  }
+
+  /// Checks a fix with horizontal dilution 'hdop' given the average acceleration over
+  /// the 'dt' seconds since the last fix. The first fix only sets the starting
+  /// position, so None is returned for it.
+  pub fn step(&mut self, fix: &GpsCoord, hdop: f32, avg_accel: &RawPoint, dt: f64) -> Option<Step> {
+    self.step_with(fix, hdop, avg_accel, dt, &[])
+  }
+
+  /// Same as step, with the scores of other detectors that checked the fix folded into the verdict
+  pub fn step_with(&mut self, fix: &GpsCoord, hdop: f32, avg_accel: &RawPoint, dt: f64, others: &[DetectorScore])
+                   -> Option<Step> {
+    let Some(x0) = &self.x0 else {
+      self.reset(fix);
+      return None;
+    };
+
+    let predicted = calc_new_pos(x0, &self.v0, avg_accel, &dt);
+    let velocity = calc_new_vel(&self.v0, avg_accel, &dt);
+    let (residual_m, score) = position_residual(fix, &predicted, hdop, self.gps_accuracy);
+    let verdict = Verdict::from_scores_with_ratio(core::iter::once(&score).chain(others), self.suspect_ratio);
+
+    self.x0 = Some(*fix);
+    self.v0 = velocity;
+    Some(Step { predicted, velocity, residual_m, verdict })
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_first_fix_sets_start() {
+    let mut engine = Engine::new(10.0, 0.75);
+    let still = RawPoint::new(0.0, 0.0, 0.0);
+    assert!(engine.step(&GpsCoord::new(45.0, -111.0, 0.0), 1.0, &still, 1.0).is_none());
+
+    let step = engine.step(&GpsCoord::new(45.0, -111.0, 0.0), 1.0, &still, 1.0).unwrap();
+    assert_eq!(step.residual_m, 0.0);
+    assert!(!step.verdict.is_spoofed());
+  }
+
+  #[test]
+  fn test_jump_is_spoofed() {
+    let mut engine = Engine::new(10.0, 0.75);
+    let still = RawPoint::new(0.0, 0.0, 0.0);
+    engine.step(&GpsCoord::new(45.0, -111.0, 0.0), 1.0, &still, 1.0);
+
+    let step = engine.step(&GpsCoord::new(45.01, -111.0, 0.0), 1.0, &still, 1.0).unwrap(); // ~1.1 km north
+    assert!(step.residual_m > 1000.0);
+    assert!(step.verdict.is_spoofed());
+  }
+
+  #[test]
+  fn test_zero_velocity_update() {
+    let mut engine = Engine::new(10.0, 0.75);
+    let bias = RawPoint::new(0.5, 0.0, 0.0); // m/s^2 of uncorrected bias
+    let fix = GpsCoord::new(45.0, -111.0, 0.0);
+    engine.step(&fix, 1.0, &bias, 1.0);
+    let first = engine.step(&fix, 1.0, &bias, 1.0).unwrap();
+    engine.zero_velocity_update();
+    for _ in 0..20 {
+      let step = engine.step(&fix, 1.0, &bias, 1.0).unwrap();
+      assert_eq!(step.velocity.x(), first.velocity.x()); // the bias' velocity doesn't build up from fix to fix
+      assert_eq!(step.residual_m, first.residual_m);
+      engine.zero_velocity_update();
+    }
+  }
+}
@@ -0,0 +1,130 @@
+use crate::geo::{heading_difference, wrap_heading};
+use crate::detect::verdict::DetectorScore;
+use crate::ring::FixedRing;
+
+pub const HEADING_TOLERANCE_DEG: f32 = 30.0; // Default difference allowed between the course and the heading
+pub const TURN_TOLERANCE_DEG: f32 = 20.0; // Default difference allowed between the turns of the two over the window
+pub const TURN_WINDOW_S: f64 = 5.0; // Default time the turns are compared over
+pub const MIN_SPEED_MPS: f32 = 3.0; // Default speed below which the course over ground is too noisy to compare
+const HEADINGS_KEPT: usize = 512; // Course and heading pairs kept, enough for the window at the fastest fix rate
+
+
+/// Thresholds for comparing the gps course with the magnetic heading
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeadingTolerances {
+  pub tolerance_deg: f32,
+  pub turn_tolerance_deg: f32,
+  pub window_s: f64,
+  pub min_speed_mps: f32,
+}
+
+/// Compares the gps course over ground (from RMC) with the heading from the
+/// magnetometer, corrected to true north by the local declination. The course of a
+/// spoofed trajectory has no reason to follow the way the vehicle is pointing, so a
+/// course far from the heading, or turning when the heading doesn't (or the other
+/// way round), is flagged. Only compared while moving fast enough for the course to
+/// mean something, and assumes the vehicle moves the way its x axis points.
+#[derive(Clone, Debug)]
+pub struct HeadingCheck {
+  declination_deg: f32,
+  tolerances: HeadingTolerances,
+  headings: FixedRing<(f32, f32), HEADINGS_KEPT>, // (course, true heading) at each fix's epoch while moving
+}
+
+/// Result of checking a single fix's course
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadingStep {
+  pub heading_deg: f32,             // true heading, the magnetic heading plus the declination
+  pub error_deg: Option<f32>,       // course minus heading, while moving
+  pub turn_error_deg: Option<f32>,  // course turn minus heading turn over the window, once it's covered
+  pub score: DetectorScore,
+}
+
+
+/// HeadingCheck implementations
+impl HeadingCheck {
+  /// 'declination_deg' is the local magnetic declination, east positive
+  pub fn new(declination_deg: f32, tolerances: HeadingTolerances) -> HeadingCheck {
+    HeadingCheck { declination_deg, tolerances, headings: FixedRing::new() }
+  }
+
+  /// Scores a fix at 't' with course over ground 'course_deg' at 'speed_mps', given the
+  /// magnetic heading at the fix's epoch. The score is the worst of the course's error
+  /// and the error of its turn over the window, each as a fraction of its tolerance.
+  pub fn check(&mut self, t: f64, course_deg: f32, speed_mps: f32, magnetic_deg: f32) -> HeadingStep {
+    let heading_deg = wrap_heading(magnetic_deg + self.declination_deg);
+    if speed_mps < self.tolerances.min_speed_mps {
+      self.reset(); // the course wanders while stopped, turns are compared from when it moves again
+      return HeadingStep { heading_deg, error_deg: None, turn_error_deg: None, score: DetectorScore::new("heading", 0.0) };
+    }
+    let error_deg = heading_difference(course_deg, heading_deg);
+
+    let start = self.headings.push_window(t, (course_deg, heading_deg), self.tolerances.window_s);
+    let turn_error_deg = start.map(|(_, (start_course, start_heading))| {
+      // each turn is within +-180, so wrap their difference too
+      heading_difference(heading_difference(course_deg, start_course), heading_difference(heading_deg, start_heading))
+    });
+
+    let score = (error_deg.abs() / self.tolerances.tolerance_deg)
+      .max(turn_error_deg.map_or(0.0, |turn| turn.abs() / self.tolerances.turn_tolerance_deg));
+    HeadingStep { heading_deg, error_deg: Some(error_deg), turn_error_deg, score: DetectorScore::new("heading", score) }
+  }
+
+  /// Forgets the turns, eg. after stopping
+  pub fn reset(&mut self) {
+    self.headings.clear();
+  }
+}
+
+impl Default for HeadingTolerances {
+  fn default() -> Self {
+    HeadingTolerances {
+      tolerance_deg: HEADING_TOLERANCE_DEG,
+      turn_tolerance_deg: TURN_TOLERANCE_DEG,
+      window_s: TURN_WINDOW_S,
+      min_speed_mps: MIN_SPEED_MPS,
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_following_course_is_nominal() {
+    let mut check = HeadingCheck::new(-10.0, HeadingTolerances::default());
+    assert_eq!(check.check(0.0, 123.0, 0.5, 0.0).error_deg, None); // stopped
+    for i in 0..30 {
+      let course = wrap_heading(350.0 + i as f32 * 3.0); // turning through north
+      let step = check.check(i as f64, course, 10.0, course + 10.0 + 5.0); // 5 degrees off once corrected for the declination
+      assert!(step.score.score() < 1.0, "fix {i}: {step:?}");
+    }
+  }
+
+  #[test]
+  fn test_turn_without_heading_is_flagged() {
+    let mut check = HeadingCheck::new(0.0, HeadingTolerances::default());
+    let mut last = None;
+    for i in 0..6 {
+      let course = 90.0 + i as f32 * 5.0; // the course turns while the vehicle drives straight east
+      last = Some(check.check(i as f64, course, 10.0, 90.0));
+    }
+    let last = last.unwrap();
+    assert!((last.turn_error_deg.unwrap() - 25.0).abs() < 1e-3);
+    assert!(last.error_deg.unwrap() < HEADING_TOLERANCE_DEG); // still within the absolute tolerance
+    assert!(last.score.score() > 1.0);
+  }
+
+  #[test]
+  fn test_turn_error_wraps() {
+    // a turn of 175 degrees seen one way by the course and 185 the other by the heading is the same turn
+    let mut check = HeadingCheck::new(0.0, HeadingTolerances::default());
+    check.check(0.0, 0.0, 10.0, 0.0);
+    let step = check.check(TURN_WINDOW_S, 175.0, 10.0, 185.0);
+    assert!((step.turn_error_deg.unwrap() + 10.0).abs() < 1e-3);
+    assert!(step.score.score() < 1.0);
+  }
+}
@@ -0,0 +1,8 @@
+pub mod verdict;
+pub mod position;
+pub mod engine;
+pub mod pps;
+pub mod clock;
+pub mod vertical;
+pub mod heading;
+pub mod stationary;
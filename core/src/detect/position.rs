@@ -0,0 +1,39 @@
+use crate::detect::verdict::DetectorScore;
+use crate::geo::{haversine_distance, GpsCoord};
+
+
+/// Scores how far the gps fix is from the position predicted by the accelerometer.
+///
+/// 'accuracy' is the gps accuracy in meters at an hdop of 1, so the allowed
+/// distance scales with the current hdop. A score above 1.0 means the fix is
+/// further away than the gps error can explain. Without an hdop an hdop of 1 is assumed.
+pub fn position_residual(fix: &GpsCoord, predicted: &GpsCoord, hdop: f32, accuracy: f32) -> (f32, DetectorScore) {
+  let dist = haversine_distance(fix.lat(), fix.lon(), predicted.lat(), predicted.lon());
+  let hdop = if hdop > 0.0 { hdop } else { 1.0 }; // no usable hdop yet, still checked as if it were good
+  let error_dist = hdop * accuracy;
+  let score = if error_dist > 0.0 { dist / error_dist } else { 0.0 };
+  (dist, DetectorScore::new("position", score))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_position_residual() {
+    let fix = GpsCoord::new(0.0, 0.0, 0.0);
+    let predicted = GpsCoord::new(0.0, 0.001, 0.0); // ~111 m east
+
+    let (dist, score) = position_residual(&fix, &predicted, 2.0, 10.0);
+    assert!((dist - 111.2).abs() < 1.0);
+    assert!(score.score() > 1.0);
+
+    let (_, score) = position_residual(&fix, &predicted, 20.0, 10.0);
+    assert!(score.score() < 1.0);
+
+    let (_, score) = position_residual(&fix, &predicted, 0.0, 10.0); // no hdop
+    assert!((score.score() - 11.12).abs() < 0.1);
+  }
+}
@@ -0,0 +1,78 @@
+use crate::detect::verdict::DetectorScore;
+
+pub const PPS_TOLERANCE_S: f64 = 0.02; // Default disagreement between the pps and utc time allowed per check
+pub const MAX_MISSED_EDGES: u32 = 3; // Whole second epochs in a row without an edge, once locked, before it's spoofed
+const MAX_GAP_S: f64 = 60.0; // Longest time between edges compared, beyond it the host clock drift adds up
+
+
+/// Checks the receiver's utc time against its pulse per second output. Between two
+/// epochs marked by pps edges, the utc time has to advance by the same time as the
+/// host clock saw between the edges; a receiver whose reported time jumps, or whose
+/// epochs stop lining up with its pps, is being fed a false time.
+#[derive(Clone, Debug)]
+pub struct PpsCheck {
+  tolerance_s: f64,
+  last: Option<(f64, f64)>, // (pps edge, utc seconds) of the last matched epoch
+  missed: u32,              // whole second epochs since then with no edge
+}
+
+
+/// PpsCheck implementations
+impl PpsCheck {
+  pub fn new(tolerance_s: f64) -> PpsCheck {
+    PpsCheck { tolerance_s, last: None, missed: 0 }
+  }
+
+  /// Scores a fix on a whole utc second ('utc_s', seconds since the Unix epoch) given
+  /// the time of the pps edge matched with it, if any. Missing edges only count once
+  /// edges have been seen, since the receiver holds its pps back until it has a fix.
+  pub fn check(&mut self, pps_t: Option<f64>, utc_s: f64) -> DetectorScore {
+    let Some(pps_t) = pps_t else {
+      if self.last.is_some() {
+        self.missed += 1;
+      }
+      return DetectorScore::new("pps", self.missed as f32 / MAX_MISSED_EDGES as f32);
+    };
+    self.missed = 0;
+
+    let score = match self.last {
+      Some((last_pps, last_utc)) if pps_t - last_pps <= MAX_GAP_S => {
+        let error = (utc_s - last_utc) - (pps_t - last_pps);
+        error.abs() / self.tolerance_s
+      }
+      _ => 0.0,
+    };
+    self.last = Some((pps_t, utc_s));
+    DetectorScore::new("pps", score as f32)
+  }
+
+  /// Forgets the last epoch, eg. after the gps is reset
+  pub fn reset(&mut self) {
+    self.last = None;
+    self.missed = 0;
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_pps_check() {
+    let mut check = PpsCheck::new(PPS_TOLERANCE_S);
+    assert_eq!(check.check(None, 1000.0).score(), 0.0); // not locked yet
+    assert_eq!(check.check(Some(10.0), 1000.0).score(), 0.0);
+    assert!(check.check(Some(11.001), 1001.0).score() < 1.0);
+
+    // utc jumps 5 s ahead while a single second passes
+    assert!(check.check(Some(12.0), 1007.0).score() > 1.0);
+
+    // epochs stop lining up with edges
+    for _ in 0..MAX_MISSED_EDGES - 1 {
+      assert!(check.check(None, 1008.0).score() < 1.0);
+    }
+    assert!(check.check(None, 1009.0).score() >= 1.0);
+  }
+}
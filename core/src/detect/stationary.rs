@@ -0,0 +1,166 @@
+use crate::detect::verdict::DetectorScore;
+use crate::geo::{haversine_distance, GpsCoord};
+
+pub const STATIONARY_WINDOW_S: f64 = 1.0; // Default time the imu has to be still for before a fix counts as stationary
+pub const MAX_ACCEL_STD_MPS2: f32 = 0.1; // Default largest standard deviation of the acceleration's magnitude while still
+pub const MAX_GYRO_RMS_DPS: f32 = 2.0; // Default largest rms rotation rate while still
+pub const STATIONARY_TOLERANCE_M: f32 = 25.0; // Default distance a stationary fix may wander at an hdop of 1
+const MIN_SAMPLES: usize = 10; // Fewest imu samples the stillness is judged from
+
+
+/// Thresholds for deciding the imu is still and for how far the fixes may move meanwhile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StationaryTolerances {
+  pub window_s: f64,
+  pub max_accel_std: f32, // m/s^2
+  pub max_gyro_rms: f32,  // rad/s
+  pub tolerance_m: f32,
+}
+
+/// How much the imu moved over a window of samples
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Motion {
+  pub accel_var: f32,   // variance of the acceleration's magnitude, (m/s^2)^2
+  pub gyro_energy: f32, // mean squared rotation rate, (rad/s)^2
+  pub stationary: bool,
+}
+
+/// Zero velocity detector: the imu is still when the magnitude of the acceleration, with
+/// gravity removed, barely varies (so only the noise and what's left of the bias are
+/// measured) and it's hardly rotating. The magnitude is used rather than the axes so one
+/// threshold covers every axis.
+#[derive(Clone, Copy, Debug)]
+pub struct ZeroVelocityDetector {
+  max_accel_var: f32,
+  max_gyro_energy: f32,
+}
+
+/// Checks that the fixes stay put while the imu is still. A receiver that's sitting
+/// still can't move, so a fix wandering further than the gps error allows is a strong
+/// sign the position is being spoofed. The first fix of each still period is the anchor
+/// the others are measured from.
+#[derive(Clone, Debug)]
+pub struct StationaryCheck {
+  tolerance_m: f32,
+  anchor: Option<GpsCoord>, // first fix since the imu became still
+}
+
+/// Result of checking a single fix against the anchor
+#[derive(Clone, Debug, PartialEq)]
+pub struct StationaryStep {
+  pub stationary: bool,
+  pub drift_m: f32, // distance from the anchor, 0 while moving
+  pub score: DetectorScore,
+}
+
+
+/// ZeroVelocityDetector implementations
+impl ZeroVelocityDetector {
+  pub fn new(tolerances: &StationaryTolerances) -> ZeroVelocityDetector {
+    ZeroVelocityDetector {
+      max_accel_var: tolerances.max_accel_std * tolerances.max_accel_std,
+      max_gyro_energy: tolerances.max_gyro_rms * tolerances.max_gyro_rms,
+    }
+  }
+
+  /// Measures the motion over a window of (acceleration m/s^2 with gravity removed,
+  /// rotation rate rad/s) samples. None with too few samples to tell.
+  pub fn check(&self, samples: impl IntoIterator<Item = ([f32; 3], [f32; 3])>) -> Option<Motion> {
+    let (mut n, mut mean, mut m2, mut energy) = (0usize, 0.0f32, 0.0f32, 0.0f32);
+    for (accel, gyro) in samples {
+      // Welford's running variance, so a bias left in the magnitude doesn't cost precision
+      let magnitude = libm::sqrtf(accel.iter().map(|a| a * a).sum());
+      n += 1;
+      let delta = magnitude - mean;
+      mean += delta / n as f32;
+      m2 += delta * (magnitude - mean);
+      energy += gyro.iter().map(|w| w * w).sum::<f32>();
+    }
+    if n < MIN_SAMPLES {
+      return None;
+    }
+    let accel_var = m2 / n as f32;
+    let gyro_energy = energy / n as f32;
+    let stationary = accel_var <= self.max_accel_var && gyro_energy <= self.max_gyro_energy;
+    Some(Motion { accel_var, gyro_energy, stationary })
+  }
+}
+
+
+/// StationaryCheck implementations
+impl StationaryCheck {
+  pub fn new(tolerances: &StationaryTolerances) -> StationaryCheck {
+    StationaryCheck { tolerance_m: tolerances.tolerance_m, anchor: None }
+  }
+
+  /// Scores a fix with horizontal dilution 'hdop', given whether the imu was still up to
+  /// it. The score is the distance from the anchor as a fraction of the tolerance scaled
+  /// by the hdop, 0 while moving.
+  pub fn check(&mut self, fix: &GpsCoord, hdop: f32, stationary: bool) -> StationaryStep {
+    if !stationary {
+      self.anchor = None;
+      return StationaryStep { stationary, drift_m: 0.0, score: DetectorScore::new("stationary", 0.0) };
+    }
+    let anchor = *self.anchor.get_or_insert(*fix);
+    let drift_m = haversine_distance(anchor.lat(), anchor.lon(), fix.lat(), fix.lon());
+    let allowed = hdop.max(1.0) * self.tolerance_m;
+    StationaryStep { stationary, drift_m, score: DetectorScore::new("stationary", drift_m / allowed) }
+  }
+
+  /// Forgets the anchor, eg. after a gap in the imu samples
+  pub fn reset(&mut self) {
+    self.anchor = None;
+  }
+}
+
+impl Default for StationaryTolerances {
+  fn default() -> Self {
+    StationaryTolerances {
+      window_s: STATIONARY_WINDOW_S,
+      max_accel_std: MAX_ACCEL_STD_MPS2,
+      max_gyro_rms: MAX_GYRO_RMS_DPS.to_radians(),
+      tolerance_m: STATIONARY_TOLERANCE_M,
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// 'n' samples with gravity removed, leaving a small bias along z that every other
+  /// sample adds 'wobble' to, and a steady rotation
+  fn samples(n: usize, wobble: f32, rotation: f32) -> impl Iterator<Item = ([f32; 3], [f32; 3])> {
+    (0..n).map(move |i| ([0.0, 0.0, 0.02 + (i % 2) as f32 * wobble], [rotation, 0.0, 0.0]))
+  }
+
+  #[test]
+  fn test_zero_velocity_detector() {
+    let detector = ZeroVelocityDetector::new(&StationaryTolerances::default());
+    let still = detector.check(samples(100, 0.04, 0.01)).unwrap();
+    assert!(still.stationary);
+    assert!((still.accel_var - 0.0004).abs() < 1e-5);
+
+    assert!(!detector.check(samples(100, 0.5, 0.01)).unwrap().stationary); // shaking
+    assert!(!detector.check(samples(100, 0.04, 0.2)).unwrap().stationary); // turning
+    assert!(detector.check(samples(5, 0.0, 0.0)).is_none());
+  }
+
+  #[test]
+  fn test_moving_fix_while_still_is_flagged() {
+    let mut check = StationaryCheck::new(&StationaryTolerances::default());
+    let start = GpsCoord::new(45.0, -111.0, 0.0);
+    assert_eq!(check.check(&start, 1.0, true).score.score(), 0.0);
+    assert!(check.check(&GpsCoord::new(45.0001, -111.0, 0.0), 1.0, true).score.score() < 1.0); // ~11 m of jitter
+
+    let step = check.check(&GpsCoord::new(45.001, -111.0, 0.0), 1.0, true); // ~111 m while still
+    assert!((step.drift_m - 111.2).abs() < 1.0);
+    assert!(step.score.score() > 1.0);
+
+    let step = check.check(&GpsCoord::new(45.01, -111.0, 0.0), 1.0, false); // moving, nothing to compare
+    assert_eq!(step.score.score(), 0.0);
+    assert_eq!(check.check(&GpsCoord::new(45.02, -111.0, 0.0), 1.0, true).drift_m, 0.0); // a new anchor
+  }
+}
@@ -0,0 +1,117 @@
+use core::fmt::Display;
+use heapless::Vec;
+use serde::Serialize;
+
+pub const SUSPECT_RATIO: f32 = 0.75; // Default score (fraction of a detector's threshold) at which a fix becomes suspect
+pub const MAX_SCORES: usize = 8; // Detector scores kept in a verdict, more than there are detectors
+
+
+/// Overall spoofing state of the receiver
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+  #[default]
+  Nominal,
+  Suspect,
+  Spoofed,
+}
+
+/// Output of a single detector, normalized so that 1.0 is the detector's threshold
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct DetectorScore {
+  name: &'static str,
+  score: f32,
+}
+
+/// Combined result of every detector for a single gps fix
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Verdict {
+  state: AlertState,
+  scores: Vec<DetectorScore, MAX_SCORES>,
+}
+
+
+/// DetectorScore implementations
+impl DetectorScore {
+  pub fn new(name: &'static str, score: f32) -> DetectorScore {
+    DetectorScore { name, score }
+  }
+
+  pub fn name(&self) -> &'static str {
+    self.name
+  }
+
+  pub fn score(&self) -> f32 {
+    self.score
+  }
+}
+
+
+/// Verdict implementations
+impl Verdict {
+  /// Builds a verdict from detector scores. Any score past its threshold
+  /// means spoofing, and any score close to it makes the fix suspect.
+  pub fn from_scores<'a>(scores: impl IntoIterator<Item = &'a DetectorScore>) -> Verdict {
+    Verdict::from_scores_with_ratio(scores, SUSPECT_RATIO)
+  }
+
+  /// Same as from_scores, with the score at which a fix becomes suspect given.
+  /// Every score counts towards the state, but only the first MAX_SCORES are kept.
+  pub fn from_scores_with_ratio<'a>(scores: impl IntoIterator<Item = &'a DetectorScore>, suspect_ratio: f32) -> Verdict {
+    let mut kept = Vec::new();
+    let mut max_score: f32 = 0.0;
+    for score in scores {
+      max_score = max_score.max(score.score);
+      let _ = kept.push(*score); // full, the rest only count towards the state
+    }
+    let state = if max_score >= 1.0 {
+      AlertState::Spoofed
+    } else if max_score >= suspect_ratio {
+      AlertState::Suspect
+    } else {
+      AlertState::Nominal
+    };
+    Verdict { state, scores: kept }
+  }
+
+  pub fn state(&self) -> AlertState {
+    self.state
+  }
+
+  pub fn scores(&self) -> &[DetectorScore] {
+    &self.scores
+  }
+
+  pub fn is_spoofed(&self) -> bool {
+    self.state == AlertState::Spoofed
+  }
+}
+
+impl Display for AlertState {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      AlertState::Nominal => write!(f, "nominal"),
+      AlertState::Suspect => write!(f, "suspect"),
+      AlertState::Spoofed => write!(f, "spoofed"),
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_verdict_from_scores() {
+    let verdict = Verdict::from_scores(&[]);
+    assert_eq!(verdict.state(), AlertState::Nominal);
+
+    let verdict = Verdict::from_scores(&[DetectorScore::new("a", 0.2), DetectorScore::new("b", 0.8)]);
+    assert_eq!(verdict.state(), AlertState::Suspect);
+
+    let verdict = Verdict::from_scores(&[DetectorScore::new("a", 1.2), DetectorScore::new("b", 0.1)]);
+    assert!(verdict.is_spoofed());
+  }
+}
@@ -0,0 +1,127 @@
+use crate::atmosphere::{pressure_altitude, sea_level_pressure};
+use crate::detect::verdict::DetectorScore;
+use crate::ring::FixedRing;
+
+pub const VERTICAL_WINDOW_S: f64 = 10.0; // Default time the gps and barometric climbs are compared over
+pub const VERTICAL_TOLERANCE_M: f32 = 20.0; // Default difference between the two climbs allowed over the window
+pub const MAX_REFERENCE_VDOP: f32 = 2.5; // Default largest vdop of a fix trusted to reference the barometer
+const ALTITUDES_KEPT: usize = 1024; // Altitude pairs kept, enough for the window at the fastest fix rate
+const FIX_3D: u8 = 3; // Gsa fix mode with a usable altitude
+
+
+/// Thresholds for comparing the gps and barometric altitudes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VerticalTolerances {
+  pub window_s: f64,
+  pub tolerance_m: f32,
+  pub max_vdop: f32,
+}
+
+/// Compares how far the gps altitude (msl, from GGA) climbs or falls with how far the
+/// barometric altitude does. The air pressure doesn't follow a spoofed position, so a
+/// receiver being walked up or down (or held level while the vehicle climbs) stands out.
+/// The barometer's sea level pressure is referenced at the first trusted 3D fix; only
+/// changes over the window are compared, so the weather drifting the reference doesn't
+/// matter.
+#[derive(Clone, Debug)]
+pub struct VerticalCheck {
+  tolerances: VerticalTolerances,
+  sea_level_pa: Option<f32>,
+  altitudes: FixedRing<(f32, f32), ALTITUDES_KEPT>, // (gps, barometric) altitude at each fix's epoch
+}
+
+/// Result of checking a single fix's altitude
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerticalStep {
+  pub baro_alt_m: Option<f32>,  // barometric altitude, once referenced
+  pub gps_climb_m: f32,         // change in gps altitude over the window
+  pub baro_climb_m: f32,        // change in barometric altitude over the window
+  pub score: DetectorScore,
+}
+
+
+/// VerticalCheck implementations
+impl VerticalCheck {
+  pub fn new(tolerances: VerticalTolerances) -> VerticalCheck {
+    VerticalCheck { tolerances, sea_level_pa: None, altitudes: FixedRing::new() }
+  }
+
+  /// Scores a fix at 't' with gps altitude 'gps_alt_m', given the fix 'mode' and 'vdop'
+  /// from GSA and the air pressure at the fix's epoch. Fixes without a 3D solution
+  /// aren't compared. The score is the difference between the climbs over the window as
+  /// a fraction of the tolerance, 0 until the window is covered.
+  pub fn check(&mut self, t: f64, gps_alt_m: f32, mode: u8, vdop: f32, pressure_pa: f32) -> VerticalStep {
+    let trusted = mode == FIX_3D && vdop > 0.0 && vdop <= self.tolerances.max_vdop;
+    if self.sea_level_pa.is_none() && trusted {
+      self.sea_level_pa = Some(sea_level_pressure(pressure_pa, gps_alt_m));
+    }
+    let baro_alt_m = self.sea_level_pa.map(|sea_level| pressure_altitude(pressure_pa, sea_level));
+    let (Some(baro), true) = (baro_alt_m, mode == FIX_3D) else {
+      return VerticalStep { baro_alt_m, gps_climb_m: 0.0, baro_climb_m: 0.0, score: DetectorScore::new("vertical", 0.0) };
+    };
+
+    let Some((_, (start_gps, start_baro))) = self.altitudes.push_window(t, (gps_alt_m, baro), self.tolerances.window_s) else {
+      return VerticalStep { baro_alt_m, gps_climb_m: 0.0, baro_climb_m: 0.0, score: DetectorScore::new("vertical", 0.0) };
+    };
+
+    let gps_climb_m = gps_alt_m - start_gps;
+    let baro_climb_m = baro - start_baro;
+    let score = (gps_climb_m - baro_climb_m).abs() / self.tolerances.tolerance_m;
+    VerticalStep { baro_alt_m, gps_climb_m, baro_climb_m, score: DetectorScore::new("vertical", score) }
+  }
+
+  /// Forgets the altitudes, eg. after a gap in the fixes. The sea level reference is kept.
+  pub fn reset(&mut self) {
+    self.altitudes.clear();
+  }
+
+  /// The sea level pressure the barometer was referenced with, once it has been
+  pub fn sea_level_pa(&self) -> Option<f32> {
+    self.sea_level_pa
+  }
+}
+
+impl Default for VerticalTolerances {
+  fn default() -> Self {
+    VerticalTolerances { window_s: VERTICAL_WINDOW_S, tolerance_m: VERTICAL_TOLERANCE_M, max_vdop: MAX_REFERENCE_VDOP }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::atmosphere::STANDARD_PRESSURE_PA;
+
+  /// Pressure at 'alt' in the standard atmosphere
+  fn pressure(alt: f32) -> f32 {
+    STANDARD_PRESSURE_PA * libm::powf(1.0 - alt / 44330.0, 5.255)
+  }
+
+  #[test]
+  fn test_matching_climb_is_nominal() {
+    let mut check = VerticalCheck::new(VerticalTolerances::default());
+    assert_eq!(check.check(0.0, 100.0, 2, 1.0, pressure(100.0)).baro_alt_m, None); // 2D fix, not referenced
+    for i in 0..60 {
+      let alt = 100.0 + i as f32 * 2.0; // climbing at 2 m/s
+      let step = check.check(i as f64, alt, 3, 1.2, pressure(alt));
+      assert!(step.score.score() < 1.0, "fix {i}: {step:?}");
+    }
+    assert!(check.sea_level_pa().is_some());
+  }
+
+  #[test]
+  fn test_divergent_climb_is_flagged() {
+    let mut check = VerticalCheck::new(VerticalTolerances::default());
+    let mut last = None;
+    for i in 0..20 {
+      let gps_alt = 100.0 + i as f32 * 5.0; // the gps climbs at 5 m/s while the barometer stays level
+      last = Some(check.check(i as f64, gps_alt, 3, 1.2, pressure(100.0)));
+    }
+    let last = last.unwrap();
+    assert!((last.gps_climb_m - 50.0).abs() < 1e-3);
+    assert!(last.baro_climb_m.abs() < 0.5);
+    assert!(last.score.score() > 1.0);
+  }
+}
@@ -0,0 +1,113 @@
+use core::f32::consts::PI;
+use core::fmt::Display;
+
+const EARTH_RAD: f32 = 6371000.0; // radius of earth in m
+
+
+/// Struct to hold gps coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsCoord {
+  lat: f32,
+  lon: f32,
+  alt: f32,
+}
+
+
+/// GpsCoord implementations
+impl GpsCoord {
+  pub fn new(lat: f32, lon: f32, alt: f32) -> GpsCoord {
+    GpsCoord { lat, lon, alt }
+  }
+
+  pub fn lat(&self) -> f32 {
+    self.lat
+  }
+
+  pub fn lon(&self) -> f32 {
+    self.lon
+  }
+
+  pub fn alt(&self) -> f32 {
+    self.alt
+  }
+}
+
+impl Display for GpsCoord {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "(lat: {}, lon: {}, alt: {})", self.lat, self.lon, self.alt)
+  }
+}
+
+
+fn degrees_to_radians(degrees: f32) -> f32 {
+  degrees * PI / 180.0
+}
+
+/// Computes the distance in meters between two points on earth.
+/// Takes in latitude and longitude of two points, and returns
+/// the distance between them in meters.
+pub fn haversine_distance(lat1: f32, lon1: f32, lat2: f32, lon2: f32) -> f32 {
+  let d_lat = degrees_to_radians(lat2 - lat1);
+  let d_lon = degrees_to_radians(lon2 - lon1);
+
+  let lat1 = degrees_to_radians(lat1);
+  let lat2 = degrees_to_radians(lat2);
+
+  let half_lat = libm::sinf(d_lat / 2.0);
+  let half_lon = libm::sinf(d_lon / 2.0);
+  let a = half_lat * half_lat + half_lon * half_lon * libm::cosf(lat1) * libm::cosf(lat2);
+  let c = 2.0 * libm::atan2f(libm::sqrtf(a), libm::sqrtf(1.0 - a));
+  c * EARTH_RAD
+}
+
+
+/// Difference 'a' - 'b' between two headings in degrees, wrapped to -180 to 180
+pub fn heading_difference(a: f32, b: f32) -> f32 {
+  wrap_heading(a - b + 180.0) - 180.0
+}
+
+
+/// Wraps an angle in degrees to 0 to 360, as f32::rem_euclid (which needs std) does
+pub fn wrap_heading(degrees: f32) -> f32 {
+  let wrapped = degrees % 360.0;
+  if wrapped < 0.0 { wrapped + 360.0 } else { wrapped }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_degrees_to_radians() {
+    let deg = 180.0;
+    let rad = degrees_to_radians(deg);
+    assert_eq!(rad, PI);
+  }
+
+  #[test]
+  fn test_haversine_distance() {
+    let lat1 = 0.0;
+    let lon1 = 0.0;
+    let lat2 = 0.0;
+    let lon2 = 0.0;
+    let dist = haversine_distance(lat1, lon1, lat2, lon2);
+    assert_eq!(dist, 0.0);
+
+    let lat1 = 0.0;
+    let lon1 = 0.0;
+    let lat2 = 10.0;
+    let lon2 = 10.0;
+    let dist = haversine_distance(lat1, lon1, lat2, lon2);
+    assert_eq!(dist, 1568520.6);
+  }
+
+  #[test]
+  fn test_heading_difference() {
+    assert_eq!(heading_difference(10.0, 350.0), 20.0);
+    assert_eq!(heading_difference(350.0, 10.0), -20.0);
+    assert_eq!(heading_difference(180.0, 0.0), -180.0);
+    assert_eq!(heading_difference(-170.0, 170.0), 20.0);
+  }
+}
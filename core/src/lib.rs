@@ -0,0 +1,10 @@
+// Math and spoofing detection shared by the Linux tools and microcontroller builds.
+// Nothing here allocates, buffers have a fixed capacity. Builds without std unless
+// the "std" feature is on, as it is for the host crate and the tests.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod atmosphere;
+pub mod detect;
+pub mod geo;
+pub mod nav;
+pub mod ring;
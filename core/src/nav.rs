@@ -0,0 +1,133 @@
+use core::fmt::Display;
+use core::ops::{Add, AddAssign, Div};
+
+use crate::geo::GpsCoord;
+
+
+/// A converted acceleration (or a velocity integrated from one)
+#[derive(Clone, Copy, Debug)]
+pub struct RawPoint {
+  x: f32,
+  y: f32,
+  z: f32,
+}
+
+
+/// Predicts the position of the gps by averaging the acceleration over a period of time
+pub fn calc_new_pos(old_pos: &GpsCoord, vel: &RawPoint, accel: &RawPoint, time: &f64) -> GpsCoord {
+  let t = *time as f32;
+  let new_lat = old_pos.lat() + vel.x() * t + 0.5 * accel.x() * t * t;
+  let new_lon = old_pos.lon() + vel.y() * t + 0.5 * accel.y() * t * t;
+  let new_alt = old_pos.alt() + vel.z() * t + 0.5 * accel.z() * t * t;
+  GpsCoord::new(new_lat, new_lon, new_alt)
+}
+
+
+/// Predicts the velocity of the gps by averaging the acceleration over a period of time
+pub fn calc_new_vel(old_vel: &RawPoint, accel: &RawPoint, time: &f64) -> RawPoint {
+  let new_lat = old_vel.x() + accel.x() * (*time as f32);
+  let new_lon = old_vel.y() + accel.y() * (*time as f32);
+  let new_alt = old_vel.z() + accel.z() * (*time as f32);
+  RawPoint::new(new_lat, new_lon, new_alt)
+}
+
+
+impl Display for RawPoint {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "Accel: (x: {:.3}, y: {:.3}, z: {:.3})", self.x, self.y, self.z)
+  }
+}
+
+impl RawPoint {
+  pub fn new(x: f32, y: f32, z: f32) -> Self {
+    RawPoint {x, y, z}
+  }
+
+  pub fn x(&self) -> f32 {
+    self.x
+  }
+
+  pub fn y(&self) -> f32 {
+    self.y
+  }
+
+  pub fn z(&self) -> f32 {
+    self.z
+  }
+}
+
+impl Add for RawPoint {
+  type Output = Self;
+
+  fn add(self, other: Self) -> Self {
+    RawPoint {
+      x: self.x + other.x,
+      y: self.y + other.y,
+      z: self.z + other.z,
+    }
+  }
+}
+
+impl AddAssign for RawPoint {
+  fn add_assign(&mut self, other: Self) {
+    *self = RawPoint {
+      x: self.x + other.x,
+      y: self.y + other.y,
+      z: self.z + other.z,
+    }
+  }
+}
+
+impl Div<RawPoint> for RawPoint {
+  type Output = Self;
+
+  fn div(self, divisor: Self) -> Self {
+    RawPoint {
+      x: self.x / divisor.x,
+      y: self.y / divisor.y,
+      z: self.z / divisor.z,
+    }
+  }
+}
+
+impl Div<f32> for RawPoint {
+  type Output = Self;
+
+  fn div(self, divisor: f32) -> Self {
+    RawPoint {
+      x: self.x / divisor,
+      y: self.y / divisor,
+      z: self.z / divisor,
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_calc_new_pos() {
+    let old_pos = GpsCoord::new(0.0, 0.0, 0.0);
+    let vel = RawPoint::new(1.0, 1.0, 1.0);
+    let accel = RawPoint::new(1.0, 1.0, 1.0);
+    let time = 1.0;
+    let new_pos = calc_new_pos(&old_pos, &vel, &accel, &time);
+    assert_eq!(new_pos.lat(), 1.5);
+    assert_eq!(new_pos.lon(), 1.5);
+    assert_eq!(new_pos.alt(), 1.5);
+  }
+
+  #[test]
+  fn test_calc_new_vel() {
+    let old_vel = RawPoint::new(0.0, 0.0, 0.0);
+    let accel = RawPoint::new(1.0, 1.0, 1.0);
+    let time = 1.0;
+    let new_vel = calc_new_vel(&old_vel, &accel, &time);
+    assert_eq!(new_vel.x(), 1.0);
+    assert_eq!(new_vel.y(), 1.0);
+    assert_eq!(new_vel.z(), 1.0);
+  }
+}
@@ -0,0 +1,125 @@
+use heapless::Deque;
+
+/// A value and its time
+pub type Timed<T> = (f64, T);
+
+
+/// Buffer of up to N values in time order, the oldest overwritten first. The
+/// allocation free counterpart of the host crate's TimedRing, sized at compile time.
+#[derive(Clone, Debug)]
+pub struct FixedRing<T, const N: usize> {
+  items: Deque<Timed<T>, N>,
+}
+
+
+/// FixedRing implementations
+impl<T, const N: usize> FixedRing<T, N> {
+  pub const fn new() -> FixedRing<T, N> {
+    FixedRing { items: Deque::new() }
+  }
+
+  /// Adds a value, dropping the oldest if full. Values older than the newest
+  /// are rejected (returns false) so the buffer stays in time order.
+  pub fn push(&mut self, t: f64, value: T) -> bool {
+    if self.last_t().is_some_and(|last| t < last) {
+      return false;
+    }
+    if self.items.is_full() {
+      self.items.pop_front();
+    }
+    self.items.push_back((t, value)).is_ok()
+  }
+
+  pub fn len(&self) -> usize {
+    self.items.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
+  }
+
+  /// Time of the oldest value
+  pub fn first_t(&self) -> Option<f64> {
+    self.items.front().map(|(t, _)| *t)
+  }
+
+  /// Time of the newest value
+  pub fn last_t(&self) -> Option<f64> {
+    self.items.back().map(|(t, _)| *t)
+  }
+
+  pub fn latest(&self) -> Option<&Timed<T>> {
+    self.items.back()
+  }
+
+  /// Values oldest first
+  pub fn iter(&self) -> impl Iterator<Item = &Timed<T>> {
+    self.items.iter()
+  }
+
+  /// Drops values older than 't'
+  pub fn discard_before(&mut self, t: f64) {
+    while self.items.front().is_some_and(|(item_t, _)| *item_t < t) {
+      self.items.pop_front();
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.items.clear();
+  }
+
+  /// Adds a value at 't' and returns the start of the window of 'window' seconds ending
+  /// at it: the newest value at least 'window' old, None until the buffer reaches that
+  /// far back. Values before the start are dropped, later windows start later still.
+  /// If time went backwards the buffer starts over from the new value.
+  pub fn push_window(&mut self, t: f64, value: T, window: f64) -> Option<Timed<T>> where T: Copy {
+    if !self.push(t, value) {
+      self.clear();
+      self.push(t, value);
+    }
+    let start = self.iter().take_while(|(item_t, _)| *item_t <= t - window).last().copied()?;
+    self.discard_before(start.0);
+    Some(start)
+  }
+}
+
+impl<T, const N: usize> Default for FixedRing<T, N> {
+  fn default() -> Self {
+    FixedRing::new()
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_overwrites_oldest() {
+    let mut ring: FixedRing<i32, 3> = FixedRing::new();
+    for i in 0..5 {
+      assert!(ring.push(i as f64, i));
+    }
+    assert_eq!(ring.len(), 3);
+    assert_eq!(ring.first_t(), Some(2.0));
+    assert_eq!(ring.latest(), Some(&(4.0, 4)));
+    assert!(!ring.push(1.0, 1)); // out of order
+
+    ring.discard_before(4.0);
+    assert_eq!(ring.iter().map(|(_, v)| *v).sum::<i32>(), 4);
+  }
+
+  #[test]
+  fn test_push_window() {
+    let mut ring: FixedRing<i32, 8> = FixedRing::new();
+    assert_eq!(ring.push_window(0.0, 0, 2.0), None);
+    assert_eq!(ring.push_window(1.0, 1, 2.0), None); // not a window old yet
+    assert_eq!(ring.push_window(2.5, 2, 2.0), Some((0.0, 0)));
+    assert_eq!(ring.push_window(3.5, 3, 2.0), Some((1.0, 1)));
+    assert_eq!(ring.first_t(), Some(1.0)); // the values before the start are dropped
+
+    assert_eq!(ring.push_window(0.5, 4, 2.0), None); // time went backwards
+    assert_eq!(ring.len(), 1);
+  }
+}
@@ -0,0 +1,220 @@
+use rppal::i2c::I2c;
+use std::fmt::Display;
+
+use crate::bus::i2c::{self as bus, BusError};
+
+pub use gps_spoofing_core::atmosphere::{pressure_altitude, sea_level_pressure, STANDARD_PRESSURE_PA};
+
+pub const BMP280_ADDR: u16 = 0x76; // I2C address with SDO low (0x77 with it high)
+const CALIB_00: u8 = 0x88; // First of the temperature and pressure trimming registers
+const CALIB_H1: u8 = 0xA1; // First humidity trimming register (BME280)
+const CALIB_H2: u8 = 0xE1; // Rest of the humidity trimming registers (BME280)
+const CHIP_ID: u8 = 0xD0; // Chip id register
+const CTRL_HUM: u8 = 0xF2; // Humidity oversampling (BME280), applied on the next write to CTRL_MEAS
+const CTRL_MEAS: u8 = 0xF4; // Temperature and pressure oversampling and the mode
+const CONFIG: u8 = 0xF5; // Standby time and iir filter
+const PRESS_MSB: u8 = 0xF7; // First of the measurement registers (pressure, temperature, then humidity)
+const BMP280_IDS: [u8; 3] = [0x56, 0x57, 0x58]; // Chip ids of BMP280 samples and production parts
+const BME280_ID: u8 = 0x60; // Chip id of the BME280
+const MEAS_NORMAL: u8 = 0b0101_0111; // Temperature x2 (010), pressure x16 (101) oversampling, measuring continuously (11)
+const CONFIG_FILTERED: u8 = 0b0001_0000; // 0.5 ms standby (000), iir filter coefficient 16 (100)
+const HUM_X1: u8 = 0x01; // Humidity oversampling x1
+
+
+/// Which Bosch sensor is fitted, the BME280 also measures humidity
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BaroVariant {
+  Bmp280,
+  Bme280,
+}
+
+/// Reasons the barometer couldn't be set up or read
+#[derive(Debug)]
+pub enum BaroError {
+  Bus(BusError),
+  WrongDevice(u8), // the chip id of whatever answered
+}
+
+/// A compensated measurement
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BaroReading {
+  pub pressure_pa: f32,
+  pub temperature_c: f32,
+  pub humidity: Option<f32>, // relative humidity in percent, from a BME280
+}
+
+/// Trimming parameters programmed into each sensor at the factory
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Trim {
+  t: [f64; 3],
+  p: [f64; 9],
+  h: [f64; 6],
+}
+
+/// A BMP280 or BME280 measuring continuously
+pub struct Barometer {
+  i2c: I2c,
+  variant: BaroVariant,
+  trim: Trim,
+}
+
+
+/// Barometer implementations
+impl Barometer {
+  /// Opens the sensor at 'address' on 'bus', checks what it is, reads its trimming
+  /// parameters and starts it measuring with the iir filter on
+  pub fn init(bus: u8, address: u16) -> Result<Barometer, BaroError> {
+    let mut i2c = bus::open(bus, address)?;
+    let mut id = [0; 1];
+    bus::read_registers(&i2c, CHIP_ID, &mut id)?;
+    let variant = match id[0] {
+      id if BMP280_IDS.contains(&id) => BaroVariant::Bmp280,
+      BME280_ID => BaroVariant::Bme280,
+      id => return Err(BaroError::WrongDevice(id)),
+    };
+
+    let mut calib = [0; 24];
+    bus::read_registers(&i2c, CALIB_00, &mut calib)?;
+    let mut calib_h = [0; 8];
+    if variant == BaroVariant::Bme280 {
+      bus::read_registers(&i2c, CALIB_H1, &mut calib_h[..1])?;
+      bus::read_registers(&i2c, CALIB_H2, &mut calib_h[1..])?;
+      bus::write_register(&mut i2c, CTRL_HUM, HUM_X1)?;
+    }
+    bus::write_register(&mut i2c, CONFIG, CONFIG_FILTERED)?;
+    bus::write_register(&mut i2c, CTRL_MEAS, MEAS_NORMAL)?;
+    Ok(Barometer { i2c, variant, trim: Trim::parse(&calib, &calib_h) })
+  }
+
+  /// Reads the latest measurement
+  pub fn read(&self) -> Result<BaroReading, BaroError> {
+    let mut data = [0; 8];
+    let len = if self.variant == BaroVariant::Bme280 { 8 } else { 6 };
+    bus::read_registers(&self.i2c, PRESS_MSB, &mut data[..len])?;
+    let adc_p = (data[0] as u32) << 12 | (data[1] as u32) << 4 | (data[2] as u32) >> 4;
+    let adc_t = (data[3] as u32) << 12 | (data[4] as u32) << 4 | (data[5] as u32) >> 4;
+    let adc_h = u16::from_be_bytes([data[6], data[7]]);
+
+    let (temperature_c, t_fine) = self.trim.temperature(adc_t);
+    Ok(BaroReading {
+      pressure_pa: self.trim.pressure(adc_p, t_fine) as f32,
+      temperature_c: temperature_c as f32,
+      humidity: (self.variant == BaroVariant::Bme280).then(|| self.trim.humidity(adc_h, t_fine) as f32),
+    })
+  }
+
+  pub fn variant(&self) -> BaroVariant {
+    self.variant
+  }
+}
+
+
+/// Trim implementations
+impl Trim {
+  /// Parses the 24 temperature and pressure trimming registers and the 8 humidity ones
+  /// (H1, then the 7 from 0xE1; zeros on a BMP280)
+  pub fn parse(calib: &[u8; 24], calib_h: &[u8; 8]) -> Trim {
+    let unsigned = |i: usize| u16::from_le_bytes([calib[i], calib[i + 1]]) as f64;
+    let signed = |i: usize| i16::from_le_bytes([calib[i], calib[i + 1]]) as f64;
+    Trim {
+      t: [unsigned(0), signed(2), signed(4)],
+      p: [unsigned(6), signed(8), signed(10), signed(12), signed(14), signed(16), signed(18), signed(20), signed(22)],
+      h: [
+        calib_h[0] as f64,
+        i16::from_le_bytes([calib_h[1], calib_h[2]]) as f64,
+        calib_h[3] as f64,
+        ((calib_h[4] as i8 as i16) << 4 | (calib_h[5] & 0x0F) as i16) as f64,
+        ((calib_h[6] as i8 as i16) << 4 | (calib_h[5] >> 4) as i16) as f64,
+        calib_h[7] as i8 as f64,
+      ],
+    }
+  }
+
+  /// Compensated temperature in degrees C, and the fine temperature the other measurements are compensated with
+  pub fn temperature(&self, adc_t: u32) -> (f64, f64) {
+    let [t1, t2, t3] = self.t;
+    let adc_t = adc_t as f64;
+    let var1 = (adc_t / 16384.0 - t1 / 1024.0) * t2;
+    let var2 = (adc_t / 131072.0 - t1 / 8192.0).powi(2) * t3;
+    let t_fine = var1 + var2;
+    (t_fine / 5120.0, t_fine)
+  }
+
+  /// Compensated pressure in Pa
+  pub fn pressure(&self, adc_p: u32, t_fine: f64) -> f64 {
+    let [p1, p2, p3, p4, p5, p6, p7, p8, p9] = self.p;
+    let mut var1 = t_fine / 2.0 - 64000.0;
+    let mut var2 = var1 * var1 * p6 / 32768.0;
+    var2 += var1 * p5 * 2.0;
+    var2 = var2 / 4.0 + p4 * 65536.0;
+    var1 = (p3 * var1 * var1 / 524288.0 + p2 * var1) / 524288.0;
+    var1 = (1.0 + var1 / 32768.0) * p1;
+    if var1 == 0.0 {
+      return 0.0; // unprogrammed trimming, avoids dividing by zero
+    }
+    let mut p = 1048576.0 - adc_p as f64;
+    p = (p - var2 / 4096.0) * 6250.0 / var1;
+    var1 = p9 * p * p / 2147483648.0;
+    var2 = p * p8 / 32768.0;
+    p + (var1 + var2 + p7) / 16.0
+  }
+
+  /// Compensated relative humidity in percent
+  pub fn humidity(&self, adc_h: u16, t_fine: f64) -> f64 {
+    let [h1, h2, h3, h4, h5, h6] = self.h;
+    let var = t_fine - 76800.0;
+    let var = (adc_h as f64 - (h4 * 64.0 + h5 / 16384.0 * var))
+      * (h2 / 65536.0 * (1.0 + h6 / 67108864.0 * var * (1.0 + h3 / 67108864.0 * var)));
+    (var * (1.0 - h1 * var / 524288.0)).clamp(0.0, 100.0)
+  }
+}
+
+
+impl From<BusError> for BaroError {
+  fn from(e: BusError) -> Self {
+    BaroError::Bus(e)
+  }
+}
+
+impl Display for BaroError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      BaroError::Bus(e) => write!(f, "{e}"),
+      BaroError::WrongDevice(id) => write!(f, "unexpected device (chip id {id:#04x}, expected a bmp280 or bme280)"),
+    }
+  }
+}
+
+impl std::error::Error for BaroError {}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_compensation() {
+    // worked example from the BMP280 datasheet
+    let trim = Trim {
+      t: [27504.0, 26435.0, -1000.0],
+      p: [36477.0, -10685.0, 3024.0, 2855.0, 140.0, -7.0, 15500.0, -14600.0, 6000.0],
+      h: [0.0; 6],
+    };
+    let (temperature, t_fine) = trim.temperature(519888);
+    assert!((temperature - 25.08).abs() < 0.01);
+    assert!((trim.pressure(415148, t_fine) - 100653.27).abs() < 0.1);
+  }
+
+  #[test]
+  fn test_parse_trim() {
+    let mut calib = [0; 24];
+    calib[0..2].copy_from_slice(&27504u16.to_le_bytes());
+    calib[4..6].copy_from_slice(&(-1000i16).to_le_bytes());
+    // H4 = 0x123 (0xE4 << 4 | low nibble of 0xE5), H5 = -257 (signed 0xE6 << 4 | high nibble of 0xE5)
+    let calib_h = [75, 0x6A, 0x01, 0, 0x12, 0xF3, 0xEF, 0x1E];
+    let trim = Trim::parse(&calib, &calib_h);
+    assert_eq!(trim.t, [27504.0, 0.0, -1000.0]);
+    assert_eq!(trim.h, [75.0, 362.0, 0.0, 291.0, -257.0, 30.0]);
+  }
+}
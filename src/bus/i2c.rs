@@ -0,0 +1,93 @@
+use rppal::i2c::I2c;
+use std::fmt::Display;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub(crate) const ENXIO: i32 = 6; // errno when nothing acknowledges the address
+pub(crate) const ETIMEDOUT: i32 = 110; // errno when the bus times out (eg. a device holding SDA low)
+pub(crate) const EREMOTEIO: i32 = 121; // errno when the device stops acknowledging part way through a transfer
+
+static I2C_READ_ERRORS: AtomicU64 = AtomicU64::new(0); // Failed register reads since start up, from any device
+
+
+/// Reasons an i2c transfer failed, shared by the drivers of every device on the bus
+#[derive(Debug)]
+pub enum BusError {
+  Bus(rppal::i2c::Error),
+  Nack,    // nothing acknowledged the address, usually a wiring or address problem
+  Timeout, // the transfer didn't finish in time
+}
+
+
+/// Opens i2c 'bus' for talking to the device at 'address'. Each device gets its own
+/// handle, so drivers sharing a bus don't have to agree on the slave address.
+pub fn open(bus: u8, address: u16) -> Result<I2c, BusError> {
+  let mut i2c = I2c::with_bus(bus)?;
+  i2c.set_slave_address(address)?;
+  Ok(i2c)
+}
+
+
+/// Writes a single register
+pub fn write_register(i2c: &mut I2c, register: u8, value: u8) -> Result<(), BusError> {
+  write_registers(i2c, register, &[value])
+}
+
+
+/// Writes consecutive registers starting at 'register'
+pub fn write_registers(i2c: &mut I2c, register: u8, values: &[u8]) -> Result<(), BusError> {
+  let mut data = Vec::with_capacity(values.len() + 1);
+  data.push(register);
+  data.extend_from_slice(values);
+  i2c.write(&data)?;
+  Ok(())
+}
+
+
+/// Reads consecutive registers starting at 'register', counting failed reads
+pub fn read_registers(i2c: &I2c, register: u8, buffer: &mut [u8]) -> Result<(), BusError> {
+  i2c.write_read(&[register], buffer).map_err(|e| {
+    count_read_error();
+    BusError::from(e)
+  })
+}
+
+
+/// Counts a failed register read, for drivers that talk to the bus through embedded-hal
+pub(crate) fn count_read_error() {
+  I2C_READ_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+
+/// Failed register reads since start up
+pub fn read_errors() -> u64 {
+  I2C_READ_ERRORS.load(Ordering::Relaxed)
+}
+
+
+/// BusError implementations
+impl From<rppal::i2c::Error> for BusError {
+  fn from(e: rppal::i2c::Error) -> Self {
+    match &e {
+      rppal::i2c::Error::Io(io_error) => match io_error.raw_os_error() {
+        Some(ENXIO) | Some(EREMOTEIO) => BusError::Nack,
+        Some(ETIMEDOUT) => BusError::Timeout,
+        _ if io_error.kind() == io::ErrorKind::TimedOut => BusError::Timeout,
+        _ => BusError::Bus(e),
+      },
+      _ => BusError::Bus(e),
+    }
+  }
+}
+
+impl Display for BusError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      BusError::Bus(e) => write!(f, "i2c bus error: {e}"),
+      BusError::Nack => write!(f, "no acknowledgement, check the wiring and address"),
+      BusError::Timeout => write!(f, "i2c transfer timed out"),
+    }
+  }
+}
+
+impl std::error::Error for BusError {}
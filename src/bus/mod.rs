@@ -0,0 +1 @@
+pub mod i2c;
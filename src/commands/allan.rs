@@ -0,0 +1,113 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use gps_spoofing_detection::config::settings::Config;
+use gps_spoofing_detection::mpu6050::accel::{DataPointType, ImuError, GRAVITY_ACCEL};
+use gps_spoofing_detection::mpu6050::allan::{self, AxisNoise, NoiseProfile, MIN_CLUSTERS};
+
+use crate::commands::common::{self, log_event, sensor_error, Exit, ImuHandle, Verbosity};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(60); // Time between progress messages while recording
+
+type AxisSamples = [Vec<f32>; 3]; // One series of samples per axis
+
+
+/// Records the still imu for 'duration' at 'rate_hz', computes the Allan deviation of
+/// each axis and writes the noise terms read off it to 'output' (toml)
+pub fn run(config: &Config, duration: Duration, rate_hz: u32, output: &str, verbosity: Verbosity) -> Exit {
+  let mut events = match common::open_event_log(config) {
+    Ok(events) => events,
+    Err(exit) => return exit,
+  };
+  let i2c = match common::open_imu(config) {
+    Ok(i2c) => i2c,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(config.imu.model.name(), &e));
+      return Exit::Sensor;
+    }
+  };
+  let sensor = i2c.borrow().model().name();
+
+  verbosity.info(&format!("Recording the imu for {} s at {rate_hz} Hz, keep it still...", duration.as_secs()));
+  let (accel_samples, gyro_samples) = match record(&i2c, duration, rate_hz, verbosity) {
+    Ok(samples) => samples,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(sensor, &e.to_string()));
+      return Exit::Sensor;
+    }
+  };
+  if accel_samples[0].len() < 2 * MIN_CLUSTERS {
+    eprintln!("Only {} samples were recorded, too few to analyse", accel_samples[0].len());
+    return Exit::Failure;
+  }
+
+  let rate = rate_hz as f64;
+  let axis = |samples: &Vec<f32>| allan::analyze(allan::overlapping_adev(samples, rate));
+  let profile = NoiseProfile {
+    sensor: common::sensor_info(&i2c, config).device_id,
+    sample_rate_hz: rate,
+    duration_s: accel_samples[0].len() as f64 / rate,
+    accel: accel_samples.each_ref().map(axis),
+    gyro: gyro_samples.each_ref().map(axis),
+  };
+
+  print_summary("accel", "m/s^2", &profile.accel);
+  print_summary("gyro", "rad/s", &profile.gyro);
+  match profile.save(output) {
+    Ok(()) => {
+      verbosity.info(&format!("Saved noise profile to {output}"));
+      Exit::Ok
+    }
+    Err(e) => {
+      eprintln!("Could not write noise profile {output}: {e}");
+      Exit::Failure
+    }
+  }
+}
+
+
+/// Samples the accelerometer (m/s^2) and gyroscope (rad/s) at a fixed rate. A failed
+/// read ends the recording, a gap would corrupt the longer averaging times.
+fn record(imu: &ImuHandle, duration: Duration, rate_hz: u32, verbosity: Verbosity)
+          -> Result<(AxisSamples, AxisSamples), ImuError> {
+  let mut imu = imu.borrow_mut();
+  let accel_scale = GRAVITY_ACCEL / imu.accel_sensitivity();
+  let gyro_scale = (1.0 / imu.gyro_sensitivity()).to_radians();
+  let period = Duration::from_secs(1) / rate_hz;
+  let mut accel_samples = AxisSamples::default();
+  let mut gyro_samples = AxisSamples::default();
+
+  let start = Instant::now();
+  let mut next = start;
+  let mut next_progress = start + PROGRESS_INTERVAL;
+  while next - start < duration {
+    let sample = imu.read_raw()?;
+    let a = [sample.accel.x(), sample.accel.y(), sample.accel.z()];
+    let g = [sample.gyro.x(), sample.gyro.y(), sample.gyro.z()];
+    for i in 0..3 {
+      accel_samples[i].push(a[i] as f32 * accel_scale);
+      gyro_samples[i].push(g[i] as f32 * gyro_scale);
+    }
+
+    let now = Instant::now();
+    if now >= next_progress {
+      verbosity.info(&format!("{} of {} s recorded", (now - start).as_secs(), duration.as_secs()));
+      next_progress += PROGRESS_INTERVAL;
+    }
+    next += period;
+    thread::sleep(next.saturating_duration_since(now));
+  }
+  Ok((accel_samples, gyro_samples))
+}
+
+
+/// Prints the noise terms of each axis of a sensor
+fn print_summary(sensor: &str, unit: &str, axes: &[AxisNoise; 3]) {
+  let optional = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{v:.3e}"));
+  println!("{sensor} ({unit})");
+  for (name, noise) in ["x", "y", "z"].iter().zip(axes) {
+    let bias_instability = noise.bias_instability.zip(noise.bias_instability_tau_s)
+      .map_or("-".to_string(), |(value, tau_s)| format!("{value:.3e} at {tau_s:.1} s"));
+    println!("  {name}  random walk {} /sqrt(Hz)  bias instability {bias_instability}  rate random walk {} sqrt(Hz)",
+             optional(noise.random_walk), optional(noise.rate_random_walk));
+  }
+}
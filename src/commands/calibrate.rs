@@ -0,0 +1,220 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+use gps_spoofing_detection::config::settings::Config;
+use gps_spoofing_detection::imu::driver::Imu;
+use gps_spoofing_detection::mpu6050::accel::{DataPointType, ImuError};
+use gps_spoofing_detection::mpu6050::calibration::Calibration;
+use gps_spoofing_detection::mpu6050::six_position::{self, AccelCorrection, POSITIONS, STILL_TOLERANCE_G};
+use gps_spoofing_detection::mpu6050::temperature::{BiasPoint, TemperatureModel, MIN_SPAN_C};
+use gps_spoofing_detection::output::events::EventLog;
+
+use crate::commands::common::{self, log_event, sensor_error, Exit, ImuHandle, Verbosity};
+
+const POSITION_SAMPLES: u32 = 1000; // Samples averaged in each orientation of the six position calibration
+const BIAS_POINT_SAMPLES: u32 = 500; // Samples averaged into each point of the temperature calibration
+const BIAS_POINT_INTERVAL: Duration = Duration::from_secs(10); // Time between points of the temperature calibration
+
+
+/// The optional, longer calibrations to run before finding the offsets
+pub struct CalibrateOptions {
+  pub six_position: bool,
+  pub temperature_run: Option<Duration>, // how long to record bias against temperature for
+  pub degree: usize,                     // of the temperature model's polynomials
+}
+
+
+/// Calibrates the mpu6050 and reports and saves the offsets found. The sensor must be kept still.
+///
+/// With 'six_position' the user is first guided through holding the sensor in each
+/// orientation to solve for its scale and misalignment. With 'temperature_run' the bias
+/// is recorded while the sensor's temperature changes and a model of it is fitted.
+/// Whichever isn't redone is kept from an earlier calibration of the same device.
+pub fn run(config: &Config, options: &CalibrateOptions, verbosity: Verbosity) -> Exit {
+  let mut events = match common::open_event_log(config) {
+    Ok(events) => events,
+    Err(exit) => return exit,
+  };
+  let i2c = match common::open_imu(config) {
+    Ok(i2c) => i2c,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(config.imu.model.name(), &e));
+      return Exit::Sensor;
+    }
+  };
+  let sensor = i2c.borrow().model().name();
+
+  let saved = saved_calibration(&i2c, config);
+  let correction = if options.six_position {
+    let correction = match six_position_calibration(&i2c, &mut events, verbosity) {
+      Ok(correction) => correction,
+      Err(exit) => return exit,
+    };
+    if !prompt("Place the sensor in its mounting position and keep it still, then press enter") {
+      return Exit::Failure;
+    }
+    Some(correction)
+  } else {
+    saved.as_ref().and_then(|saved| saved.accel_correction)
+  };
+  let temperature_model = match options.temperature_run {
+    Some(duration) => match temperature_calibration(&i2c, duration, options.degree, &mut events, verbosity) {
+      Ok(model) => Some(model),
+      Err(exit) => return exit,
+    },
+    None => saved.and_then(|saved| saved.temperature_model),
+  };
+
+  let mut calibration = match common::calibrate(&i2c, config, &mut events, verbosity) {
+    Ok(calibration) => calibration,
+    Err(e) => return imu_failure(&mut events, sensor, &e),
+  };
+  calibration.accel_correction = correction;
+  calibration.temperature_model = temperature_model;
+  common::save_calibration(&calibration, config, verbosity);
+  let (accel_offsets, gyro_offsets) = (calibration.accel_offsets(), calibration.gyro_offsets());
+  verbosity.info(&format!("accel offsets {} {} {}", accel_offsets.x(), accel_offsets.y(), accel_offsets.z()));
+  verbosity.info(&format!("gyro offsets  {} {} {}", gyro_offsets.x(), gyro_offsets.y(), gyro_offsets.z()));
+  Exit::Ok
+}
+
+
+/// Guides the user through the six orientations and solves for the correction.
+/// Fails if the user gave up (closed stdin), the sensor couldn't be read or the readings were unusable.
+fn six_position_calibration(i2c: &ImuHandle, events: &mut EventLog, verbosity: Verbosity)
+                            -> Result<AccelCorrection, Exit> {
+  let sensor = i2c.borrow().model().name();
+  let mut readings = Vec::new();
+  let mut references = Vec::new();
+
+  for (name, reference) in POSITIONS {
+    loop {
+      if !prompt(&format!("Place the sensor with {name} and keep it still, then press enter")) {
+        return Err(Exit::Failure);
+      }
+      let (mean, std_dev) = average_reading(i2c.borrow_mut().as_mut()).map_err(|e| imu_failure(events, sensor, &e))?;
+      if std_dev > STILL_TOLERANCE_G {
+        eprintln!("The sensor moved while measuring, try again");
+        continue;
+      }
+      let magnitude = mean.iter().map(|v| v * v).sum::<f32>().sqrt();
+      let alignment = (0..3).map(|i| mean[i] * reference[i]).sum::<f32>() / magnitude;
+      if alignment < 0.9 {
+        eprintln!("The sensor doesn't have {name}, try again");
+        continue;
+      }
+      verbosity.detail(&format!("{name}: {:.4} {:.4} {:.4} g", mean[0], mean[1], mean[2]));
+      readings.push(mean);
+      references.push(reference);
+      break;
+    }
+  }
+
+  let Some(fit) = six_position::solve(&readings, &references) else {
+    eprintln!("Could not solve for the correction, the readings are degenerate");
+    return Err(Exit::Failure);
+  };
+  let m = fit.correction.matrix;
+  let b = fit.correction.bias;
+  verbosity.info(&format!("bias   {:.4} {:.4} {:.4} g", b[0], b[1], b[2]));
+  verbosity.info(&format!("matrix {:.4} {:.4} {:.4}", m[0][0], m[0][1], m[0][2]));
+  verbosity.info(&format!("       {:.4} {:.4} {:.4}", m[1][0], m[1][1], m[1][2]));
+  verbosity.info(&format!("       {:.4} {:.4} {:.4}", m[2][0], m[2][1], m[2][2]));
+  verbosity.info(&format!("fit error {:.4} g rms", fit.rms_error_g));
+  Ok(fit.correction)
+}
+
+
+/// Averages readings in g, returning the mean and the largest per axis standard deviation
+fn average_reading(imu: &mut dyn Imu) -> Result<([f32; 3], f32), ImuError> {
+  let mut sum = [0.0f64; 3];
+  let mut sum_sq = [0.0f64; 3];
+  for _ in 0..POSITION_SAMPLES {
+    let reading = imu.acceleration_g()?;
+    for i in 0..3 {
+      sum[i] += reading[i] as f64;
+      sum_sq[i] += (reading[i] as f64).powi(2);
+    }
+  }
+  let n = POSITION_SAMPLES as f64;
+  let mean = sum.map(|s| s / n);
+  let std_dev = (0..3).map(|i| (sum_sq[i] / n - mean[i].powi(2)).max(0.0).sqrt()).fold(0.0, f64::max);
+  Ok((mean.map(|v| v as f32), std_dev as f32))
+}
+
+
+/// Records the bias at regular intervals for 'duration' while the sensor is kept still
+/// and its temperature changes, then fits a model of bias against temperature
+fn temperature_calibration(i2c: &ImuHandle, duration: Duration, degree: usize, events: &mut EventLog,
+                           verbosity: Verbosity) -> Result<TemperatureModel, Exit> {
+  verbosity.info(&format!("Recording bias against temperature for {} s, keep the sensor still \
+                           while it warms up or cools down...", duration.as_secs()));
+  let sensor = i2c.borrow().model().name();
+  let start = Instant::now();
+  let mut points = Vec::new();
+  while start.elapsed() < duration {
+    let point = average_bias(i2c.borrow_mut().as_mut()).map_err(|e| imu_failure(events, sensor, &e))?;
+    verbosity.detail(&format!("{:.2} C: accel {:.1} {:.1} {:.1} gyro {:.1} {:.1} {:.1}", point.temperature_c,
+                              point.accel[0], point.accel[1], point.accel[2], point.gyro[0], point.gyro[1], point.gyro[2]));
+    points.push(point);
+    thread::sleep(BIAS_POINT_INTERVAL.min(duration.saturating_sub(start.elapsed())));
+  }
+
+  match TemperatureModel::fit(&points, degree) {
+    Some(model) => {
+      verbosity.info(&format!("Fitted bias model over {:.1} to {:.1} C", model.min_c, model.max_c));
+      Ok(model)
+    }
+    None => {
+      let span = points.iter().map(|p| p.temperature_c).fold(f32::NEG_INFINITY, f32::max)
+        - points.iter().map(|p| p.temperature_c).fold(f32::INFINITY, f32::min);
+      eprintln!("The temperature only changed by {:.1} C, at least {MIN_SPAN_C} C is needed to fit a model",
+                span.max(0.0));
+      Err(Exit::Failure)
+    }
+  }
+}
+
+
+/// Averages raw samples into a single bias point
+fn average_bias(imu: &mut dyn Imu) -> Result<BiasPoint, ImuError> {
+  let mut point = BiasPoint { temperature_c: 0.0, accel: [0.0; 3], gyro: [0.0; 3] };
+  for _ in 0..BIAS_POINT_SAMPLES {
+    let sample = imu.read_raw()?;
+    point.temperature_c += sample.temperature_c;
+    let accel = [sample.accel.x(), sample.accel.y(), sample.accel.z()];
+    let gyro = [sample.gyro.x(), sample.gyro.y(), sample.gyro.z()];
+    for i in 0..3 {
+      point.accel[i] += accel[i] as f32;
+      point.gyro[i] += gyro[i] as f32;
+    }
+  }
+  let n = BIAS_POINT_SAMPLES as f32;
+  Ok(BiasPoint {
+    temperature_c: point.temperature_c / n,
+    accel: point.accel.map(|v| v / n),
+    gyro: point.gyro.map(|v| v / n),
+  })
+}
+
+
+/// Reports a failed read of the imu 'sensor'
+fn imu_failure(events: &mut EventLog, sensor: &str, e: &ImuError) -> Exit {
+  log_event(events, &sensor_error(sensor, &e.to_string()));
+  Exit::Sensor
+}
+
+
+/// The saved calibration, if it was taken on this device
+fn saved_calibration(device: &ImuHandle, config: &Config) -> Option<Calibration> {
+  let saved = Calibration::load(config.imu.calibration_file()?).ok()?;
+  (saved.sensor.device_id == common::sensor_info(device, config).device_id).then_some(saved)
+}
+
+
+/// Asks the user to do something and waits for enter. Returns false if stdin was closed.
+fn prompt(message: &str) -> bool {
+  eprintln!("{message}");
+  let mut line = String::new();
+  matches!(io::stdin().read_line(&mut line), Ok(n) if n > 0)
+}
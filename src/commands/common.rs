@@ -0,0 +1,405 @@
+use std::cell::RefCell;
+use std::io;
+use std::panic;
+use std::path::Path;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use adafruit_gps::Gps;
+use rppal::i2c::I2c;
+use gps_spoofing_detection::bmp280::baro::Barometer;
+use gps_spoofing_detection::bus::i2c as bus;
+use gps_spoofing_detection::compass::calibration::MagCalibration;
+use gps_spoofing_detection::compass::mag::{self, MagError, Magnetometer};
+use gps_spoofing_detection::config::settings::{Acquisition, Config, MagConfig, PpsSource};
+use gps_spoofing_detection::ds3231::rtc;
+use gps_spoofing_detection::imu::driver::{self as imu_driver, Imu, ImuModel};
+use gps_spoofing_detection::mpu6050::accel::{self, DataPointType, ImuError, ImuSample, Sensitivity};
+use gps_spoofing_detection::mpu6050::calibration::{Calibration, SensorInfo};
+use gps_spoofing_detection::mpu6050::fifo::{Fifo, TimedSample};
+use gps_spoofing_detection::mpu6050::interrupt::{InterruptReader, StampedSample};
+use gps_spoofing_detection::neo6m::gps::GpsData;
+use gps_spoofing_detection::output::events::{Event, EventLog};
+use gps_spoofing_detection::timing::pps::PpsReader;
+
+
+/// Exit status of a command, so scripts can tell failures apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+  Ok = 0,
+  Failure = 1, // anything not covered below (eg. files that can't be written)
+  Config = 2,  // bad arguments or config (clap also uses 2 for usage errors)
+  Sensor = 3,  // a sensor couldn't be opened, failed a check or stopped responding
+  Spoofed = 4, // spoofing was detected in a replayed session
+}
+
+const FIRST_INTERRUPT_TIMEOUT: Duration = Duration::from_secs(1); // Wait for the first data ready interrupt before falling back to polling
+const INTERRUPT_TIMEOUT: Duration = Duration::from_millis(500); // Longest gap between data ready interrupts once they've started
+
+
+/// Reads batches of converted imu samples, polled, from the fifo or on interrupts as configured
+pub enum ImuReader {
+  Poll,
+  Fifo(Fifo),
+  Interrupt(InterruptReader),
+}
+
+/// Converted imu samples read back to back, with when each was sampled
+pub type SampleBatch = Vec<(Instant, ImuSample)>;
+
+/// The opened imu, borrowed by whichever reads it
+pub type ImuHandle = RefCell<Box<dyn Imu>>;
+
+/// How much progress output goes to stderr
+#[derive(Clone, Copy, Debug)]
+pub struct Verbosity {
+  level: u8, // 0 = errors only, 1 = progress, 2+ = per fix details
+}
+
+
+/// Exit implementations
+impl From<Exit> for ExitCode {
+  fn from(exit: Exit) -> ExitCode {
+    ExitCode::from(exit as u8)
+  }
+}
+
+
+/// Verbosity implementations
+impl Verbosity {
+  pub fn new(verbose: u8, quiet: bool) -> Verbosity {
+    Verbosity { level: if quiet { 0 } else { 1 + verbose } }
+  }
+
+  /// Progress messages, hidden by --quiet
+  pub fn info(&self, message: &str) {
+    if self.level >= 1 {
+      eprintln!("{message}");
+    }
+  }
+
+  /// Per fix details, shown with --verbose
+  pub fn detail(&self, message: &str) {
+    if self.level >= 2 {
+      eprintln!("{message}");
+    }
+  }
+}
+
+
+/// Opens the event log from the config (json lines to stdout if no file is set)
+pub fn open_event_log(config: &Config) -> Result<EventLog, Exit> {
+  match &config.output.event_log {
+    Some(path) => EventLog::rotating_file(path, config.output.event_log_max_bytes, config.output.event_log_files)
+      .map_err(|e| {
+        eprintln!("Could not open event log {path}: {e}");
+        Exit::Failure
+      }),
+    None => Ok(EventLog::stdout()),
+  }
+}
+
+
+/// Opens the gps serial port. The gps crate panics if the port can't be opened,
+/// so the panic is caught and turned into an error here.
+pub fn open_gps(config: &Config) -> Result<Gps, String> {
+  let port = &config.gps.port;
+  if !Path::new(port).exists() {
+    return Err(format!("gps serial port {port} does not exist"));
+  }
+  let baud_rate = config.gps.baud_rate.to_string();
+
+  let hook = panic::take_hook();
+  panic::set_hook(Box::new(|_| {}));
+  let gps = panic::catch_unwind(|| Gps::new(port, &baud_rate));
+  panic::set_hook(hook);
+  gps.map_err(|_| format!("could not open gps serial port {port}"))
+}
+
+
+/// Opens the gps pps input from the config, or None if it isn't wired
+pub fn open_pps(config: &Config) -> Result<Option<PpsReader>, String> {
+  let Some(pps) = &config.gps.pps else {
+    return Ok(None);
+  };
+  let reader = match pps.source {
+    PpsSource::Gpio => PpsReader::gpio(pps.pin).map_err(|e| format!("could not open pps on gpio {}: {e}", pps.pin)),
+    PpsSource::Kernel => PpsReader::kernel(&pps.device).map_err(|e| format!("could not open pps device {}: {e}", pps.device)),
+  };
+  reader.map(Some)
+}
+
+
+/// Opens the DS3231 from the config, or None if there isn't one
+pub fn open_rtc(config: &Config) -> Result<Option<I2c>, String> {
+  let Some(rtc) = &config.rtc else {
+    return Ok(None);
+  };
+  rtc::init_ds3231(rtc.i2c_bus, rtc.address)
+    .map(Some)
+    .map_err(|e| format!("could not open ds3231 at {:#04x} on i2c-{}: {e}", rtc.address, rtc.i2c_bus))
+}
+
+
+/// Opens the BMP280 or BME280 from the config, or None if there isn't one
+pub fn open_baro(config: &Config) -> Result<Option<Barometer>, String> {
+  let Some(baro) = &config.baro else {
+    return Ok(None);
+  };
+  Barometer::init(baro.i2c_bus, baro.address)
+    .map(Some)
+    .map_err(|e| format!("could not open barometer at {:#04x} on i2c-{}: {e}", baro.address, baro.i2c_bus))
+}
+
+
+/// Opens the magnetometer from the config, or None if there isn't one. With mag.bypass
+/// the imu's auxiliary bus is connected to the main one first.
+pub fn open_mag(config: &Config) -> Result<Option<Magnetometer>, String> {
+  let Some(mag) = &config.mag else {
+    return Ok(None);
+  };
+  if mag.bypass {
+    let imu = &config.imu;
+    bus::open(imu.i2c_bus, imu.address).map_err(MagError::from)
+      .and_then(|mut i2c| mag::enable_bypass(&mut i2c))
+      .map_err(|e| format!("could not enable the i2c bypass of the imu at {:#04x} on i2c-{}: {e}", imu.address, imu.i2c_bus))?;
+  }
+  Magnetometer::init(mag.chip, mag.i2c_bus, mag.address())
+    .map(Some)
+    .map_err(|e| format!("could not open {:?} at {:#04x} on i2c-{}: {e}", mag.chip, mag.address(), mag.i2c_bus))
+}
+
+
+/// Loads the magnetometer's hard and soft iron calibration
+pub fn load_mag_calibration(mag: &MagConfig) -> Result<MagCalibration, String> {
+  MagCalibration::load(&mag.calibration_file)
+    .map_err(|e| format!("could not load {} ({e}), run `compass --calibrate` first", mag.calibration_file))
+}
+
+
+/// Opens the imu with the ranges from the config, checking it's answering and is the model
+/// configured. With imu.model = "auto" the bus is probed for one, around the other devices on it.
+pub fn open_imu(config: &Config) -> Result<ImuHandle, String> {
+  let imu = &config.imu;
+  let others = [config.rtc.as_ref().map(|rtc| (rtc.i2c_bus, rtc.address)),
+                config.baro.as_ref().map(|baro| (baro.i2c_bus, baro.address)),
+                config.mag.as_ref().map(|mag| (mag.i2c_bus, mag.address()))];
+  let skip: Vec<u16> = others.iter().flatten().filter(|(bus, _)| *bus == imu.i2c_bus).map(|(_, address)| *address).collect();
+  imu_driver::open(imu.model, imu.i2c_bus, imu.address, &skip, &imu.settings())
+    .map(RefCell::new)
+    .map_err(|e| format!("could not open {} at {:#04x} on i2c-{}: {e}", imu.model.name(), imu.address, imu.i2c_bus))
+}
+
+
+/// ImuReader implementations
+impl ImuReader {
+  /// Sets up the acquisition chosen in the config (enabling the fifo or starting the
+  /// interrupt thread if it's used). Falls back to polling if no data ready interrupts
+  /// arrive, eg. when the INT line isn't wired, or if the imu found by probing isn't an
+  /// mpu6050.
+  pub fn open(device: &ImuHandle, config: &Config) -> Result<ImuReader, ImuError> {
+    let imu = &config.imu;
+    let model = device.borrow().model();
+    if imu.acquisition != Acquisition::Poll && model != ImuModel::Mpu6050 {
+      eprintln!("The {} can only be polled, falling back to polling", model.name());
+      return Ok(ImuReader::Poll);
+    }
+    match imu.acquisition {
+      Acquisition::Poll => Ok(ImuReader::Poll),
+      Acquisition::Fifo => Ok(ImuReader::Fifo(Fifo::enable(device.borrow_mut().i2c(), &imu.settings())?)),
+      Acquisition::Interrupt => {
+        let reader = InterruptReader::start(imu.i2c_bus, device.borrow().address(), imu.int_pin)?;
+        match reader.collect(1, FIRST_INTERRUPT_TIMEOUT) {
+          Ok(_) => Ok(ImuReader::Interrupt(reader)),
+          Err(e) => {
+            eprintln!("No data ready interrupts on gpio {} ({e}), falling back to polling", imu.int_pin);
+            Ok(ImuReader::Poll)
+          }
+        }
+      }
+    }
+  }
+
+  /// Reads 'n' samples. Polled samples are timed by the host clock as they're read,
+  /// fifo samples at the sample period back from when each burst was read and interrupt
+  /// samples at their interrupt.
+  /// Samples queued before the call are discarded, so the batch starts now.
+  pub fn read(&mut self, device: &ImuHandle, n: u32, calibration: &Calibration, sensitivity: &Sensitivity)
+              -> Result<SampleBatch, ImuError> {
+    let mut device = device.borrow_mut();
+    match self {
+      ImuReader::Poll => {
+        let mut samples = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+          let raw = device.read_raw()?;
+          samples.push((Instant::now(), accel::convert_sample(&raw, calibration, sensitivity)));
+        }
+        Ok(samples)
+      }
+      ImuReader::Fifo(fifo) => {
+        let timed = fifo.collect(device.i2c(), n as usize)?;
+        Ok(convert_fifo(fifo, &timed, calibration, sensitivity))
+      }
+      ImuReader::Interrupt(reader) => {
+        reader.clear();
+        let stamped = reader.collect(n as usize, INTERRUPT_TIMEOUT)?;
+        Ok(convert_stamped(&stamped, calibration, sensitivity))
+      }
+    }
+  }
+
+  /// Reads the samples following on from the last call, for a reader that runs
+  /// continuously: nothing queued is discarded and the fifo isn't reset, so the
+  /// calls cover the time between them without gaps. Polls 'n' samples, reads one
+  /// fifo burst (empty after an overflow) or waits for 'n' interrupts.
+  pub fn read_next(&mut self, device: &ImuHandle, n: u32, calibration: &Calibration, sensitivity: &Sensitivity)
+                   -> Result<SampleBatch, ImuError> {
+    match self {
+      ImuReader::Poll => self.read(device, n, calibration, sensitivity),
+      ImuReader::Fifo(fifo) => {
+        thread::sleep(fifo.poll_interval());
+        let burst = fifo.read(device.borrow_mut().i2c())?;
+        Ok(convert_fifo(fifo, &burst.samples, calibration, sensitivity))
+      }
+      ImuReader::Interrupt(reader) => {
+        let stamped = reader.collect(n as usize, INTERRUPT_TIMEOUT)?;
+        Ok(convert_stamped(&stamped, calibration, sensitivity))
+      }
+    }
+  }
+}
+
+
+/// Converts fifo samples, timing them from when the fifo was reset
+fn convert_fifo(fifo: &Fifo, timed: &[TimedSample], calibration: &Calibration, sensitivity: &Sensitivity) -> SampleBatch {
+  timed.iter().map(|timed| {
+    let raw = timed.sample;
+    let at = fifo.reset_at() + Duration::from_secs_f64(timed.t);
+    (at, accel::convert_sample(&raw, calibration, sensitivity))
+  }).collect()
+}
+
+/// Converts samples timed at their data ready interrupt
+fn convert_stamped(stamped: &[StampedSample], calibration: &Calibration, sensitivity: &Sensitivity) -> SampleBatch {
+  stamped.iter().map(|stamped| {
+    let raw = stamped.sample;
+    (stamped.at, accel::convert_sample(&raw, calibration, sensitivity))
+  }).collect()
+}
+
+
+/// Reuses the saved calibration if it isn't stale, otherwise calibrates (see calibrate).
+/// The six position correction and temperature model of a stale calibration are kept,
+/// they don't drift like the offsets do.
+pub fn load_or_calibrate(device: &ImuHandle, config: &Config, events: &mut EventLog, verbosity: Verbosity)
+                         -> Result<Calibration, ImuError> {
+  let sensor = sensor_info(device, config);
+  let mut kept = None;
+  if let Some(path) = config.imu.calibration_file() {
+    match Calibration::load(path) {
+      Ok(saved) => {
+        let temperature = device.borrow_mut().read_temperature().ok();
+        let now = SystemTime::now();
+        match saved.stale_reason(&config.imu.staleness_policy(), &sensor, temperature, now) {
+          None => {
+            verbosity.info(&format!("Using saved calibration from {path}"));
+            log_event(events, &Event::CalibrationLoaded {
+              sensor: device.borrow().model().name().to_string(),
+              accel_offset: saved.accel_offset,
+              gyro_offset: saved.gyro_offset,
+              age_s: saved.age(now).as_secs(),
+            });
+            return Ok(saved);
+          }
+          Some(reason) => verbosity.info(&format!("Saved calibration is stale ({reason})")),
+        }
+        if saved.sensor.device_id == sensor.device_id {
+          kept = Some(saved);
+        }
+      }
+      Err(e) if e.kind() == io::ErrorKind::NotFound => verbosity.info("No saved calibration"),
+      Err(e) => eprintln!("Could not load calibration {path}: {e}"),
+    }
+  }
+  let mut calibration = calibrate(device, config, events, verbosity)?;
+  if let Some(kept) = kept {
+    calibration.accel_correction = kept.accel_correction;
+    calibration.temperature_model = kept.temperature_model;
+  }
+  save_calibration(&calibration, config, verbosity);
+  Ok(calibration)
+}
+
+
+/// Finds the offsets of the imu (it must be still) and logs them
+pub fn calibrate(device: &ImuHandle, config: &Config, events: &mut EventLog, verbosity: Verbosity)
+                 -> Result<Calibration, ImuError> {
+  let imu = &config.imu;
+  verbosity.info(&format!("Calibrating {}...", device.borrow().model().name()));
+  let start = Instant::now();
+  let (accel_offsets, gyro_offsets) =
+    accel::calibrate_imu(device.borrow_mut().as_mut(), Some(imu.calib_time()), Some(imu.calib_diff), Some(imu.calib_consistent))?;
+  verbosity.info("Calibration complete");
+
+  log_event(events, &Event::CalibrationComplete {
+    sensor: device.borrow().model().name().to_string(),
+    accel_offset: [accel_offsets.x(), accel_offsets.y(), accel_offsets.z()],
+    gyro_offset: [gyro_offsets.x(), gyro_offsets.y(), gyro_offsets.z()],
+    duration_s: start.elapsed().as_secs_f64(),
+  });
+
+  let temperature = device.borrow_mut().read_temperature().ok();
+  Ok(Calibration::new(sensor_info(device, config), temperature, &accel_offsets, &gyro_offsets))
+}
+
+
+/// Saves the calibration to the configured file, if there is one
+pub fn save_calibration(calibration: &Calibration, config: &Config, verbosity: Verbosity) {
+  if let Some(path) = config.imu.calibration_file() {
+    match calibration.save(path) {
+      Ok(()) => verbosity.info(&format!("Saved calibration to {path}")),
+      Err(e) => eprintln!("Could not save calibration to {path}: {e}"),
+    }
+  }
+}
+
+
+/// Identifies the sensor and the settings it's calibrated for, by the model and address
+/// the imu was found at (which differ from the config's when it was probed for)
+pub fn sensor_info(device: &ImuHandle, config: &Config) -> SensorInfo {
+  let imu = &config.imu;
+  let device = device.borrow();
+  SensorInfo {
+    device_id: imu.device_id.clone()
+      .unwrap_or_else(|| format!("{}-i2c{}-{:#04x}", device.model().name(), imu.i2c_bus, device.address())),
+    accel_range_g: imu.accel_range_g,
+    gyro_range_dps: imu.gyro_range_dps,
+  }
+}
+
+
+/// Writes an event, falling back to stderr if the log can't be written
+pub fn log_event(events: &mut EventLog, event: &Event) {
+  if let Err(e) = events.log(event) {
+    eprintln!("Could not write event log: {e}");
+  }
+}
+
+pub fn fix_received(gps_data: &GpsData) -> Event {
+  Event::FixReceived {
+    fix_time: gps_data.iso_time(),
+    lat: gps_data.lat(),
+    lon: gps_data.lon(),
+    alt: gps_data.alt(),
+    hdop: gps_data.hor_prec(),
+    sats_used: gps_data.sats_used().len(),
+  }
+}
+
+pub fn sensor_error(sensor: &str, message: &str) -> Event {
+  Event::SensorError { sensor: sensor.to_string(), message: message.to_string() }
+}
+
+pub fn output_error(output: &str, message: &str) -> Event {
+  Event::OutputError { output: output.to_string(), message: message.to_string() }
+}
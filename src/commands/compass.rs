@@ -0,0 +1,125 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use gps_spoofing_detection::compass::calibration::{magnitude, MagCalibration};
+use gps_spoofing_detection::compass::heading::tilt_compensated_heading;
+use gps_spoofing_detection::compass::mag::Magnetometer;
+use gps_spoofing_detection::config::settings::{Config, MagConfig};
+
+use crate::commands::common::{self, Exit, Verbosity};
+
+const CALIBRATION_PERIOD: Duration = Duration::from_millis(20); // Time between readings while calibrating
+const READINGS_AVERAGED: usize = 10; // Readings of the field and gravity averaged for the heading printed
+const MAX_FAILED_READS: usize = 50; // Failed reads in a row before calibrating gives up
+
+
+/// Prints the magnetometer's field and the tilt compensated heading, after first
+/// calibrating it for 'calibrate' if given (the sensor has to be turned through every
+/// orientation, mounted in the vehicle if it's fitted to one)
+pub fn run(config: &Config, calibrate: Option<Duration>, verbosity: Verbosity) -> Exit {
+  let (mag, mag_config) = match (common::open_mag(config), &config.mag) {
+    (Ok(Some(mag)), Some(mag_config)) => (mag, mag_config),
+    (Ok(_), _) => {
+      eprintln!("No [mag] section in the config");
+      return Exit::Config;
+    }
+    (Err(e), _) => {
+      eprintln!("{e}");
+      return Exit::Sensor;
+    }
+  };
+
+  if let Some(duration) = calibrate {
+    match calibrate_mag(&mag, mag_config, duration, verbosity) {
+      Ok(calibration) => {
+        let (offset, scale) = (calibration.offset_ut, calibration.scale);
+        verbosity.info(&format!("hard iron {:.2} {:.2} {:.2} uT", offset[0], offset[1], offset[2]));
+        verbosity.info(&format!("soft iron {:.4} {:.4} {:.4}", scale[0], scale[1], scale[2]));
+        verbosity.info(&format!("field {:.1} uT, fit error {:.2} uT rms", calibration.field_ut, calibration.rms_error_ut));
+      }
+      Err(exit) => return exit,
+    }
+  }
+
+  let calibration = match common::load_mag_calibration(mag_config) {
+    Ok(calibration) => Some(calibration),
+    Err(e) => {
+      verbosity.info(&format!("Uncalibrated: {e}"));
+      None
+    }
+  };
+  let field = match average(READINGS_AVERAGED, || mag.read().map_err(|e| e.to_string())) {
+    Ok(field) => field,
+    Err(e) => {
+      println!("mag  {e}");
+      return Exit::Sensor;
+    }
+  };
+  let corrected = calibration.as_ref().map_or(field, |calibration| calibration.apply(field));
+  println!("mag  {:?}", mag.chip());
+  println!("  field       {:.1} {:.1} {:.1} uT ({:.1} uT)", corrected[0], corrected[1], corrected[2], magnitude(corrected));
+
+  let up = common::open_imu(config).and_then(|imu| {
+    average(READINGS_AVERAGED, || imu.borrow_mut().acceleration_g().map_err(|e| e.to_string()))
+  });
+  match up.map(|up| tilt_compensated_heading(corrected, up)) {
+    Ok(Some(heading)) => {
+      let declination = config.detector.heading.as_ref().map_or(0.0, |heading| heading.declination_deg);
+      println!("  heading     {heading:.1} deg magnetic, {:.1} deg true", (heading + declination).rem_euclid(360.0));
+    }
+    Ok(None) => println!("  heading     - (the x axis is pointing straight up or down)"),
+    Err(e) => println!("  heading     - (no tilt from the imu: {e})"),
+  }
+  Exit::Ok
+}
+
+
+/// Records the field while the user turns the sensor, fits the calibration and saves it
+fn calibrate_mag(mag: &Magnetometer, mag_config: &MagConfig, duration: Duration, verbosity: Verbosity)
+                 -> Result<MagCalibration, Exit> {
+  verbosity.info(&format!("Recording the field for {} s, slowly turn the sensor through every orientation...",
+                          duration.as_secs()));
+  let start = Instant::now();
+  let mut readings = Vec::new();
+  let mut failed_in_row = 0;
+  while start.elapsed() < duration {
+    match mag.read() {
+      Ok(reading) => {
+        failed_in_row = 0;
+        readings.push(reading);
+      }
+      Err(e) => {
+        failed_in_row += 1;
+        if failed_in_row >= MAX_FAILED_READS {
+          eprintln!("Could not read the magnetometer: {e}");
+          return Err(Exit::Sensor);
+        }
+      }
+    }
+    thread::sleep(CALIBRATION_PERIOD);
+  }
+
+  let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+  let Some(calibration) = MagCalibration::fit(&readings, timestamp) else {
+    eprintln!("Could not fit the calibration from {} readings, turn the sensor further about every axis", readings.len());
+    return Err(Exit::Failure);
+  };
+  if let Err(e) = calibration.save(&mag_config.calibration_file) {
+    eprintln!("Could not save the calibration to {}: {e}", mag_config.calibration_file);
+    return Err(Exit::Failure);
+  }
+  verbosity.info(&format!("Saved the calibration to {}", mag_config.calibration_file));
+  Ok(calibration)
+}
+
+
+/// Averages 'n' readings of a vector
+fn average(n: usize, mut read: impl FnMut() -> Result<[f32; 3], String>) -> Result<[f32; 3], String> {
+  let mut sum = [0.0; 3];
+  for _ in 0..n {
+    let reading = read()?;
+    for i in 0..3 {
+      sum[i] += reading[i];
+    }
+  }
+  Ok(sum.map(|v| v / n as f32))
+}
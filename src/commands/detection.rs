@@ -0,0 +1,208 @@
+use gps_spoofing_detection::compass::calibration::MagCalibration;
+use gps_spoofing_detection::compass::heading::tilt_compensated_heading;
+use gps_spoofing_detection::config::settings::Config;
+use gps_spoofing_detection::detect::clock::ClockCheck;
+use gps_spoofing_detection::detect::engine::{Engine, Step};
+use gps_spoofing_detection::detect::heading::HeadingCheck;
+use gps_spoofing_detection::detect::pps::PpsCheck;
+use gps_spoofing_detection::detect::stationary::{StationaryCheck, ZeroVelocityDetector};
+use gps_spoofing_detection::detect::vertical::VerticalCheck;
+use gps_spoofing_detection::mpu6050::accel::RawPoint;
+use gps_spoofing_detection::session::replay::Recorded;
+use gps_spoofing_detection::timing::align::Window;
+use gps_spoofing_detection::timing::pps::on_whole_second;
+
+use crate::commands::common::Verbosity;
+use crate::commands::sensors::{Fix, Sensors};
+
+
+/// What turns the magnetometer's readings into a heading
+pub struct Compass {
+  pub calibration: MagCalibration,
+  pub up: [f32; 3], // the accelerometer's reading at rest, which gives the tilt of the mounting
+}
+
+/// Readings lined up with the fixes by time, from the sensor threads or a recording
+pub trait Readings {
+  /// The imu samples between 't0' and 't1' interpolated to both ends, None if they don't cover it
+  fn imu_window(&self, t0: f64, t1: f64) -> Option<Window>;
+
+  /// The (acceleration, rotation rate) samples between 't0' and 't1'
+  fn imu_samples(&self, t0: f64, t1: f64) -> Vec<([f32; 3], [f32; 3])>;
+
+  /// The air pressure at 't', None without a barometer or readings that cover 't'
+  fn pressure_at(&self, t: f64) -> Option<f32>;
+
+  /// The uncalibrated magnetic field at 't', None without a magnetometer or readings that cover 't'
+  fn field_at(&self, t: f64) -> Option<[f32; 3]>;
+}
+
+/// What detection made of a fix
+pub enum Outcome {
+  Start,                   // the first fix since starting over, the prediction starts from it
+  NoImu,                   // no imu samples cover the time since the last fix, the prediction starts over from it
+  Step(Box<Step>, Window), // the fix checked against the prediction from the acceleration over the window
+}
+
+/// Detection fix by fix, shared by monitor and replay: the prediction from the imu and
+/// every other check enabled in the config, each given the readings at the fix's epoch.
+/// While the imu is still, the velocity is reset to zero at each fix so the
+/// accelerometer's bias can't build up, and the fixes have to stay put.
+pub struct Detection {
+  engine: Engine,
+  pub pps: Option<PpsCheck>, // the checks are dropped when their sensor is lost
+  pub clock: Option<ClockCheck>,
+  pub vertical: Option<VerticalCheck>,
+  pub heading: Option<HeadingCheck>,
+  stationary: Option<(ZeroVelocityDetector, StationaryCheck, f64)>, // with the window the stillness is judged over
+  compass: Option<Compass>,
+  last_t: Option<f64>, // epoch of the previous fix, None to start over from the next one
+}
+
+
+/// Detection implementations
+impl Detection {
+  /// Detection with the checks enabled in 'config'. The heading is only checked with a 'compass'.
+  pub fn new(config: &Config, compass: Option<Compass>) -> Detection {
+    let detector = &config.detector;
+    Detection {
+      engine: Engine::new(detector.gps_accuracy_m, detector.suspect_ratio),
+      pps: config.gps.pps.as_ref().map(|pps| PpsCheck::new(pps.tolerance_s())),
+      clock: detector.clock.as_ref().map(|clock| ClockCheck::new(clock.tolerances())),
+      vertical: detector.vertical.as_ref().map(|vertical| VerticalCheck::new(vertical.tolerances())),
+      heading: detector.heading.as_ref().filter(|_| compass.is_some())
+        .map(|heading| HeadingCheck::new(heading.declination_deg, heading.tolerances())),
+      stationary: detector.stationary.as_ref().map(|stationary| {
+        let tolerances = stationary.tolerances();
+        (ZeroVelocityDetector::new(&tolerances), StationaryCheck::new(&tolerances), tolerances.window_s)
+      }),
+      compass,
+      last_t: None,
+    }
+  }
+
+  /// Starts the prediction over from the next fix, eg. after imu samples were missed
+  pub fn restart(&mut self) {
+    self.last_t = None;
+  }
+
+  /// Checks the fix at 't' (seconds on the readings' clock). The prediction between two
+  /// fixes uses the imu samples taken between their epochs, interpolated to the epochs
+  /// themselves, and is started over when they don't cover the interval.
+  pub fn step(&mut self, t: f64, fix: &Fix, readings: &impl Readings, verbosity: Verbosity) -> Outcome {
+    let gps_data = &fix.data;
+    let previous_t = self.last_t.replace(t);
+
+    // epochs on a whole utc second have to line up with the pps
+    let mut scores = Vec::new();
+    if let (Some(check), Some(utc)) = (&mut self.pps, gps_data.utc_seconds()) {
+      if on_whole_second(utc) {
+        scores.push(check.check(fix.pps_t, utc));
+      }
+    }
+    // and the gps time has to keep with the reference clock
+    if let (Some(check), Some(utc), Some(host_s)) = (&mut self.clock, gps_data.utc_seconds(), fix.host_s) {
+      let clock = check.check(utc, host_s);
+      verbosity.detail(&format!("clock offset {:.3} s (step {:.3} s, slew {})", clock.offset_s, clock.step_s,
+                                clock.slew_ppm.map_or("-".to_string(), |slew| format!("{slew:.0} ppm"))));
+      scores.push(clock.score);
+    }
+    // and the gps altitude has to climb and fall with the air pressure
+    if let (Some(check), Some(pressure)) = (&mut self.vertical, readings.pressure_at(t)) {
+      let vertical = check.check(t, gps_data.alt(), gps_data.mode(), gps_data.ver_prec(), pressure);
+      verbosity.detail(&format!("altitude {:.1} m, barometric {} (climbs {:.1} / {:.1} m)", gps_data.alt(),
+                                vertical.baro_alt_m.map_or("-".to_string(), |alt| format!("{alt:.1} m")),
+                                vertical.gps_climb_m, vertical.baro_climb_m));
+      scores.push(vertical.score);
+    }
+    // and the course has to follow the way the vehicle is pointing
+    let magnetic = self.compass.as_ref().zip(readings.field_at(t))
+      .and_then(|(compass, field)| tilt_compensated_heading(compass.calibration.apply(field), compass.up));
+    if let (Some(check), Some(magnetic)) = (&mut self.heading, magnetic) {
+      let heading = check.check(t, gps_data.course(), gps_data.speed_mps(), magnetic);
+      verbosity.detail(&format!("course {:.0} heading {:.0} (error {}, turn error {})", gps_data.course(), heading.heading_deg,
+                                heading.error_deg.map_or("-".to_string(), |error| format!("{error:.0}")),
+                                heading.turn_error_deg.map_or("-".to_string(), |error| format!("{error:.0}"))));
+      scores.push(heading.score);
+    }
+
+    // the acceleration between the two fixes
+    let Some(previous_t) = previous_t else {
+      self.start_over(fix);
+      return Outcome::Start;
+    };
+    let Some(window) = readings.imu_window(previous_t, t) else {
+      self.start_over(fix);
+      return Outcome::NoImu;
+    };
+    let [x, y, z] = window.mean;
+    let avg_accel = RawPoint::new(x, y, z);
+
+    // and while the imu is still the fix has to stay put
+    let mut still = false;
+    if let Some((detector, check, window_s)) = &mut self.stationary {
+      if let Some(motion) = detector.check(readings.imu_samples(t - *window_s, t)) {
+        let step = check.check(&gps_data.coord(), gps_data.hor_prec(), motion.stationary);
+        verbosity.detail(&format!("{} (accel std {:.3} m/s^2, gyro rms {:.2} deg/s), drift {:.1} m",
+                                  if motion.stationary { "still" } else { "moving" }, motion.accel_var.sqrt(),
+                                  motion.gyro_energy.sqrt().to_degrees(), step.drift_m));
+        scores.push(step.score);
+        still = motion.stationary;
+      }
+    }
+
+    // compare
+    let Some(step) = self.engine.step_with(&gps_data.coord(), gps_data.hor_prec(), &avg_accel, window.dt, &scores) else {
+      return Outcome::Start;
+    };
+    if still {
+      self.engine.zero_velocity_update(); // the velocity at this fix is known to be zero
+    }
+    Outcome::Step(Box::new(step), window)
+  }
+
+  /// Starts the prediction from 'fix' without checking it
+  fn start_over(&mut self, fix: &Fix) {
+    self.engine.reset(&fix.data.coord());
+    if let Some((_, check, _)) = &mut self.stationary {
+      check.reset();
+    }
+  }
+}
+
+
+impl Readings for Sensors {
+  fn imu_window(&self, t0: f64, t1: f64) -> Option<Window> {
+    Sensors::imu_window(self, t0, t1)
+  }
+
+  fn imu_samples(&self, t0: f64, t1: f64) -> Vec<([f32; 3], [f32; 3])> {
+    Sensors::imu_samples(self, t0, t1)
+  }
+
+  fn pressure_at(&self, t: f64) -> Option<f32> {
+    Sensors::pressure_at(self, t)
+  }
+
+  fn field_at(&self, t: f64) -> Option<[f32; 3]> {
+    Sensors::field_at(self, t)
+  }
+}
+
+impl Readings for Recorded {
+  fn imu_window(&self, t0: f64, t1: f64) -> Option<Window> {
+    Recorded::imu_window(self, t0, t1)
+  }
+
+  fn imu_samples(&self, t0: f64, t1: f64) -> Vec<([f32; 3], [f32; 3])> {
+    Recorded::imu_samples(self, t0, t1)
+  }
+
+  fn pressure_at(&self, t: f64) -> Option<f32> {
+    Recorded::pressure_at(self, t)
+  }
+
+  fn field_at(&self, t: f64) -> Option<[f32; 3]> {
+    Recorded::field_at(self, t)
+  }
+}
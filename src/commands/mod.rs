@@ -0,0 +1,13 @@
+pub mod common;
+pub mod sensors;
+pub mod detection;
+pub mod monitor;
+pub mod calibrate;
+pub mod record;
+pub mod replay;
+pub mod simulate;
+pub mod selftest;
+pub mod status;
+pub mod allan;
+pub mod rtc;
+pub mod compass;
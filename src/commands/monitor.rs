@@ -0,0 +1,260 @@
+use gps_spoofing_detection::{neo6m, mpu6050, output};
+use gps_spoofing_detection::config::settings::{ClockReference, Config};
+use gps_spoofing_detection::detect::verdict::Verdict;
+use gps_spoofing_detection::output::events::{Event, EventLog};
+
+use crate::commands::common::{self, log_event, fix_received, output_error, sensor_error, Exit, ImuReader, Verbosity};
+use crate::commands::detection::{Compass, Detection, Outcome};
+use crate::commands::sensors::{Devices, Fault, Sensors};
+
+
+/// Everything detection results are reported to
+struct Outputs {
+  events: EventLog,
+  gpsd: Option<output::gpsd::GpsdServer>,
+  mqtt: Option<output::mqtt::MqttPublisher>,
+  metrics: Option<output::metrics::MetricsServer>,
+  alarm: Option<output::alarm::Alarm<output::alarm::RppalAlarmPins>>,
+}
+
+
+/// Runs live detection until the gps or imu is lost
+pub fn run(config: &Config, verbosity: Verbosity) -> Exit {
+  let mut events = match common::open_event_log(config) {
+    Ok(events) => events,
+    Err(exit) => return exit,
+  };
+
+  // GPS setup
+  let mut gps = match common::open_gps(config) {
+    Ok(gps) => gps,
+    Err(e) => {
+      log_event(&mut events, &sensor_error("gps", &e));
+      return Exit::Sensor;
+    }
+  };
+  neo6m::gps::init_gps(&mut gps, config.gps.update_rate_ms);
+  let pps = match common::open_pps(config) {
+    Ok(pps) => pps,
+    Err(e) => {
+      log_event(&mut events, &sensor_error("pps", &e));
+      return Exit::Sensor;
+    }
+  };
+
+  // Accelerometer setup (the GY-521 accelerometer/gyro)
+  let i2c = match common::open_imu(config) {
+    Ok(i2c) => i2c,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(config.imu.model.name(), &e));
+      return Exit::Sensor;
+    }
+  };
+  let imu_name = i2c.borrow().model().name();
+  let calibration = match common::load_or_calibrate(&i2c, config, &mut events, verbosity) {
+    Ok(calibration) => calibration,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(imu_name, &e.to_string()));
+      return Exit::Sensor;
+    }
+  };
+  let reader = match ImuReader::open(&i2c, config) {
+    Ok(reader) => reader,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(imu_name, &e.to_string()));
+      return Exit::Sensor;
+    }
+  };
+
+  // Real time clock, when it's the clock detector's reference
+  let reference_rtc = config.detector.clock.as_ref().is_some_and(|clock| clock.reference == ClockReference::Rtc);
+  let rtc = match common::open_rtc(config) {
+    Ok(rtc) => rtc.filter(|_| reference_rtc),
+    Err(e) => {
+      log_event(&mut events, &sensor_error("ds3231", &e));
+      return Exit::Sensor;
+    }
+  };
+
+  // Barometer, when the vertical detector is on
+  let baro = match common::open_baro(config) {
+    Ok(baro) => baro.filter(|_| config.detector.vertical.is_some()),
+    Err(e) => {
+      log_event(&mut events, &sensor_error("barometer", &e));
+      return Exit::Sensor;
+    }
+  };
+
+  // Magnetometer, when the heading detector is on
+  let mag = match common::open_mag(config) {
+    Ok(mag) => mag.filter(|_| config.detector.heading.is_some()),
+    Err(e) => {
+      log_event(&mut events, &sensor_error("magnetometer", &e));
+      return Exit::Sensor;
+    }
+  };
+  let sensitivity = i2c.borrow().sensitivity();
+  let compass = match (&mag, &config.mag) {
+    (Some(_), Some(mag_config)) => match common::load_mag_calibration(mag_config) {
+      Ok(mag_calibration) => {
+        let up = mpu6050::accel::rest_acceleration(&calibration, sensitivity.accel);
+        Some(Compass { calibration: mag_calibration, up: [up.x(), up.y(), up.z()] })
+      }
+      Err(e) => {
+        log_event(&mut events, &sensor_error("magnetometer", &e));
+        return Exit::Sensor;
+      }
+    },
+    _ => None,
+  };
+
+  let mut outputs = Outputs { events, gpsd: None, mqtt: None, metrics: None, alarm: None };
+
+  // gpsd compatible server (detection still runs if the port is taken, eg. by a real gpsd)
+  outputs.gpsd = config.output.gpsd.as_ref().and_then(|addr| {
+    output::gpsd::GpsdServer::bind(addr, &config.gps.port)
+      .map_err(|e| eprintln!("Could not start gpsd server on {addr}: {e}"))
+      .ok()
+  });
+
+
+  outputs.mqtt = config.output.mqtt.as_ref().and_then(|mqtt| {
+    output::mqtt::MqttPublisher::connect(&mqtt.mqtt_config())
+      .map_err(|e| eprintln!("Could not set up mqtt publisher: {e}"))
+      .ok()
+  });
+
+
+  outputs.metrics = config.output.metrics.as_ref().and_then(|addr| {
+    output::metrics::MetricsServer::bind(addr)
+      .map_err(|e| eprintln!("Could not start metrics endpoint on {addr}: {e}"))
+      .ok()
+  });
+
+
+  outputs.alarm = config.output.alarm.as_ref().and_then(|alarm| {
+    output::alarm::RppalAlarmPins::new(alarm.pin, alarm.active_low, alarm.ack_pin)
+      .map(output::alarm::Alarm::new)
+      .map_err(|e| eprintln!("Could not set up gpio alarm: {e}"))
+      .ok()
+  });
+
+
+  verbosity.info("Waiting for gps fix...");
+  let fix = neo6m::gps::wait_for_fix(&mut gps, config.gps.fix_timeout());
+  if fix.is_none() {
+    log_event(&mut outputs.events, &sensor_error("gps", "timed out waiting for gps fix"));
+    return Exit::Sensor; // change to waiting for gps fix again
+  }
+
+  let devices = Devices { gps, pps, imu: i2c, reader, rtc, baro, mag };
+  let sensors = Sensors::start(devices, calibration, sensitivity, config.imu.settings().sample_rate_hz());
+  detect_spoofing(&sensors, config, imu_name, compass, &mut outputs, verbosity);
+  Exit::Sensor // detection only stops when a sensor is lost
+}
+
+
+/// Detects spoofing by comparing the predicted position to the actual position, along
+/// with the other checks (see Detection). Intervals the imu couldn't be read for are
+/// reported as a sensor fault rather than given a verdict, and the prediction restarts
+/// from the next good fix. Imu faults are reported against 'imu_name', the model found.
+fn detect_spoofing(sensors: &Sensors, config: &Config, imu_name: &str, compass: Option<Compass>, outputs: &mut Outputs,
+                   verbosity: Verbosity) {
+  let mut detection = Detection::new(config, compass);
+  let mut last_t = f64::NEG_INFINITY;
+  let mut last_verdict = Verdict::default();
+  loop {
+    let (t, fix) = match sensors.next_fix(last_t) {
+      Ok(fix) => fix,
+      Err(Fault::Imu(e)) => {
+        log_event(&mut outputs.events, &sensor_error(imu_name, &e));
+        detection.restart();
+        continue;
+      }
+      Err(Fault::PpsLost(e)) => {
+        log_event(&mut outputs.events, &sensor_error("pps", &e));
+        detection.pps = None;
+        continue;
+      }
+      Err(Fault::RtcLost(e)) => {
+        log_event(&mut outputs.events, &sensor_error("ds3231", &e));
+        detection.clock = None;
+        continue;
+      }
+      Err(Fault::BaroLost(e)) => {
+        log_event(&mut outputs.events, &sensor_error("barometer", &e));
+        detection.vertical = None;
+        continue;
+      }
+      Err(Fault::MagLost(e)) => {
+        log_event(&mut outputs.events, &sensor_error("magnetometer", &e));
+        detection.heading = None;
+        continue;
+      }
+      Err(Fault::ImuLost(e)) => {
+        log_event(&mut outputs.events, &sensor_error(imu_name, &e));
+        return;
+      }
+      Err(Fault::GpsLost) => {
+        log_event(&mut outputs.events, &sensor_error("gps", "lost connection to gps"));
+        return;
+      }
+    };
+    last_t = t;
+    let gps_data = &fix.data;
+    let outcome = detection.step(t, &fix, sensors, verbosity);
+
+    // every fix goes out, with the verdict of the last fix checked when this one couldn't be
+    if let Outcome::Step(step, _) = &outcome {
+      last_verdict = step.verdict.clone();
+    }
+    if let Some(gpsd) = &outputs.gpsd {
+      gpsd.publish(gps_data, &last_verdict);
+    }
+    if let Some(metrics) = &outputs.metrics {
+      metrics.update_fix(gps_data);
+      metrics.set_i2c_read_errors(mpu6050::accel::i2c_read_errors());
+    }
+
+    let (step, window) = match outcome {
+      Outcome::Step(step, window) => (step, window),
+      Outcome::Start => {
+        log_event(&mut outputs.events, &fix_received(gps_data));
+        continue;
+      }
+      Outcome::NoImu => {
+        log_event(&mut outputs.events, &sensor_error(imu_name, "no imu samples cover the time since the last fix"));
+        log_event(&mut outputs.events, &fix_received(gps_data));
+        continue;
+      }
+    };
+    let dt = window.dt;
+    log_event(&mut outputs.events, &Event::PredictionMade {
+      lat: step.predicted.lat(),
+      lon: step.predicted.lon(),
+      alt: step.predicted.alt(),
+      vel: [step.velocity.x(), step.velocity.y(), step.velocity.z()],
+      dt,
+    });
+    log_event(&mut outputs.events, &fix_received(gps_data));
+    verbosity.detail(&format!("fix {:.6}, {:.6} residual {:.1} m ({})",
+                              gps_data.lat(), gps_data.lon(), step.residual_m, step.verdict.state()));
+
+    let verdict = &step.verdict;
+    if let Err(e) = outputs.events.log_verdict(verdict, step.residual_m) {
+      eprintln!("Could not write event log: {e}");
+    }
+    if let Some(alarm) = &mut outputs.alarm {
+      alarm.update(verdict.state());
+    }
+    if let Some(metrics) = &outputs.metrics {
+      metrics.update_verdict(verdict, step.residual_m);
+      metrics.set_imu_rate(window.samples as f32 / dt as f32);
+    }
+    if let Some(mqtt) = &mut outputs.mqtt {
+      if let Err(e) = mqtt.publish(gps_data, &step.predicted, &step.velocity, verdict) {
+        log_event(&mut outputs.events, &output_error("mqtt", &format!("dropped telemetry: {e}")));
+      }
+    }
+  }
+}
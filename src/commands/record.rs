@@ -0,0 +1,216 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Instant;
+use adafruit_gps::{Gps, GpsSentence};
+use gps_spoofing_detection::{neo6m, mpu6050};
+use gps_spoofing_detection::config::settings::{ClockReference, Config};
+use gps_spoofing_detection::neo6m::nmea::SentenceSource;
+use gps_spoofing_detection::session::record::{SessionRecord, SessionWriter};
+
+use crate::commands::common::{self, log_event, sensor_error, Exit, ImuReader, Verbosity};
+use crate::commands::sensors::{Devices, Fault, Sensors};
+
+
+/// Passes the gps' sentences on to the gps thread, sending each one with the time it
+/// arrived to the recording as well
+struct Tee {
+  gps: Gps,
+  sentences: Sender<(Instant, GpsSentence)>,
+}
+
+/// The recording and how far into the sensors' buffers it has got
+struct Recording {
+  writer: SessionWriter,
+  origin: Instant, // the instant the sensors' times count from
+  imu_t: f64,      // time of the last imu sample written
+  pressure_t: f64, // time of the last barometer reading written
+  field_t: f64,    // time of the last magnetometer reading written
+}
+
+
+/// Records raw gps sentences with the epoch each fix was timed at, calibrated imu samples,
+/// and the barometer and magnetometer readings to 'path' for replaying later. The sensors
+/// are read on the same threads as monitor, so replay sees the readings monitor would have.
+/// Stops after 'fixes' fixes, or when the gps is lost if no count is given.
+pub fn run(config: &Config, path: &str, fixes: Option<u32>, verbosity: Verbosity) -> Exit {
+  let mut events = match common::open_event_log(config) {
+    Ok(events) => events,
+    Err(exit) => return exit,
+  };
+  let mut gps = match common::open_gps(config) {
+    Ok(gps) => gps,
+    Err(e) => {
+      log_event(&mut events, &sensor_error("gps", &e));
+      return Exit::Sensor;
+    }
+  };
+  neo6m::gps::init_gps(&mut gps, config.gps.update_rate_ms);
+  let pps = match common::open_pps(config) {
+    Ok(pps) => pps,
+    Err(e) => {
+      log_event(&mut events, &sensor_error("pps", &e));
+      return Exit::Sensor;
+    }
+  };
+  let i2c = match common::open_imu(config) {
+    Ok(i2c) => i2c,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(config.imu.model.name(), &e));
+      return Exit::Sensor;
+    }
+  };
+  let sensor = i2c.borrow().model().name();
+  let calibration = match common::load_or_calibrate(&i2c, config, &mut events, verbosity) {
+    Ok(calibration) => calibration,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(sensor, &e.to_string()));
+      return Exit::Sensor;
+    }
+  };
+  let sensitivity = i2c.borrow().sensitivity();
+  let reader = match ImuReader::open(&i2c, config) {
+    Ok(reader) => reader,
+    Err(e) => {
+      log_event(&mut events, &sensor_error(sensor, &e.to_string()));
+      return Exit::Sensor;
+    }
+  };
+
+  // the rtc only when it's the clock detector's reference, as in monitor, so the
+  // recorded reference times are the ones monitor would check
+  let reference_rtc = config.detector.clock.as_ref().is_some_and(|clock| clock.reference == ClockReference::Rtc);
+  let rtc = match common::open_rtc(config) {
+    Ok(rtc) => rtc.filter(|_| reference_rtc),
+    Err(e) => {
+      log_event(&mut events, &sensor_error("ds3231", &e));
+      return Exit::Sensor;
+    }
+  };
+  // the barometer and magnetometer whenever they're set up, so their checks can be replayed
+  let baro = match common::open_baro(config) {
+    Ok(baro) => baro,
+    Err(e) => {
+      log_event(&mut events, &sensor_error("barometer", &e));
+      return Exit::Sensor;
+    }
+  };
+  let mag = match common::open_mag(config) {
+    Ok(mag) => mag,
+    Err(e) => {
+      log_event(&mut events, &sensor_error("magnetometer", &e));
+      return Exit::Sensor;
+    }
+  };
+
+  let rest = mpu6050::accel::rest_acceleration(&calibration, sensitivity.accel);
+  let (tx, sentences) = mpsc::channel();
+  let devices = Devices { gps: Tee { gps, sentences: tx }, pps, imu: i2c, reader, rtc, baro, mag };
+  let mut sensors = Sensors::start(devices, calibration, sensitivity, config.imu.settings().sample_rate_hz());
+
+  let writer = match SessionWriter::create_from(path, sensors.origin()) {
+    Ok(writer) => writer,
+    Err(e) => {
+      eprintln!("Could not create {path}: {e}");
+      return Exit::Failure;
+    }
+  };
+  verbosity.info(&format!("Recording to {path}..."));
+
+  let mut recording = Recording { writer, origin: sensors.origin(), imu_t: f64::NEG_INFINITY,
+                                  pressure_t: f64::NEG_INFINITY, field_t: f64::NEG_INFINITY };
+  if let Err(e) = recording.writer.write(&SessionRecord::Rest { t: 0.0, accel: [rest.x(), rest.y(), rest.z()] }) {
+    eprintln!("Could not write {path}: {e}");
+    return Exit::Failure;
+  }
+
+  // each fix with the readings buffered up to it, then the epoch it was timed at
+  let mut recorded = 0;
+  let mut last_t = f64::NEG_INFINITY;
+  let exit = loop {
+    if fixes.is_some_and(|fixes| recorded >= fixes) {
+      break Exit::Ok;
+    }
+    let (t, fix) = match sensors.next_fix(last_t) {
+      Ok(fix) => fix,
+      Err(Fault::Imu(e)) => {
+        log_event(&mut events, &sensor_error(sensor, &e));
+        continue;
+      }
+      Err(Fault::PpsLost(e)) => {
+        log_event(&mut events, &sensor_error("pps", &e));
+        continue;
+      }
+      Err(Fault::RtcLost(e)) => {
+        log_event(&mut events, &sensor_error("ds3231", &e));
+        continue;
+      }
+      Err(Fault::BaroLost(e)) => {
+        log_event(&mut events, &sensor_error("barometer", &e));
+        continue;
+      }
+      Err(Fault::MagLost(e)) => {
+        log_event(&mut events, &sensor_error("magnetometer", &e));
+        continue;
+      }
+      Err(Fault::ImuLost(e)) => {
+        log_event(&mut events, &sensor_error(sensor, &e));
+        break Exit::Sensor;
+      }
+      Err(Fault::GpsLost) => {
+        log_event(&mut events, &sensor_error("gps", "lost connection to gps"));
+        break if fixes.is_some() { Exit::Sensor } else { Exit::Ok };
+      }
+    };
+    last_t = t;
+
+    let epoch = SessionRecord::Epoch { t, pps_t: fix.pps_t, host_s: fix.host_s };
+    if let Err(e) = recording.write_readings(&sensors, &sentences).and_then(|_| recording.writer.write(&epoch)) {
+      eprintln!("Could not write {path}: {e}");
+      break Exit::Failure;
+    }
+    recorded += 1;
+    verbosity.detail(&format!("recorded fix {recorded}"));
+  };
+
+  sensors.stop();
+  if let Err(e) = recording.write_readings(&sensors, &sentences).and_then(|_| recording.writer.flush()) {
+    eprintln!("Could not write {path}: {e}");
+    return Exit::Failure;
+  }
+  verbosity.info(&format!("Recorded {recorded} fixes"));
+  exit
+}
+
+
+/// Recording implementations
+impl Recording {
+  /// Writes the readings buffered since the last call and the sentences that have arrived
+  fn write_readings(&mut self, sensors: &Sensors, sentences: &Receiver<(Instant, GpsSentence)>) -> std::io::Result<()> {
+    for sample in sensors.imu_after(self.imu_t) {
+      self.writer.write(&SessionRecord::Imu { t: sample.t, accel: sample.accel, temperature_c: Some(sample.temperature_c),
+                                              gyro: Some(sample.gyro) })?;
+      self.imu_t = sample.t;
+    }
+    for (t, pressure_pa) in sensors.pressures_after(self.pressure_t) {
+      self.writer.write(&SessionRecord::Pressure { t, pressure_pa })?;
+      self.pressure_t = t;
+    }
+    for (t, field) in sensors.fields_after(self.field_t) {
+      self.writer.write(&SessionRecord::Field { t, field })?;
+      self.field_t = t;
+    }
+    for (at, sentence) in sentences.try_iter() {
+      let t = at.saturating_duration_since(self.origin).as_secs_f64();
+      self.writer.write(&SessionRecord::Gps { t, sentence })?;
+    }
+    Ok(())
+  }
+}
+
+
+impl SentenceSource for Tee {
+  fn next_sentence(&mut self) -> GpsSentence {
+    let sentence = self.gps.update();
+    let _ = self.sentences.send((Instant::now(), sentence.clone()));
+    sentence
+  }
+}
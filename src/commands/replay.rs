@@ -0,0 +1,80 @@
+use gps_spoofing_detection::config::settings::Config;
+use gps_spoofing_detection::detect::verdict::AlertState;
+use gps_spoofing_detection::output::events::Event;
+use gps_spoofing_detection::session::{record, replay};
+use gps_spoofing_detection::session::replay::Recorded;
+
+use crate::commands::common::{self, log_event, fix_received, sensor_error, Exit, Verbosity};
+use crate::commands::detection::{Compass, Detection, Outcome};
+use crate::commands::sensors::Fix;
+
+
+/// Runs detection over a recorded (or simulated) session with the same checks as
+/// monitor, logging events as monitor would. Checks whose readings weren't recorded are
+/// skipped. Exits with Exit::Spoofed if any fix was found to be spoofed.
+pub fn run(config: &Config, path: &str, verbosity: Verbosity) -> Exit {
+  let records = match record::read_session(path) {
+    Ok(records) => records,
+    Err(e) => {
+      eprintln!("Could not read session {path}: {e}");
+      return Exit::Failure;
+    }
+  };
+  let mut events = match common::open_event_log(config) {
+    Ok(events) => events,
+    Err(exit) => return exit,
+  };
+
+  let recorded = Recorded::new(&records);
+  let compass = match (&config.mag, recorded.rest()) {
+    (Some(mag_config), Some(up)) if config.detector.heading.is_some() => match common::load_mag_calibration(mag_config) {
+      Ok(calibration) => Some(Compass { calibration, up }),
+      Err(e) => {
+        log_event(&mut events, &sensor_error("magnetometer", &e));
+        return Exit::Sensor;
+      }
+    },
+    _ => None,
+  };
+  let mut detection = Detection::new(config, compass);
+  let (mut fixes, mut suspect, mut spoofed) = (0, 0, 0);
+
+  for epoch in replay::epochs(&records) {
+    fixes += 1;
+    let fix = Fix { data: epoch.fix, pps_t: epoch.pps_t, host_s: epoch.host_s };
+
+    let (step, window) = match detection.step(epoch.t, &fix, &recorded, verbosity) {
+      Outcome::Step(step, window) => (step, window),
+      Outcome::Start => {
+        log_event(&mut events, &fix_received(&fix.data)); // starting position
+        continue;
+      }
+      Outcome::NoImu => {
+        log_event(&mut events, &sensor_error(config.imu.model.name(), "no imu samples cover the time since the last fix"));
+        log_event(&mut events, &fix_received(&fix.data));
+        continue;
+      }
+    };
+    log_event(&mut events, &Event::PredictionMade {
+      lat: step.predicted.lat(),
+      lon: step.predicted.lon(),
+      alt: step.predicted.alt(),
+      vel: [step.velocity.x(), step.velocity.y(), step.velocity.z()],
+      dt: window.dt,
+    });
+    log_event(&mut events, &fix_received(&fix.data));
+    if let Err(e) = events.log_verdict(&step.verdict, step.residual_m) {
+      eprintln!("Could not write event log: {e}");
+    }
+    verbosity.detail(&format!("t {:.1}s residual {:.1} m ({})", epoch.t, step.residual_m, step.verdict.state()));
+
+    match step.verdict.state() {
+      AlertState::Nominal => {}
+      AlertState::Suspect => suspect += 1,
+      AlertState::Spoofed => spoofed += 1,
+    }
+  }
+
+  verbosity.info(&format!("Replayed {fixes} fixes: {suspect} suspect, {spoofed} spoofed"));
+  if spoofed > 0 { Exit::Spoofed } else { Exit::Ok }
+}
@@ -0,0 +1,113 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use clap::ValueEnum;
+use rppal::i2c::I2c;
+use gps_spoofing_detection::config::settings::Config;
+use gps_spoofing_detection::ds3231::rtc::{self, RtcClock};
+use gps_spoofing_detection::neo6m;
+use gps_spoofing_detection::timing::calendar::iso_time;
+
+use crate::commands::common::{self, Exit, Verbosity};
+
+const PPS_TIMEOUT: Duration = Duration::from_millis(1500); // Longest wait for the pps edge after a fix
+
+
+/// Where the rtc's time is set from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimeSource {
+  System, // the system clock, once NTP has set it
+  Gps,    // the next gps fix, on its pps edge if pps is wired
+}
+
+
+/// Prints the DS3231's time and temperature, or sets its time from 'set'
+pub fn run(config: &Config, set: Option<TimeSource>, verbosity: Verbosity) -> Exit {
+  let mut i2c = match common::open_rtc(config) {
+    Ok(Some(i2c)) => i2c,
+    Ok(None) => {
+      eprintln!("No [rtc] section in the config");
+      return Exit::Config;
+    }
+    Err(e) => {
+      eprintln!("{e}");
+      return Exit::Sensor;
+    }
+  };
+
+  if let Some(source) = set {
+    let set = match source {
+      TimeSource::System => set_from_system(&mut i2c),
+      TimeSource::Gps => set_from_gps(&mut i2c, config, verbosity),
+    };
+    match set {
+      Ok(seconds) => verbosity.info(&format!("Set the rtc to {}", iso_time(seconds as f64))),
+      Err(e) => {
+        eprintln!("Could not set the rtc: {e}");
+        return Exit::Sensor;
+      }
+    }
+  }
+
+  verbosity.info("Reading rtc...");
+  let clock = match RtcClock::sync(&i2c) {
+    Ok(clock) => clock,
+    Err(e) => {
+      println!("rtc  {e}");
+      return Exit::Sensor;
+    }
+  };
+  let rtc_s = clock.now_s();
+  let system_s = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+  println!("rtc  ds3231");
+  println!("  time        {}", iso_time(rtc_s));
+  println!("  system      {} ({:+.3} s)", iso_time(system_s), system_s - rtc_s);
+  if let Ok(temperature) = rtc::read_temperature(&i2c) {
+    println!("  temperature {temperature:.2} C");
+  }
+  Exit::Ok
+}
+
+
+/// Sets the rtc on the next whole second of the system clock
+fn set_from_system(i2c: &mut I2c) -> Result<i64, String> {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+  thread::sleep(Duration::from_secs(1) - Duration::from_nanos(now.subsec_nanos() as u64));
+  let seconds = now.as_secs() as i64 + 1;
+  rtc::set_time(i2c, seconds).map_err(|e| e.to_string())?;
+  Ok(seconds)
+}
+
+
+/// Sets the rtc on the second after the next gps fix, on its pps edge if pps is wired.
+/// Without pps the second is taken to start as the fix's RMC sentence arrives, so the
+/// rtc is behind by the receiver's output delay (usually a few hundred milliseconds).
+fn set_from_gps(i2c: &mut I2c, config: &Config, verbosity: Verbosity) -> Result<i64, String> {
+  let mut gps = common::open_gps(config)?;
+  neo6m::gps::init_gps(&mut gps, config.gps.update_rate_ms);
+  let mut pps = common::open_pps(config)?;
+
+  verbosity.info("Waiting for gps fix...");
+  let deadline = Instant::now() + config.gps.fix_timeout();
+  let (utc, at) = loop {
+    if Instant::now() >= deadline {
+      return Err("timed out waiting for gps fix".to_string());
+    }
+    let (data, at) = neo6m::gps::get_gps_timed(&mut gps).ok_or("lost connection to gps")?;
+    if let Some(utc) = data.utc_seconds().filter(|_| data.mode() >= 2) {
+      break (utc, at);
+    }
+  };
+  let seconds = utc.floor() as i64 + 1;
+
+  match &mut pps {
+    Some(pps) => {
+      pps.wait(PPS_TIMEOUT).map_err(|e| e.to_string())?.ok_or("no pps edge after the fix")?;
+    }
+    None => {
+      verbosity.info("No pps, the rtc will be behind by the gps output delay");
+      thread::sleep(Duration::from_secs_f64(1.0 - utc.fract()).saturating_sub(at.elapsed()));
+    }
+  }
+  rtc::set_time(i2c, seconds).map_err(|e| e.to_string())?;
+  Ok(seconds)
+}
@@ -0,0 +1,134 @@
+use adafruit_gps::{Gps, GpsSentence};
+use rppal::i2c::I2c;
+use gps_spoofing_detection::imu::driver::Imu;
+use gps_spoofing_detection::mpu6050::accel;
+use gps_spoofing_detection::config::settings::Config;
+use gps_spoofing_detection::compass::calibration::magnitude;
+use gps_spoofing_detection::ds3231::rtc::RtcClock;
+use gps_spoofing_detection::timing::calendar::iso_time;
+
+use crate::commands::common::{self, Exit, Verbosity};
+
+const SENTENCES_TO_READ: u32 = 20; // Sentences read from the gps when checking its output
+const MIN_VALID_SENTENCES: u32 = 3; // Valid sentences needed to pass
+const ACCEL_SAMPLES: u32 = 50; // Samples averaged when checking the accelerometer
+const GRAVITY_TOLERANCE: f32 = 0.3; // Allowed difference from 1 g at rest, in g
+
+
+/// Checks that each sensor is connected and answering sensibly, printing one
+/// PASS/FAIL line per check. The imu should be at rest.
+pub fn run(config: &Config, verbosity: Verbosity) -> Exit {
+  let mut failed = false;
+  let mut report = |check: &str, result: Result<String, String>| match result {
+    Ok(detail) => println!("PASS  {check}: {detail}"),
+    Err(detail) => {
+      println!("FAIL  {check}: {detail}");
+      failed = true;
+    }
+  };
+
+  verbosity.info("Checking gps...");
+  match common::open_gps(config) {
+    Ok(mut gps) => {
+      report("gps port", Ok(format!("{} at {} baud", config.gps.port, config.gps.baud_rate)));
+      report("gps output", check_sentences(&mut gps));
+    }
+    Err(e) => report("gps port", Err(e)),
+  }
+
+  verbosity.info("Checking imu...");
+  let imu = &config.imu;
+  match I2c::with_bus(imu.i2c_bus) {
+    Ok(_) => {
+      report("i2c bus", Ok(format!("i2c-{}", imu.i2c_bus)));
+      match common::open_imu(config) {
+        Ok(device) => {
+          let mut device = device.borrow_mut();
+          report("imu identity", Ok(format!("{} at {:#04x}", device.model().name(), device.address())));
+          report("accelerometer", check_gravity(device.as_mut()));
+        }
+        Err(e) => report("imu identity", Err(e)),
+      }
+    }
+    Err(e) => report("i2c bus", Err(format!("could not open i2c-{}: {e}", imu.i2c_bus))),
+  }
+
+  if let Some(rtc) = &config.rtc {
+    verbosity.info("Checking rtc...");
+    let clock = common::open_rtc(config).and_then(|i2c| {
+      let i2c = i2c.expect("the rtc is configured");
+      RtcClock::sync(&i2c).map_err(|e| format!("ds3231 at {:#04x}: {e}", rtc.address))
+    });
+    report("rtc", clock.map(|clock| format!("ds3231 at {:#04x} reads {}", rtc.address, iso_time(clock.now_s()))));
+  }
+
+  if let Some(baro) = &config.baro {
+    verbosity.info("Checking barometer...");
+    let reading = common::open_baro(config).and_then(|barometer| {
+      let barometer = barometer.expect("the barometer is configured");
+      let variant = barometer.variant();
+      barometer.read().map(|reading| (variant, reading)).map_err(|e| format!("barometer at {:#04x}: {e}", baro.address))
+    });
+    report("barometer", reading.map(|(variant, reading)| {
+      format!("{variant:?} at {:#04x} reads {:.1} hPa, {:.1} C", baro.address, reading.pressure_pa / 100.0, reading.temperature_c)
+    }));
+  }
+
+  if let Some(mag) = &config.mag {
+    verbosity.info("Checking magnetometer...");
+    let field = common::open_mag(config).and_then(|magnetometer| {
+      let magnetometer = magnetometer.expect("the magnetometer is configured");
+      magnetometer.read().map_err(|e| format!("{:?} at {:#04x}: {e}", mag.chip, mag.address()))
+    });
+    report("magnetometer", field.map(|field| format!("{:?} at {:#04x} reads {:.1} uT", mag.chip, mag.address(), magnitude(field))));
+    report("mag calibration", common::load_mag_calibration(mag).map(|calibration| {
+      format!("{} ({:.1} uT field)", mag.calibration_file, calibration.field_ut)
+    }));
+  }
+
+  if failed { Exit::Sensor } else { Exit::Ok }
+}
+
+
+/// Reads a few sentences and checks enough of them parse
+fn check_sentences(gps: &mut Gps) -> Result<String, String> {
+  let mut valid = 0;
+  for _ in 0..SENTENCES_TO_READ {
+    match gps.update() {
+      GpsSentence::NoConnection => return Err("no data received".to_string()),
+      GpsSentence::InvalidBytes => return Err("garbled data, check the baud rate".to_string()),
+      GpsSentence::InvalidSentence => {}
+      _ => valid += 1,
+    }
+  }
+  if valid >= MIN_VALID_SENTENCES {
+    Ok(format!("{valid} of {SENTENCES_TO_READ} sentences valid"))
+  } else {
+    Err(format!("only {valid} of {SENTENCES_TO_READ} sentences valid"))
+  }
+}
+
+
+/// Checks the uncalibrated acceleration at rest is close to 1 g
+fn check_gravity(imu: &mut dyn Imu) -> Result<String, String> {
+  let errors_before = accel::i2c_read_errors();
+
+  let mut sum = [0.0; 3];
+  for _ in 0..ACCEL_SAMPLES {
+    // keep reading on errors to count how many fail
+    if let Ok(reading) = imu.acceleration_g() {
+      (0..3).for_each(|i| sum[i] += reading[i]);
+    }
+  }
+
+  let errors = accel::i2c_read_errors() - errors_before;
+  if errors > 0 {
+    return Err(format!("{errors} of {ACCEL_SAMPLES} reads failed"));
+  }
+  let g = sum.iter().map(|v| (v / ACCEL_SAMPLES as f32).powi(2)).sum::<f32>().sqrt();
+  if (g - 1.0).abs() <= GRAVITY_TOLERANCE {
+    Ok(format!("{g:.2} g at rest"))
+  } else {
+    Err(format!("{g:.2} g at rest, expected about 1 g"))
+  }
+}
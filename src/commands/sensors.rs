@@ -0,0 +1,356 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use adafruit_gps::Gps;
+use rppal::i2c::I2c;
+use gps_spoofing_detection::neo6m;
+use gps_spoofing_detection::bmp280::baro::Barometer;
+use gps_spoofing_detection::compass::mag::Magnetometer;
+use gps_spoofing_detection::ds3231::rtc::RtcClock;
+use gps_spoofing_detection::mpu6050::accel::Sensitivity;
+use gps_spoofing_detection::mpu6050::calibration::Calibration;
+use gps_spoofing_detection::neo6m::gps::GpsData;
+use gps_spoofing_detection::neo6m::nmea::SentenceSource;
+use gps_spoofing_detection::timing::align::{self, Window, MAX_GAP_PERIODS};
+use gps_spoofing_detection::timing::pps::{EpochTimer, PpsReader};
+use gps_spoofing_detection::timing::ring::TimedRing;
+
+use crate::commands::common::{ImuHandle, ImuReader};
+
+const IMU_BUFFER_S: f32 = 5.0; // Seconds of imu samples kept for aligning with fixes
+const MIN_IMU_BUFFER: usize = 8192; // Fewest imu samples kept, a few seconds when polling as fast as the bus allows
+const GPS_BUFFER: usize = 16; // Fixes kept until the detector gets to them
+const IMU_CHUNK: u32 = 10; // Samples read between writes to the buffer when polling or on interrupts
+const MAX_IMU_FAULTS: u32 = 5; // Failed imu reads in a row before the imu is considered lost
+const IMU_LAG: Duration = Duration::from_millis(250); // Longest wait for the imu samples to reach a fix
+const WAIT_STEP: Duration = Duration::from_millis(5); // Sleep between checks of the buffers
+const PPS_WAIT: Duration = Duration::from_millis(100); // Longest wait for a pps edge before checking the threads should stop
+const RTC_RESYNC: Duration = Duration::from_secs(60); // How often the rtc is read again, so the host clock's drift doesn't build up
+const POLL_PERIOD: Duration = Duration::from_millis(50); // Time between barometer and magnetometer reads
+const POLL_BUFFER: usize = 256; // Barometer and magnetometer readings kept for aligning with fixes, over 10 s
+const MAX_POLL_FAULTS: u32 = 5; // Failed barometer or magnetometer reads in a row before it's considered lost
+
+type GyroRing = TimedRing<([f32; 3], f32)>; // Rotation rates and die temperatures at the same times as the accelerations
+
+
+/// Why the sensor threads couldn't provide the next fix
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+  GpsLost,         // the gps stopped sending fixes, its thread has stopped
+  Imu(String),     // an imu read failed, the samples around it are missing
+  ImuLost(String), // the imu failed too many times in a row, its thread has stopped
+  PpsLost(String), // the pps couldn't be read, fixes are timed from their RMC sentence from now on
+  RtcLost(String), // the rtc couldn't be read, fixes have no reference time from now on
+  BaroLost(String), // the barometer failed too many times in a row, its thread has stopped
+  MagLost(String),  // the magnetometer failed too many times in a row, its thread has stopped
+}
+
+/// The sensors to read, opened and set up. The gps is anything its sentences can be read
+/// from, adafruit_gps' Gps or an NmeaReader on an embedded-hal serial port.
+pub struct Devices<G = Gps> {
+  pub gps: G,
+  pub pps: Option<PpsReader>,
+  pub imu: ImuHandle,
+  pub reader: ImuReader,
+  pub rtc: Option<I2c>, // the reference clock for fix times, the system clock if None
+  pub baro: Option<Barometer>,
+  pub mag: Option<Magnetometer>,
+}
+
+/// A fix with the pps edge that marked its epoch
+#[derive(Clone, Debug)]
+pub struct Fix {
+  pub data: GpsData,
+  pub pps_t: Option<f64>,  // seconds from the origin, for epochs on a whole utc second when pps is wired
+  pub host_s: Option<f64>, // the reference clock at the epoch, seconds since the Unix epoch (None until the rtc is read)
+}
+
+/// An imu sample as buffered, for recording
+#[derive(Clone, Copy, Debug)]
+pub struct ImuSample {
+  pub t: f64,          // seconds from the origin
+  pub accel: [f32; 3], // m/s^2, calibrated
+  pub gyro: [f32; 3],  // rad/s, calibrated
+  pub temperature_c: f32,
+}
+
+/// Reads the gps and imu on their own threads, each writing its readings into a
+/// ring buffer timed from a shared origin, so the imu samples can be lined up with
+/// each fix by the time they were taken rather than by when they were read. With pps
+/// wired, a third thread times the edges and each fix is timed at the edge of its epoch.
+/// With an rtc, a fourth keeps its time for timestamping the fixes on an independent clock,
+/// and a barometer or magnetometer each get another that buffers their readings the same
+/// way as the imu's.
+pub struct Sensors {
+  imu: Arc<Mutex<TimedRing<[f32; 3]>>>,
+  gyro: Arc<Mutex<GyroRing>>,
+  pressure: Arc<Mutex<TimedRing<f32>>>,
+  field: Arc<Mutex<TimedRing<[f32; 3]>>>,
+  fixes: Arc<Mutex<TimedRing<Fix>>>,
+  origin: Instant, // the instant the readings' times count from
+  imu_gap: f64, // longest gap between imu samples a window may span, seconds
+  faults: Receiver<Fault>,
+  running: Arc<AtomicBool>,
+  threads: Vec<JoinHandle<()>>,
+}
+
+
+/// Sensors implementations
+impl Sensors {
+  /// Starts reading the devices, with the imu corrected by 'calibration' and
+  /// 'sample_rate_hz' of its samples buffered a second. Windows with a gap of more
+  /// than a few sample periods between the imu samples are rejected.
+  pub fn start<G: SentenceSource + Send + 'static>(devices: Devices<G>, calibration: Calibration, sensitivity: Sensitivity,
+                                                   sample_rate_hz: f32) -> Sensors {
+    let Devices { gps, pps, imu: i2c, reader, rtc, baro, mag } = devices;
+    let origin = Instant::now();
+    let capacity = ((sample_rate_hz * IMU_BUFFER_S) as usize).max(MIN_IMU_BUFFER);
+    let imu = Arc::new(Mutex::new(TimedRing::new(capacity)));
+    let gyro = Arc::new(Mutex::new(TimedRing::new(capacity)));
+    let fixes = Arc::new(Mutex::new(TimedRing::new(GPS_BUFFER)));
+    let pressure = Arc::new(Mutex::new(TimedRing::new(POLL_BUFFER)));
+    let field = Arc::new(Mutex::new(TimedRing::new(POLL_BUFFER)));
+    let running = Arc::new(AtomicBool::new(true));
+    let timer = Arc::new(Mutex::new(EpochTimer::new()));
+    let (fault_tx, faults) = mpsc::channel();
+    let mut threads = Vec::new();
+
+    if let Some(pps) = pps {
+      let (timer, running, faults) = (timer.clone(), running.clone(), fault_tx.clone());
+      threads.push(thread::spawn(move || read_pps(pps, origin, &timer, &running, &faults)));
+    }
+    let clock = rtc.map(|rtc| {
+      let clock = Arc::new(Mutex::new(None));
+      let (shared, running, faults) = (clock.clone(), running.clone(), fault_tx.clone());
+      threads.push(thread::spawn(move || read_rtc(rtc, &shared, &running, &faults)));
+      clock
+    });
+    if let Some(baro) = baro {
+      let (pressure, running, faults) = (pressure.clone(), running.clone(), fault_tx.clone());
+      let read = move || baro.read().map(|reading| reading.pressure_pa);
+      threads.push(thread::spawn(move || read_polled(read, Fault::BaroLost, origin, &pressure, &running, &faults)));
+    }
+    if let Some(mag) = mag {
+      let (field, running, faults) = (field.clone(), running.clone(), fault_tx.clone());
+      threads.push(thread::spawn(move || read_polled(move || mag.read(), Fault::MagLost, origin, &field, &running, &faults)));
+    }
+    {
+      let (fixes, running, faults) = (fixes.clone(), running.clone(), fault_tx.clone());
+      threads.push(thread::spawn(move || read_gps(gps, origin, &timer, clock.as_deref(), &fixes, &running, &faults)));
+    }
+    {
+      let (imu, gyro, running) = (imu.clone(), gyro.clone(), running.clone());
+      threads.push(thread::spawn(move || {
+        read_imu(i2c, reader, &calibration, &sensitivity, origin, &imu, &gyro, &running, &fault_tx)
+      }));
+    }
+    let imu_gap = MAX_GAP_PERIODS / sample_rate_hz as f64;
+    Sensors { imu, gyro, pressure, field, fixes, origin, imu_gap, faults, running, threads }
+  }
+
+  /// Waits for the first fix newer than 't' (seconds from the origin), returning it with
+  /// its time. A fault from either thread is returned instead as soon as it's reported.
+  pub fn next_fix(&self, t: f64) -> Result<(f64, Fix), Fault> {
+    loop {
+      match self.faults.try_recv() {
+        Ok(fault) => return Err(fault),
+        Err(TryRecvError::Disconnected) => return Err(Fault::GpsLost),
+        Err(TryRecvError::Empty) => {}
+      }
+      if let Some((fix_t, fix)) = self.fixes.lock().unwrap().after(t).next() {
+        return Ok((*fix_t, fix.clone()));
+      }
+      thread::sleep(WAIT_STEP);
+    }
+  }
+
+  /// The imu samples between 't0' and 't1' interpolated to both ends, waiting briefly
+  /// for the imu thread to catch up to 't1'. None if the buffer doesn't cover the interval
+  /// (samples were missed, or it's older than the buffer holds).
+  pub fn imu_window(&self, t0: f64, t1: f64) -> Option<Window> {
+    let deadline = Instant::now() + IMU_LAG;
+    loop {
+      let imu = self.imu.lock().unwrap();
+      if imu.last_t().is_some_and(|last| last >= t1) || Instant::now() >= deadline {
+        return align::window(&imu, t0, t1, self.imu_gap);
+      }
+      drop(imu);
+      thread::sleep(WAIT_STEP);
+    }
+  }
+
+  /// The (acceleration, rotation rate) samples between 't0' and 't1', for telling whether
+  /// the imu was still. Call after imu_window, which waits for the samples to reach 't1'.
+  pub fn imu_samples(&self, t0: f64, t1: f64) -> Vec<([f32; 3], [f32; 3])> {
+    let imu = self.imu.lock().unwrap();
+    let gyro = self.gyro.lock().unwrap();
+    imu.between(t0, t1).zip(gyro.between(t0, t1)).map(|((_, accel), (_, (gyro, _)))| (*accel, *gyro)).collect()
+  }
+
+  /// The imu samples buffered after 't', oldest first, for recording them
+  pub fn imu_after(&self, t: f64) -> Vec<ImuSample> {
+    let imu = self.imu.lock().unwrap();
+    let gyro = self.gyro.lock().unwrap();
+    imu.after(t).zip(gyro.after(t))
+      .map(|((t, accel), (_, (gyro, temperature_c)))| ImuSample { t: *t, accel: *accel, gyro: *gyro, temperature_c: *temperature_c })
+      .collect()
+  }
+
+  /// The barometer readings buffered after 't', oldest first
+  pub fn pressures_after(&self, t: f64) -> Vec<(f64, f32)> {
+    self.pressure.lock().unwrap().after(t).copied().collect()
+  }
+
+  /// The magnetometer readings buffered after 't', oldest first
+  pub fn fields_after(&self, t: f64) -> Vec<(f64, [f32; 3])> {
+    self.field.lock().unwrap().after(t).copied().collect()
+  }
+
+  /// The instant the times of the readings and fixes count from
+  pub fn origin(&self) -> Instant {
+    self.origin
+  }
+
+  /// The air pressure at 't' (seconds from the origin), interpolated between the
+  /// barometer readings around it. None without a barometer or readings that cover 't'.
+  pub fn pressure_at(&self, t: f64) -> Option<f32> {
+    align::interpolate(&self.pressure.lock().unwrap(), t, POLL_PERIOD.as_secs_f64() * MAX_GAP_PERIODS)
+  }
+
+  /// The uncalibrated magnetic field at 't' (seconds from the origin), interpolated the same way
+  pub fn field_at(&self, t: f64) -> Option<[f32; 3]> {
+    align::interpolate(&self.field.lock().unwrap(), t, POLL_PERIOD.as_secs_f64() * MAX_GAP_PERIODS)
+  }
+
+  /// Stops the threads and waits for them to finish
+  pub fn stop(&mut self) {
+    self.running.store(false, Ordering::Relaxed);
+    for thread in self.threads.drain(..) {
+      let _ = thread.join();
+    }
+  }
+}
+
+impl Drop for Sensors {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+
+/// Pps thread: passes each edge to the epoch timer until the pps can't be read
+fn read_pps(mut pps: PpsReader, origin: Instant, timer: &Mutex<EpochTimer>, running: &AtomicBool,
+            faults: &Sender<Fault>) {
+  while running.load(Ordering::Relaxed) {
+    match pps.wait(PPS_WAIT) {
+      Ok(Some(at)) => timer.lock().unwrap().edge(at.saturating_duration_since(origin).as_secs_f64()),
+      Ok(None) => {}
+      Err(e) => {
+        let _ = faults.send(Fault::PpsLost(e.to_string()));
+        return;
+      }
+    }
+  }
+}
+
+
+/// Rtc thread: reads the rtc's time on the tick of its seconds every RTC_RESYNC until it can't be read
+fn read_rtc(rtc: I2c, clock: &Mutex<Option<RtcClock>>, running: &AtomicBool, faults: &Sender<Fault>) {
+  while running.load(Ordering::Relaxed) {
+    match RtcClock::sync(&rtc) {
+      Ok(synced) => *clock.lock().unwrap() = Some(synced),
+      Err(e) => {
+        *clock.lock().unwrap() = None;
+        let _ = faults.send(Fault::RtcLost(e.to_string()));
+        return;
+      }
+    }
+    let resync = Instant::now() + RTC_RESYNC;
+    while running.load(Ordering::Relaxed) && Instant::now() < resync {
+      thread::sleep(PPS_WAIT);
+    }
+  }
+}
+
+
+/// Barometer and magnetometer threads: buffer a reading every POLL_PERIOD at the time
+/// it was read, giving up with the 'lost' fault after MAX_POLL_FAULTS failed reads in a row
+fn read_polled<T, E: Display>(mut read: impl FnMut() -> Result<T, E>, lost: fn(String) -> Fault, origin: Instant,
+                              readings: &Mutex<TimedRing<T>>, running: &AtomicBool, faults: &Sender<Fault>) {
+  let mut faults_in_row = 0;
+  while running.load(Ordering::Relaxed) {
+    match read() {
+      Ok(reading) => {
+        faults_in_row = 0;
+        readings.lock().unwrap().push(origin.elapsed().as_secs_f64(), reading);
+      }
+      Err(e) => {
+        faults_in_row += 1;
+        if faults_in_row >= MAX_POLL_FAULTS {
+          let _ = faults.send(lost(e.to_string()));
+          return;
+        }
+      }
+    }
+    thread::sleep(POLL_PERIOD);
+  }
+}
+
+
+/// Gps thread: buffers each fix at the time of its epoch until the gps is lost. The
+/// reference time of the epoch is read from the rtc's clock if there is one, otherwise
+/// from the system clock.
+#[allow(clippy::too_many_arguments)]
+fn read_gps(mut gps: impl SentenceSource, origin: Instant, timer: &Mutex<EpochTimer>, clock: Option<&Mutex<Option<RtcClock>>>,
+            fixes: &Mutex<TimedRing<Fix>>, running: &AtomicBool, faults: &Sender<Fault>) {
+  while running.load(Ordering::Relaxed) {
+    let Some((data, at)) = neo6m::gps::get_gps_timed(&mut gps) else {
+      let _ = faults.send(Fault::GpsLost);
+      return;
+    };
+    let arrival_t = at.saturating_duration_since(origin).as_secs_f64();
+    let epoch = timer.lock().unwrap().epoch(arrival_t, data.utc_seconds());
+    let now_s = match clock {
+      Some(clock) => clock.lock().unwrap().map(|clock| clock.now_s()),
+      None => Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()),
+    };
+    let host_s = now_s.map(|now_s| now_s - at.elapsed().as_secs_f64() - (arrival_t - epoch.t));
+    fixes.lock().unwrap().push(epoch.t, Fix { data, pps_t: epoch.pps_t, host_s });
+  }
+}
+
+
+/// Imu thread: buffers the calibrated acceleration, rotation rate and temperature of every sample at
+/// the time it was taken, reporting failed reads and giving up after MAX_IMU_FAULTS in a row
+#[allow(clippy::too_many_arguments)]
+fn read_imu(i2c: ImuHandle, mut reader: ImuReader, calibration: &Calibration, sensitivity: &Sensitivity, origin: Instant,
+            imu: &Mutex<TimedRing<[f32; 3]>>, gyro: &Mutex<GyroRing>, running: &AtomicBool,
+            faults: &Sender<Fault>) {
+  let mut faults_in_row = 0;
+  while running.load(Ordering::Relaxed) {
+    match reader.read_next(&i2c, IMU_CHUNK, calibration, sensitivity) {
+      Ok(samples) => {
+        faults_in_row = 0;
+        let (mut imu, mut gyro) = (imu.lock().unwrap(), gyro.lock().unwrap());
+        for (at, sample) in samples.iter().filter(|(at, _)| *at >= origin) {
+          let (t, accel) = ((*at - origin).as_secs_f64(), sample.accel);
+          // pushed or rejected together, so the two stay paired
+          if imu.push(t, [accel.x(), accel.y(), accel.z()]) {
+            gyro.push(t, (sample.gyro, sample.temperature_c));
+          }
+        }
+      }
+      Err(e) => {
+        faults_in_row += 1;
+        if faults_in_row >= MAX_IMU_FAULTS {
+          let _ = faults.send(Fault::ImuLost(e.to_string()));
+          return;
+        }
+        let _ = faults.send(Fault::Imu(e.to_string()));
+      }
+    }
+  }
+}
@@ -0,0 +1,21 @@
+use gps_spoofing_detection::session::record::SessionWriter;
+use gps_spoofing_detection::session::simulate::{simulate, Scenario};
+
+use crate::commands::common::{Exit, Verbosity};
+
+
+/// Writes a simulated session to 'path' that can be replayed like a recording
+pub fn run(scenario: &Scenario, path: &str, verbosity: Verbosity) -> Exit {
+  let records = simulate(scenario);
+  let written = SessionWriter::create(path).and_then(|mut writer| {
+    records.iter().try_for_each(|record| writer.write(record))?;
+    writer.flush()
+  });
+  if let Err(e) = written {
+    eprintln!("Could not write {path}: {e}");
+    return Exit::Failure;
+  }
+
+  verbosity.info(&format!("Wrote {} fixes ({} records) to {path}", scenario.fixes, records.len()));
+  Exit::Ok
+}
@@ -0,0 +1,66 @@
+use gps_spoofing_detection::{mpu6050, neo6m};
+use gps_spoofing_detection::config::settings::Config;
+
+use crate::commands::common::{self, Exit, Verbosity};
+
+
+/// Prints the current state of each sensor: the latest fix and a raw imu reading
+pub fn run(config: &Config, verbosity: Verbosity) -> Exit {
+  let mut ok = true;
+
+  verbosity.info("Reading gps...");
+  println!("gps  {} at {} baud", config.gps.port, config.gps.baud_rate);
+  match common::open_gps(config).map(|mut gps| neo6m::gps::get_gps(&mut gps)) {
+    Ok(Some(data)) => {
+      let mode = match data.mode() {
+        3 => "3d fix",
+        2 => "2d fix",
+        _ => "no fix",
+      };
+      println!("  mode        {mode}");
+      println!("  time        {}", data.iso_time().unwrap_or_else(|| "unknown".to_string()));
+      println!("  position    {:.6}, {:.6}  alt {:.1} m", data.lat(), data.lon(), data.alt());
+      println!("  speed       {:.2} m/s  course {:.1}", data.speed_mps(), data.course());
+      println!("  dop         h {:.1}  v {:.1}  p {:.1}", data.hor_prec(), data.ver_prec(), data.pos_prec());
+      println!("  satellites  {} used, {} in view", data.sats_used().len(), data.satellites().len());
+      if let Some(snr) = data.mean_snr() {
+        println!("  mean snr    {snr:.1} dB-Hz");
+      }
+    }
+    Ok(None) => {
+      println!("  not responding");
+      ok = false;
+    }
+    Err(e) => {
+      println!("  {e}");
+      ok = false;
+    }
+  }
+
+  verbosity.info("Reading imu...");
+  let imu = &config.imu;
+  println!("imu  {} on i2c-{} at {:#04x}", imu.model.name(), imu.i2c_bus, imu.address);
+  match common::open_imu(config) {
+    Ok(device) => {
+      let mut device = device.borrow_mut();
+      println!("  model       {} at {:#04x}", device.model().name(), device.address());
+      if let Ok(temperature) = device.read_temperature() {
+        println!("  temperature {temperature:.1} C");
+      }
+      match device.acceleration_g() {
+        Ok([x, y, z]) => println!("  accel       {x:.3} {y:.3} {z:.3} g (uncalibrated, +-{} g)", imu.accel_range_g),
+        Err(e) => {
+          println!("  accel       unreadable: {e}");
+          ok = false;
+        }
+      }
+      println!("  read errors {}", mpu6050::accel::i2c_read_errors());
+    }
+    Err(e) => {
+      println!("  {e}");
+      ok = false;
+    }
+  }
+
+  if ok { Exit::Ok } else { Exit::Sensor }
+}
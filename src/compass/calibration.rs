@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+
+use crate::mpu6050::least_squares;
+
+pub const MIN_SAMPLES: usize = 100; // Fewest readings a calibration is fitted from
+const MIN_COVERAGE: f32 = 1.0; // Each axis has to swing over at least this many field strengths
+
+
+/// Hard and soft iron correction of a magnetometer. The field read while it's turned
+/// through every orientation traces an ellipsoid: its centre is the hard iron offset
+/// (magnetized parts fixed to the sensor), and its stretch along each axis the soft
+/// iron distortion (iron nearby bending the earth's field) and the axes' own gains.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MagCalibration {
+  pub timestamp: u64,      // unix seconds the calibration was taken at
+  pub offset_ut: [f32; 3], // hard iron offset, subtracted from each reading
+  pub scale: [f32; 3],     // soft iron scale of each axis, applied after the offset
+  pub field_ut: f32,       // strength of the field the readings were fitted to
+  pub rms_error_ut: f32,   // how far the corrected readings are from a sphere
+}
+
+
+/// MagCalibration implementations
+impl MagCalibration {
+  /// Fits an axis aligned ellipsoid to readings (microtesla) taken while the sensor was
+  /// turned through every orientation. None if there are too few readings or they don't
+  /// cover every axis well enough to pin the ellipsoid down.
+  pub fn fit(readings: &[[f32; 3]], timestamp: u64) -> Option<MagCalibration> {
+    if readings.len() < MIN_SAMPLES {
+      return None;
+    }
+    // a x^2 + b y^2 + c z^2 + d x + e y + f z = 1
+    let rows: Vec<Vec<f64>> = readings.iter()
+      .map(|r| r.map(|v| v as f64))
+      .map(|[x, y, z]| vec![x * x, y * y, z * z, x, y, z])
+      .collect();
+    let coefficients = least_squares::fit(&rows, &vec![1.0; rows.len()])?;
+    let (quadratic, linear) = coefficients.split_at(3);
+    if quadratic.iter().any(|a| *a <= 0.0) {
+      return None; // not an ellipsoid
+    }
+    let centre: Vec<f64> = (0..3).map(|i| -linear[i] / (2.0 * quadratic[i])).collect();
+    let g = 1.0 + (0..3).map(|i| quadratic[i] * centre[i] * centre[i]).sum::<f64>();
+    let radii: Vec<f64> = quadratic.iter().map(|a| (g / a).sqrt()).collect();
+    if radii.iter().any(|r| !r.is_finite()) {
+      return None;
+    }
+    let field = radii.iter().product::<f64>().cbrt();
+
+    let mut calibration = MagCalibration {
+      timestamp,
+      offset_ut: [0, 1, 2].map(|i| centre[i] as f32),
+      scale: [0, 1, 2].map(|i| (field / radii[i]) as f32),
+      field_ut: field as f32,
+      rms_error_ut: 0.0,
+    };
+    for axis in 0..3 {
+      let (min, max) = readings.iter().map(|r| r[axis])
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+      if (max - min) * calibration.scale[axis] < MIN_COVERAGE * calibration.field_ut {
+        return None; // not turned far enough about this axis
+      }
+    }
+    let squared_error = readings.iter()
+      .map(|r| (magnitude(calibration.apply(*r)) - calibration.field_ut).powi(2))
+      .sum::<f32>();
+    calibration.rms_error_ut = (squared_error / readings.len() as f32).sqrt();
+    Some(calibration)
+  }
+
+  /// Corrects a reading (microtesla)
+  pub fn apply(&self, reading: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|i| (reading[i] - self.offset_ut[i]) * self.scale[i])
+  }
+
+  /// Reads a calibration saved with save
+  pub fn load(path: &str) -> io::Result<MagCalibration> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+  }
+
+  /// Writes the calibration as json, through a temporary file renamed into place
+  pub fn save(&self, path: &str) -> io::Result<()> {
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+    fs::rename(&tmp, path)
+  }
+}
+
+
+/// Length of a vector
+pub fn magnitude(v: [f32; 3]) -> f32 {
+  v.iter().map(|c| c * c).sum::<f32>().sqrt()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fit_recovers_hard_and_soft_iron() {
+    let (offset, gain, field) = ([12.0, -30.0, 5.0], [1.2, 0.9, 1.05], 50.0);
+    let mut readings = Vec::new();
+    for i in 0..20 {
+      for j in 0..20 {
+        let (theta, phi) = (i as f32 / 20.0 * std::f32::consts::PI, j as f32 / 20.0 * std::f32::consts::TAU);
+        let unit = [theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()];
+        readings.push([0, 1, 2].map(|k| unit[k] * field * gain[k] + offset[k]));
+      }
+    }
+    let calibration = MagCalibration::fit(&readings, 0).unwrap();
+    for k in 0..3 {
+      assert!((calibration.offset_ut[k] - offset[k]).abs() < 0.01);
+      assert!((calibration.scale[k] * gain[k] - gain.iter().product::<f32>().cbrt()).abs() < 1e-3);
+    }
+    assert!(calibration.rms_error_ut < 0.01);
+
+    // turned about a single axis only
+    let flat: Vec<[f32; 3]> = readings.iter().map(|r| [r[0], r[1], offset[2]]).collect();
+    assert!(MagCalibration::fit(&flat, 0).is_none());
+  }
+}
@@ -0,0 +1,68 @@
+pub use gps_spoofing_core::geo::heading_difference;
+
+
+/// Heading of the sensor's x axis in degrees from magnetic north (0 to 360, clockwise),
+/// from the calibrated magnetic field and the accelerometer's reading of gravity ('up',
+/// the specific force at rest, which points away from the ground). Both have to be in
+/// the same axes. Tilting the sensor doesn't change the heading, as long as the x axis
+/// isn't pointing straight up or down. None if either vector is degenerate.
+pub fn tilt_compensated_heading(field: [f32; 3], up: [f32; 3]) -> Option<f32> {
+  let down = normalize(up.map(|v| -v))?;
+  let east = normalize(cross(down, field))?; // the field points north (and down, north of the equator)
+  let north = cross(east, down);
+  let forward = [1.0, 0.0, 0.0];
+  let heading = dot(forward, east).atan2(dot(forward, north)).to_degrees();
+  Some(heading.rem_euclid(360.0))
+}
+
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+  [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+  a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> Option<[f32; 3]> {
+  let length = dot(v, v).sqrt();
+  (length > f32::EPSILON).then(|| v.map(|c| c / length))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The field (x north, y west, z up) with a 60 degree dip, seen by a sensor with its x
+  /// axis at 'heading', rolled by 'roll' degrees about x
+  fn sensor_frame(heading: f32, roll: f32) -> ([f32; 3], [f32; 3]) {
+    let (dip, heading, roll) = (60f32.to_radians(), heading.to_radians(), roll.to_radians());
+    let field = [dip.cos(), 0.0, -dip.sin()];
+    let up = [0.0, 0.0, 9.8];
+    // world to sensor: yaw by -heading about z (heading is clockwise, z is up), then roll about x
+    let yaw = |v: [f32; 3]| {
+      let (s, c) = (-heading).sin_cos();
+      [c * v[0] + s * v[1], -s * v[0] + c * v[1], v[2]]
+    };
+    let rolled = |v: [f32; 3]| {
+      let (s, c) = roll.sin_cos();
+      [v[0], c * v[1] + s * v[2], -s * v[1] + c * v[2]]
+    };
+    (rolled(yaw(field)), rolled(yaw(up)))
+  }
+
+  #[test]
+  fn test_tilt_compensated_heading() {
+    for heading in [0.0, 45.0, 90.0, 200.0, 315.0] {
+      for roll in [0.0, 20.0, -35.0] {
+        let (field, up) = sensor_frame(heading, roll);
+        let measured = tilt_compensated_heading(field, up).unwrap();
+        assert!(heading_difference(measured, heading).abs() < 0.01, "{heading} rolled {roll}: {measured}");
+      }
+    }
+    assert_eq!(heading_difference(10.0, 350.0), 20.0);
+    assert!(tilt_compensated_heading([0.0; 3], [0.0, 0.0, 9.8]).is_none());
+  }
+}
@@ -0,0 +1,217 @@
+use rppal::i2c::I2c;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::i2c::{self as bus, BusError};
+
+pub const HMC5883L_ADDR: u16 = 0x1E; // Fixed I2C address of the HMC5883L
+pub const QMC5883L_ADDR: u16 = 0x0D; // Fixed I2C address of the QMC5883L (sold on most "HMC5883L" boards now)
+pub const AK8963_ADDR: u16 = 0x0C; // I2C address of the MPU9250's magnetometer, behind the mpu's bypass
+
+const HMC_CONFIG_A: u8 = 0x00; // Averaging, output rate and measurement mode
+const HMC_CONFIG_B: u8 = 0x01; // Gain
+const HMC_MODE: u8 = 0x02; // Continuous, single or idle
+const HMC_DATA: u8 = 0x03; // First output register (x, z, y, big endian)
+const HMC_ID_A: u8 = 0x0A; // First of the three identification registers
+const HMC_ID: [u8; 3] = *b"H43"; // Contents of the identification registers
+const HMC_AVERAGE_8_15HZ: u8 = 0x70; // 8 samples averaged per output, 15 Hz
+const HMC_GAIN_1_3GA: u8 = 0x20; // +-1.3 Ga range
+const HMC_CONTINUOUS: u8 = 0x00; // Continuous measurement mode
+const HMC_LSB_PER_UT: f32 = 10.9; // At +-1.3 Ga (1090 LSB/Ga)
+const HMC_OVERFLOW: i16 = -4096; // Output of an axis that overflowed
+
+const QMC_DATA: u8 = 0x00; // First output register (x, y, z, little endian)
+const QMC_STATUS: u8 = 0x06; // Data ready and overflow flags
+const QMC_CONTROL_1: u8 = 0x09; // Oversampling, range, output rate and mode
+const QMC_SET_RESET: u8 = 0x0B; // Set/reset period, 0x01 as the datasheet recommends
+const QMC_CHIP_ID: u8 = 0x0D; // Chip id register
+const QMC_ID: u8 = 0xFF; // Contents of the chip id register
+const QMC_CONTINUOUS_8G: u8 = 0x1D; // 512 oversampling, +-8 G, 200 Hz, continuous
+const QMC_LSB_PER_UT: f32 = 30.0; // At +-8 G (3000 LSB/G)
+const QMC_OVERFLOW: u8 = 0x02; // STATUS bit set when an axis overflowed
+
+const AK_WIA: u8 = 0x00; // Device id register
+const AK_ID: u8 = 0x48; // Contents of the device id register
+const AK_DATA: u8 = 0x03; // First output register (x, y, z, little endian), followed by ST2
+const AK_CNTL1: u8 = 0x0A; // Output width and mode
+const AK_ASA: u8 = 0x10; // Factory sensitivity adjustments, readable in fuse rom mode
+const AK_POWER_DOWN: u8 = 0x00; // Mode to pass through between other modes
+const AK_FUSE_ROM: u8 = 0x0F; // Mode that makes the sensitivity adjustments readable
+const AK_CONTINUOUS_16BIT: u8 = 0x16; // 16 bit output, continuous measurement at 100 Hz
+const AK_UT_PER_LSB: f32 = 0.15; // With 16 bit output
+const AK_OVERFLOW: u8 = 0x08; // ST2 bit set when the field was too strong to measure
+const AK_MODE_CHANGE: Duration = Duration::from_millis(1); // Settling time between modes
+
+const MPU_INT_PIN_CFG: u8 = 0x37; // mpu6050/9250 register with the auxiliary bus bypass bit
+const MPU_USER_CTRL: u8 = 0x6A; // mpu6050/9250 register with the auxiliary bus master enable
+const BYPASS_EN: u8 = 0x02; // INT_PIN_CFG bit that connects the auxiliary bus to the main one
+const I2C_MST_EN: u8 = 0x20; // USER_CTRL bit that gives the auxiliary bus to the mpu's own master
+
+
+/// Supported magnetometers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MagChip {
+  Hmc5883l,
+  #[default]
+  Qmc5883l,
+  Ak8963, // inside the MPU9250, reached through the mpu's bypass
+}
+
+/// Reasons the magnetometer couldn't be set up or read
+#[derive(Debug)]
+pub enum MagError {
+  Bus(BusError),
+  WrongDevice(MagChip), // whatever answered isn't the configured chip
+  Overflow,             // the field was too strong to measure, eg. a magnet close by
+}
+
+/// A magnetometer measuring continuously
+pub struct Magnetometer {
+  i2c: I2c,
+  chip: MagChip,
+  adjust: [f32; 3], // factory sensitivity adjustment of each axis (AK8963 only)
+}
+
+
+/// MagChip implementations
+impl MagChip {
+  /// The chip's usual address
+  pub fn default_address(self) -> u16 {
+    match self {
+      MagChip::Hmc5883l => HMC5883L_ADDR,
+      MagChip::Qmc5883l => QMC5883L_ADDR,
+      MagChip::Ak8963 => AK8963_ADDR,
+    }
+  }
+}
+
+
+/// Magnetometer implementations
+impl Magnetometer {
+  /// Opens the 'chip' at 'address' on 'bus', checks it's the chip expected and starts it
+  /// measuring continuously
+  pub fn init(chip: MagChip, bus: u8, address: u16) -> Result<Magnetometer, MagError> {
+    let mut i2c = bus::open(bus, address)?;
+    let mut adjust = [1.0; 3];
+    match chip {
+      MagChip::Hmc5883l => {
+        let mut id = [0; 3];
+        bus::read_registers(&i2c, HMC_ID_A, &mut id)?;
+        if id != HMC_ID {
+          return Err(MagError::WrongDevice(chip));
+        }
+        bus::write_register(&mut i2c, HMC_CONFIG_A, HMC_AVERAGE_8_15HZ)?;
+        bus::write_register(&mut i2c, HMC_CONFIG_B, HMC_GAIN_1_3GA)?;
+        bus::write_register(&mut i2c, HMC_MODE, HMC_CONTINUOUS)?;
+      }
+      MagChip::Qmc5883l => {
+        let mut id = [0; 1];
+        bus::read_registers(&i2c, QMC_CHIP_ID, &mut id)?;
+        if id[0] != QMC_ID {
+          return Err(MagError::WrongDevice(chip));
+        }
+        bus::write_register(&mut i2c, QMC_SET_RESET, 0x01)?;
+        bus::write_register(&mut i2c, QMC_CONTROL_1, QMC_CONTINUOUS_8G)?;
+      }
+      MagChip::Ak8963 => {
+        let mut id = [0; 1];
+        bus::read_registers(&i2c, AK_WIA, &mut id)?;
+        if id[0] != AK_ID {
+          return Err(MagError::WrongDevice(chip));
+        }
+        bus::write_register(&mut i2c, AK_CNTL1, AK_POWER_DOWN)?;
+        thread::sleep(AK_MODE_CHANGE);
+        bus::write_register(&mut i2c, AK_CNTL1, AK_FUSE_ROM)?;
+        thread::sleep(AK_MODE_CHANGE);
+        let mut asa = [0; 3];
+        bus::read_registers(&i2c, AK_ASA, &mut asa)?;
+        adjust = asa.map(sensitivity_adjustment);
+        bus::write_register(&mut i2c, AK_CNTL1, AK_POWER_DOWN)?;
+        thread::sleep(AK_MODE_CHANGE);
+        bus::write_register(&mut i2c, AK_CNTL1, AK_CONTINUOUS_16BIT)?;
+      }
+    }
+    Ok(Magnetometer { i2c, chip, adjust })
+  }
+
+  /// Reads the latest field in microtesla, in the axes of the imu it's mounted with (the
+  /// AK8963's axes are swapped into the MPU9250 accelerometer's)
+  pub fn read(&self) -> Result<[f32; 3], MagError> {
+    match self.chip {
+      MagChip::Hmc5883l => {
+        let mut data = [0; 6];
+        bus::read_registers(&self.i2c, HMC_DATA, &mut data)?;
+        let [x, z, y] = [0, 2, 4].map(|i| i16::from_be_bytes([data[i], data[i + 1]]));
+        if [x, y, z].contains(&HMC_OVERFLOW) {
+          return Err(MagError::Overflow);
+        }
+        Ok([x, y, z].map(|v| v as f32 / HMC_LSB_PER_UT))
+      }
+      MagChip::Qmc5883l => {
+        let mut data = [0; 7];
+        bus::read_registers(&self.i2c, QMC_DATA, &mut data)?;
+        if data[QMC_STATUS as usize] & QMC_OVERFLOW != 0 {
+          return Err(MagError::Overflow);
+        }
+        Ok([0, 2, 4].map(|i| i16::from_le_bytes([data[i], data[i + 1]]) as f32 / QMC_LSB_PER_UT))
+      }
+      MagChip::Ak8963 => {
+        let mut data = [0; 7]; // ST2 has to be read for the next measurement to be latched
+        bus::read_registers(&self.i2c, AK_DATA, &mut data)?;
+        if data[6] & AK_OVERFLOW != 0 {
+          return Err(MagError::Overflow);
+        }
+        let [x, y, z] = [0, 1, 2].map(|i| {
+          i16::from_le_bytes([data[2 * i], data[2 * i + 1]]) as f32 * AK_UT_PER_LSB * self.adjust[i]
+        });
+        Ok([y, x, -z])
+      }
+    }
+  }
+
+  pub fn chip(&self) -> MagChip {
+    self.chip
+  }
+}
+
+
+/// Connects the mpu6050/9250's auxiliary i2c bus to the main one, so a magnetometer on
+/// it (the MPU9250's own, or the HMC5883L of a GY-87 board) can be reached directly.
+/// 'imu' is a handle to the mpu itself.
+pub fn enable_bypass(imu: &mut I2c) -> Result<(), MagError> {
+  let mut user_ctrl = [0; 1];
+  bus::read_registers(imu, MPU_USER_CTRL, &mut user_ctrl)?;
+  bus::write_register(imu, MPU_USER_CTRL, user_ctrl[0] & !I2C_MST_EN)?;
+  let mut int_pin_cfg = [0; 1];
+  bus::read_registers(imu, MPU_INT_PIN_CFG, &mut int_pin_cfg)?;
+  bus::write_register(imu, MPU_INT_PIN_CFG, int_pin_cfg[0] | BYPASS_EN)?;
+  Ok(())
+}
+
+
+/// Scale of an AK8963 axis from its factory adjustment register
+fn sensitivity_adjustment(asa: u8) -> f32 {
+  (asa as f32 - 128.0) / 256.0 + 1.0
+}
+
+
+impl From<BusError> for MagError {
+  fn from(e: BusError) -> Self {
+    MagError::Bus(e)
+  }
+}
+
+impl Display for MagError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      MagError::Bus(e) => write!(f, "{e}"),
+      MagError::WrongDevice(chip) => write!(f, "the device answering isn't a {chip:?}"),
+      MagError::Overflow => write!(f, "the magnetic field is too strong to measure"),
+    }
+  }
+}
+
+impl std::error::Error for MagError {}
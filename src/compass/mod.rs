@@ -0,0 +1,3 @@
+pub mod mag;
+pub mod calibration;
+pub mod heading;
@@ -0,0 +1,860 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::fs;
+use std::time::Duration;
+
+use crate::bmp280::baro::BMP280_ADDR;
+use crate::compass::mag::MagChip;
+use crate::detect::clock::{ClockTolerances, MAX_OFFSET_S, SLEW_TOLERANCE_PPM, SLEW_WINDOW_S, STEP_TOLERANCE_S};
+use crate::detect::heading::{HeadingTolerances, HEADING_TOLERANCE_DEG, MIN_SPEED_MPS, TURN_TOLERANCE_DEG, TURN_WINDOW_S};
+use crate::detect::pps::PPS_TOLERANCE_S;
+use crate::detect::stationary::{StationaryTolerances, MAX_ACCEL_STD_MPS2, MAX_GYRO_RMS_DPS, STATIONARY_TOLERANCE_M, STATIONARY_WINDOW_S};
+use crate::detect::verdict::SUSPECT_RATIO;
+use crate::detect::vertical::{VerticalTolerances, MAX_REFERENCE_VDOP, VERTICAL_TOLERANCE_M, VERTICAL_WINDOW_S};
+use crate::ds3231::rtc::DS3231_ADDR;
+use crate::imu::driver::ImuModel;
+use crate::mpu6050::accel::{ACCEL_RANGES, CALIB_CONSISTENT, CALIB_DIFF, CALIB_TIME, GYRO_RANGES, MPU6050_ADDR};
+use crate::mpu6050::calibration::{StalenessPolicy, MAX_AGE_DAYS, MAX_TEMP_DIFF_C};
+use crate::mpu6050::registers::{AccelRange, ClockSource, DlpfBandwidth, GyroRange, ImuSettings, DLPF_BANDWIDTHS_HZ, SAMPLE_RATE_DIVIDER};
+use crate::neo6m::gps::UPDATE_RATE;
+use crate::output::mqtt::{MqttConfig, MqttTls, MqttTopics};
+
+pub const ENV_PREFIX: &str = "GPS_SPOOFING__"; // eg. GPS_SPOOFING__GPS__BAUD_RATE=115200 overrides gps.baud_rate
+const ENV_SEPARATOR: &str = "__"; // Separates sections in an environment override
+const BAUD_RATES: [u32; 7] = [4800, 9600, 14400, 19200, 38400, 57600, 115200]; // Rates the Neo-6M supports
+const MAX_BCM_PIN: u8 = 27; // Highest gpio pin on the Pi's header
+const I2C_CLOCK_HZ: u32 = 100_000; // The Pi's default i2c clock
+const SAMPLE_BUS_BITS: f32 = 160.0; // Bits on the bus per imu sample, 14 bytes and the addressing at 9 bits a byte
+const MAX_BUS_SHARE: f32 = 0.5; // Share of the bus the imu samples may take, leaving room for the other sensors
+
+
+/// Typed configuration, loaded from a TOML file. Every value has a default,
+/// so an empty file (or no file) gives the same behavior as the built in constants.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  pub gps: GpsConfig,
+  pub imu: ImuConfig,
+  pub detector: DetectorConfig,
+  pub output: OutputConfig,
+  pub rtc: Option<RtcConfig>, // a DS3231 real time clock, if fitted
+  pub baro: Option<BaroConfig>, // a BMP280 or BME280 barometer, if fitted
+  pub mag: Option<MagConfig>,   // a magnetometer, if fitted
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpsConfig {
+  pub port: String,
+  pub baud_rate: u32,
+  pub update_rate_ms: u32,
+  pub fix_timeout_s: u64,
+  pub pps: Option<PpsSection>, // the receiver's pulse per second output, if wired
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PpsSection {
+  pub source: PpsSource,
+  pub pin: u8,           // BCM numbering, for a gpio source
+  pub device: String,    // for a kernel source
+  pub tolerance_ms: f64, // disagreement allowed between the pps and the reported utc time
+}
+
+/// The clock the gps time is compared with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockReference {
+  #[default]
+  System, // the system clock, kept by NTP
+  Rtc,    // the DS3231 in the [rtc] section, a holdover clock that doesn't need a network
+}
+
+/// Where pps edges are read from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PpsSource {
+  #[default]
+  Gpio,   // the PPS line on a gpio, timed by this program
+  Kernel, // a Linux pps device (eg. from the pps-gpio overlay), timed by the kernel
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImuConfig {
+  pub model: ImuModel,          // "auto" probes the bus for a supported imu, starting at the address
+  pub i2c_bus: u8,
+  pub i2c_clock_hz: u32,        // the bus clock, set by the os (dtparam=i2c_arm_baudrate on a Pi)
+  pub address: u16,
+  pub accel_range_g: u8,
+  pub gyro_range_dps: u16,
+  pub dlpf_hz: u16,             // low pass filter bandwidth
+  pub sample_rate_divider: u8,
+  pub clock_source: ClockSource,
+  pub acquisition: Acquisition,
+  pub int_pin: u8, // gpio (BCM) the INT line is wired to, for interrupt acquisition
+  pub samples_per_prediction: u32,
+  pub calib_time_ms: u64,
+  pub calib_diff: i16,
+  pub calib_consistent: u8,
+  pub calibration_file: Option<String>, // saved calibration, calibrates at every start up if empty
+  pub calibration_max_age_days: u32,
+  pub calibration_max_temp_diff_c: f32,
+  pub device_id: Option<String>, // identifies the sensor in saved calibrations, from the bus and address if not set
+}
+
+/// How imu samples are read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Acquisition {
+  #[default]
+  Poll,      // read the output registers in a loop, timed by the host clock
+  Fifo,      // read the fifo in bursts, timed from the sample rate
+  Interrupt, // read each sample on its data ready interrupt, timed at the interrupt
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RtcConfig {
+  pub i2c_bus: u8,
+  pub address: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BaroConfig {
+  pub i2c_bus: u8,
+  pub address: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MagConfig {
+  pub chip: MagChip,
+  pub i2c_bus: u8,
+  pub address: Option<u16>,     // the chip's usual address if not set
+  pub bypass: bool,             // reach it through the imu's auxiliary bus (MPU9250, GY-87 boards)
+  pub calibration_file: String, // hard and soft iron calibration, written by `compass --calibrate`
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetectorConfig {
+  pub gps_accuracy_m: f32, // gps accuracy at an hdop of 1
+  pub suspect_ratio: f32,  // fraction of a threshold at which a fix becomes suspect
+  pub clock: Option<ClockSection>, // compares the gps time with the host clock, if it's disciplined
+  pub vertical: Option<VerticalSection>, // compares the gps altitude with the barometer's
+  pub heading: Option<HeadingSection>,   // compares the gps course with the magnetometer's heading
+  pub stationary: Option<StationarySection>, // holds the fixes still and the velocity at zero while the imu is still
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockSection {
+  pub reference: ClockReference,
+  pub max_offset_ms: f64,      // largest offset between the gps and host clocks
+  pub step_tolerance_ms: f64,  // largest change in the offset from one fix to the next
+  pub slew_tolerance_ppm: f64, // largest steady drift of the offset
+  pub slew_window_s: f64,      // time the drift is fitted over
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerticalSection {
+  pub window_s: f64,    // time the gps and barometric climbs are compared over
+  pub tolerance_m: f32, // difference between the climbs allowed over the window
+  pub max_vdop: f32,    // largest vdop of a fix the barometer is referenced at
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadingSection {
+  pub declination_deg: f32,    // local magnetic declination, east positive
+  pub tolerance_deg: f32,      // difference allowed between the course and the heading
+  pub turn_tolerance_deg: f32, // difference allowed between their turns over the window
+  pub window_s: f64,           // time the turns are compared over
+  pub min_speed_mps: f32,      // speed below which the course isn't compared
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StationarySection {
+  pub window_s: f64,           // time the imu has to be still for
+  pub max_accel_std_mps2: f32, // largest standard deviation of the acceleration's magnitude while still
+  pub max_gyro_rms_dps: f32,   // largest rms rotation rate while still
+  pub tolerance_m: f32,        // distance the fixes may wander while still, at an hdop of 1
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+  pub event_log: Option<String>, // file path, stdout if not set
+  pub event_log_max_bytes: u64,
+  pub event_log_files: u32,
+  pub gpsd: Option<String>,    // address to serve the gpsd protocol on
+  pub metrics: Option<String>, // address to serve prometheus metrics on
+  pub mqtt: Option<MqttSection>,
+  pub alarm: Option<AlarmSection>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttSection {
+  pub host: String,
+  pub port: u16,
+  pub client_id: String,
+  pub topic_prefix: Option<String>, // gps_spoofing/<client_id> if not set
+  pub qos: u8,
+  pub username: Option<String>,
+  pub password: Option<String>,
+  pub ca_path: Option<String>, // enables tls
+  pub client_cert_path: Option<String>,
+  pub client_key_path: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlarmSection {
+  pub pin: u8, // BCM numbering
+  pub active_low: bool,
+  pub ack_pin: u8,
+}
+
+/// Reasons a configuration couldn't be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+  Read(String, std::io::Error),
+  Parse(String),
+  Override(String, String), // (key, message)
+  Invalid(Vec<String>),
+}
+
+
+/// Config implementations
+impl Config {
+  /// Loads a config file (or the defaults if 'path' is None), applies environment
+  /// overrides, then command line overrides ("section.key=value"), and validates the result
+  pub fn load(path: Option<&str>, cli_overrides: &[String]) -> Result<Config, ConfigError> {
+    let text = match path {
+      Some(path) => fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_string(), e))?,
+      None => String::new(),
+    };
+    let mut table: toml::Table = toml::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+
+    for (var, value) in std::env::vars() {
+      if let Some(key) = var.strip_prefix(ENV_PREFIX) {
+        let key = key.to_lowercase().replace(ENV_SEPARATOR, ".");
+        set_key(&mut table, &key, &value)?;
+      }
+    }
+    for setting in cli_overrides {
+      let (key, value) = setting.split_once('=')
+        .ok_or_else(|| ConfigError::Override(setting.clone(), "expected key=value".to_string()))?;
+      set_key(&mut table, key.trim(), value.trim())?;
+    }
+
+    let config: Config = toml::Value::Table(table).try_into().map_err(|e: toml::de::Error| ConfigError::Parse(e.to_string()))?;
+    config.validate()?;
+    Ok(config)
+  }
+
+  /// Parses and validates a config from TOML text, without any overrides
+  pub fn from_toml(text: &str) -> Result<Config, ConfigError> {
+    let config: Config = toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+    config.validate()?;
+    Ok(config)
+  }
+
+  /// Checks every value is usable, collecting all problems rather than stopping at the first
+  pub fn validate(&self) -> Result<(), ConfigError> {
+    let mut errors = Vec::new();
+    let mut check = |ok: bool, message: String| if !ok { errors.push(message) };
+
+    check(!self.gps.port.is_empty(), "gps.port must not be empty".to_string());
+    check(BAUD_RATES.contains(&self.gps.baud_rate),
+          format!("gps.baud_rate must be one of {:?} (got {})", BAUD_RATES, self.gps.baud_rate));
+    check((100..=10000).contains(&self.gps.update_rate_ms),
+          format!("gps.update_rate_ms must be between 100 and 10000 (got {})", self.gps.update_rate_ms));
+    check(self.gps.fix_timeout_s > 0, "gps.fix_timeout_s must be greater than 0".to_string());
+    if let Some(pps) = &self.gps.pps {
+      check(pps.pin <= MAX_BCM_PIN, format!("gps.pps.pin must be at most {MAX_BCM_PIN} (got {})", pps.pin));
+      check(!(pps.source == PpsSource::Gpio && self.imu.acquisition == Acquisition::Interrupt && pps.pin == self.imu.int_pin),
+            "gps.pps.pin and imu.int_pin must be different pins".to_string());
+      check(!pps.device.is_empty(), "gps.pps.device must not be empty".to_string());
+      check(pps.tolerance_ms > 0.0, format!("gps.pps.tolerance_ms must be greater than 0 (got {})", pps.tolerance_ms));
+    }
+
+    check((0x08..=0x77).contains(&self.imu.address),
+          format!("imu.address must be a 7-bit i2c address between 0x08 and 0x77 (got {:#04x})", self.imu.address));
+    check(ACCEL_RANGES.contains(&self.imu.accel_range_g),
+          format!("imu.accel_range_g must be one of {:?} (got {})", ACCEL_RANGES, self.imu.accel_range_g));
+    check(GYRO_RANGES.contains(&self.imu.gyro_range_dps),
+          format!("imu.gyro_range_dps must be one of {:?} (got {})", GYRO_RANGES, self.imu.gyro_range_dps));
+    check(DLPF_BANDWIDTHS_HZ.contains(&self.imu.dlpf_hz),
+          format!("imu.dlpf_hz must be one of {:?} (got {})", DLPF_BANDWIDTHS_HZ, self.imu.dlpf_hz));
+    check(self.imu.acquisition == Acquisition::Poll || matches!(self.imu.model, ImuModel::Mpu6050 | ImuModel::Auto),
+          "imu.acquisition = \"fifo\" or \"interrupt\" needs an mpu6050, the other imus are polled".to_string());
+    let sample_rate_hz = self.imu.settings().sample_rate_hz();
+    check(sample_rate_hz * SAMPLE_BUS_BITS <= self.imu.i2c_clock_hz as f32 * MAX_BUS_SHARE,
+          format!("imu sample rate of {sample_rate_hz} Hz is more than the {} Hz i2c bus can carry, raise imu.sample_rate_divider \
+                   (or set imu.i2c_clock_hz if the bus is faster)", self.imu.i2c_clock_hz));
+    check(self.imu.int_pin <= MAX_BCM_PIN, format!("imu.int_pin must be at most {MAX_BCM_PIN} (got {})", self.imu.int_pin));
+    check(self.imu.samples_per_prediction > 0, "imu.samples_per_prediction must be greater than 0".to_string());
+    check(self.imu.calib_diff > 0, format!("imu.calib_diff must be greater than 0 (got {})", self.imu.calib_diff));
+    check(self.imu.calib_consistent > 0,
+          format!("imu.calib_consistent must be greater than 0 (got {})", self.imu.calib_consistent));
+    check(self.imu.calibration_max_age_days > 0, "imu.calibration_max_age_days must be greater than 0".to_string());
+    check(self.imu.calibration_max_temp_diff_c > 0.0,
+          format!("imu.calibration_max_temp_diff_c must be greater than 0 (got {})", self.imu.calibration_max_temp_diff_c));
+
+    check(self.detector.gps_accuracy_m > 0.0,
+          format!("detector.gps_accuracy_m must be greater than 0 (got {})", self.detector.gps_accuracy_m));
+    check(self.detector.suspect_ratio > 0.0 && self.detector.suspect_ratio <= 1.0,
+          format!("detector.suspect_ratio must be in (0, 1] (got {})", self.detector.suspect_ratio));
+    if let Some(clock) = &self.detector.clock {
+      check(clock.reference != ClockReference::Rtc || self.rtc.is_some(),
+            "detector.clock.reference = \"rtc\" needs an [rtc] section".to_string());
+      check(clock.max_offset_ms > 0.0, format!("detector.clock.max_offset_ms must be greater than 0 (got {})", clock.max_offset_ms));
+      check(clock.step_tolerance_ms > 0.0,
+            format!("detector.clock.step_tolerance_ms must be greater than 0 (got {})", clock.step_tolerance_ms));
+      check(clock.slew_tolerance_ppm > 0.0,
+            format!("detector.clock.slew_tolerance_ppm must be greater than 0 (got {})", clock.slew_tolerance_ppm));
+      check(clock.slew_window_s > 0.0, format!("detector.clock.slew_window_s must be greater than 0 (got {})", clock.slew_window_s));
+    }
+    if let Some(rtc) = &self.rtc {
+      check((0x08..=0x77).contains(&rtc.address),
+            format!("rtc.address must be a 7-bit i2c address between 0x08 and 0x77 (got {:#04x})", rtc.address));
+      check(rtc.i2c_bus != self.imu.i2c_bus || rtc.address != self.imu.address,
+            format!("rtc.address and imu.address are both {:#04x} on i2c-{} (wire the mpu6050's AD0 high and set imu.address = 0x69)",
+                    rtc.address, rtc.i2c_bus));
+    }
+    if let Some(vertical) = &self.detector.vertical {
+      check(self.baro.is_some(), "[detector.vertical] needs a [baro] section".to_string());
+      check(vertical.window_s > 0.0, format!("detector.vertical.window_s must be greater than 0 (got {})", vertical.window_s));
+      check(vertical.tolerance_m > 0.0, format!("detector.vertical.tolerance_m must be greater than 0 (got {})", vertical.tolerance_m));
+      check(vertical.max_vdop > 0.0, format!("detector.vertical.max_vdop must be greater than 0 (got {})", vertical.max_vdop));
+    }
+    if let Some(baro) = &self.baro {
+      check((0x08..=0x77).contains(&baro.address),
+            format!("baro.address must be a 7-bit i2c address between 0x08 and 0x77 (got {:#04x})", baro.address));
+      let rtc_clash = self.rtc.as_ref().is_some_and(|rtc| rtc.i2c_bus == baro.i2c_bus && rtc.address == baro.address);
+      let imu_clash = self.imu.i2c_bus == baro.i2c_bus && self.imu.address == baro.address;
+      check(!rtc_clash && !imu_clash,
+            format!("baro.address {:#04x} is already used on i2c-{} by another sensor", baro.address, baro.i2c_bus));
+    }
+    if let Some(heading) = &self.detector.heading {
+      check(self.mag.is_some(), "[detector.heading] needs a [mag] section".to_string());
+      check((-180.0..=180.0).contains(&heading.declination_deg),
+            format!("detector.heading.declination_deg must be between -180 and 180 (got {})", heading.declination_deg));
+      check(heading.tolerance_deg > 0.0, format!("detector.heading.tolerance_deg must be greater than 0 (got {})", heading.tolerance_deg));
+      check(heading.turn_tolerance_deg > 0.0,
+            format!("detector.heading.turn_tolerance_deg must be greater than 0 (got {})", heading.turn_tolerance_deg));
+      check(heading.window_s > 0.0, format!("detector.heading.window_s must be greater than 0 (got {})", heading.window_s));
+      check(heading.min_speed_mps >= 0.0,
+            format!("detector.heading.min_speed_mps must not be negative (got {})", heading.min_speed_mps));
+    }
+    if let Some(stationary) = &self.detector.stationary {
+      check(stationary.window_s > 0.0, format!("detector.stationary.window_s must be greater than 0 (got {})", stationary.window_s));
+      check(stationary.max_accel_std_mps2 > 0.0,
+            format!("detector.stationary.max_accel_std_mps2 must be greater than 0 (got {})", stationary.max_accel_std_mps2));
+      check(stationary.max_gyro_rms_dps > 0.0,
+            format!("detector.stationary.max_gyro_rms_dps must be greater than 0 (got {})", stationary.max_gyro_rms_dps));
+      check(stationary.tolerance_m > 0.0,
+            format!("detector.stationary.tolerance_m must be greater than 0 (got {})", stationary.tolerance_m));
+    }
+    if let Some(mag) = &self.mag {
+      let address = mag.address();
+      check((0x08..=0x77).contains(&address),
+            format!("mag.address must be a 7-bit i2c address between 0x08 and 0x77 (got {address:#04x})"));
+      check(mag.chip != MagChip::Ak8963 || mag.bypass, "mag.chip = \"ak8963\" needs mag.bypass = true".to_string());
+      let taken = [Some((self.imu.i2c_bus, self.imu.address)),
+                   self.rtc.as_ref().map(|rtc| (rtc.i2c_bus, rtc.address)),
+                   self.baro.as_ref().map(|baro| (baro.i2c_bus, baro.address))];
+      check(!taken.contains(&Some((mag.i2c_bus, address))),
+            format!("mag.address {address:#04x} is already used on i2c-{} by another sensor", mag.i2c_bus));
+      check(!mag.calibration_file.is_empty(), "mag.calibration_file must not be empty".to_string());
+    }
+
+    check(self.output.event_log_max_bytes > 0, "output.event_log_max_bytes must be greater than 0".to_string());
+    if let Some(mqtt) = &self.output.mqtt {
+      check(!mqtt.host.is_empty(), "output.mqtt.host must not be empty".to_string());
+      check(mqtt.port > 0, "output.mqtt.port must be greater than 0".to_string());
+      check(mqtt.qos <= 2, format!("output.mqtt.qos must be 0, 1 or 2 (got {})", mqtt.qos));
+      check(mqtt.client_cert_path.is_some() == mqtt.client_key_path.is_some(),
+            "output.mqtt.client_cert_path and client_key_path must be set together".to_string());
+      check(mqtt.client_cert_path.is_none() || mqtt.ca_path.is_some(),
+            "output.mqtt.ca_path is required for client certificates".to_string());
+    }
+    if let Some(alarm) = &self.output.alarm {
+      check(alarm.pin <= MAX_BCM_PIN, format!("output.alarm.pin must be at most {MAX_BCM_PIN} (got {})", alarm.pin));
+      check(alarm.ack_pin <= MAX_BCM_PIN, format!("output.alarm.ack_pin must be at most {MAX_BCM_PIN} (got {})", alarm.ack_pin));
+      check(alarm.pin != alarm.ack_pin, "output.alarm.pin and ack_pin must be different pins".to_string());
+    }
+
+    if errors.is_empty() {
+      Ok(())
+    } else {
+      Err(ConfigError::Invalid(errors))
+    }
+  }
+}
+
+
+/// GpsConfig implementations
+impl GpsConfig {
+  pub fn fix_timeout(&self) -> Duration {
+    Duration::from_secs(self.fix_timeout_s)
+  }
+}
+
+impl Default for GpsConfig {
+  fn default() -> Self {
+    GpsConfig {
+      port: "/dev/ttyS0".to_string(),
+      baud_rate: 9600,
+      update_rate_ms: UPDATE_RATE,
+      fix_timeout_s: 60,
+      pps: None,
+    }
+  }
+}
+
+
+/// PpsSection implementations
+impl PpsSection {
+  pub fn tolerance_s(&self) -> f64 {
+    self.tolerance_ms / 1000.0
+  }
+}
+
+impl Default for PpsSection {
+  fn default() -> Self {
+    PpsSection {
+      source: PpsSource::Gpio,
+      pin: 18,
+      device: "/dev/pps0".to_string(),
+      tolerance_ms: PPS_TOLERANCE_S * 1000.0,
+    }
+  }
+}
+
+
+/// ImuConfig implementations
+impl ImuConfig {
+  pub fn calib_time(&self) -> Duration {
+    Duration::from_millis(self.calib_time_ms)
+  }
+
+  /// Register settings for the mpu6050 (the values are validated on load)
+  pub fn settings(&self) -> ImuSettings {
+    ImuSettings {
+      accel_range: AccelRange::from_g(self.accel_range_g).unwrap_or_default(),
+      gyro_range: GyroRange::from_dps(self.gyro_range_dps).unwrap_or_default(),
+      dlpf: DlpfBandwidth::from_hz(self.dlpf_hz).unwrap_or_default(),
+      sample_rate_divider: self.sample_rate_divider,
+      clock_source: self.clock_source,
+    }
+  }
+
+  /// Where the calibration is saved, or None to calibrate at every start up
+  pub fn calibration_file(&self) -> Option<&str> {
+    self.calibration_file.as_deref().filter(|path| !path.is_empty())
+  }
+
+  /// When a saved calibration should be redone
+  pub fn staleness_policy(&self) -> StalenessPolicy {
+    StalenessPolicy {
+      max_age: Duration::from_secs(self.calibration_max_age_days as u64 * 86400),
+      max_temp_diff_c: self.calibration_max_temp_diff_c,
+    }
+  }
+}
+
+impl Default for ImuConfig {
+  fn default() -> Self {
+    ImuConfig {
+      model: ImuModel::Mpu6050,
+      i2c_bus: 1,
+      i2c_clock_hz: I2C_CLOCK_HZ,
+      address: MPU6050_ADDR,
+      accel_range_g: 2,
+      gyro_range_dps: 2000,
+      dlpf_hz: 10,
+      sample_rate_divider: SAMPLE_RATE_DIVIDER,
+      clock_source: ClockSource::Internal,
+      acquisition: Acquisition::Poll,
+      int_pin: 17,
+      samples_per_prediction: 500,
+      calib_time_ms: CALIB_TIME,
+      calib_diff: CALIB_DIFF,
+      calib_consistent: CALIB_CONSISTENT,
+      calibration_file: Some("imu_calibration.json".to_string()),
+      calibration_max_age_days: MAX_AGE_DAYS,
+      calibration_max_temp_diff_c: MAX_TEMP_DIFF_C,
+      device_id: None,
+    }
+  }
+}
+
+
+impl Default for DetectorConfig {
+  fn default() -> Self {
+    DetectorConfig {
+      gps_accuracy_m: 10.0,
+      suspect_ratio: SUSPECT_RATIO,
+      clock: None,
+      vertical: None,
+      heading: None,
+      stationary: None,
+    }
+  }
+}
+
+/// ClockSection implementations
+impl ClockSection {
+  pub fn tolerances(&self) -> ClockTolerances {
+    ClockTolerances {
+      max_offset_s: self.max_offset_ms / 1000.0,
+      step_s: self.step_tolerance_ms / 1000.0,
+      slew_ppm: self.slew_tolerance_ppm,
+      slew_window_s: self.slew_window_s,
+    }
+  }
+}
+
+impl Default for ClockSection {
+  fn default() -> Self {
+    ClockSection {
+      reference: ClockReference::System,
+      max_offset_ms: MAX_OFFSET_S * 1000.0,
+      step_tolerance_ms: STEP_TOLERANCE_S * 1000.0,
+      slew_tolerance_ppm: SLEW_TOLERANCE_PPM,
+      slew_window_s: SLEW_WINDOW_S,
+    }
+  }
+}
+
+/// VerticalSection implementations
+impl VerticalSection {
+  pub fn tolerances(&self) -> VerticalTolerances {
+    VerticalTolerances { window_s: self.window_s, tolerance_m: self.tolerance_m, max_vdop: self.max_vdop }
+  }
+}
+
+impl Default for VerticalSection {
+  fn default() -> Self {
+    VerticalSection { window_s: VERTICAL_WINDOW_S, tolerance_m: VERTICAL_TOLERANCE_M, max_vdop: MAX_REFERENCE_VDOP }
+  }
+}
+
+/// HeadingSection implementations
+impl HeadingSection {
+  pub fn tolerances(&self) -> HeadingTolerances {
+    HeadingTolerances {
+      tolerance_deg: self.tolerance_deg,
+      turn_tolerance_deg: self.turn_tolerance_deg,
+      window_s: self.window_s,
+      min_speed_mps: self.min_speed_mps,
+    }
+  }
+}
+
+impl Default for HeadingSection {
+  fn default() -> Self {
+    HeadingSection {
+      declination_deg: 0.0,
+      tolerance_deg: HEADING_TOLERANCE_DEG,
+      turn_tolerance_deg: TURN_TOLERANCE_DEG,
+      window_s: TURN_WINDOW_S,
+      min_speed_mps: MIN_SPEED_MPS,
+    }
+  }
+}
+
+/// StationarySection implementations
+impl StationarySection {
+  pub fn tolerances(&self) -> StationaryTolerances {
+    StationaryTolerances {
+      window_s: self.window_s,
+      max_accel_std: self.max_accel_std_mps2,
+      max_gyro_rms: self.max_gyro_rms_dps.to_radians(),
+      tolerance_m: self.tolerance_m,
+    }
+  }
+}
+
+impl Default for StationarySection {
+  fn default() -> Self {
+    StationarySection {
+      window_s: STATIONARY_WINDOW_S,
+      max_accel_std_mps2: MAX_ACCEL_STD_MPS2,
+      max_gyro_rms_dps: MAX_GYRO_RMS_DPS,
+      tolerance_m: STATIONARY_TOLERANCE_M,
+    }
+  }
+}
+
+impl Default for OutputConfig {
+  fn default() -> Self {
+    OutputConfig {
+      event_log: None,
+      event_log_max_bytes: 10_000_000,
+      event_log_files: 5,
+      gpsd: Some("127.0.0.1:2947".to_string()),
+      metrics: None,
+      mqtt: None,
+      alarm: None,
+    }
+  }
+}
+
+impl Default for RtcConfig {
+  fn default() -> Self {
+    RtcConfig { i2c_bus: 1, address: DS3231_ADDR }
+  }
+}
+
+impl Default for BaroConfig {
+  fn default() -> Self {
+    BaroConfig { i2c_bus: 1, address: BMP280_ADDR }
+  }
+}
+
+/// MagConfig implementations
+impl MagConfig {
+  pub fn address(&self) -> u16 {
+    self.address.unwrap_or(self.chip.default_address())
+  }
+}
+
+impl Default for MagConfig {
+  fn default() -> Self {
+    MagConfig {
+      chip: MagChip::default(),
+      i2c_bus: 1,
+      address: None,
+      bypass: false,
+      calibration_file: "mag_calibration.json".to_string(),
+    }
+  }
+}
+
+/// MqttSection implementations
+impl MqttSection {
+  /// Converts the section into the publisher's settings
+  pub fn mqtt_config(&self) -> MqttConfig {
+    let mut config = MqttConfig::new(&self.host, self.port, &self.client_id);
+    config.qos = self.qos;
+    config.username = self.username.clone();
+    config.password = self.password.clone();
+    if let Some(prefix) = &self.topic_prefix {
+      config.topics = MqttTopics::with_prefix(prefix);
+    }
+    config.tls = self.ca_path.as_ref().map(|ca_path| MqttTls {
+      ca_path: ca_path.clone(),
+      client_cert_path: self.client_cert_path.clone(),
+      client_key_path: self.client_key_path.clone(),
+    });
+    config
+  }
+}
+
+impl Default for MqttSection {
+  fn default() -> Self {
+    MqttSection {
+      host: "localhost".to_string(),
+      port: 1883,
+      client_id: "gps_spoofing_detection".to_string(),
+      topic_prefix: None,
+      qos: 1,
+      username: None,
+      password: None,
+      ca_path: None,
+      client_cert_path: None,
+      client_key_path: None,
+    }
+  }
+}
+
+impl Default for AlarmSection {
+  fn default() -> Self {
+    AlarmSection { pin: 17, active_low: false, ack_pin: 27 }
+  }
+}
+
+
+impl Display for ConfigError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ConfigError::Read(path, e) => write!(f, "could not read config file {path}: {e}"),
+      ConfigError::Parse(e) => write!(f, "invalid config: {e}"),
+      ConfigError::Override(key, message) => write!(f, "invalid override {key}: {message}"),
+      ConfigError::Invalid(errors) => write!(f, "invalid config:\n  {}", errors.join("\n  ")),
+    }
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+
+/// Sets a dotted key (eg. "output.mqtt.host") in a TOML table, creating missing sections.
+/// Values are read as TOML (so numbers and booleans keep their type), or as a string otherwise.
+fn set_key(table: &mut toml::Table, key: &str, raw: &str) -> Result<(), ConfigError> {
+  let parts: Vec<&str> = key.split('.').collect();
+  if parts.iter().any(|p| p.is_empty()) {
+    return Err(ConfigError::Override(key.to_string(), "keys look like section.name".to_string()));
+  }
+  let value = toml::from_str::<toml::Table>(&format!("value = {raw}"))
+    .ok()
+    .and_then(|mut t| t.remove("value"))
+    .unwrap_or_else(|| toml::Value::String(raw.to_string()));
+
+  let (last, sections) = parts.split_last().expect("split always yields a part");
+  let mut current = table;
+  for section in sections {
+    let entry = current.entry(section.to_string()).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    current = entry.as_table_mut()
+      .ok_or_else(|| ConfigError::Override(key.to_string(), format!("{section} is not a section")))?;
+  }
+  current.insert(last.to_string(), value);
+  Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_defaults_are_valid() {
+    let config = Config::from_toml("").unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.imu.address, 0x68);
+  }
+
+  #[test]
+  fn test_parse_sections() {
+    let config = Config::from_toml(r#"
+      [gps]
+      port = "/dev/ttyAMA0"
+      baud_rate = 115200
+
+      [output.mqtt]
+      host = "broker.local"
+      qos = 2
+    "#).unwrap();
+    assert_eq!(config.gps.port, "/dev/ttyAMA0");
+    assert_eq!(config.gps.update_rate_ms, UPDATE_RATE); // untouched values keep their defaults
+    assert_eq!(config.output.mqtt.unwrap().port, 1883);
+  }
+
+  #[test]
+  fn test_validation_lists_every_error() {
+    let err = Config::from_toml("[imu]\naccel_range_g = 3\ngyro_range_dps = 10\ncalib_consistent = 0\n").unwrap_err();
+    let ConfigError::Invalid(errors) = err else { panic!("expected validation errors") };
+    assert_eq!(errors.len(), 3);
+    assert!(errors[0].starts_with("imu.accel_range_g must be one of"));
+  }
+
+  #[test]
+  fn test_imu_settings() {
+    let config = Config::from_toml("[imu]\naccel_range_g = 8\ndlpf_hz = 44\nclock_source = \"pll_gyro_x\"\nacquisition = \"fifo\"\n").unwrap();
+    assert_eq!(config.imu.acquisition, Acquisition::Fifo);
+    let settings = config.imu.settings();
+    assert_eq!(settings.accel_range, AccelRange::G8);
+    assert_eq!(settings.dlpf, DlpfBandwidth::Hz44);
+    assert_eq!(settings.clock_source, ClockSource::PllGyroX);
+    assert_eq!(Config::default().imu.settings(), ImuSettings::default());
+
+    assert!(matches!(Config::from_toml("[imu]\ndlpf_hz = 50\n"), Err(ConfigError::Invalid(_))));
+    assert!(matches!(Config::from_toml("[imu]\nacquisition = \"interrupt\"\nint_pin = 40\n"), Err(ConfigError::Invalid(_))));
+    assert!(matches!(Config::from_toml("[imu]\nclock_source = \"crystal\"\n"), Err(ConfigError::Parse(_))));
+
+    let config = Config::from_toml("[imu]\nmodel = \"lsm6ds3\"\naddress = 0x6a\n").unwrap();
+    assert_eq!(config.imu.model, ImuModel::Lsm6ds3);
+    assert!(matches!(Config::from_toml("[imu]\nmodel = \"bmi160\"\nacquisition = \"fifo\"\n"), Err(ConfigError::Invalid(_))));
+    assert!(Config::from_toml("[imu]\nmodel = \"auto\"\nacquisition = \"fifo\"\n").is_ok());
+
+    assert!(matches!(Config::from_toml("[imu]\nsample_rate_divider = 0\n"), Err(ConfigError::Invalid(_)))); // 1 kHz on 100 kHz
+    assert!(Config::from_toml("[imu]\nsample_rate_divider = 0\ni2c_clock_hz = 400000\n").is_ok());
+  }
+
+  #[test]
+  fn test_pps_section() {
+    let config = Config::from_toml("[gps.pps]\nsource = \"kernel\"\ntolerance_ms = 5\n").unwrap();
+    let pps = config.gps.pps.unwrap();
+    assert_eq!(pps.source, PpsSource::Kernel);
+    assert_eq!(pps.device, "/dev/pps0");
+    assert_eq!(pps.tolerance_s(), 0.005);
+    assert!(Config::default().gps.pps.is_none());
+
+    let clash = "[gps.pps]\npin = 17\n[imu]\nacquisition = \"interrupt\"\nint_pin = 17\n";
+    assert!(matches!(Config::from_toml(clash), Err(ConfigError::Invalid(_))));
+  }
+
+  #[test]
+  fn test_clock_section() {
+    let config = Config::from_toml("[detector.clock]\nstep_tolerance_ms = 50\n").unwrap();
+    let tolerances = config.detector.clock.unwrap().tolerances();
+    assert_eq!(tolerances.step_s, 0.05);
+    assert_eq!(tolerances.max_offset_s, MAX_OFFSET_S);
+    assert!(matches!(Config::from_toml("[detector.clock]\nslew_window_s = 0\n"), Err(ConfigError::Invalid(_))));
+  }
+
+  #[test]
+  fn test_rtc_section() {
+    let config = Config::from_toml("[rtc]\n[imu]\naddress = 0x69\n[detector.clock]\nreference = \"rtc\"\n").unwrap();
+    assert_eq!(config.rtc.unwrap().address, 0x68);
+    assert!(matches!(Config::from_toml("[rtc]\n"), Err(ConfigError::Invalid(_)))); // clashes with the mpu6050
+    assert!(matches!(Config::from_toml("[detector.clock]\nreference = \"rtc\"\n"), Err(ConfigError::Invalid(_))));
+  }
+
+  #[test]
+  fn test_vertical_section() {
+    let config = Config::from_toml("[baro]\naddress = 0x77\n[detector.vertical]\ntolerance_m = 30\n").unwrap();
+    assert_eq!(config.baro.unwrap().address, 0x77);
+    let tolerances = config.detector.vertical.unwrap().tolerances();
+    assert_eq!(tolerances.tolerance_m, 30.0);
+    assert_eq!(tolerances.window_s, VERTICAL_WINDOW_S);
+    assert!(matches!(Config::from_toml("[detector.vertical]\n"), Err(ConfigError::Invalid(_)))); // no barometer
+    assert!(matches!(Config::from_toml("[baro]\naddress = 0x68\n"), Err(ConfigError::Invalid(_)))); // clashes with the mpu6050
+  }
+
+  #[test]
+  fn test_heading_section() {
+    let config = Config::from_toml("[mag]\nchip = \"hmc5883l\"\n[detector.heading]\ndeclination_deg = -3.5\n").unwrap();
+    assert_eq!(config.mag.unwrap().address(), 0x1E);
+    let heading = config.detector.heading.unwrap();
+    assert_eq!(heading.declination_deg, -3.5);
+    assert_eq!(heading.tolerances().window_s, TURN_WINDOW_S);
+    assert!(matches!(Config::from_toml("[detector.heading]\n"), Err(ConfigError::Invalid(_)))); // no magnetometer
+    assert!(matches!(Config::from_toml("[mag]\nchip = \"ak8963\"\n"), Err(ConfigError::Invalid(_)))); // needs the bypass
+  }
+
+  #[test]
+  fn test_stationary_section() {
+    let config = Config::from_toml("[detector.stationary]\nmax_gyro_rms_dps = 1.0\n").unwrap();
+    let tolerances = config.detector.stationary.unwrap().tolerances();
+    assert!((tolerances.max_gyro_rms - 1.0f32.to_radians()).abs() < 1e-6);
+    assert_eq!(tolerances.window_s, STATIONARY_WINDOW_S);
+    assert!(matches!(Config::from_toml("[detector.stationary]\nwindow_s = 0\n"), Err(ConfigError::Invalid(_))));
+  }
+
+  #[test]
+  fn test_unknown_key_rejected() {
+    assert!(matches!(Config::from_toml("[gps]\nbaud = 9600\n"), Err(ConfigError::Parse(_))));
+  }
+
+  #[test]
+  fn test_overrides() {
+    let overrides = vec!["gps.baud_rate=38400".to_string(), "output.alarm.pin=5".to_string(), "gps.port=/dev/ttyUSB0".to_string()];
+    let config = Config::load(None, &overrides).unwrap();
+    assert_eq!(config.gps.baud_rate, 38400);
+    assert_eq!(config.gps.port, "/dev/ttyUSB0");
+    assert_eq!(config.output.alarm.unwrap().pin, 5);
+
+    let bad = vec!["gps.baud_rate".to_string()];
+    assert!(matches!(Config::load(None, &bad), Err(ConfigError::Override(_, _))));
+  }
+}
@@ -0,0 +1,207 @@
+use rppal::i2c::I2c;
+use std::fmt::Display;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bus::i2c::{self as bus, BusError};
+use crate::timing::calendar::{civil_from_days, days_from_civil};
+
+pub const DS3231_ADDR: u16 = 0x68; // I2C address of the DS3231 (the same as an MPU6050 with AD0 low)
+const SECONDS: u8 = 0x00; // First of the seven time registers (seconds to year, BCD)
+const CONTROL: u8 = 0x0E; // Control register
+const STATUS: u8 = 0x0F; // Status register
+const TEMP_MSB: u8 = 0x11; // Temperature, whole degrees (the next register holds quarters)
+const EOSC: u8 = 0x80; // CONTROL bit that stops the oscillator on battery power when set
+const OSF: u8 = 0x80; // STATUS bit set when the oscillator has stopped, so the time can't be trusted
+const CENTURY: u8 = 0x80; // Month register bit set when the year rolls past 99
+const HOUR_12: u8 = 0x40; // Hours register bit for 12 hour mode
+const PM: u8 = 0x20; // Hours register bit for the afternoon in 12 hour mode
+const SYNC_POLL: Duration = Duration::from_millis(2); // How often the seconds are read while waiting for them to tick
+const SYNC_TIMEOUT: Duration = Duration::from_millis(1500); // Longest wait for the seconds to tick
+
+
+/// Reasons the DS3231 couldn't be read or set
+#[derive(Debug)]
+pub enum RtcError {
+  Bus(BusError),
+  OscillatorStopped, // the rtc lost power (or was never set) and its time is meaningless
+  InvalidTime,       // the time registers don't hold a valid date, or the date can't be stored
+  Stuck,             // the seconds didn't tick over
+}
+
+/// The DS3231's time carried on by the host's monotonic clock from the moment its
+/// seconds ticked over, so it can be read to better than the rtc's one second resolution
+#[derive(Clone, Copy, Debug)]
+pub struct RtcClock {
+  seconds: i64, // rtc time at 'at', seconds since the Unix epoch
+  at: Instant,
+}
+
+
+/// Opens the DS3231 at 'address' on 'bus', checking it's answering
+pub fn init_ds3231(bus: u8, address: u16) -> Result<I2c, RtcError> {
+  let i2c = bus::open(bus, address)?;
+  bus::read_registers(&i2c, STATUS, &mut [0; 1])?;
+  Ok(i2c)
+}
+
+
+/// Reads the time as seconds since the Unix epoch. Fails if the oscillator has
+/// stopped since the time was last set.
+pub fn read_time(i2c: &I2c) -> Result<i64, RtcError> {
+  let mut status = [0; 1];
+  bus::read_registers(i2c, STATUS, &mut status)?;
+  if status[0] & OSF != 0 {
+    return Err(RtcError::OscillatorStopped);
+  }
+  let mut registers = [0; 7];
+  bus::read_registers(i2c, SECONDS, &mut registers)?;
+  decode_time(&registers).ok_or(RtcError::InvalidTime)
+}
+
+
+/// Sets the time to 'unix_s' seconds since the Unix epoch, starts the oscillator and
+/// clears the stopped flag. The rtc starts the new second as the seconds are written,
+/// so this should be called on a whole second.
+pub fn set_time(i2c: &mut I2c, unix_s: i64) -> Result<(), RtcError> {
+  let registers = encode_time(unix_s).ok_or(RtcError::InvalidTime)?;
+  bus::write_registers(i2c, SECONDS, &registers)?;
+
+  let mut control = [0; 1];
+  bus::read_registers(i2c, CONTROL, &mut control)?;
+  bus::write_register(i2c, CONTROL, control[0] & !EOSC)?;
+  let mut status = [0; 1];
+  bus::read_registers(i2c, STATUS, &mut status)?;
+  bus::write_register(i2c, STATUS, status[0] & !OSF)?;
+  Ok(())
+}
+
+
+/// Reads the temperature in degrees C (0.25 degree resolution, updated every 64 s)
+pub fn read_temperature(i2c: &I2c) -> Result<f32, RtcError> {
+  let mut temp = [0; 2];
+  bus::read_registers(i2c, TEMP_MSB, &mut temp)?;
+  Ok(temp[0] as i8 as f32 + (temp[1] >> 6) as f32 * 0.25)
+}
+
+
+/// Decodes the seven time registers into seconds since the Unix epoch
+pub fn decode_time(registers: &[u8; 7]) -> Option<i64> {
+  let second = from_bcd(registers[0] & 0x7F)?;
+  let minute = from_bcd(registers[1] & 0x7F)?;
+  let hour = if registers[2] & HOUR_12 != 0 {
+    let hour = from_bcd(registers[2] & 0x1F)? % 12;
+    if registers[2] & PM != 0 { hour + 12 } else { hour }
+  } else {
+    from_bcd(registers[2] & 0x3F)?
+  };
+  let day = from_bcd(registers[4] & 0x3F)?;
+  let month = from_bcd(registers[5] & 0x1F)?;
+  let century = if registers[5] & CENTURY != 0 { 2100 } else { 2000 };
+  let year = century + from_bcd(registers[6])? as i32;
+  if second > 59 || minute > 59 || hour > 23 || !(1..=31).contains(&day) || !(1..=12).contains(&month) {
+    return None;
+  }
+  let days = days_from_civil(year, month as u32, day as u32);
+  Some(days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64)
+}
+
+
+/// Encodes seconds since the Unix epoch into the seven time registers (24 hour mode),
+/// or None if the date is outside the years the rtc can hold
+pub fn encode_time(unix_s: i64) -> Option<[u8; 7]> {
+  let days = unix_s.div_euclid(86400);
+  let secs = unix_s.rem_euclid(86400);
+  let (year, month, day) = civil_from_days(days);
+  if !(2000..2200).contains(&year) {
+    return None;
+  }
+  let weekday = (days + 4).rem_euclid(7) as u8; // 1970-01-01 was a thursday, 0 = sunday
+  let century = if year >= 2100 { CENTURY } else { 0 };
+  Some([
+    to_bcd((secs % 60) as u8),
+    to_bcd((secs / 60 % 60) as u8),
+    to_bcd((secs / 3600) as u8),
+    weekday + 1,
+    to_bcd(day as u8),
+    to_bcd(month as u8) | century,
+    to_bcd((year % 100) as u8),
+  ])
+}
+
+
+/// RtcClock implementations
+impl RtcClock {
+  /// Waits for the rtc's seconds to tick over (up to a second and a half) and
+  /// starts counting from that moment
+  pub fn sync(i2c: &I2c) -> Result<RtcClock, RtcError> {
+    let start = read_time(i2c)?;
+    let deadline = Instant::now() + SYNC_TIMEOUT;
+    while Instant::now() < deadline {
+      thread::sleep(SYNC_POLL);
+      let seconds = read_time(i2c)?;
+      if seconds != start {
+        return Ok(RtcClock { seconds, at: Instant::now() });
+      }
+    }
+    Err(RtcError::Stuck)
+  }
+
+  /// The rtc time now, seconds since the Unix epoch
+  pub fn now_s(&self) -> f64 {
+    self.seconds as f64 + self.at.elapsed().as_secs_f64()
+  }
+}
+
+
+fn from_bcd(value: u8) -> Option<u8> {
+  let (tens, units) = (value >> 4, value & 0x0F);
+  (tens < 10 && units < 10).then_some(tens * 10 + units)
+}
+
+fn to_bcd(value: u8) -> u8 {
+  ((value / 10) << 4) | (value % 10)
+}
+
+
+impl From<BusError> for RtcError {
+  fn from(e: BusError) -> Self {
+    RtcError::Bus(e)
+  }
+}
+
+impl Display for RtcError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RtcError::Bus(e) => write!(f, "{e}"),
+      RtcError::OscillatorStopped => write!(f, "the rtc's oscillator has stopped, its time needs setting"),
+      RtcError::InvalidTime => write!(f, "the rtc doesn't hold a valid time"),
+      RtcError::Stuck => write!(f, "the rtc's seconds aren't counting"),
+    }
+  }
+}
+
+impl std::error::Error for RtcError {}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_time_registers() {
+    // 2023-11-14 22:13:20, a tuesday
+    let registers = encode_time(1_700_000_000).unwrap();
+    assert_eq!(registers, [0x20, 0x13, 0x22, 3, 0x14, 0x11, 0x23]);
+    assert_eq!(decode_time(&registers), Some(1_700_000_000));
+
+    // 10:13:20 pm in 12 hour mode
+    let twelve_hour = [0x20, 0x13, HOUR_12 | PM | 0x10, 3, 0x14, 0x11, 0x23];
+    assert_eq!(decode_time(&twelve_hour), Some(1_700_000_000));
+
+    assert_eq!(decode_time(&[0x60, 0, 0, 1, 1, 1, 0]), None); // 60 seconds
+    assert_eq!(decode_time(&[0x0A, 0, 0, 1, 1, 1, 0]), None); // not bcd
+    assert!(encode_time(0).is_none()); // 1970 is before the rtc's calendar
+  }
+}
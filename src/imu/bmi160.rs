@@ -0,0 +1,120 @@
+use rppal::i2c::I2c;
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::i2c as bus;
+use crate::imu::driver::{self, Imu, ImuModel};
+use crate::mpu6050::accel::{self, ImuError, RawSample};
+use crate::mpu6050::registers::{AccelRange, GyroRange, ImuSettings};
+
+pub const BMI160_CHIP_ID: u8 = 0xD1; // Value of the CHIP_ID register on a BMI160
+pub const CHIP_ID: u8 = 0x00; // Device identity
+const DATA_GYR_X_L: u8 = 0x0C; // First of the gyroscope, accelerometer, sensor time and status registers
+const TEMPERATURE_L: u8 = 0x20; // Temperature output, the last register of a sample read from DATA_GYR_X_L
+const ACC_CONF: u8 = 0x40; // Accelerometer filter mode (bits 6:4) and output rate (bits 3:0)
+const ACC_RANGE: u8 = 0x41; // Accelerometer full scale
+const GYR_CONF: u8 = 0x42; // Gyroscope filter mode (bits 5:4) and output rate (bits 3:0)
+const GYR_RANGE: u8 = 0x43; // Gyroscope full scale
+const CMD: u8 = 0x7E; // Command register
+const ACC_NORMAL_MODE: u8 = 0x11; // CMD value that starts the accelerometer
+const GYR_NORMAL_MODE: u8 = 0x15; // CMD value that starts the gyroscope
+const NORMAL_FILTER: u8 = 0x20; // ACC_CONF/GYR_CONF filter mode bits: normal, 3 dB point at about 0.4 of the output rate
+const ACC_START_UP: Duration = Duration::from_millis(5); // Accelerometer start up time
+const GYR_START_UP: Duration = Duration::from_millis(80); // Gyroscope start up time
+const OUTPUT_RATES: [(f32, u8); 7] = [(25.0, 6), (50.0, 7), (100.0, 8), (200.0, 9), (400.0, 10), (800.0, 11),
+                                      (1600.0, 12)]; // Output rates in Hz and their ACC_CONF/GYR_CONF value
+const ACCEL_SENSITIVITY: f32 = 16384.0; // Accelerometer sensitivity in LSB/g at +-2 g (halves each time the range doubles)
+const GYRO_SENSITIVITY: f32 = 131.2; // Gyroscope sensitivity in LSB/(deg/s) at +-250 deg/s (halves each time the range doubles)
+const TEMP_SENSITIVITY: f32 = 512.0; // Temperature sensitivity in LSB/C
+const TEMP_OFFSET_C: f32 = 23.0; // Temperature at a reading of 0
+
+
+/// A BMI160
+pub struct Bmi160 {
+  i2c: I2c,
+  address: u16,
+  settings: ImuSettings,
+}
+
+
+/// Bmi160 implementations
+impl Bmi160 {
+  /// Opens the BMI160 at 'address' on 'bus', checks it's one and starts it measuring with 'settings'
+  pub fn init(bus: u8, address: u16, settings: &ImuSettings) -> Result<Bmi160, ImuError> {
+    let mut i2c = bus::open(bus, address)?;
+    let mut chip_id = [0; 1];
+    accel::read_registers(&i2c, CHIP_ID, &mut chip_id)?;
+    if chip_id[0] != BMI160_CHIP_ID {
+      return Err(ImuError::WrongDevice(chip_id[0]));
+    }
+
+    let rate = driver::rate_code(settings.sample_rate_hz(), &OUTPUT_RATES);
+    accel::write_register(&mut i2c, ACC_CONF, NORMAL_FILTER | rate)?;
+    accel::write_register(&mut i2c, ACC_RANGE, accel_range_bits(settings.accel_range))?;
+    accel::write_register(&mut i2c, GYR_CONF, NORMAL_FILTER | rate)?;
+    accel::write_register(&mut i2c, GYR_RANGE, gyro_range_bits(settings.gyro_range))?;
+    accel::write_register(&mut i2c, CMD, ACC_NORMAL_MODE)?;
+    thread::sleep(ACC_START_UP);
+    accel::write_register(&mut i2c, CMD, GYR_NORMAL_MODE)?;
+    thread::sleep(GYR_START_UP);
+    Ok(Bmi160 { i2c, address, settings: *settings })
+  }
+}
+
+impl Imu for Bmi160 {
+  fn model(&self) -> ImuModel {
+    ImuModel::Bmi160
+  }
+
+  fn address(&self) -> u16 {
+    self.address
+  }
+
+  fn read_raw(&mut self) -> Result<RawSample, ImuError> {
+    let mut data = [0; (TEMPERATURE_L - DATA_GYR_X_L + 2) as usize];
+    accel::read_registers(&self.i2c, DATA_GYR_X_L, &mut data)?;
+    let word = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]);
+    let temperature = word((TEMPERATURE_L - DATA_GYR_X_L) as usize);
+    Ok(driver::raw_sample([word(6), word(8), word(10)], convert_temperature(temperature), [word(0), word(2), word(4)]))
+  }
+
+  fn read_temperature(&mut self) -> Result<f32, ImuError> {
+    let mut data = [0; 2];
+    accel::read_registers(&self.i2c, TEMPERATURE_L, &mut data)?;
+    Ok(convert_temperature(i16::from_le_bytes(data)))
+  }
+
+  fn accel_sensitivity(&self) -> f32 {
+    ACCEL_SENSITIVITY / (1 << self.settings.accel_range as u8) as f32
+  }
+
+  fn gyro_sensitivity(&self) -> f32 {
+    GYRO_SENSITIVITY / (1 << self.settings.gyro_range as u8) as f32
+  }
+
+  fn i2c(&mut self) -> &mut I2c {
+    &mut self.i2c
+  }
+}
+
+
+/// ACC_RANGE value
+fn accel_range_bits(range: AccelRange) -> u8 {
+  match range {
+    AccelRange::G2 => 0x03,
+    AccelRange::G4 => 0x05,
+    AccelRange::G8 => 0x08,
+    AccelRange::G16 => 0x0C,
+  }
+}
+
+/// GYR_RANGE value, which counts down from +-2000 deg/s
+fn gyro_range_bits(range: GyroRange) -> u8 {
+  3 - range as u8
+}
+
+
+/// Converts the temperature registers to degrees C
+fn convert_temperature(raw: i16) -> f32 {
+  raw as f32 / TEMP_SENSITIVITY + TEMP_OFFSET_C
+}
@@ -0,0 +1,124 @@
+use rppal::i2c::I2c;
+use serde::{Deserialize, Serialize};
+
+use crate::imu::{bmi160::Bmi160, icm20948::Icm20948, lsm6ds3::Lsm6ds3, mpu::Mpu, probe};
+use crate::mpu6050::accel::{AccelPoint, DataPointType, GyroPoint, ImuError, RawSample, Sensitivity};
+use crate::mpu6050::registers::ImuSettings;
+
+
+/// Supported imus
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImuModel {
+  Auto, // probe the bus for any of the others
+  #[default]
+  Mpu6050,
+  Mpu9250,
+  Icm20948,
+  Lsm6ds3,
+  Bmi160,
+}
+
+/// An accelerometer and gyroscope set up with the configured ranges and sample rate.
+/// Samples are returned in the chip's own units (LSB) in the same axes, and converted
+/// with its sensitivities, so the calibration and everything after it don't depend on
+/// which chip it is.
+pub trait Imu: Send {
+  fn model(&self) -> ImuModel;
+
+  fn address(&self) -> u16;
+
+  /// Reads the accelerometer, die temperature and gyroscope in one transfer
+  fn read_raw(&mut self) -> Result<RawSample, ImuError>;
+
+  /// Reads the die temperature in degrees C
+  fn read_temperature(&mut self) -> Result<f32, ImuError>;
+
+  /// Accelerometer sensitivity in LSB/g at the configured range
+  fn accel_sensitivity(&self) -> f32;
+
+  /// Gyroscope sensitivity in LSB/(deg/s) at the configured range
+  fn gyro_sensitivity(&self) -> f32;
+
+  /// Both sensitivities, for converting samples
+  fn sensitivity(&self) -> Sensitivity {
+    Sensitivity { accel: self.accel_sensitivity(), gyro: self.gyro_sensitivity() }
+  }
+
+  /// The bus handle, for the mpu6050's fifo
+  fn i2c(&mut self) -> &mut I2c;
+
+  /// Reads an uncorrected acceleration in g (gravity included)
+  fn acceleration_g(&mut self) -> Result<[f32; 3], ImuError> {
+    let accel = self.read_raw()?.accel;
+    Ok([accel.x(), accel.y(), accel.z()].map(|v| v as f32 / self.accel_sensitivity()))
+  }
+}
+
+
+/// ImuModel implementations
+impl ImuModel {
+  /// Name used in device ids and messages
+  pub fn name(self) -> &'static str {
+    match self {
+      ImuModel::Auto => "imu",
+      ImuModel::Mpu6050 => "mpu6050",
+      ImuModel::Mpu9250 => "mpu9250",
+      ImuModel::Icm20948 => "icm20948",
+      ImuModel::Lsm6ds3 => "lsm6ds3",
+      ImuModel::Bmi160 => "bmi160",
+    }
+  }
+}
+
+
+/// Opens the 'model' at 'address' on 'bus', checks it's the chip expected and sets it up
+/// with 'settings'. With ImuModel::Auto the bus is probed for a supported chip instead,
+/// starting at 'address' and skipping the addresses in 'skip' (other devices on the bus).
+pub fn open(model: ImuModel, bus: u8, address: u16, skip: &[u16], settings: &ImuSettings)
+            -> Result<Box<dyn Imu>, ImuError> {
+  let (model, address) = match model {
+    ImuModel::Auto => probe::probe(bus, address, skip)?,
+    model => (model, address),
+  };
+  Ok(match model {
+    ImuModel::Auto => unreachable!("probing finds a model"),
+    ImuModel::Mpu6050 | ImuModel::Mpu9250 => Box::new(Mpu::init(model, bus, address, settings)?),
+    ImuModel::Icm20948 => Box::new(Icm20948::init(bus, address, settings)?),
+    ImuModel::Lsm6ds3 => Box::new(Lsm6ds3::init(bus, address, settings)?),
+    ImuModel::Bmi160 => Box::new(Bmi160::init(bus, address, settings)?),
+  })
+}
+
+
+/// Output data rate setting for a chip whose rates are listed as (Hz, register value),
+/// slowest first: the slowest rate at least 'rate_hz', or the fastest there is
+pub(crate) fn rate_code(rate_hz: f32, rates: &[(f32, u8)]) -> u8 {
+  rates.iter().find(|(hz, _)| *hz >= rate_hz).or(rates.last()).map_or(0, |(_, code)| *code)
+}
+
+
+/// Builds a sample from the axes read from a chip
+pub(crate) fn raw_sample(accel: [i16; 3], temperature_c: f32, gyro: [i16; 3]) -> RawSample {
+  RawSample {
+    accel: AccelPoint::new(accel[0], accel[1], accel[2]),
+    temperature_c,
+    gyro: GyroPoint::new(gyro[0], gyro[1], gyro[2]),
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_rate_code() {
+    let rates = [(12.5, 1), (26.0, 2), (52.0, 3), (104.0, 4)];
+    assert_eq!(rate_code(1.0, &rates), 1);
+    assert_eq!(rate_code(26.0, &rates), 2);
+    assert_eq!(rate_code(60.0, &rates), 4);
+    assert_eq!(rate_code(1000.0, &rates), 4);
+  }
+}
@@ -0,0 +1,126 @@
+use rppal::i2c::I2c;
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::i2c as bus;
+use crate::imu::driver::{self, Imu, ImuModel};
+use crate::mpu6050::accel::{self, ImuError, RawSample};
+use crate::mpu6050::registers::{DlpfBandwidth, ImuSettings};
+
+pub const ICM20948_WHO_AM_I: u8 = 0xEA; // Value of the WHO_AM_I register on an ICM-20948
+pub const WHO_AM_I: u8 = 0x00; // Device identity (bank 0)
+const PWR_MGMT_1: u8 = 0x06; // Sleep and clock source (bank 0)
+const PWR_MGMT_2: u8 = 0x07; // Accelerometer and gyroscope axis disables (bank 0)
+const ACCEL_XOUT_H: u8 = 0x2D; // First of the accelerometer, gyroscope and temperature output registers (bank 0)
+const TEMP_OUT_H: u8 = 0x39; // Temperature output (bank 0)
+const REG_BANK_SEL: u8 = 0x7F; // User bank select (bits 5:4), in every bank
+const GYRO_SMPLRT_DIV: u8 = 0x00; // Gyroscope sample rate divider (bank 2)
+const GYRO_CONFIG_1: u8 = 0x01; // Gyroscope low pass filter (bits 5:3), full scale (bits 2:1) and filter enable (bit 0) (bank 2)
+const ACCEL_SMPLRT_DIV_1: u8 = 0x10; // Accelerometer sample rate divider, high bits (bank 2)
+const ACCEL_CONFIG: u8 = 0x14; // Accelerometer low pass filter, full scale and filter enable, as GYRO_CONFIG_1 (bank 2)
+const CLOCK_AUTO: u8 = 0x01; // PWR_MGMT_1 value: awake, best available clock
+const FILTERED_OUTPUT_RATE_HZ: f32 = 1125.0; // Output rate before the divider with the low pass filter on
+const TEMP_SENSITIVITY: f32 = 333.87; // Temperature sensitivity in LSB/C
+const TEMP_OFFSET_C: f32 = 21.0; // Temperature at a reading of 0
+const WAKE_UP: Duration = Duration::from_millis(20); // Time for the sensors to start after waking
+
+
+/// An ICM-20948, the MPU9250's successor. Same scales as the mpu6050, but the registers
+/// are split over four banks and the rates and filters are set up differently.
+pub struct Icm20948 {
+  i2c: I2c,
+  address: u16,
+  settings: ImuSettings,
+}
+
+
+/// Icm20948 implementations
+impl Icm20948 {
+  /// Opens the ICM-20948 at 'address' on 'bus', checks it's one, wakes it and writes 'settings'
+  pub fn init(bus: u8, address: u16, settings: &ImuSettings) -> Result<Icm20948, ImuError> {
+    let mut i2c = bus::open(bus, address)?;
+    select_bank(&mut i2c, 0)?; // whatever ran before may have left another bank selected
+    let mut who_am_i = [0; 1];
+    accel::read_registers(&i2c, WHO_AM_I, &mut who_am_i)?;
+    if who_am_i[0] != ICM20948_WHO_AM_I {
+      return Err(ImuError::WrongDevice(who_am_i[0]));
+    }
+
+    accel::write_register(&mut i2c, PWR_MGMT_1, CLOCK_AUTO)?;
+    accel::write_register(&mut i2c, PWR_MGMT_2, 0)?;
+    thread::sleep(WAKE_UP);
+
+    let divider = (FILTERED_OUTPUT_RATE_HZ / settings.sample_rate_hz() - 1.0).round().clamp(0.0, 255.0) as u8;
+    select_bank(&mut i2c, 2)?;
+    accel::write_register(&mut i2c, GYRO_SMPLRT_DIV, divider)?;
+    accel::write_register(&mut i2c, GYRO_CONFIG_1, config_bits(settings.gyro_range as u8, settings.dlpf))?;
+    bus::write_registers(&mut i2c, ACCEL_SMPLRT_DIV_1, &[0, divider])?;
+    accel::write_register(&mut i2c, ACCEL_CONFIG, config_bits(settings.accel_range as u8, settings.dlpf))?;
+    select_bank(&mut i2c, 0)?;
+    Ok(Icm20948 { i2c, address, settings: *settings })
+  }
+}
+
+impl Imu for Icm20948 {
+  fn model(&self) -> ImuModel {
+    ImuModel::Icm20948
+  }
+
+  fn address(&self) -> u16 {
+    self.address
+  }
+
+  fn read_raw(&mut self) -> Result<RawSample, ImuError> {
+    let mut data = [0; 14];
+    accel::read_registers(&self.i2c, ACCEL_XOUT_H, &mut data)?;
+    let word = |i: usize| i16::from_be_bytes([data[i], data[i + 1]]);
+    Ok(driver::raw_sample([word(0), word(2), word(4)], convert_temperature(word(12)), [word(6), word(8), word(10)]))
+  }
+
+  fn read_temperature(&mut self) -> Result<f32, ImuError> {
+    let mut data = [0; 2];
+    accel::read_registers(&self.i2c, TEMP_OUT_H, &mut data)?;
+    Ok(convert_temperature(i16::from_be_bytes(data)))
+  }
+
+  fn accel_sensitivity(&self) -> f32 {
+    self.settings.accel_range.sensitivity()
+  }
+
+  fn gyro_sensitivity(&self) -> f32 {
+    self.settings.gyro_range.sensitivity()
+  }
+
+  fn i2c(&mut self) -> &mut I2c {
+    &mut self.i2c
+  }
+}
+
+
+/// Switches the register bank the following transfers go to
+fn select_bank(i2c: &mut I2c, bank: u8) -> Result<(), ImuError> {
+  accel::write_register(i2c, REG_BANK_SEL, bank << 4)
+}
+
+
+/// GYRO_CONFIG_1 or ACCEL_CONFIG value for the range (0 to 3, as on the mpu6050) and the
+/// mpu6050 filter with the closest bandwidth. The widest turns the filter off, as it does
+/// on the mpu6050.
+fn config_bits(range: u8, dlpf: DlpfBandwidth) -> u8 {
+  let filter = match dlpf {
+    DlpfBandwidth::Hz260 => return range << 1,
+    DlpfBandwidth::Hz184 => 1,
+    DlpfBandwidth::Hz94 => 2,
+    DlpfBandwidth::Hz44 => 3,
+    DlpfBandwidth::Hz21 => 4,
+    DlpfBandwidth::Hz10 => 5,
+    DlpfBandwidth::Hz5 => 6,
+  };
+  filter << 3 | range << 1 | 1
+}
+
+
+/// Converts the temperature registers to degrees C
+fn convert_temperature(raw: i16) -> f32 {
+  raw as f32 / TEMP_SENSITIVITY + TEMP_OFFSET_C
+}
@@ -0,0 +1,103 @@
+use rppal::i2c::I2c;
+
+use crate::bus::i2c as bus;
+use crate::imu::driver::{self, Imu, ImuModel};
+use crate::mpu6050::accel::{self, ImuError, RawSample};
+use crate::mpu6050::registers::{AccelRange, ImuSettings};
+
+pub const LSM6DS3_WHO_AM_I: [u8; 2] = [0x69, 0x6A]; // Value of the WHO_AM_I register on an LSM6DS3 and an LSM6DS3TR-C
+pub const WHO_AM_I: u8 = 0x0F; // Device identity
+const CTRL1_XL: u8 = 0x10; // Accelerometer output rate (bits 7:4) and full scale (bits 3:2)
+const CTRL2_G: u8 = 0x11; // Gyroscope output rate (bits 7:4) and full scale (bits 3:2)
+const CTRL3_C: u8 = 0x12; // Block data update (bit 6) and address auto increment (bit 2)
+const OUT_TEMP_L: u8 = 0x20; // First of the temperature, gyroscope and accelerometer output registers
+const BDU_IF_INC: u8 = 0x44; // CTRL3_C value: outputs latched until both bytes are read, auto increment
+const OUTPUT_RATES: [(f32, u8); 8] = [(12.5, 1), (26.0, 2), (52.0, 3), (104.0, 4), (208.0, 5), (416.0, 6),
+                                      (833.0, 7), (1660.0, 8)]; // Output rates in Hz and their CTRL1_XL/CTRL2_G value
+const ACCEL_MG_PER_LSB: f32 = 0.061; // Accelerometer sensitivity at +-2 g (doubles each time the range doubles)
+const GYRO_MDPS_PER_LSB: f32 = 8.75; // Gyroscope sensitivity at +-245 deg/s (doubles each time the range doubles)
+const TEMP_SENSITIVITY: f32 = 16.0; // Temperature sensitivity in LSB/C
+const TEMP_OFFSET_C: f32 = 25.0; // Temperature at a reading of 0
+
+
+/// An LSM6DS3 (or the LSM6DS3TR-C of most breakout boards). Its +-245 deg/s range
+/// stands in for +-250.
+pub struct Lsm6ds3 {
+  i2c: I2c,
+  address: u16,
+  settings: ImuSettings,
+}
+
+
+/// Lsm6ds3 implementations
+impl Lsm6ds3 {
+  /// Opens the LSM6DS3 at 'address' on 'bus', checks it's one and starts it measuring with 'settings'
+  pub fn init(bus: u8, address: u16, settings: &ImuSettings) -> Result<Lsm6ds3, ImuError> {
+    let mut i2c = bus::open(bus, address)?;
+    let mut who_am_i = [0; 1];
+    accel::read_registers(&i2c, WHO_AM_I, &mut who_am_i)?;
+    if !LSM6DS3_WHO_AM_I.contains(&who_am_i[0]) {
+      return Err(ImuError::WrongDevice(who_am_i[0]));
+    }
+
+    let rate = driver::rate_code(settings.sample_rate_hz(), &OUTPUT_RATES) << 4;
+    accel::write_register(&mut i2c, CTRL3_C, BDU_IF_INC)?;
+    accel::write_register(&mut i2c, CTRL1_XL, rate | accel_range_bits(settings.accel_range))?;
+    accel::write_register(&mut i2c, CTRL2_G, rate | (settings.gyro_range as u8) << 2)?;
+    Ok(Lsm6ds3 { i2c, address, settings: *settings })
+  }
+}
+
+impl Imu for Lsm6ds3 {
+  fn model(&self) -> ImuModel {
+    ImuModel::Lsm6ds3
+  }
+
+  fn address(&self) -> u16 {
+    self.address
+  }
+
+  fn read_raw(&mut self) -> Result<RawSample, ImuError> {
+    let mut data = [0; 14];
+    accel::read_registers(&self.i2c, OUT_TEMP_L, &mut data)?;
+    let word = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]);
+    Ok(driver::raw_sample([word(8), word(10), word(12)], convert_temperature(word(0)), [word(2), word(4), word(6)]))
+  }
+
+  fn read_temperature(&mut self) -> Result<f32, ImuError> {
+    let mut data = [0; 2];
+    accel::read_registers(&self.i2c, OUT_TEMP_L, &mut data)?;
+    Ok(convert_temperature(i16::from_le_bytes(data)))
+  }
+
+  fn accel_sensitivity(&self) -> f32 {
+    1000.0 / (ACCEL_MG_PER_LSB * (1 << self.settings.accel_range as u8) as f32)
+  }
+
+  fn gyro_sensitivity(&self) -> f32 {
+    1000.0 / (GYRO_MDPS_PER_LSB * (1 << self.settings.gyro_range as u8) as f32)
+  }
+
+  fn i2c(&mut self) -> &mut I2c {
+    &mut self.i2c
+  }
+}
+
+
+/// CTRL1_XL full scale bits, which aren't in range order
+fn accel_range_bits(range: AccelRange) -> u8 {
+  let bits = match range {
+    AccelRange::G2 => 0b00,
+    AccelRange::G4 => 0b10,
+    AccelRange::G8 => 0b11,
+    AccelRange::G16 => 0b01,
+  };
+  bits << 2
+}
+
+
+/// Converts the temperature registers to degrees C
+fn convert_temperature(raw: i16) -> f32 {
+  raw as f32 / TEMP_SENSITIVITY + TEMP_OFFSET_C
+}
+
@@ -0,0 +1,6 @@
+pub mod bmi160;
+pub mod driver;
+pub mod icm20948;
+pub mod lsm6ds3;
+pub mod mpu;
+pub mod probe;
@@ -0,0 +1,93 @@
+use rppal::i2c::I2c;
+
+use crate::bus::i2c as bus;
+use crate::imu::driver::{Imu, ImuModel};
+use crate::mpu6050::accel::{self, ImuError, RawSample, MPU6050_WHO_AM_I};
+use crate::mpu6050::device::Mpu6050;
+use crate::mpu6050::registers::{self, ImuSettings};
+
+pub const MPU9250_WHO_AM_I: [u8; 2] = [0x71, 0x73]; // Value of the WHO_AM_I register on an MPU9250 and an MPU9255
+const MPU9250_TEMP_SENSITIVITY: f32 = 333.87; // MPU9250 temperature sensitivity in LSB/C
+const MPU9250_TEMP_OFFSET_C: f32 = 21.0; // MPU9250 temperature at a reading of 0
+
+
+/// An MPU6050 or MPU9250. The MPU9250's accelerometer and gyroscope are register
+/// compatible with the MPU6050's, only its WHO_AM_I and temperature scale differ.
+pub struct Mpu {
+  device: Mpu6050<I2c>,
+  model: ImuModel,
+  address: u16,
+  settings: ImuSettings,
+}
+
+
+/// Mpu implementations
+impl Mpu {
+  /// Opens the mpu at 'address' on 'bus', checks it's the 'model' expected and writes 'settings'
+  pub fn init(model: ImuModel, bus: u8, address: u16, settings: &ImuSettings) -> Result<Mpu, ImuError> {
+    let mut device = Mpu6050::new(bus::open(bus, address)?, address as u8);
+    match model {
+      ImuModel::Mpu9250 => {
+        let who_am_i = device.who_am_i()?;
+        if !MPU9250_WHO_AM_I.contains(&who_am_i) {
+          return Err(ImuError::WrongDevice(who_am_i));
+        }
+        device.configure(settings)?;
+      }
+      _ => device.init(settings)?,
+    }
+    Ok(Mpu { device, model, address, settings: *settings })
+  }
+
+  /// Converts the temperature registers to degrees C
+  fn temperature(&self, raw: i16) -> f32 {
+    match self.model {
+      ImuModel::Mpu9250 => raw as f32 / MPU9250_TEMP_SENSITIVITY + MPU9250_TEMP_OFFSET_C,
+      _ => accel::convert_temperature(raw),
+    }
+  }
+}
+
+impl Imu for Mpu {
+  fn model(&self) -> ImuModel {
+    self.model
+  }
+
+  fn address(&self) -> u16 {
+    self.address
+  }
+
+  fn read_raw(&mut self) -> Result<RawSample, ImuError> {
+    let mut data = [0; 14];
+    self.device.read_registers(registers::ACCEL_XOUT_H, &mut data)?;
+    let sample = accel::parse_raw_sample(&data);
+    Ok(RawSample { temperature_c: self.temperature(i16::from_be_bytes([data[6], data[7]])), ..sample })
+  }
+
+  fn read_temperature(&mut self) -> Result<f32, ImuError> {
+    let raw = self.device.read_raw_temperature()?;
+    Ok(self.temperature(raw))
+  }
+
+  fn accel_sensitivity(&self) -> f32 {
+    self.settings.accel_range.sensitivity()
+  }
+
+  fn gyro_sensitivity(&self) -> f32 {
+    self.settings.gyro_range.sensitivity()
+  }
+
+  fn i2c(&mut self) -> &mut I2c {
+    self.device.bus()
+  }
+}
+
+
+/// Whether a WHO_AM_I value is one of the mpus'
+pub fn identify(who_am_i: u8) -> Option<ImuModel> {
+  match who_am_i {
+    MPU6050_WHO_AM_I => Some(ImuModel::Mpu6050),
+    id if MPU9250_WHO_AM_I.contains(&id) => Some(ImuModel::Mpu9250),
+    _ => None,
+  }
+}
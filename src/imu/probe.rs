@@ -0,0 +1,68 @@
+use rppal::i2c::I2c;
+
+use crate::bus::i2c as bus;
+use crate::imu::driver::ImuModel;
+use crate::imu::{bmi160, icm20948, lsm6ds3, mpu};
+use crate::mpu6050::accel::ImuError;
+use crate::mpu6050::registers;
+
+pub const PROBE_ADDRESSES: [u16; 4] = [0x68, 0x69, 0x6A, 0x6B]; // Addresses every supported imu can be strapped to
+
+
+/// Finds a supported imu on 'bus', trying 'first' and then the usual imu addresses,
+/// apart from those in 'skip' (other devices, eg. a DS3231 at 0x68, that could
+/// answer the identity registers with anything). Returns the model and its address.
+pub fn probe(bus: u8, first: u16, skip: &[u16]) -> Result<(ImuModel, u16), ImuError> {
+  let mut i2c = bus::open(bus, first)?;
+  let addresses = std::iter::once(first).chain(PROBE_ADDRESSES.into_iter().filter(|address| *address != first));
+  for address in addresses.filter(|address| !skip.contains(address)) {
+    if i2c.set_slave_address(address).is_err() {
+      continue;
+    }
+    if let Some(model) = identify(|register| read_register(&i2c, register)) {
+      return Ok((model, address));
+    }
+  }
+  Err(ImuError::NotFound)
+}
+
+
+/// Identifies an imu from its identity registers, read with 'read' (None if the read
+/// failed, eg. nothing answered). The mpus' WHO_AM_I is tried first, it's the furthest
+/// from the start of the register map where the others keep theirs.
+pub fn identify(read: impl Fn(u8) -> Option<u8>) -> Option<ImuModel> {
+  read(registers::WHO_AM_I).and_then(mpu::identify)
+    .or_else(|| (read(icm20948::WHO_AM_I)? == icm20948::ICM20948_WHO_AM_I).then_some(ImuModel::Icm20948))
+    .or_else(|| (read(bmi160::CHIP_ID)? == bmi160::BMI160_CHIP_ID).then_some(ImuModel::Bmi160))
+    .or_else(|| lsm6ds3::LSM6DS3_WHO_AM_I.contains(&read(lsm6ds3::WHO_AM_I)?).then_some(ImuModel::Lsm6ds3))
+}
+
+
+/// Reads a single register, None if nothing answered. Not counted as a failed read, most
+/// of the addresses probed are expected to be empty.
+fn read_register(i2c: &I2c, register: u8) -> Option<u8> {
+  let mut value = [0; 1];
+  i2c.write_read(&[register], &mut value).ok()?;
+  Some(value[0])
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_identify() {
+    let chip = |registers: &'static [(u8, u8)]| {
+      identify(|register| registers.iter().find(|(r, _)| *r == register).map(|(_, value)| *value))
+    };
+    assert_eq!(chip(&[(0x75, 0x68)]), Some(ImuModel::Mpu6050));
+    assert_eq!(chip(&[(0x75, 0x73), (0x00, 0x00)]), Some(ImuModel::Mpu9250));
+    assert_eq!(chip(&[(0x75, 0x00), (0x00, 0xEA)]), Some(ImuModel::Icm20948));
+    assert_eq!(chip(&[(0x00, 0xD1), (0x0F, 0x00)]), Some(ImuModel::Bmi160));
+    assert_eq!(chip(&[(0x00, 0x00), (0x0F, 0x6A)]), Some(ImuModel::Lsm6ds3));
+    assert_eq!(chip(&[(0x75, 0x70), (0x00, 0x12), (0x0F, 0x00)]), None); // an mpu6500
+    assert_eq!(chip(&[]), None); // nothing answering
+  }
+}
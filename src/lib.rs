@@ -1,3 +1,13 @@
 // Re-export modules
+pub mod bus;
 pub mod neo6m;
 pub mod mpu6050;
+pub mod imu;
+pub mod ds3231;
+pub mod bmp280;
+pub mod compass;
+pub use gps_spoofing_core::detect;
+pub mod output;
+pub mod config;
+pub mod session;
+pub mod timing;
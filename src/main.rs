@@ -1,100 +1,182 @@
-mod neo6m;
-mod mpu6050;
-
-use std::cell::{RefCell, RefMut};
-use std::time::{ Instant, Duration };
-use adafruit_gps::Gps;
-use rppal::i2c::I2c;
-
-const PORT_NAME: &str = "/dev/ttyS0";
-const BAUD_RATE: &str = "9600";
-const GPS_ACCURACY: f32 = 10.0; // meters
-const GPS_FIX_TIMEOUT: Duration = Duration::from_secs(60); // How long to wait for gps fix before timing out
+use std::process::ExitCode;
+use std::time::Duration;
+use clap::{ArgAction, Parser, Subcommand};
+use gps_spoofing_detection::config::settings::Config;
+use gps_spoofing_detection::mpu6050::temperature::DEGREE;
+use gps_spoofing_detection::session::simulate::Scenario;
+
+mod commands;
+use commands::calibrate::CalibrateOptions;
+use commands::common::{Exit, Verbosity};
+use commands::rtc::TimeSource;
+
+
+/// Detects gps spoofing by checking each fix against the position predicted from an accelerometer
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+  /// Config file (toml), see config.example.toml
+  #[arg(long, global = true, value_name = "FILE")]
+  config: Option<String>,
+
+  /// Overrides a config value, eg. --set gps.port=/dev/ttyUSB0 (can be repeated)
+  #[arg(long = "set", global = true, value_name = "SECTION.KEY=VALUE")]
+  overrides: Vec<String>,
+
+  /// Shows more progress on stderr (-vv for more)
+  #[arg(short, long, global = true, action = ArgAction::Count)]
+  verbose: u8,
+
+  /// Only reports errors on stderr
+  #[arg(short, long, global = true, conflicts_with = "verbose")]
+  quiet: bool,
+
+  /// Defaults to monitor
+  #[command(subcommand)]
+  command: Option<Command>,
+}
 
+#[derive(Subcommand)]
+enum Command {
+  /// Runs live spoofing detection
+  Monitor,
+  /// Calibrates the accelerometer (keep it still) and prints the offsets
+  Calibrate {
+    /// First guides you through six orientations to correct scale and misalignment
+    #[arg(long)]
+    six_position: bool,
+    /// First records the bias for this many seconds while the temperature changes, to model its drift
+    #[arg(long, value_name = "SECONDS")]
+    temperature: Option<u64>,
+    /// Degree of the temperature model's polynomials
+    #[arg(long, default_value_t = DEGREE, requires = "temperature")]
+    degree: usize,
+  },
+  /// Records gps sentences and accelerometer samples for replaying later
+  Record {
+    /// Session file to write (json lines)
+    output: String,
+    /// Stops after this many fixes instead of when the gps is lost
+    #[arg(long)]
+    fixes: Option<u32>,
+  },
+  /// Runs detection over a recorded or simulated session
+  Replay {
+    /// Session file to read
+    input: String,
+  },
+  /// Writes a simulated session, optionally with spoofing injected
+  Simulate {
+    /// Session file to write (json lines)
+    output: String,
+    /// Number of fixes (one per second)
+    #[arg(long, default_value_t = 60)]
+    fixes: u32,
+    /// First fix to spoof
+    #[arg(long, value_name = "FIX")]
+    spoof_after: Option<u32>,
+    /// Speed the spoofed position is dragged away at in m/s
+    #[arg(long, default_value_t = 30.0, value_name = "M/S")]
+    spoof_drift: f32,
+    /// Standard deviation of the accelerometer noise in m/s^2
+    #[arg(long, default_value_t = 0.0, value_name = "M/S^2")]
+    accel_noise: f32,
+    /// Accelerometer samples per second
+    #[arg(long, default_value_t = 100, value_name = "HZ")]
+    imu_rate: u32,
+    /// Seed for the accelerometer noise
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+  },
+  /// Checks each sensor is connected and answering sensibly
+  Selftest,
+  /// Prints the current gps fix and accelerometer reading
+  Status,
+  /// Records the still imu and writes its noise profile from the Allan deviation
+  Allan {
+    /// Noise profile to write (toml)
+    #[arg(long, default_value = "noise_profile.toml", value_name = "FILE")]
+    output: String,
+    /// How long to record for, noise with longer correlation times needs longer runs
+    #[arg(long, default_value_t = 3600, value_name = "SECONDS")]
+    duration: u64,
+    /// Samples per second
+    #[arg(long, default_value_t = 100, value_name = "HZ", value_parser = clap::value_parser!(u32).range(1..=1000))]
+    rate: u32,
+  },
+  /// Prints the DS3231 real time clock's time and temperature, or sets its time
+  Rtc {
+    /// Sets the time from the system clock or the gps
+    #[arg(long, value_enum, value_name = "SOURCE")]
+    set_from: Option<TimeSource>,
+  },
+  /// Prints the magnetometer's field and heading, or calibrates it
+  Compass {
+    /// First records the field for this many seconds while the sensor is turned through every orientation, to calibrate it
+    #[arg(long, value_name = "SECONDS")]
+    calibrate: Option<u64>,
+  },
+}
 
-fn main() {
-  // GPS setup
-  #[allow(clippy::needless_borrow)] // In the future, PORT_NAME and BAUD_RATE will be used elsewhere
-  let mut gps = Gps::new(&PORT_NAME, &BAUD_RATE);
-  neo6m::gps::init_gps(&mut gps);
 
-  // Accelerometer setup
-  let i2c = RefCell::new(mpu6050::accel::init_mpu6050()); // Set up I2C device (the GY-521 accelerometer/gyro)
+fn main() -> ExitCode {
+  let cli = Cli::parse();
+  let verbosity = Verbosity::new(cli.verbose, cli.quiet);
 
-  println!("Calibrating MPU6050...");
-  let (accel_offsets, _gyro_offsets) = {
-    let i2c = i2c.borrow_mut();
-    mpu6050::accel::calibrate_mpu6050(i2c, None, None, None) // Calibrate the accelerometer
+  let config = match Config::load(cli.config.as_deref(), &cli.overrides) {
+    Ok(config) => config,
+    Err(e) => {
+      eprintln!("{e}");
+      return Exit::Config.into();
+    }
   };
-  println!("Calibration complete");
-
-
-  println!("Waiting for gps fix...");
-  let fix = neo6m::gps::wait_for_fix(&mut gps, GPS_FIX_TIMEOUT);
-  if fix.is_none() {
-    println!("Timed out waiting for gps fix");
-    return; // change to waiting for gps fix again
-  }
-
-  let i2c = i2c.borrow_mut();
-  detect_spoofing(&mut gps, &accel_offsets, &i2c);
 
-}
-
-
-
-/// Detects spoofing by comparing the predicted position to the actual position
-fn detect_spoofing(gps: &mut Gps, accel_offsets: &mpu6050::accel::AccelPoint, i2c: &RefMut<'_, I2c>) {
-  let mut gps_data = neo6m::gps::get_gps(gps).expect("Lost connection to gps");
-  
-  let mut x0 = neo6m::gps::GpsCoord::new(gps_data.lat(), gps_data.lon(), gps_data.alt()); // Initial position
-  let mut v0 = mpu6050::accel::RawPoint::new(0.0, 0.0, 0.0); // Initial velocity
-
-  loop {
-    // predict position
-    println!("using initial position of {x0}");
-    let (predicted_pos, new_vel) = predict_position(500, i2c, accel_offsets, &x0, &v0);
-    gps_data = neo6m::gps::get_gps(gps).expect("Lost connection to gps");
-    x0 = neo6m::gps::GpsCoord::new(gps_data.lat(), gps_data.lon(), gps_data.alt()); // Initial position
-    println!("{x0}");
-    // compare
-    let dist = neo6m::gps::haversine_distance(x0.lat(), x0.lon(), predicted_pos.lat(), predicted_pos.lon());
-    let error_dist = gps_data.hor_prec() * GPS_ACCURACY;
-    if dist > error_dist {
-      println!("dist: {}, error_dist: {}", dist, error_dist);
-      println!("Spoofing detected");
+  let exit = match cli.command.unwrap_or(Command::Monitor) {
+    Command::Monitor => commands::monitor::run(&config, verbosity),
+    Command::Calibrate { six_position, temperature, degree } => {
+      let options = CalibrateOptions { six_position, temperature_run: temperature.map(Duration::from_secs), degree };
+      commands::calibrate::run(&config, &options, verbosity)
     }
-    // reset values
-    v0 = new_vel;
-  }
+    Command::Record { output, fixes } => commands::record::run(&config, &output, fixes, verbosity),
+    Command::Replay { input } => commands::replay::run(&config, &input, verbosity),
+    Command::Simulate { output, fixes, spoof_after, spoof_drift, accel_noise, imu_rate, seed } => {
+      let scenario = Scenario {
+        fixes,
+        spoof_after,
+        spoof_drift_mps: spoof_drift,
+        accel_noise,
+        imu_rate_hz: imu_rate,
+        seed,
+        ..Default::default()
+      };
+      commands::simulate::run(&scenario, &output, verbosity)
+    }
+    Command::Selftest => commands::selftest::run(&config, verbosity),
+    Command::Status => commands::status::run(&config, verbosity),
+    Command::Allan { output, duration, rate } =>
+      commands::allan::run(&config, Duration::from_secs(duration), rate, &output, verbosity),
+    Command::Rtc { set_from } => commands::rtc::run(&config, set_from, verbosity),
+    Command::Compass { calibrate } => commands::compass::run(&config, calibrate.map(Duration::from_secs), verbosity),
+  };
+  exit.into()
 }
 
 
-/// Predicts the position of the gps using the accelerometer
-fn predict_position(num_iters: u32, 
-                    i2c: &RefMut<'_, I2c>, 
-                    accel_offsets: &mpu6050::accel::AccelPoint, 
-                    x0: &neo6m::gps::GpsCoord, 
-                    v0: &mpu6050::accel::RawPoint) -> 
-                      (neo6m::gps::GpsCoord, mpu6050::accel::RawPoint) {
-  let start = Instant::now();
-  let avg_accel = average_acceleration(num_iters, i2c, accel_offsets);
-  let t = start.elapsed().as_secs_f64();
 
-  let predicted_pos = neo6m::gps::calc_new_pos(x0, v0, &avg_accel, &t);
-  let v0 = neo6m::gps::calc_new_vel(v0, &avg_accel, &t);
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::CommandFactory;
 
-  (predicted_pos, v0)
-}
+  #[test]
+  fn test_cli() {
+    Cli::command().debug_assert();
 
+    let cli = Cli::parse_from(["gps_spoofing_detection", "replay", "session.jsonl", "-q", "--set", "gps.port=/dev/ttyUSB0"]);
+    assert!(cli.quiet);
+    assert_eq!(cli.overrides, ["gps.port=/dev/ttyUSB0"]);
+    assert!(matches!(cli.command, Some(Command::Replay { input }) if input == "session.jsonl"));
 
-/// Returns the average acceleration over a period of time
-fn average_acceleration(num_iters: u32, i2c: &RefMut<'_, I2c>, accel_offsets: &mpu6050::accel::AccelPoint) -> mpu6050::accel::RawPoint {
-  let mut accel_sum = mpu6050::accel::RawPoint::new(0.0, 0.0, 0.0);
-  for _ in 0..num_iters {
-    let accel_point = mpu6050::accel::get_converted_acceleration(i2c, accel_offsets);
-    accel_sum += accel_point;
+    assert!(Cli::try_parse_from(["gps_spoofing_detection", "-v", "-q"]).is_err());
   }
-  accel_sum / num_iters as f32
 }
-
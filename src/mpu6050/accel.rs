@@ -1,15 +1,25 @@
 use rppal::i2c::I2c;
 use std::cell::RefMut;
 use std::fmt::Display;
-use std::ops::{Add, Sub, Div, AddAssign};
+use std::ops::{Add, Sub, Div};
 use std::time::{Duration, Instant};
 
-const MPU6050_ADDR: u16 = 0x68; // I2C address of the MPU6050
-const CALIB_TIME: u64 = 10000; // Default mpu6050 calibration time in milliseconds
-const CALIB_DIFF: i16 = 2; // Default difference between iterations to be considered consistent
-const CALIB_CONSISTENT: u8 = 5; // Default number of iterations that must be consistent to be considered the average
-const GRAVITY_ACCEL: f32 = 9.80665; // Gravity acceleration in m/s^2
-const ACCEL_SENSITIVITY: f32 = 16384.0; // Accelerometer sensitivity in LSB/g (Currently set to +-2g)
+use crate::bus::i2c::{self as bus, BusError};
+use crate::imu::driver::Imu;
+use crate::mpu6050::calibration::Calibration;
+use crate::mpu6050::device::Mpu6050Error;
+use crate::mpu6050::registers;
+
+pub use gps_spoofing_core::nav::RawPoint;
+
+pub const MPU6050_ADDR: u16 = 0x68; // I2C address of the MPU6050
+pub const MPU6050_WHO_AM_I: u8 = 0x68; // Value of the WHO_AM_I register on a genuine MPU6050
+pub const CALIB_TIME: u64 = 10000; // Default mpu6050 calibration time in milliseconds
+pub const CALIB_DIFF: i16 = 2; // Default difference between iterations to be considered consistent
+pub const CALIB_CONSISTENT: u8 = 5; // Default number of iterations that must be consistent to be considered the average
+pub const GRAVITY_ACCEL: f32 = 9.80665; // Gravity acceleration in m/s^2
+pub const ACCEL_RANGES: [u8; 4] = [2, 4, 8, 16]; // Supported accelerometer ranges in +-g
+pub const GYRO_RANGES: [u16; 4] = [250, 500, 1000, 2000]; // Supported gyroscope ranges in +-deg/s
 
 
 
@@ -31,30 +41,45 @@ pub enum GyroPoint {
   Gyro(DataPoint),
 }
 
+/// Every sensor register of the mpu6050, unconverted apart from the temperature
 #[derive(Clone, Copy, Debug)]
-pub struct RawPoint {
-  x: f32,
-  y: f32,
-  z: f32,
+pub struct RawSample {
+  pub accel: AccelPoint,
+  pub temperature_c: f32,
+  pub gyro: GyroPoint,
 }
 
+/// A converted acceleration and rotation rate, and the die temperature they were read at
+#[derive(Clone, Copy, Debug)]
+pub struct ImuSample {
+  pub accel: RawPoint,
+  pub gyro: [f32; 3], // rad/s
+  pub temperature_c: f32,
+}
 
+/// Scale factors of the readings at the configured ranges
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sensitivity {
+  pub accel: f32, // LSB/g
+  pub gyro: f32,  // LSB/(deg/s)
+}
 
-/// Initializes the mpu6050 and returns an I2c device
-pub fn init_mpu6050() -> I2c {
-  let mut i2c = I2c::new().unwrap();
-  i2c.set_slave_address(MPU6050_ADDR).unwrap();
-
-  let _ = i2c.write_read(&[0x6B, 0x08], &mut [0; 1]); // enable the mpu6050
-
-  let _ = i2c.write_read(&[0x1A, 0x05], &mut [0; 1]); // enable gyro low pass filter
-  let _ = i2c.write_read(&[0x1B, 0x18], &mut [0; 1]); // set gyro range to +-1000 deg/s
 
-  i2c
+/// Errors from the mpu6050 driver
+#[derive(Debug)]
+pub enum ImuError {
+  Bus(rppal::i2c::Error),   // the bus couldn't be opened or a transfer failed
+  Gpio(rppal::gpio::Error), // the interrupt pin couldn't be set up or waited on
+  Nack,                     // nothing acknowledged at the address (eg. a loose wire)
+  WrongDevice(u8),          // WHO_AM_I read back something other than the imu expected
+  NotFound,                 // probing the bus found no supported imu
+  Timeout,                  // the bus timed out
+  FifoOverflow,             // the fifo kept overflowing, samples were lost
 }
 
 
-/// Iterates over mpu6050 data points to find average to be used to zero the
+
+/// Iterates over imu data points to find average to be used to zero the
 /// output.
 /// 
 /// Returns a tuple containing (acceleration offset, gyroscope offset) where
@@ -63,9 +88,9 @@ pub fn init_mpu6050() -> I2c {
 /// 'max_cal_time' is number of seconds to calibrate for
 /// 'diff_between_iters' is the maximum difference between iterations to be considered consistent
 /// 'consistent_iters' is the number of iterations that must be consistent to be considered the average
-pub fn calibrate_mpu6050(i2c: RefMut<I2c>, max_calibration_time: Option<Duration>, 
+pub fn calibrate_imu(imu: &mut dyn Imu, max_calibration_time: Option<Duration>,
                      diff_between_iters: Option<i16>, consistent_iters: Option<u8>)
-                     -> (AccelPoint, GyroPoint) {
+                     -> Result<(AccelPoint, GyroPoint), ImuError> {
   // set default values if none given
   let max_calibration_time = max_calibration_time.unwrap_or(Duration::from_millis(CALIB_TIME));
   let diff_between_iters = diff_between_iters.unwrap_or(CALIB_DIFF);
@@ -88,15 +113,18 @@ pub fn calibrate_mpu6050(i2c: RefMut<I2c>, max_calibration_time: Option<Duration
   let mut consistent_gyro_iters  = 0;
 
   // final values to be used for calibration
-  let mut avg_accel_offset = get_acceleration(&i2c);
-  let mut avg_gyro_offset  = get_gyroscope(&i2c);
+  let first = imu.read_raw()?;
+  let mut avg_accel_offset = first.accel;
+  let mut avg_gyro_offset  = first.gyro;
 
   let start = Instant::now();
 
   loop {
+    let sample = imu.read_raw()?;
+
     // calculate acceleration (if needed)
     if !avg_accel_found {
-      let accel_point = get_acceleration(&i2c);
+      let accel_point = sample.accel;
       accel_vec.push(accel_point);
       let avg = get_average(&accel_vec);
 
@@ -118,7 +146,7 @@ pub fn calibrate_mpu6050(i2c: RefMut<I2c>, max_calibration_time: Option<Duration
     
     // calculate gyroscope (if needed)
     if !avg_gyro_found {
-      let gyro_point = get_gyroscope(&i2c);
+      let gyro_point = sample.gyro;
       gyro_vec.push(gyro_point);
       let avg = get_average(&gyro_vec);
 
@@ -143,17 +171,17 @@ pub fn calibrate_mpu6050(i2c: RefMut<I2c>, max_calibration_time: Option<Duration
     }
     // check if max time has been exceeded
     if start.elapsed() >= max_calibration_time {
-      println!("Calibration time exceeded");
+      eprintln!("Calibration time exceeded");
       break;
     };
   }
 
-  (avg_accel_offset, avg_gyro_offset)
+  Ok((avg_accel_offset, avg_gyro_offset))
 }
 
 
 /// Returns the mean average of a vector of DataPoints
-fn get_average<T>(points: &Vec<T>) -> T 
+fn get_average<T>(points: &[T]) -> T 
 where
   T: DataPointType + Default
 {
@@ -176,59 +204,149 @@ where
 }
 
 
+/// Writes a single register
+pub(crate) fn write_register(i2c: &mut I2c, register: u8, value: u8) -> Result<(), ImuError> {
+  Ok(bus::write_register(i2c, register, value)?)
+}
+
+
+/// Reads consecutive registers starting at 'register', counting failed reads
+pub(crate) fn read_registers(i2c: &I2c, register: u8, buffer: &mut [u8]) -> Result<(), ImuError> {
+  Ok(bus::read_registers(i2c, register, buffer)?)
+}
+
+
 /// Reads mpu6050 and returns the acceleration data as a DataPoint struct
-fn get_acceleration(i2c: &RefMut<I2c>) -> AccelPoint {
+fn get_acceleration(i2c: &RefMut<I2c>) -> Result<AccelPoint, ImuError> {
   let mut accel_data = [0; 6];
-  let _ = i2c.write_read(&[0x3B], &mut accel_data);
+  read_registers(i2c, registers::ACCEL_XOUT_H, &mut accel_data)?;
 
   let accel_x = i16::from_be_bytes([accel_data[0], accel_data[1]]);
   let accel_y = i16::from_be_bytes([accel_data[2], accel_data[3]]);
   let accel_z = i16::from_be_bytes([accel_data[4], accel_data[5]]);
-  AccelPoint::Accel(DataPoint {x: accel_x, y: accel_y, z: accel_z})
+  Ok(AccelPoint::Accel(DataPoint {x: accel_x, y: accel_y, z: accel_z}))
+}
+
+/// Reads the acceleration, temperature and gyroscope registers in one transfer
+pub fn get_raw_sample(i2c: &RefMut<I2c>) -> Result<RawSample, ImuError> {
+  let mut data = [0; 14];
+  read_registers(i2c, registers::ACCEL_XOUT_H, &mut data)?;
+  Ok(parse_raw_sample(&data))
+}
+
+/// Parses the 14 bytes of the accelerometer, temperature and gyroscope registers
+/// (the same layout as a fifo frame with all of them enabled)
+pub(crate) fn parse_raw_sample(data: &[u8]) -> RawSample {
+  let word = |i: usize| i16::from_be_bytes([data[i], data[i + 1]]);
+  RawSample {
+    accel: AccelPoint::new(word(0), word(2), word(4)),
+    temperature_c: convert_temperature(word(6)),
+    gyro: GyroPoint::new(word(8), word(10), word(12)),
+  }
+}
+
+/// Returns the number of failed acceleration and gyroscope reads since start up
+pub fn i2c_read_errors() -> u64 {
+  bus::read_errors()
 }
 
-/// Reads mpu6050 and returns the gyroscope data as a DataPoint struct
-fn get_gyroscope(i2c: &RefMut<I2c>) -> GyroPoint {
-  let mut gyro_data = [0; 6];
-  let _ = i2c.write_read(&[0x43], &mut gyro_data);
 
-  let gyro_x = i16::from_be_bytes([gyro_data[0], gyro_data[1]]);
-  let gyro_y = i16::from_be_bytes([gyro_data[2], gyro_data[3]]);
-  let gyro_z = i16::from_be_bytes([gyro_data[4], gyro_data[5]]);
-  GyroPoint::Gyro(DataPoint {x: gyro_x, y: gyro_y, z: gyro_z})
+/// Reads the die temperature in degrees C
+pub fn read_temperature(i2c: &I2c) -> Result<f32, ImuError> {
+  let mut temp_data = [0; 2];
+  read_registers(i2c, registers::TEMP_OUT_H, &mut temp_data)?;
+  Ok(convert_temperature(i16::from_be_bytes(temp_data)))
+}
+
+/// Converts the raw temperature registers to degrees C
+pub(crate) fn convert_temperature(raw: i16) -> f32 {
+  raw as f32 / 340.0 + 36.53
 }
 
 
-/// Converts raw acceleration data to m/s^2
-fn convert_raw_point(raw_point: AccelPoint) -> RawPoint {
+/// Converts raw acceleration data to m/s^2 given the sensitivity in LSB/g
+fn convert_raw_point(raw_point: AccelPoint, sensitivity: f32) -> RawPoint {
   match raw_point {
     AccelPoint::Accel(accel_data) => {
-      let x = accel_data.x as f32 / ACCEL_SENSITIVITY * GRAVITY_ACCEL;
-      let y = accel_data.y as f32 / ACCEL_SENSITIVITY * GRAVITY_ACCEL;
-      let z = accel_data.z as f32 / ACCEL_SENSITIVITY * GRAVITY_ACCEL;
-      RawPoint {x, y, z}
+      let x = accel_data.x as f32 / sensitivity * GRAVITY_ACCEL;
+      let y = accel_data.y as f32 / sensitivity * GRAVITY_ACCEL;
+      let z = accel_data.z as f32 / sensitivity * GRAVITY_ACCEL;
+      RawPoint::new(x, y, z)
     }
   }
 }
 
-/// Gets an acceleration point, applies the calibration offsets, and converts to m/s^2
-pub fn get_converted_acceleration(i2c: &RefMut<I2c>, accel_offsets: &AccelPoint) -> RawPoint {
-  let accel_point = get_acceleration(i2c);
-  let offset_acceleration = accel_point - *accel_offsets;
-  convert_raw_point(offset_acceleration)
+/// Gets a sample, removes the bias drift since calibrating, converts the acceleration to
+/// m/s^2 with gravity removed, and converts the rotation rate to rad/s with its offsets removed.
+///
+/// With a six position correction the bias is known, so the whole reading is corrected (bias,
+/// scale and misalignment) and gravity is removed as 1 g along the corrected reading at rest.
+/// Without one the bias can't be told apart from gravity, so the reading at rest (both) is
+/// subtracted. Either way gravity is taken to point the way it did when the offsets were
+/// taken: a device mounted in another orientation has to be calibrated again (`calibrate`),
+/// or it reads part of gravity as acceleration.
+pub fn get_converted_sample(i2c: &RefMut<I2c>, calibration: &Calibration, sensitivity: &Sensitivity)
+                            -> Result<ImuSample, ImuError> {
+  Ok(convert_sample(&get_raw_sample(i2c)?, calibration, sensitivity))
+}
+
+/// Converts a sample read elsewhere (eg. from the fifo), see get_converted_sample
+pub fn convert_sample(raw: &RawSample, calibration: &Calibration, sensitivity: &Sensitivity) -> ImuSample {
+  let temperature_c = raw.temperature_c;
+  let drift = calibration.accel_drift(temperature_c);
+  let to_g = |point: AccelPoint| [point.x(), point.y(), point.z()].map(|lsb| lsb as f32 / sensitivity.accel);
+  let (reading, rest) = (to_g(raw.accel), to_g(calibration.accel_offsets()));
+  let reading = [0, 1, 2].map(|i| reading[i] - drift[i] / sensitivity.accel);
+
+  let accel_g = match &calibration.accel_correction {
+    Some(correction) => {
+      let (corrected, up) = (correction.apply(reading), correction.apply(rest));
+      let norm = up.iter().map(|v| v * v).sum::<f32>().sqrt();
+      let gravity = if norm > 0.0 { up.map(|v| v / norm) } else { [0.0; 3] };
+      [0, 1, 2].map(|i| corrected[i] - gravity[i])
+    }
+    None => [0, 1, 2].map(|i| reading[i] - rest[i]),
+  };
+  let accel = RawPoint::new(accel_g[0] * GRAVITY_ACCEL, accel_g[1] * GRAVITY_ACCEL, accel_g[2] * GRAVITY_ACCEL);
+
+  let offset_gyro = raw.gyro - calibration.gyro_offsets();
+  let offset_gyro = [offset_gyro.x(), offset_gyro.y(), offset_gyro.z()];
+  let gyro_drift = calibration.gyro_drift(temperature_c);
+  let gyro = [0, 1, 2].map(|i| ((offset_gyro[i] as f32 - gyro_drift[i]) / sensitivity.gyro).to_radians());
+  ImuSample { accel, gyro, temperature_c }
+}
+
+/// The reading at rest the calibration was taken from (gravity plus the bias) in m/s^2,
+/// which points up in the sensor's axes while it's mounted the same way
+pub fn rest_acceleration(calibration: &Calibration, sensitivity: f32) -> RawPoint {
+  convert_raw_point(calibration.accel_offsets(), sensitivity)
+}
+
+/// Same as get_converted_sample, without the temperature
+pub fn get_converted_acceleration(i2c: &RefMut<I2c>, calibration: &Calibration, sensitivity: &Sensitivity)
+                                  -> Result<RawPoint, ImuError> {
+  Ok(get_converted_sample(i2c, calibration, sensitivity)?.accel)
+}
+
+/// Gets an uncorrected acceleration point in g (gravity included)
+pub fn get_acceleration_g(i2c: &RefMut<I2c>, sensitivity: f32) -> Result<[f32; 3], ImuError> {
+  let accel_point = get_acceleration(i2c)?;
+  Ok([accel_point.x(), accel_point.y(), accel_point.z()].map(|v| v as f32 / sensitivity))
 }
 
 #[allow(dead_code)] // this will be used when gyroscope data is taken into account
-/// Gets a gyroscope point and applies the calibration offsets
-pub fn get_offset_gyroscope(i2c: &RefMut<I2c>, gyro_offsets: &GyroPoint) -> GyroPoint {
-  let gyro_point = get_gyroscope(i2c);
-  gyro_point - *gyro_offsets
+/// Gets a gyroscope point and applies the calibration offsets and the bias drift since calibrating
+pub fn get_offset_gyroscope(i2c: &RefMut<I2c>, calibration: &Calibration) -> Result<GyroPoint, ImuError> {
+  let sample = get_raw_sample(i2c)?;
+  let offset_gyro = sample.gyro - calibration.gyro_offsets();
+  let drift = calibration.gyro_drift(sample.temperature_c).map(|d| d.round() as i16);
+  Ok(offset_gyro - GyroPoint::new(drift[0], drift[1], drift[2]))
 }
 
 
 
 
-trait DataPointType {
+pub trait DataPointType {
   fn new(x: i16, y: i16, z: i16) -> Self;
   fn x(&self) -> i16;
   fn y(&self) -> i16;
@@ -237,7 +355,6 @@ trait DataPointType {
 
 
 /// DataPoint Implementations
-
 impl DataPointType for DataPoint {
   fn new(x: i16, y: i16, z: i16) -> Self {
     DataPoint {x, y, z}
@@ -294,7 +411,6 @@ impl Div<i16> for DataPoint {
 
 
 /// Acceleration Implementations
-
 impl DataPointType for AccelPoint {
   fn new(x: i16, y: i16, z: i16) -> Self {
     AccelPoint::Accel(DataPoint::new(x, y, z))
@@ -365,7 +481,6 @@ impl Div<i16> for AccelPoint {
 
 
 /// Gyroscope Implementations
-
 impl DataPointType for GyroPoint {
   fn new(x: i16, y: i16, z: i16) -> Self {
     GyroPoint::Gyro(DataPoint::new(x, y, z))
@@ -435,77 +550,54 @@ impl Div<i16> for GyroPoint {
 }
 
 
-/// Raw Acceleration Implementations
-impl Display for RawPoint {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "Accel: (x: {:.3}, y: {:.3}, z: {:.3})", self.x, self.y, self.z)
-  }
-}
-
-impl RawPoint {
-  pub fn new(x: f32, y: f32, z: f32) -> Self {
-    RawPoint {x, y, z}
-  }
-
-  pub fn x(&self) -> f32 {
-    self.x
-  }
-
-  pub fn y(&self) -> f32 {
-    self.y
-  }
-
-  pub fn z(&self) -> f32 {
-    self.z
+/// ImuError implementations
+impl From<BusError> for ImuError {
+  fn from(e: BusError) -> Self {
+    match e {
+      BusError::Bus(e) => ImuError::Bus(e),
+      BusError::Nack => ImuError::Nack,
+      BusError::Timeout => ImuError::Timeout,
+    }
   }
 }
 
-impl Add for RawPoint {
-  type Output = Self;
-
-  fn add(self, other: Self) -> Self {
-    RawPoint {
-      x: self.x + other.x,
-      y: self.y + other.y,
-      z: self.z + other.z,
+impl From<Mpu6050Error<rppal::i2c::Error>> for ImuError {
+  fn from(e: Mpu6050Error<rppal::i2c::Error>) -> Self {
+    match e {
+      Mpu6050Error::Bus(e) => ImuError::from(e),
+      Mpu6050Error::WrongDevice(id) => ImuError::WrongDevice(id),
     }
   }
 }
 
-impl AddAssign for RawPoint {
-  fn add_assign(&mut self, other: Self) {
-    *self = RawPoint {
-      x: self.x + other.x,
-      y: self.y + other.y,
-      z: self.z + other.z,
-    }
+impl From<rppal::i2c::Error> for ImuError {
+  fn from(e: rppal::i2c::Error) -> Self {
+    ImuError::from(BusError::from(e))
   }
 }
 
-impl Div<RawPoint> for RawPoint {
-  type Output = Self;
-
-  fn div(self, divisor: Self) -> Self {
-    RawPoint {
-      x: self.x / divisor.x,
-      y: self.y / divisor.y,
-      z: self.z / divisor.z,
-    }
+impl From<rppal::gpio::Error> for ImuError {
+  fn from(e: rppal::gpio::Error) -> Self {
+    ImuError::Gpio(e)
   }
 }
 
-impl Div<f32> for RawPoint {
-  type Output = Self;
-
-  fn div(self, divisor: f32) -> Self {
-    RawPoint {
-      x: self.x / divisor,
-      y: self.y / divisor,
-      z: self.z / divisor,
+impl Display for ImuError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ImuError::Bus(e) => write!(f, "i2c bus error: {e}"),
+      ImuError::Gpio(e) => write!(f, "interrupt pin error: {e}"),
+      ImuError::Nack => write!(f, "no acknowledgement from the imu, check the wiring and address"),
+      ImuError::WrongDevice(id) => write!(f, "unexpected device (WHO_AM_I {id:#04x})"),
+      ImuError::NotFound => write!(f, "no supported imu found on the bus"),
+      ImuError::Timeout => write!(f, "i2c transfer timed out"),
+      ImuError::FifoOverflow => write!(f, "fifo overflowed, samples were lost"),
     }
   }
 }
 
+impl std::error::Error for ImuError {}
+
 
 
 #[cfg(test)]
@@ -527,6 +619,20 @@ mod tests {
     assert_eq!(avg.z, 5);
   }
 
+  #[test]
+  fn test_imu_error_from_i2c() {
+    use crate::bus::i2c::{ENXIO, EREMOTEIO, ETIMEDOUT};
+    use std::io;
+
+    let os_error = |errno| rppal::i2c::Error::Io(io::Error::from_raw_os_error(errno));
+    assert!(matches!(ImuError::from(os_error(ENXIO)), ImuError::Nack));
+    assert!(matches!(ImuError::from(os_error(EREMOTEIO)), ImuError::Nack));
+    assert!(matches!(ImuError::from(os_error(ETIMEDOUT)), ImuError::Timeout));
+    assert!(matches!(ImuError::from(os_error(5)), ImuError::Bus(_))); // EIO
+    assert!(matches!(ImuError::from(rppal::i2c::Error::InvalidSlaveAddress(0x80)), ImuError::Bus(_)));
+    assert!(ImuError::WrongDevice(0x70).to_string().contains("0x70"));
+  }
+
   #[test]
   fn test_add_datapoint() {
     let p1 = DataPoint {x: 1, y: 2, z: 3};
@@ -556,4 +662,26 @@ mod tests {
     assert_eq!(p2.z, 18);
   }
 
+  #[test]
+  fn test_convert_sample_applies_correction() {
+    use crate::mpu6050::calibration::SensorInfo;
+    use crate::mpu6050::six_position::AccelCorrection;
+
+    let sensor = SensorInfo { device_id: "test".to_string(), accel_range_g: 2, gyro_range_dps: 250 };
+    let sensitivity = Sensitivity { accel: 16384.0, gyro: 131.0 };
+    // at rest the sensor reads 1 g up z plus a 0.1 g bias on x and z, and its z axis reads 2% high
+    let rest = AccelPoint::new(1638, 0, 18350);
+    let mut calibration = Calibration::new(sensor, None, &rest, &GyroPoint::new(0, 0, 0));
+    let correction = AccelCorrection { matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0 / 1.02]], bias: [0.1, 0.0, 0.1] };
+    calibration.accel_correction = Some(correction);
+
+    let still = RawSample { accel: rest, temperature_c: 25.0, gyro: GyroPoint::new(131, 0, 0) };
+    let sample = convert_sample(&still, &calibration, &sensitivity);
+    assert!(sample.accel.x().abs() < 0.01 && sample.accel.z().abs() < 0.01, "{:?}", sample.accel);
+    assert!((sample.gyro[0] - 1.0f32.to_radians()).abs() < 1e-6);
+
+    // 0.5 g more up z is 0.5 g once the scale error is corrected, not 0.51 g
+    let climbing = RawSample { accel: AccelPoint::new(1638, 0, 18350 + 8356), ..still };
+    assert!((convert_sample(&climbing, &calibration, &sensitivity).accel.z() - 0.5 * GRAVITY_ACCEL).abs() < 0.01);
+  }
 }
\ No newline at end of file
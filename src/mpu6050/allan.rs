@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+
+pub const POINTS_PER_DECADE: usize = 10; // Averaging times computed per decade of tau
+pub const MIN_CLUSTERS: usize = 10; // Fewest independent clusters an averaging time needs for its deviation to be trusted
+const SLOPE_TOLERANCE: f64 = 0.15; // Largest difference from the ideal slope for a noise term to be read off the curve
+const BIAS_INSTABILITY_FACTOR: f64 = 0.664; // sqrt(2 ln 2 / pi), ratio of the flat part of the curve to the bias instability
+
+
+/// Allan deviation at one averaging time
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AdevPoint {
+  pub tau_s: f64,
+  pub adev: f64,
+}
+
+/// Noise terms of one axis, read off its Allan deviation curve. Units are those of
+/// the samples (m/s^2 for the accelerometer, rad/s for the gyroscope).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AxisNoise {
+  pub random_walk: Option<f64>,      // velocity/angle random walk, unit/sqrt(Hz) (the -1/2 slope at tau = 1 s)
+  pub bias_instability: Option<f64>, // unit, from the flat bottom of the curve
+  pub bias_instability_tau_s: Option<f64>,
+  pub rate_random_walk: Option<f64>, // unit*sqrt(Hz) (the +1/2 slope at tau = 3 s)
+  pub adev: Vec<AdevPoint>,
+}
+
+/// Noise parameters of an imu, saved as toml for tuning detector thresholds by hand or
+/// with other tools (nothing in this crate reads it back yet)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoiseProfile {
+  pub sensor: String,
+  pub sample_rate_hz: f64,
+  pub duration_s: f64,
+  pub accel: [AxisNoise; 3], // m/s^2
+  pub gyro: [AxisNoise; 3],  // rad/s
+}
+
+
+/// Averaging cluster sizes (in samples) spaced evenly on a log scale, up to
+/// the largest that still leaves MIN_CLUSTERS clusters in 'n' samples
+pub fn cluster_sizes(n: usize, points_per_decade: usize) -> Vec<usize> {
+  let max = n / MIN_CLUSTERS;
+  let mut sizes: Vec<usize> = Vec::new();
+  let mut i = 0;
+  loop {
+    let m = 10f64.powf(i as f64 / points_per_decade as f64).round() as usize;
+    if m > max {
+      break;
+    }
+    if sizes.last() != Some(&m) {
+      sizes.push(m);
+    }
+    i += 1;
+  }
+  sizes
+}
+
+
+/// Overlapping Allan deviation of samples taken at 'rate_hz'
+pub fn overlapping_adev(samples: &[f32], rate_hz: f64) -> Vec<AdevPoint> {
+  let n = samples.len();
+  if n < 2 * MIN_CLUSTERS {
+    return Vec::new();
+  }
+  let tau0 = 1.0 / rate_hz;
+
+  // integrate (with the mean removed to keep the sums small)
+  let mean = samples.iter().map(|&s| s as f64).sum::<f64>() / n as f64;
+  let mut theta = Vec::with_capacity(n + 1);
+  theta.push(0.0);
+  for &sample in samples {
+    let last = theta[theta.len() - 1];
+    theta.push(last + (sample as f64 - mean) * tau0);
+  }
+
+  cluster_sizes(n, POINTS_PER_DECADE).into_iter().map(|m| {
+    let tau = m as f64 * tau0;
+    let terms = theta.len() - 2 * m;
+    let sum: f64 = (0..terms).map(|k| (theta[k + 2 * m] - 2.0 * theta[k + m] + theta[k]).powi(2)).sum();
+    AdevPoint { tau_s: tau, adev: (sum / (2.0 * tau * tau * terms as f64)).sqrt() }
+  }).collect()
+}
+
+
+/// Reads the noise terms off an Allan deviation curve
+pub fn analyze(adev: Vec<AdevPoint>) -> AxisNoise {
+  let random_walk = line_at(&adev, -0.5, 1.0);
+  let rate_random_walk = line_at(&adev, 0.5, 3.0);
+  let bias_instability = flat_bottom(&adev);
+
+  AxisNoise {
+    random_walk,
+    bias_instability: bias_instability.map(|min| min.adev / BIAS_INSTABILITY_FACTOR),
+    bias_instability_tau_s: bias_instability.map(|min| min.tau_s),
+    rate_random_walk,
+    adev,
+  }
+}
+
+
+/// The lowest point of the curve, if the curve has flattened out there. A minimum at the
+/// longest averaging time that's still falling (eg. white noise, or a recording too short
+/// to reach the flat part) doesn't give the bias instability, so None is returned.
+fn flat_bottom(adev: &[AdevPoint]) -> Option<AdevPoint> {
+  let (index, min) = adev.iter().copied().enumerate().min_by(|a, b| a.1.adev.total_cmp(&b.1.adev))?;
+  if index + 1 < adev.len() {
+    return Some(min); // turns back up after it
+  }
+  let previous = adev.get(index.checked_sub(1)?)?;
+  let slope = (min.adev.log10() - previous.adev.log10()) / (min.tau_s.log10() - previous.tau_s.log10());
+  (slope.abs() <= SLOPE_TOLERANCE).then_some(min)
+}
+
+
+/// Fits a line of 'slope' (log-log) through the part of the curve closest to that
+/// slope and returns its value at 'tau_s', or None if no part of the curve has it
+fn line_at(adev: &[AdevPoint], slope: f64, tau_s: f64) -> Option<f64> {
+  let (index, local_slope) = adev.windows(2).enumerate()
+    .map(|(i, w)| (i, (w[1].adev.log10() - w[0].adev.log10()) / (w[1].tau_s.log10() - w[0].tau_s.log10())))
+    .filter(|(_, s)| s.is_finite())
+    .min_by(|a, b| (a.1 - slope).abs().total_cmp(&(b.1 - slope).abs()))?;
+  if (local_slope - slope).abs() > SLOPE_TOLERANCE {
+    return None;
+  }
+  let point = adev[index];
+  let intercept = point.adev.log10() - slope * point.tau_s.log10();
+  Some(10f64.powf(intercept + slope * tau_s.log10()))
+}
+
+
+/// NoiseProfile implementations
+impl NoiseProfile {
+  pub fn load(path: &str) -> io::Result<NoiseProfile> {
+    let text = fs::read_to_string(path)?;
+    toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+  }
+
+  pub fn save(&self, path: &str) -> io::Result<()> {
+    let text = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(path, text)
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Deterministic standard normal samples (Box-Muller over an xorshift generator)
+  fn white_noise(n: usize, sigma: f32) -> Vec<f32> {
+    let mut state = 0x2545F4914F6CDD1Du64;
+    let mut uniform = move || {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      ((state >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    };
+    (0..n).map(|_| {
+      let (u1, u2) = (uniform(), uniform());
+      ((-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()) as f32 * sigma
+    }).collect()
+  }
+
+  #[test]
+  fn test_cluster_sizes() {
+    let sizes = cluster_sizes(1000, 10);
+    assert_eq!(sizes[..5], [1, 2, 3, 4, 5]);
+    assert_eq!(*sizes.last().unwrap(), 100);
+    assert!(sizes.windows(2).all(|w| w[0] < w[1]));
+  }
+
+  #[test]
+  fn test_white_noise_random_walk() {
+    // white noise of sigma at rate f has a random walk coefficient of sigma / sqrt(f)
+    let rate = 100.0;
+    let adev = overlapping_adev(&white_noise(200_000, 0.05), rate);
+    assert!((adev[0].adev - 0.05).abs() < 0.002); // tau0 is the sample standard deviation
+
+    let noise = analyze(adev);
+    let expected = 0.05 / rate.sqrt();
+    let random_walk = noise.random_walk.unwrap();
+    assert!((random_walk - expected).abs() / expected < 0.05, "{random_walk} vs {expected}");
+    assert!(noise.rate_random_walk.is_none()); // white noise never turns back up
+    assert!(noise.bias_instability.is_none()); // or flattens
+  }
+
+  #[test]
+  fn test_bias_instability_from_flat_bottom() {
+    let curve = |adevs: &[f64]| adevs.iter().enumerate()
+      .map(|(i, &adev)| AdevPoint { tau_s: 10f64.powi(i as i32), adev })
+      .collect::<Vec<_>>();
+    let noise = analyze(curve(&[1.0, 0.32, 0.1, 0.09, 0.1, 0.3]));
+    assert!((noise.bias_instability.unwrap() - 0.09 / BIAS_INSTABILITY_FACTOR).abs() < 1e-9);
+    assert_eq!(noise.bias_instability_tau_s, Some(1000.0));
+    assert!(analyze(curve(&[1.0, 0.32, 0.1, 0.098])).bias_instability.is_some()); // flat at the end
+    assert!(analyze(curve(&[1.0, 0.32, 0.1])).bias_instability.is_none()); // still falling
+  }
+
+  #[test]
+  fn test_profile_round_trip() {
+    let noise = analyze(overlapping_adev(&white_noise(10_000, 0.01), 100.0));
+    let profile = NoiseProfile {
+      sensor: "mpu6050".to_string(),
+      sample_rate_hz: 100.0,
+      duration_s: 100.0,
+      accel: [noise.clone(), noise.clone(), noise.clone()],
+      gyro: [noise.clone(), noise.clone(), noise],
+    };
+    let path = std::env::temp_dir().join(format!("noise_profile_test_{}.toml", std::process::id()));
+    let path = path.to_str().unwrap();
+    profile.save(path).unwrap();
+    let loaded = NoiseProfile::load(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded.accel[0].random_walk, profile.accel[0].random_walk);
+    assert_eq!(loaded.gyro[2].adev.len(), profile.gyro[2].adev.len());
+  }
+}
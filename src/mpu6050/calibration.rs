@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::mpu6050::accel::{AccelPoint, DataPointType, GyroPoint};
+use crate::mpu6050::six_position::AccelCorrection;
+use crate::mpu6050::temperature::TemperatureModel;
+
+pub const CALIBRATION_VERSION: u32 = 1; // Bump when the saved calibration's fields change meaning
+pub const MAX_AGE_DAYS: u32 = 30; // Default age after which a saved calibration is redone
+pub const MAX_TEMP_DIFF_C: f32 = 10.0; // Default temperature change after which a saved calibration is redone
+
+
+/// Calibration results saved to disk so the sensor doesn't have to be kept
+/// still for a new calibration at every start up
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+  pub version: u32,
+  #[serde(flatten)]
+  pub sensor: SensorInfo,
+  pub timestamp: u64,             // unix seconds the calibration was taken at
+  pub temperature_c: Option<f32>, // die temperature during calibration, None if it couldn't be read
+  pub accel_offset: [i16; 3],
+  pub gyro_offset: [i16; 3],
+  #[serde(default)]
+  pub accel_correction: Option<AccelCorrection>, // from a six position calibration, when one was done
+  #[serde(default)]
+  pub temperature_model: Option<TemperatureModel>, // from a temperature calibration, when one was done
+}
+
+/// The sensor and settings a calibration was taken with, which must match for it to be reused
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorInfo {
+  pub device_id: String,
+  pub accel_range_g: u8,
+  pub gyro_range_dps: u16,
+}
+
+/// When a saved calibration is too old to trust
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StalenessPolicy {
+  pub max_age: Duration,
+  pub max_temp_diff_c: f32,
+}
+
+
+/// Calibration implementations
+impl Calibration {
+  /// A calibration taken now
+  pub fn new(sensor: SensorInfo, temperature_c: Option<f32>, accel_offsets: &AccelPoint, gyro_offsets: &GyroPoint) -> Calibration {
+    Calibration {
+      version: CALIBRATION_VERSION,
+      sensor,
+      timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+      temperature_c,
+      accel_offset: [accel_offsets.x(), accel_offsets.y(), accel_offsets.z()],
+      gyro_offset: [gyro_offsets.x(), gyro_offsets.y(), gyro_offsets.z()],
+      accel_correction: None,
+      temperature_model: None,
+    }
+  }
+
+  pub fn accel_offsets(&self) -> AccelPoint {
+    AccelPoint::new(self.accel_offset[0], self.accel_offset[1], self.accel_offset[2])
+  }
+
+  pub fn gyro_offsets(&self) -> GyroPoint {
+    GyroPoint::new(self.gyro_offset[0], self.gyro_offset[1], self.gyro_offset[2])
+  }
+
+  /// The scale and misalignment correction, or none if no six position calibration was done
+  pub fn correction(&self) -> AccelCorrection {
+    self.accel_correction.unwrap_or_default()
+  }
+
+  /// Accelerometer bias drift (LSB) since calibrating, zero without a temperature model
+  pub fn accel_drift(&self, temperature_c: f32) -> [f32; 3] {
+    match (&self.temperature_model, self.temperature_c) {
+      (Some(model), Some(calibrated_c)) => model.accel_drift(calibrated_c, temperature_c),
+      _ => [0.0; 3],
+    }
+  }
+
+  /// Gyroscope bias drift (LSB) since calibrating, zero without a temperature model
+  pub fn gyro_drift(&self, temperature_c: f32) -> [f32; 3] {
+    match (&self.temperature_model, self.temperature_c) {
+      (Some(model), Some(calibrated_c)) => model.gyro_drift(calibrated_c, temperature_c),
+      _ => [0.0; 3],
+    }
+  }
+
+  /// Time since the calibration was taken (zero if the clock is behind it)
+  pub fn age(&self, now: SystemTime) -> Duration {
+    let taken = UNIX_EPOCH + Duration::from_secs(self.timestamp);
+    now.duration_since(taken).unwrap_or_default()
+  }
+
+  /// Reads a calibration saved with save
+  pub fn load(path: &str) -> io::Result<Calibration> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+  }
+
+  /// Writes the calibration as json. A temporary file is renamed into place so a
+  /// power cut while saving can't leave a half written calibration behind.
+  pub fn save(&self, path: &str) -> io::Result<()> {
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+    fs::rename(&tmp, path)
+  }
+
+  /// Returns why the calibration shouldn't be reused for 'sensor' at 'temperature_c',
+  /// or None if it's still good
+  pub fn stale_reason(&self, policy: &StalenessPolicy, sensor: &SensorInfo, temperature_c: Option<f32>,
+                      now: SystemTime) -> Option<String> {
+    if self.version != CALIBRATION_VERSION {
+      return Some(format!("saved with format version {}", self.version));
+    }
+    if self.sensor.device_id != sensor.device_id {
+      return Some(format!("taken on device {}", self.sensor.device_id));
+    }
+    if self.sensor.accel_range_g != sensor.accel_range_g || self.sensor.gyro_range_dps != sensor.gyro_range_dps {
+      return Some(format!("taken at +-{} g and +-{} deg/s", self.sensor.accel_range_g, self.sensor.gyro_range_dps));
+    }
+    let age = self.age(now);
+    if age > policy.max_age {
+      return Some(format!("{} days old", age.as_secs() / 86400));
+    }
+    if let (Some(then), Some(now)) = (self.temperature_c, temperature_c) {
+      // a temperature model compensates the drift, but only over the range it was fitted
+      let compensated = self.temperature_model.as_ref().is_some_and(|model| model.covers(now));
+      if !compensated && (now - then).abs() > policy.max_temp_diff_c {
+        return Some(format!("taken at {then:.1} C, sensor is now {now:.1} C"));
+      }
+    }
+    None
+  }
+}
+
+
+/// StalenessPolicy implementations
+impl Default for StalenessPolicy {
+  fn default() -> Self {
+    StalenessPolicy {
+      max_age: Duration::from_secs(MAX_AGE_DAYS as u64 * 86400),
+      max_temp_diff_c: MAX_TEMP_DIFF_C,
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mpu6050::temperature::BiasPoint;
+
+  fn sensor() -> SensorInfo {
+    SensorInfo { device_id: "i2c-1-0x68".to_string(), accel_range_g: 2, gyro_range_dps: 2000 }
+  }
+
+  fn calibration() -> Calibration {
+    Calibration::new(sensor(), Some(25.0), &AccelPoint::new(10, -20, 16000), &GyroPoint::new(1, 2, 3))
+  }
+
+  #[test]
+  fn test_save_and_load() {
+    let path = std::env::temp_dir().join(format!("calibration_test_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let saved = calibration();
+    saved.save(path).unwrap();
+    let loaded = Calibration::load(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded, saved);
+    assert_eq!(loaded.accel_offsets().z(), 16000);
+  }
+
+  #[test]
+  fn test_staleness() {
+    let policy = StalenessPolicy::default();
+    let calibration = calibration();
+    let now = SystemTime::now();
+    assert_eq!(calibration.stale_reason(&policy, &sensor(), Some(27.0), now), None);
+    assert_eq!(calibration.stale_reason(&policy, &sensor(), None, now), None);
+
+    let later = now + Duration::from_secs(31 * 86400);
+    assert!(calibration.stale_reason(&policy, &sensor(), Some(25.0), later).is_some());
+    assert!(calibration.stale_reason(&policy, &sensor(), Some(40.0), now).is_some());
+
+    let other = SensorInfo { accel_range_g: 4, ..sensor() };
+    assert!(calibration.stale_reason(&policy, &other, Some(25.0), now).is_some());
+    let other = SensorInfo { device_id: "i2c-1-0x69".to_string(), ..sensor() };
+    assert!(calibration.stale_reason(&policy, &other, Some(25.0), now).is_some());
+  }
+
+  #[test]
+  fn test_temperature_model() {
+    let points: Vec<BiasPoint> = (0..30).map(|i| {
+      let t = 20.0 + i as f32;
+      BiasPoint { temperature_c: t, accel: [4.0 * t, 0.0, 0.0], gyro: [0.0, -t, 0.0] }
+    }).collect();
+    let mut calibration = calibration();
+    assert_eq!(calibration.accel_drift(40.0), [0.0; 3]);
+
+    calibration.temperature_model = TemperatureModel::fit(&points, 1);
+    assert!((calibration.accel_drift(35.0)[0] - 40.0).abs() < 1e-2); // calibrated at 25 C
+    assert!((calibration.gyro_drift(35.0)[1] + 10.0).abs() < 1e-2);
+
+    // a covered temperature change no longer makes the calibration stale
+    let policy = StalenessPolicy::default();
+    assert_eq!(calibration.stale_reason(&policy, &sensor(), Some(40.0), SystemTime::now()), None);
+    assert!(calibration.stale_reason(&policy, &sensor(), Some(60.0), SystemTime::now()).is_some());
+  }
+}
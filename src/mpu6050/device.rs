@@ -0,0 +1,185 @@
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use std::fmt::Display;
+
+use crate::bus::i2c as bus;
+use crate::mpu6050::accel::{self, RawSample, MPU6050_WHO_AM_I};
+use crate::mpu6050::registers::{self, ImuSettings};
+
+
+/// An mpu6050 on any bus implementing the embedded-hal blocking i2c traits: rppal's I2c
+/// on a Pi, linux-embedded-hal's I2cdev on other Linux boards, or a mock in tests.
+/// Also drives the register compatible MPU9250, see 'configure'.
+pub struct Mpu6050<I2C> {
+  i2c: I2C,
+  address: u8,
+}
+
+/// Errors from the mpu6050 driver, 'E' being the bus implementation's own error
+#[derive(Debug)]
+pub enum Mpu6050Error<E> {
+  Bus(E),          // a transfer failed
+  WrongDevice(u8), // WHO_AM_I read back something other than an mpu6050's
+}
+
+
+/// Mpu6050 implementations
+impl<I2C, E> Mpu6050<I2C> where I2C: Write<Error = E> + WriteRead<Error = E> {
+  /// Wraps the bus for talking to the mpu6050 at 'address'. Nothing is sent until 'init'.
+  pub fn new(i2c: I2C, address: u8) -> Self {
+    Mpu6050 { i2c, address }
+  }
+
+  /// Checks an mpu6050 is answering and writes 'settings', waking it
+  pub fn init(&mut self, settings: &ImuSettings) -> Result<(), Mpu6050Error<E>> {
+    let who_am_i = self.who_am_i()?;
+    if who_am_i != MPU6050_WHO_AM_I {
+      return Err(Mpu6050Error::WrongDevice(who_am_i));
+    }
+    self.configure(settings)
+  }
+
+  /// Writes the ranges, low pass filter, sample rate and clock source (waking it) without
+  /// checking WHO_AM_I, for compatible chips that identify themselves differently
+  pub fn configure(&mut self, settings: &ImuSettings) -> Result<(), Mpu6050Error<E>> {
+    for (register, value) in settings.register_writes() {
+      self.write_register(register, value)?;
+    }
+    Ok(())
+  }
+
+  /// Reads the WHO_AM_I register to check what is answering at the address
+  pub fn who_am_i(&mut self) -> Result<u8, Mpu6050Error<E>> {
+    let mut who_am_i = [0; 1];
+    self.read_registers(registers::WHO_AM_I, &mut who_am_i)?;
+    Ok(who_am_i[0])
+  }
+
+  /// Reads the acceleration, temperature and gyroscope registers in one transfer
+  pub fn read_raw(&mut self) -> Result<RawSample, Mpu6050Error<E>> {
+    let mut data = [0; 14];
+    self.read_registers(registers::ACCEL_XOUT_H, &mut data)?;
+    Ok(accel::parse_raw_sample(&data))
+  }
+
+  /// Reads the temperature registers, unconverted as the scale depends on the chip
+  pub fn read_raw_temperature(&mut self) -> Result<i16, Mpu6050Error<E>> {
+    let mut data = [0; 2];
+    self.read_registers(registers::TEMP_OUT_H, &mut data)?;
+    Ok(i16::from_be_bytes(data))
+  }
+
+  /// Writes a single register
+  pub fn write_register(&mut self, register: u8, value: u8) -> Result<(), Mpu6050Error<E>> {
+    self.i2c.write(self.address, &[register, value]).map_err(Mpu6050Error::Bus)
+  }
+
+  /// Reads consecutive registers starting at 'register', counting failed reads
+  pub fn read_registers(&mut self, register: u8, buffer: &mut [u8]) -> Result<(), Mpu6050Error<E>> {
+    self.i2c.write_read(self.address, &[register], buffer).map_err(|e| {
+      bus::count_read_error();
+      Mpu6050Error::Bus(e)
+    })
+  }
+
+  pub fn address(&self) -> u8 {
+    self.address
+  }
+
+  /// The bus, for transfers the driver doesn't cover (eg. the fifo)
+  pub fn bus(&mut self) -> &mut I2C {
+    &mut self.i2c
+  }
+
+  /// Gives the bus back
+  pub fn release(self) -> I2C {
+    self.i2c
+  }
+}
+
+impl<E: std::fmt::Debug> Display for Mpu6050Error<E> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Mpu6050Error::Bus(e) => write!(f, "i2c bus error: {e:?}"),
+      Mpu6050Error::WrongDevice(id) => write!(f, "unexpected device (WHO_AM_I {id:#04x})"),
+    }
+  }
+}
+
+impl<E: std::fmt::Debug> std::error::Error for Mpu6050Error<E> {}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mpu6050::accel::DataPointType;
+
+  /// A bus with a single device whose registers are an array
+  struct MockBus {
+    address: u8,
+    registers: [u8; 128],
+    writes: Vec<(u8, u8)>,
+  }
+
+  impl Write for MockBus {
+    type Error = &'static str;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+      if address != self.address {
+        return Err("nack");
+      }
+      for (i, value) in bytes[1..].iter().enumerate() {
+        self.registers[bytes[0] as usize + i] = *value;
+        self.writes.push((bytes[0] + i as u8, *value));
+      }
+      Ok(())
+    }
+  }
+
+  impl WriteRead for MockBus {
+    type Error = &'static str;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+      if address != self.address {
+        return Err("nack");
+      }
+      let start = bytes[0] as usize;
+      buffer.copy_from_slice(&self.registers[start..start + buffer.len()]);
+      Ok(())
+    }
+  }
+
+  fn mock(who_am_i: u8) -> MockBus {
+    let mut registers = [0; 128];
+    registers[registers::WHO_AM_I as usize] = who_am_i;
+    MockBus { address: 0x68, registers, writes: Vec::new() }
+  }
+
+  #[test]
+  fn test_init() {
+    let settings = ImuSettings::default();
+    let mut mpu = Mpu6050::new(mock(MPU6050_WHO_AM_I), 0x68);
+    mpu.init(&settings).unwrap();
+    assert_eq!(mpu.release().writes, settings.register_writes().to_vec());
+
+    let mut mpu = Mpu6050::new(mock(0x70), 0x68);
+    assert!(matches!(mpu.init(&settings), Err(Mpu6050Error::WrongDevice(0x70))));
+    assert!(mpu.release().writes.is_empty());
+
+    let mut mpu = Mpu6050::new(mock(MPU6050_WHO_AM_I), 0x69);
+    assert!(matches!(mpu.init(&settings), Err(Mpu6050Error::Bus("nack"))));
+  }
+
+  #[test]
+  fn test_read_raw() {
+    let mut bus = mock(MPU6050_WHO_AM_I);
+    let data = [0x40, 0x00, 0xC0, 0x00, 0x00, 0x10, 0xEE, 0x50, 0x00, 0x83, 0xFF, 0x7D, 0x00, 0x00];
+    bus.registers[registers::ACCEL_XOUT_H as usize..][..14].copy_from_slice(&data);
+    let mut mpu = Mpu6050::new(bus, 0x68);
+    let sample = mpu.read_raw().unwrap();
+    assert_eq!((sample.accel.x(), sample.accel.y(), sample.accel.z()), (16384, -16384, 16));
+    assert_eq!((sample.gyro.x(), sample.gyro.y(), sample.gyro.z()), (131, -131, 0));
+    assert!((sample.temperature_c - 23.2).abs() < 0.1);
+    assert_eq!(mpu.read_raw_temperature().unwrap(), -4528);
+  }
+}
@@ -0,0 +1,220 @@
+use rppal::i2c::I2c;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::mpu6050::accel::{self, ImuError, RawSample};
+use crate::mpu6050::registers::{self, ImuSettings};
+
+pub const FIFO_SIZE: usize = 1024; // Bytes the mpu6050 fifo holds
+pub const FRAME_SIZE: usize = 14; // Bytes per sample with the accelerometer, temperature and gyroscope enabled
+const FIFO_SENSORS: u8 = 0xF8; // FIFO_EN bits for the temperature, every gyroscope axis and the accelerometer
+const USER_CTRL_FIFO_EN: u8 = 0x40; // USER_CTRL bit that enables the fifo
+const USER_CTRL_FIFO_RESET: u8 = 0x04; // USER_CTRL bit that empties the fifo
+const FIFO_OFLOW: u8 = 0x10; // INT_ENABLE/INT_STATUS bit for a fifo overflow
+const MAX_RESTARTS: u32 = 3; // Overflows in a row collect gives up after
+const MIN_RATE_SAMPLES: u64 = 200; // Samples since a reset before the sample period is measured rather than assumed
+
+
+/// A sample read from the fifo, with its time tied to when its burst was read
+#[derive(Clone, Copy, Debug)]
+pub struct TimedSample {
+  pub t: f64, // seconds since the fifo was last reset
+  pub sample: RawSample,
+}
+
+/// Samples read in one burst
+#[derive(Clone, Debug, Default)]
+pub struct Burst {
+  pub samples: Vec<TimedSample>,
+  pub overflowed: bool, // samples were lost before these, so they don't follow on from the last burst
+}
+
+/// Reads the mpu6050 fifo in bursts and timestamps each sample. The sensor's oscillator
+/// can be a percent or so off, so the samples aren't simply counted at the nominal rate:
+/// each burst is anchored to the host clock when it was read instead.
+#[derive(Debug)]
+pub struct Fifo {
+  sample_period: f64,  // nominal seconds between samples
+  next_index: u64,     // samples since the last reset
+  last_t: Option<f64>, // time of the last sample read since the reset
+  reset_at: Instant,
+  overflows: u64,
+}
+
+
+/// Fifo implementations
+impl Fifo {
+  /// A fifo for samples at 'sample_rate_hz', not yet enabled on the sensor
+  pub fn new(sample_rate_hz: f32) -> Fifo {
+    Fifo { sample_period: 1.0 / sample_rate_hz as f64, next_index: 0, last_t: None, reset_at: Instant::now(), overflows: 0 }
+  }
+
+  /// Enables the fifo with every sensor written to it, at the rate set by 'settings'
+  pub fn enable(i2c: &mut I2c, settings: &ImuSettings) -> Result<Fifo, ImuError> {
+    let mut fifo = Fifo::new(settings.sample_rate_hz());
+    accel::write_register(i2c, registers::INT_ENABLE, FIFO_OFLOW)?;
+    accel::write_register(i2c, registers::FIFO_EN, FIFO_SENSORS)?;
+    fifo.reset(i2c)?;
+    Ok(fifo)
+  }
+
+  /// Empties the fifo and restarts the sample times from 0
+  pub fn reset(&mut self, i2c: &mut I2c) -> Result<(), ImuError> {
+    accel::write_register(i2c, registers::USER_CTRL, USER_CTRL_FIFO_RESET)?;
+    accel::write_register(i2c, registers::USER_CTRL, USER_CTRL_FIFO_EN)?;
+    let mut status = [0; 1];
+    accel::read_registers(i2c, registers::INT_STATUS, &mut status)?; // clear a stale overflow flag
+    self.next_index = 0;
+    self.last_t = None;
+    self.reset_at = Instant::now();
+    Ok(())
+  }
+
+  /// Reads every whole sample waiting in the fifo. After an overflow (or if the
+  /// count isn't a whole number of frames) the fifo is reset to realign it and
+  /// the burst is empty and marked as overflowed.
+  pub fn read(&mut self, i2c: &mut I2c) -> Result<Burst, ImuError> {
+    let mut status = [0; 1];
+    accel::read_registers(i2c, registers::INT_STATUS, &mut status)?;
+    let mut count = [0; 2];
+    accel::read_registers(i2c, registers::FIFO_COUNT_H, &mut count)?;
+    let counted_at = Instant::now(); // the newest frame counted was taken less than a sample before
+
+    let Some(frames) = frames_waiting(u16::from_be_bytes(count) as usize, status[0] & FIFO_OFLOW != 0) else {
+      self.overflows += 1;
+      self.reset(i2c)?;
+      return Ok(Burst { samples: Vec::new(), overflowed: true });
+    };
+    let mut data = vec![0; frames * FRAME_SIZE];
+    if !data.is_empty() {
+      accel::read_registers(i2c, registers::FIFO_R_W, &mut data)?;
+    }
+    Ok(Burst { samples: self.timestamp(&data, counted_at), overflowed: false })
+  }
+
+  /// Resets the fifo and reads bursts until 'n' samples in a row have been collected.
+  /// Collection starts over after an overflow, since there's a gap of unknown length.
+  pub fn collect(&mut self, i2c: &mut I2c, n: usize) -> Result<Vec<TimedSample>, ImuError> {
+    let poll_interval = self.poll_interval();
+    let mut restarts = 0;
+    self.reset(i2c)?;
+    let mut samples = Vec::with_capacity(n);
+    while samples.len() < n {
+      thread::sleep(poll_interval.min(Duration::from_secs_f64(self.sample_period * (n - samples.len()) as f64)));
+      let burst = self.read(i2c)?;
+      if burst.overflowed {
+        restarts += 1;
+        if restarts > MAX_RESTARTS {
+          return Err(ImuError::FifoOverflow);
+        }
+        samples.clear();
+        continue;
+      }
+      samples.extend(burst.samples);
+    }
+    samples.truncate(n);
+    Ok(samples)
+  }
+
+  /// Splits fifo data counted at 'counted_at' into samples. The last sample is timed at
+  /// 'counted_at' and the others spaced back from it by the sample period, measured from
+  /// the samples read since the reset once there are enough of them. Samples are never
+  /// timed before the last one of the previous burst.
+  pub fn timestamp(&mut self, data: &[u8], counted_at: Instant) -> Vec<TimedSample> {
+    let frames: Vec<&[u8]> = data.chunks_exact(FRAME_SIZE).collect();
+    if frames.is_empty() {
+      return Vec::new();
+    }
+    self.next_index += frames.len() as u64;
+    let end = counted_at.saturating_duration_since(self.reset_at).as_secs_f64();
+    let period = if self.next_index >= MIN_RATE_SAMPLES { end / self.next_index as f64 } else { self.sample_period };
+    let gaps = (frames.len() - 1) as f64;
+    let start = (end - gaps * period).max(self.last_t.unwrap_or(0.0)).min(end);
+    let step = if gaps > 0.0 { (end - start) / gaps } else { 0.0 };
+    self.last_t = Some(end);
+    frames.iter().enumerate().map(|(i, frame)| {
+      TimedSample { t: start + i as f64 * step, sample: accel::parse_raw_sample(frame) }
+    }).collect()
+  }
+
+  /// How often to read bursts so the fifo is never more than half full
+  pub fn poll_interval(&self) -> Duration {
+    Duration::from_secs_f64(self.sample_period * (FIFO_SIZE / FRAME_SIZE / 2) as f64)
+  }
+
+  pub fn sample_period(&self) -> f64 {
+    self.sample_period
+  }
+
+  /// When the fifo was last reset, the time sample times count from
+  pub fn reset_at(&self) -> Instant {
+    self.reset_at
+  }
+
+  /// Overflows since the fifo was enabled
+  pub fn overflows(&self) -> u64 {
+    self.overflows
+  }
+}
+
+
+/// Whole frames waiting in a fifo holding 'count' bytes, or None if it overflowed
+/// or lost frame alignment and has to be reset
+fn frames_waiting(count: usize, overflow_flag: bool) -> Option<usize> {
+  // the fifo holds 73 and a bit frames, so a full one has lost part of a frame
+  if overflow_flag || count > FIFO_SIZE - FIFO_SIZE % FRAME_SIZE || !count.is_multiple_of(FRAME_SIZE) {
+    return None;
+  }
+  Some(count / FRAME_SIZE)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mpu6050::accel::DataPointType;
+
+  #[test]
+  fn test_frames_waiting() {
+    assert_eq!(frames_waiting(0, false), Some(0));
+    assert_eq!(frames_waiting(14 * 5, false), Some(5));
+    assert_eq!(frames_waiting(14 * 73, false), Some(73));
+    assert_eq!(frames_waiting(14 * 5, true), None); // overflow flag
+    assert_eq!(frames_waiting(14 * 5 + 3, false), None); // misaligned
+    assert_eq!(frames_waiting(FIFO_SIZE, false), None); // full
+  }
+
+  #[test]
+  fn test_timestamps_follow_sample_rate() {
+    let mut fifo = Fifo::new(200.0);
+    let mut frame = [0u8; FRAME_SIZE];
+    frame[0..2].copy_from_slice(&1000i16.to_be_bytes()); // accel x
+    frame[12..14].copy_from_slice(&(-5i16).to_be_bytes()); // gyro z
+    let data: Vec<u8> = frame.repeat(3);
+
+    let at = |t: f64| fifo.reset_at + Duration::from_secs_f64(t);
+    let (first_at, second_at) = (at(0.015), at(0.025));
+    let first = fifo.timestamp(&data, first_at);
+    let second = fifo.timestamp(&data[..FRAME_SIZE * 2], second_at);
+    let times: Vec<f64> = first.iter().chain(&second).map(|s| s.t).collect();
+    let expected = [0.005, 0.010, 0.015, 0.020, 0.025];
+    assert!(times.iter().zip(expected).all(|(t, e)| (t - e).abs() < 1e-6), "{times:?}");
+    assert_eq!(first[0].sample.accel.x(), 1000);
+    assert_eq!(first[2].sample.gyro.z(), -5);
+    assert!(fifo.timestamp(&data[..FRAME_SIZE - 1], second_at).is_empty()); // partial frames are never split
+  }
+
+  #[test]
+  fn test_timestamps_follow_a_slow_oscillator() {
+    // a sensor running 1% slow: 300 samples take 1.515 s rather than 1.5 s
+    let mut fifo = Fifo::new(200.0);
+    let data = [0u8; FRAME_SIZE].repeat(100);
+    let mut last = Vec::new();
+    for burst in 1..=3 {
+      last = fifo.timestamp(&data, fifo.reset_at + Duration::from_secs_f64(burst as f64 * 0.505));
+    }
+    assert!((last[99].t - 1.515).abs() < 1e-6); // tied to when the burst was read
+    assert!((last[99].t - last[98].t - 0.00505).abs() < 1e-6); // at the measured period
+  }
+}
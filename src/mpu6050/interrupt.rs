@@ -0,0 +1,215 @@
+use crossbeam_queue::ArrayQueue;
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use rppal::i2c::I2c;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::bus::i2c as bus;
+use crate::mpu6050::accel::{self, ImuError, RawSample};
+use crate::mpu6050::registers;
+
+pub const QUEUE_LEN: usize = 1024; // Samples buffered between the sampling thread and the reader
+const LATCH_INT_EN: u8 = 0x20; // INT_PIN_CFG bit that holds INT high until it's cleared
+const INT_RD_CLEAR: u8 = 0x10; // INT_PIN_CFG bit that clears INT on any register read
+const DATA_RDY_EN: u8 = 0x01; // INT_ENABLE bit for the data ready interrupt
+const INTERRUPT_TIMEOUT: Duration = Duration::from_millis(100); // Longest wait for an edge before checking for a stuck INT line
+const MAX_READ_ERRORS: u32 = 10; // Failed reads in a row before the sampling thread gives up
+
+
+/// A sample with the time of the interrupt that announced it
+#[derive(Clone, Copy, Debug)]
+pub struct StampedSample {
+  pub at: Instant,
+  pub sample: RawSample,
+}
+
+/// State shared between the sampling thread and the reader
+struct Shared {
+  queue: ArrayQueue<StampedSample>,
+  running: AtomicBool,
+  dropped: AtomicU64,             // samples overwritten because the reader fell behind
+  error: Mutex<Option<ImuError>>, // why the sampling thread stopped
+}
+
+/// Reads the mpu6050 on its own thread each time the data ready interrupt fires
+/// and queues the samples for another thread to take
+pub struct InterruptReader {
+  shared: Arc<Shared>,
+  handle: Option<JoinHandle<()>>,
+}
+
+
+/// InterruptReader implementations
+impl InterruptReader {
+  /// Configures the mpu6050 at 'address' on 'bus' to raise its INT line when a sample is
+  /// ready and starts sampling on the edges seen on gpio 'pin' (BCM numbering)
+  pub fn start(bus: u8, address: u16, pin: u8) -> Result<InterruptReader, ImuError> {
+    let mut i2c = bus::open(bus, address)?;
+    let mut int_pin = Gpio::new()?.get(pin)?.into_input_pulldown();
+    int_pin.set_interrupt(Trigger::RisingEdge)?;
+
+    accel::write_register(&mut i2c, registers::INT_PIN_CFG, LATCH_INT_EN | INT_RD_CLEAR)?;
+    accel::write_register(&mut i2c, registers::INT_ENABLE, DATA_RDY_EN)?;
+    accel::read_registers(&i2c, registers::INT_STATUS, &mut [0; 1])?; // clear an interrupt latched before the edge detection was set up
+
+    let shared = Arc::new(Shared {
+      queue: ArrayQueue::new(QUEUE_LEN),
+      running: AtomicBool::new(true),
+      dropped: AtomicU64::new(0),
+      error: Mutex::new(None),
+    });
+    let thread_shared = Arc::clone(&shared);
+    let handle = thread::spawn(move || {
+      if let Err(e) = sample_on_interrupt(&i2c, &mut int_pin, &thread_shared) {
+        *thread_shared.error.lock().unwrap() = Some(e);
+      }
+      thread_shared.running.store(false, Ordering::Release);
+    });
+    Ok(InterruptReader { shared, handle: Some(handle) })
+  }
+
+  /// Takes the oldest queued sample
+  pub fn pop(&self) -> Option<StampedSample> {
+    self.shared.queue.pop()
+  }
+
+  /// Discards every queued sample
+  pub fn clear(&self) {
+    while self.shared.queue.pop().is_some() {}
+  }
+
+  /// Waits for the next 'n' samples. Fails if the sampling thread stopped or
+  /// no sample arrived for 'timeout'.
+  pub fn collect(&self, n: usize, timeout: Duration) -> Result<Vec<StampedSample>, ImuError> {
+    let mut samples = Vec::with_capacity(n);
+    let mut last_sample = Instant::now();
+    while samples.len() < n {
+      match self.pop() {
+        Some(sample) => {
+          samples.push(sample);
+          last_sample = Instant::now();
+        }
+        None if !self.is_running() => return Err(self.take_error().unwrap_or(ImuError::Timeout)),
+        None if last_sample.elapsed() > timeout => return Err(ImuError::Timeout),
+        None => thread::sleep(Duration::from_millis(1)),
+      }
+    }
+    Ok(samples)
+  }
+
+  /// Whether the sampling thread is still running
+  pub fn is_running(&self) -> bool {
+    self.shared.running.load(Ordering::Acquire)
+  }
+
+  /// Why the sampling thread stopped, if it did
+  pub fn take_error(&self) -> Option<ImuError> {
+    self.shared.error.lock().unwrap().take()
+  }
+
+  /// Samples lost because the queue was full
+  pub fn dropped(&self) -> u64 {
+    self.shared.dropped.load(Ordering::Relaxed)
+  }
+
+  /// Stops the sampling thread and waits for it to finish
+  pub fn stop(&mut self) {
+    self.shared.running.store(false, Ordering::Release);
+    if let Some(handle) = self.handle.take() {
+      let _ = handle.join();
+    }
+  }
+}
+
+impl Drop for InterruptReader {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+
+/// Body of the sampling thread: reads a sample on each rising edge of INT until stopped
+fn sample_on_interrupt(i2c: &I2c, int_pin: &mut InputPin, shared: &Shared) -> Result<(), ImuError> {
+  let mut errors_in_row = 0;
+  while shared.running.load(Ordering::Acquire) {
+    let Some(_) = int_pin.poll_interrupt(false, Some(INTERRUPT_TIMEOUT))? else {
+      // an edge was missed and INT is latched high, reading clears it so edges start again
+      let _ = accel::read_registers(i2c, registers::INT_STATUS, &mut [0; 1]);
+      continue;
+    };
+    let at = Instant::now();
+
+    let mut data = [0; 14];
+    match accel::read_registers(i2c, registers::ACCEL_XOUT_H, &mut data) {
+      Ok(()) => {
+        errors_in_row = 0;
+        let sample = StampedSample { at, sample: accel::parse_raw_sample(&data) };
+        if shared.queue.force_push(sample).is_some() {
+          shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+      }
+      Err(e) => {
+        errors_in_row += 1;
+        if errors_in_row >= MAX_READ_ERRORS {
+          return Err(e);
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mpu6050::accel::{AccelPoint, DataPointType, GyroPoint};
+
+  /// A reader with no sampling thread, filled by the test
+  fn detached(running: bool) -> InterruptReader {
+    let shared = Arc::new(Shared {
+      queue: ArrayQueue::new(4),
+      running: AtomicBool::new(running),
+      dropped: AtomicU64::new(0),
+      error: Mutex::new(None),
+    });
+    InterruptReader { shared, handle: None }
+  }
+
+  fn push(reader: &InterruptReader, x: i16) {
+    let sample = RawSample { accel: AccelPoint::new(x, 0, 0), temperature_c: 25.0, gyro: GyroPoint::default() };
+    if reader.shared.queue.force_push(StampedSample { at: Instant::now(), sample }).is_some() {
+      reader.shared.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  #[test]
+  fn test_collect_in_order() {
+    let reader = detached(true);
+    (1..=3).for_each(|x| push(&reader, x));
+    let samples = reader.collect(3, Duration::from_millis(10)).unwrap();
+    let xs: Vec<i16> = samples.iter().map(|s| s.sample.accel.x()).collect();
+    assert_eq!(xs, [1, 2, 3]);
+    assert!(matches!(reader.collect(1, Duration::from_millis(10)), Err(ImuError::Timeout)));
+  }
+
+  #[test]
+  fn test_full_queue_drops_oldest() {
+    let reader = detached(true);
+    (1..=6).for_each(|x| push(&reader, x));
+    assert_eq!(reader.dropped(), 2);
+    assert_eq!(reader.pop().unwrap().sample.accel.x(), 3);
+    reader.clear();
+    assert!(reader.pop().is_none());
+  }
+
+  #[test]
+  fn test_stopped_thread_reports_error() {
+    let reader = detached(false);
+    *reader.shared.error.lock().unwrap() = Some(ImuError::Nack);
+    assert!(matches!(reader.collect(1, Duration::from_secs(10)), Err(ImuError::Nack)));
+  }
+}
@@ -0,0 +1,69 @@
+/// Solves a * x = b by gaussian elimination with partial pivoting.
+/// Returns None if 'a' is singular.
+pub fn solve_linear(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+  let n = b.len();
+  for col in 0..n {
+    let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+    if a[pivot][col].abs() < 1e-12 {
+      return None; // singular
+    }
+    a.swap(col, pivot);
+    b.swap(col, pivot);
+    let pivot_row = a[col].clone();
+    for row in col + 1..n {
+      let factor = a[row][col] / pivot_row[col];
+      for (value, pivot_value) in a[row].iter_mut().zip(&pivot_row).skip(col) {
+        *value -= factor * pivot_value;
+      }
+      b[row] -= factor * b[col];
+    }
+  }
+
+  let mut x = vec![0.0; n];
+  for row in (0..n).rev() {
+    let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+    x[row] = (b[row] - sum) / a[row][row];
+  }
+  Some(x)
+}
+
+
+/// Finds the coefficients minimizing the squared error of rows * coefficients = targets,
+/// through the normal equations. Returns None if the rows don't pin every coefficient down.
+pub fn fit(rows: &[Vec<f64>], targets: &[f64]) -> Option<Vec<f64>> {
+  let n = rows.first()?.len();
+  if rows.len() < n || rows.len() != targets.len() {
+    return None;
+  }
+
+  let mut ata = vec![vec![0.0; n]; n];
+  let mut atb = vec![0.0; n];
+  for (row, target) in rows.iter().zip(targets) {
+    for i in 0..n {
+      for j in 0..n {
+        ata[i][j] += row[i] * row[j];
+      }
+      atb[i] += row[i] * target;
+    }
+  }
+  solve_linear(ata, atb)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fit_line() {
+    let rows: Vec<Vec<f64>> = (0..5).map(|x| vec![1.0, x as f64]).collect();
+    let targets: Vec<f64> = (0..5).map(|x| 2.0 + 0.5 * x as f64).collect();
+    let coefficients = fit(&rows, &targets).unwrap();
+    assert!((coefficients[0] - 2.0).abs() < 1e-9);
+    assert!((coefficients[1] - 0.5).abs() < 1e-9);
+
+    assert!(fit(&rows[..1], &targets[..1]).is_none());
+    assert!(solve_linear(vec![vec![1.0, 2.0], vec![2.0, 4.0]], vec![1.0, 2.0]).is_none());
+  }
+}
@@ -1 +1,10 @@
 pub mod accel;
+pub mod allan;
+pub mod calibration;
+pub mod device;
+pub mod fifo;
+pub mod interrupt;
+pub mod least_squares;
+pub mod registers;
+pub mod six_position;
+pub mod temperature;
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+
+pub const SMPLRT_DIV: u8 = 0x19; // Sample rate divider
+pub const CONFIG: u8 = 0x1A; // Digital low pass filter (bits 2:0)
+pub const GYRO_CONFIG: u8 = 0x1B; // Gyroscope full scale select (bits 4:3)
+pub const ACCEL_CONFIG: u8 = 0x1C; // Accelerometer full scale select (bits 4:3)
+pub const FIFO_EN: u8 = 0x23; // Which sensors are written to the fifo
+pub const INT_PIN_CFG: u8 = 0x37; // INT pin level, drive and latching
+pub const INT_ENABLE: u8 = 0x38; // Interrupt sources (fifo overflow is bit 4, data ready bit 0)
+pub const INT_STATUS: u8 = 0x3A; // Interrupt flags, cleared when read
+pub const ACCEL_XOUT_H: u8 = 0x3B; // First of the accelerometer, temperature and gyroscope output registers
+pub const TEMP_OUT_H: u8 = 0x41; // Temperature output
+pub const GYRO_XOUT_H: u8 = 0x43; // First of the gyroscope output registers
+pub const USER_CTRL: u8 = 0x6A; // Fifo enable (bit 6) and reset (bit 2)
+pub const PWR_MGMT_1: u8 = 0x6B; // Sleep, temperature sensor disable (bit 3) and clock source (bits 2:0)
+pub const FIFO_COUNT_H: u8 = 0x72; // Bytes in the fifo (big endian, with FIFO_COUNT_L)
+pub const FIFO_R_W: u8 = 0x74; // Fifo data
+pub const WHO_AM_I: u8 = 0x75; // Device identity
+pub const DLPF_BANDWIDTHS_HZ: [u16; 7] = [260, 184, 94, 44, 21, 10, 5]; // Low pass filter settings, in register order
+const ACCEL_SENSITIVITY: f32 = 16384.0; // Accelerometer sensitivity in LSB/g at +-2g (halves each time the range doubles)
+const GYRO_SENSITIVITY: f32 = 131.0; // Gyroscope sensitivity in LSB/(deg/s) at +-250 deg/s (halves each time the range doubles)
+const GYRO_OUTPUT_RATE_HZ: f32 = 8000.0; // Gyroscope output rate with the low pass filter off
+const FILTERED_OUTPUT_RATE_HZ: f32 = 1000.0; // Gyroscope output rate with the low pass filter on
+pub const SAMPLE_RATE_DIVIDER: u8 = 9; // Default divider, 100 Hz with the filter on so a 100 kHz bus keeps up
+
+
+/// Accelerometer full scale range
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AccelRange {
+  #[default]
+  G2,
+  G4,
+  G8,
+  G16,
+}
+
+/// Gyroscope full scale range
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GyroRange {
+  Dps250,
+  Dps500,
+  Dps1000,
+  #[default]
+  Dps2000,
+}
+
+/// Digital low pass filter bandwidth (of the accelerometer, the gyroscope's is about the same)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DlpfBandwidth {
+  Hz260, // filter off
+  Hz184,
+  Hz94,
+  Hz44,
+  Hz21,
+  #[default]
+  Hz10,
+  Hz5,
+}
+
+/// Clock the mpu6050 runs from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSource {
+  #[default]
+  Internal,   // 8 MHz oscillator
+  PllGyroX,   // gyroscope x axis reference, more stable than the oscillator
+  PllGyroY,
+  PllGyroZ,
+  #[serde(rename = "pll_external_32khz")]
+  PllExternal32kHz,
+  #[serde(rename = "pll_external_19mhz")]
+  PllExternal19MHz,
+}
+
+/// Everything written to the mpu6050 when it's initialized
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImuSettings {
+  pub accel_range: AccelRange,
+  pub gyro_range: GyroRange,
+  pub dlpf: DlpfBandwidth,
+  pub sample_rate_divider: u8, // sample rate = gyroscope output rate / (1 + divider)
+  pub clock_source: ClockSource,
+}
+
+
+/// AccelRange implementations
+impl AccelRange {
+  /// The range for +-'g', if the mpu6050 supports it
+  pub fn from_g(g: u8) -> Option<AccelRange> {
+    match g {
+      2 => Some(AccelRange::G2),
+      4 => Some(AccelRange::G4),
+      8 => Some(AccelRange::G8),
+      16 => Some(AccelRange::G16),
+      _ => None,
+    }
+  }
+
+  pub fn g(self) -> u8 {
+    2 << self as u8
+  }
+
+  /// Sensitivity in LSB/g
+  pub fn sensitivity(self) -> f32 {
+    ACCEL_SENSITIVITY / (1 << self as u8) as f32
+  }
+
+  /// ACCEL_CONFIG value
+  fn bits(self) -> u8 {
+    (self as u8) << 3
+  }
+}
+
+
+/// GyroRange implementations
+impl GyroRange {
+  /// The range for +-'dps' deg/s, if the mpu6050 supports it
+  pub fn from_dps(dps: u16) -> Option<GyroRange> {
+    match dps {
+      250 => Some(GyroRange::Dps250),
+      500 => Some(GyroRange::Dps500),
+      1000 => Some(GyroRange::Dps1000),
+      2000 => Some(GyroRange::Dps2000),
+      _ => None,
+    }
+  }
+
+  pub fn dps(self) -> u16 {
+    250 << self as u16
+  }
+
+  /// Sensitivity in LSB/(deg/s)
+  pub fn sensitivity(self) -> f32 {
+    GYRO_SENSITIVITY / (1 << self as u8) as f32
+  }
+
+  /// GYRO_CONFIG value
+  fn bits(self) -> u8 {
+    (self as u8) << 3
+  }
+}
+
+
+/// DlpfBandwidth implementations
+impl DlpfBandwidth {
+  /// The filter for a bandwidth in Hz, if the mpu6050 has it
+  pub fn from_hz(hz: u16) -> Option<DlpfBandwidth> {
+    match hz {
+      260 => Some(DlpfBandwidth::Hz260),
+      184 => Some(DlpfBandwidth::Hz184),
+      94 => Some(DlpfBandwidth::Hz94),
+      44 => Some(DlpfBandwidth::Hz44),
+      21 => Some(DlpfBandwidth::Hz21),
+      10 => Some(DlpfBandwidth::Hz10),
+      5 => Some(DlpfBandwidth::Hz5),
+      _ => None,
+    }
+  }
+
+  pub fn hz(self) -> u16 {
+    DLPF_BANDWIDTHS_HZ[self as usize]
+  }
+}
+
+
+/// ImuSettings implementations
+impl ImuSettings {
+  /// Rate new samples are written to the output registers
+  pub fn sample_rate_hz(&self) -> f32 {
+    let output_rate = if self.dlpf == DlpfBandwidth::Hz260 { GYRO_OUTPUT_RATE_HZ } else { FILTERED_OUTPUT_RATE_HZ };
+    output_rate / (1.0 + self.sample_rate_divider as f32)
+  }
+
+  /// (register, value) pairs to write, in order. The first wakes the mpu6050 with the
+  /// temperature sensor enabled.
+  pub fn register_writes(&self) -> [(u8, u8); 5] {
+    [
+      (PWR_MGMT_1, self.clock_source as u8),
+      (SMPLRT_DIV, self.sample_rate_divider),
+      (CONFIG, self.dlpf as u8),
+      (GYRO_CONFIG, self.gyro_range.bits()),
+      (ACCEL_CONFIG, self.accel_range.bits()),
+    ]
+  }
+}
+
+impl Default for ImuSettings {
+  fn default() -> Self {
+    ImuSettings {
+      accel_range: AccelRange::default(),
+      gyro_range: GyroRange::default(),
+      dlpf: DlpfBandwidth::default(),
+      sample_rate_divider: SAMPLE_RATE_DIVIDER,
+      clock_source: ClockSource::default(),
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_ranges() {
+    for g in [2, 4, 8, 16] {
+      assert_eq!(AccelRange::from_g(g).unwrap().g(), g);
+    }
+    for dps in [250, 500, 1000, 2000] {
+      assert_eq!(GyroRange::from_dps(dps).unwrap().dps(), dps);
+    }
+    for hz in [260, 184, 94, 44, 21, 10, 5] {
+      assert_eq!(DlpfBandwidth::from_hz(hz).unwrap().hz(), hz);
+    }
+    assert!(AccelRange::from_g(3).is_none());
+    assert!(GyroRange::from_dps(300).is_none());
+    assert!(DlpfBandwidth::from_hz(100).is_none());
+  }
+
+  #[test]
+  fn test_sensitivity() {
+    assert_eq!(AccelRange::G2.sensitivity(), 16384.0);
+    assert_eq!(AccelRange::G16.sensitivity(), 2048.0);
+    assert_eq!(GyroRange::Dps250.sensitivity(), 131.0);
+    assert_eq!(GyroRange::Dps2000.sensitivity(), 16.375);
+  }
+
+  #[test]
+  fn test_register_writes() {
+    let settings = ImuSettings {
+      accel_range: AccelRange::G8,
+      gyro_range: GyroRange::Dps500,
+      dlpf: DlpfBandwidth::Hz44,
+      sample_rate_divider: 9,
+      clock_source: ClockSource::PllGyroX,
+    };
+    assert_eq!(settings.register_writes(), [(0x6B, 0x01), (0x19, 9), (0x1A, 0x03), (0x1B, 0x08), (0x1C, 0x10)]);
+    assert_eq!(settings.sample_rate_hz(), 100.0);
+
+    let unfiltered = ImuSettings { dlpf: DlpfBandwidth::Hz260, ..Default::default() };
+    assert_eq!(unfiltered.sample_rate_hz(), 800.0);
+  }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mpu6050::accel::RawPoint;
+use crate::mpu6050::least_squares;
+
+pub const STILL_TOLERANCE_G: f32 = 0.02; // Largest standard deviation of a reading for the sensor to count as still
+pub const POSITIONS: [(&str, [f32; 3]); 6] = [ // Orientations of the guided calibration and the reading each should give in g
+  ("+X pointing up", [1.0, 0.0, 0.0]),
+  ("-X pointing up", [-1.0, 0.0, 0.0]),
+  ("+Y pointing up", [0.0, 1.0, 0.0]),
+  ("-Y pointing up", [0.0, -1.0, 0.0]),
+  ("+Z pointing up", [0.0, 0.0, 1.0]),
+  ("-Z pointing up", [0.0, 0.0, -1.0]),
+];
+
+
+/// Corrects accelerometer readings for per axis bias, scale factor and cross axis
+/// misalignment: corrected = matrix * (reading - bias), with readings in g
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccelCorrection {
+  pub matrix: [[f32; 3]; 3], // scale factors on the diagonal, misalignment off it
+  pub bias: [f32; 3],        // g
+}
+
+/// Result of a multi-orientation calibration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SixPositionFit {
+  pub correction: AccelCorrection,
+  pub rms_error_g: f32, // how far the corrected readings are from the references
+}
+
+
+/// AccelCorrection implementations
+impl AccelCorrection {
+  /// No correction
+  pub fn identity() -> AccelCorrection {
+    AccelCorrection { matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], bias: [0.0; 3] }
+  }
+
+  /// Corrects a reading in g
+  pub fn apply(&self, reading: [f32; 3]) -> [f32; 3] {
+    let unbiased = [reading[0] - self.bias[0], reading[1] - self.bias[1], reading[2] - self.bias[2]];
+    self.scale(unbiased)
+  }
+
+  /// Applies only the scale and misalignment, for differences between two readings
+  /// (the bias cancels out of those)
+  pub fn scale(&self, v: [f32; 3]) -> [f32; 3] {
+    let m = &self.matrix;
+    [
+      m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+      m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+      m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+  }
+
+  /// Same as scale for a point in any unit
+  pub fn scale_point(&self, p: RawPoint) -> RawPoint {
+    let [x, y, z] = self.scale([p.x(), p.y(), p.z()]);
+    RawPoint::new(x, y, z)
+  }
+}
+
+impl Default for AccelCorrection {
+  fn default() -> Self {
+    AccelCorrection::identity()
+  }
+}
+
+
+/// Solves for the correction that best maps each averaged reading to its reference
+/// (both in g) by least squares. Needs at least four orientations that aren't in a
+/// plane, normally the six of POSITIONS. Returns None if the readings are degenerate.
+pub fn solve(readings: &[[f32; 3]], references: &[[f32; 3]]) -> Option<SixPositionFit> {
+  if readings.len() != references.len() || readings.len() < 4 {
+    return None;
+  }
+
+  // reference = W * [reading, 1], fitted one output axis at a time
+  let rows: Vec<Vec<f64>> = readings.iter().map(|r| vec![r[0] as f64, r[1] as f64, r[2] as f64, 1.0]).collect();
+  let mut w = Vec::new();
+  for axis in 0..3 {
+    let targets: Vec<f64> = references.iter().map(|r| r[axis] as f64).collect();
+    w.push(least_squares::fit(&rows, &targets)?);
+  }
+
+  // W = [M | c] with c = -M * bias
+  let m: Vec<Vec<f64>> = w.iter().map(|row| row[..3].to_vec()).collect();
+  let bias = least_squares::solve_linear(m.clone(), w.iter().map(|row| -row[3]).collect())?;
+  let correction = AccelCorrection {
+    matrix: [0, 1, 2].map(|i| [0, 1, 2].map(|j| m[i][j] as f32)),
+    bias: [0, 1, 2].map(|i| bias[i] as f32),
+  };
+
+  let sum_sq: f32 = readings.iter().zip(references).map(|(reading, reference)| {
+    let corrected = correction.apply(*reading);
+    (0..3).map(|i| (corrected[i] - reference[i]).powi(2)).sum::<f32>()
+  }).sum();
+  let rms_error_g = (sum_sq / readings.len() as f32).sqrt();
+
+  Some(SixPositionFit { correction, rms_error_g })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_solve_recovers_correction() {
+    // readings a sensor with known errors would give in each position
+    let truth = AccelCorrection {
+      matrix: [[1.02, 0.01, -0.005], [0.004, 0.97, 0.012], [-0.008, 0.006, 1.01]],
+      bias: [0.03, -0.05, 0.08],
+    };
+    let m: Vec<Vec<f64>> = truth.matrix.iter().map(|row| row.iter().map(|&v| v as f64).collect()).collect();
+    let references: Vec<[f32; 3]> = POSITIONS.iter().map(|p| p.1).collect();
+    let readings: Vec<[f32; 3]> = references.iter().map(|r| {
+      let unbiased = least_squares::solve_linear(m.clone(), r.iter().map(|&v| v as f64).collect()).unwrap();
+      [0, 1, 2].map(|i| unbiased[i] as f32 + truth.bias[i])
+    }).collect();
+
+    let fit = solve(&readings, &references).unwrap();
+    assert!(fit.rms_error_g < 1e-4);
+    for i in 0..3 {
+      assert!((fit.correction.bias[i] - truth.bias[i]).abs() < 1e-4);
+      for j in 0..3 {
+        assert!((fit.correction.matrix[i][j] - truth.matrix[i][j]).abs() < 1e-4);
+      }
+    }
+  }
+
+  #[test]
+  fn test_degenerate_readings() {
+    let readings = [[1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [1.0, 0.0, 0.0], [-1.0, 0.0, 0.0]];
+    let references = [[1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [1.0, 0.0, 0.0], [-1.0, 0.0, 0.0]];
+    assert!(solve(&readings, &references).is_none());
+    assert!(solve(&readings[..3], &references[..3]).is_none());
+  }
+
+  #[test]
+  fn test_identity() {
+    let point = RawPoint::new(1.0, -2.0, 3.0);
+    let scaled = AccelCorrection::identity().scale_point(point);
+    assert_eq!((scaled.x(), scaled.y(), scaled.z()), (1.0, -2.0, 3.0));
+  }
+}
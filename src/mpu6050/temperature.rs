@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mpu6050::least_squares;
+
+pub const DEGREE: usize = 2; // Default degree of the bias polynomials
+pub const MIN_SPAN_C: f32 = 5.0; // Smallest temperature range a model can be fitted over
+
+
+/// Polynomial in (x - center), centered to keep the fit well conditioned
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Polynomial {
+  pub center: f32,
+  pub coefficients: Vec<f32>, // lowest power first
+}
+
+/// Bias averaged over a short still period, in raw LSB, at one temperature
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BiasPoint {
+  pub temperature_c: f32,
+  pub accel: [f32; 3],
+  pub gyro: [f32; 3],
+}
+
+/// How the accelerometer and gyroscope biases (raw LSB) change with die temperature
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TemperatureModel {
+  pub min_c: f32, // range the model was fitted over, it isn't extrapolated past it
+  pub max_c: f32,
+  pub accel: [Polynomial; 3],
+  pub gyro: [Polynomial; 3],
+}
+
+
+/// Polynomial implementations
+impl Polynomial {
+  /// Least squares fit of the given degree, or None if there aren't enough distinct points
+  pub fn fit(xs: &[f32], ys: &[f32], degree: usize) -> Option<Polynomial> {
+    if xs.is_empty() {
+      return None;
+    }
+    let center = xs.iter().sum::<f32>() / xs.len() as f32;
+    let rows: Vec<Vec<f64>> = xs.iter()
+      .map(|&x| (0..=degree).map(|power| ((x - center) as f64).powi(power as i32)).collect())
+      .collect();
+    let targets: Vec<f64> = ys.iter().map(|&y| y as f64).collect();
+    let coefficients = least_squares::fit(&rows, &targets)?;
+    Some(Polynomial { center, coefficients: coefficients.iter().map(|&c| c as f32).collect() })
+  }
+
+  pub fn eval(&self, x: f32) -> f32 {
+    let dx = x - self.center;
+    self.coefficients.iter().rev().fold(0.0, |acc, c| acc * dx + c)
+  }
+}
+
+
+/// TemperatureModel implementations
+impl TemperatureModel {
+  /// Fits a polynomial of 'degree' to each axis. Returns None if the points span less
+  /// than MIN_SPAN_C or can't be fitted.
+  pub fn fit(points: &[BiasPoint], degree: usize) -> Option<TemperatureModel> {
+    let temperatures: Vec<f32> = points.iter().map(|p| p.temperature_c).collect();
+    let min_c = temperatures.iter().copied().fold(f32::INFINITY, f32::min);
+    let max_c = temperatures.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if points.is_empty() || max_c - min_c < MIN_SPAN_C {
+      return None;
+    }
+
+    let fit_axis = |value: &dyn Fn(&BiasPoint) -> f32| {
+      let values: Vec<f32> = points.iter().map(value).collect();
+      Polynomial::fit(&temperatures, &values, degree)
+    };
+    Some(TemperatureModel {
+      min_c,
+      max_c,
+      accel: [fit_axis(&|p| p.accel[0])?, fit_axis(&|p| p.accel[1])?, fit_axis(&|p| p.accel[2])?],
+      gyro: [fit_axis(&|p| p.gyro[0])?, fit_axis(&|p| p.gyro[1])?, fit_axis(&|p| p.gyro[2])?],
+    })
+  }
+
+  /// Whether 'temperature_c' is inside the range the model was fitted over
+  pub fn covers(&self, temperature_c: f32) -> bool {
+    (self.min_c..=self.max_c).contains(&temperature_c)
+  }
+
+  /// Change in accelerometer bias (LSB) going from 'from_c' to 'to_c'
+  pub fn accel_drift(&self, from_c: f32, to_c: f32) -> [f32; 3] {
+    self.drift(&self.accel, from_c, to_c)
+  }
+
+  /// Change in gyroscope bias (LSB) going from 'from_c' to 'to_c'
+  pub fn gyro_drift(&self, from_c: f32, to_c: f32) -> [f32; 3] {
+    self.drift(&self.gyro, from_c, to_c)
+  }
+
+  fn drift(&self, axes: &[Polynomial; 3], from_c: f32, to_c: f32) -> [f32; 3] {
+    let from_c = from_c.clamp(self.min_c, self.max_c);
+    let to_c = to_c.clamp(self.min_c, self.max_c);
+    [0, 1, 2].map(|i| axes[i].eval(to_c) - axes[i].eval(from_c))
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_polynomial_fit() {
+    let xs: Vec<f32> = (0..20).map(|i| 20.0 + i as f32).collect();
+    let ys: Vec<f32> = xs.iter().map(|x| 3.0 - 0.5 * x + 0.02 * x * x).collect();
+    let poly = Polynomial::fit(&xs, &ys, 2).unwrap();
+    for x in [20.0, 27.5, 39.0] {
+      assert!((poly.eval(x) - (3.0 - 0.5 * x + 0.02 * x * x)).abs() < 1e-2);
+    }
+  }
+
+  #[test]
+  fn test_model_drift() {
+    let points: Vec<BiasPoint> = (0..10).map(|i| {
+      let t = 25.0 + i as f32;
+      BiasPoint { temperature_c: t, accel: [100.0 + 2.0 * t, -50.0, 16384.0 - t], gyro: [10.0 - 0.5 * t, 3.0, 0.0] }
+    }).collect();
+    let model = TemperatureModel::fit(&points, 1).unwrap();
+
+    let accel = model.accel_drift(25.0, 30.0);
+    assert!((accel[0] - 10.0).abs() < 1e-2);
+    assert!(accel[1].abs() < 1e-2);
+    assert!((accel[2] + 5.0).abs() < 1e-2);
+    assert!((model.gyro_drift(30.0, 25.0)[0] - 2.5).abs() < 1e-2);
+
+    // clamped to the fitted range (25 to 34 C)
+    assert_eq!(model.accel_drift(25.0, 50.0), model.accel_drift(25.0, 34.0));
+    assert!(!model.covers(50.0));
+  }
+
+  #[test]
+  fn test_narrow_range_rejected() {
+    let points: Vec<BiasPoint> = (0..10).map(|i| {
+      BiasPoint { temperature_c: 25.0 + i as f32 * 0.1, accel: [0.0; 3], gyro: [0.0; 3] }
+    }).collect();
+    assert!(TemperatureModel::fit(&points, 2).is_none());
+  }
+}
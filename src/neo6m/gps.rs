@@ -1,43 +1,58 @@
-use adafruit_gps::gga::SatFix::NoFix;
+use adafruit_gps::gga::{GgaData, SatFix::NoFix};
+use adafruit_gps::gsa::{DimensionFix, GsaData};
+use adafruit_gps::gsv::Satellites;
+use adafruit_gps::rmc::RmcData;
 use adafruit_gps::NmeaOutput;
 use adafruit_gps::{Gps, GpsSentence};
-use std::f32::consts::PI;
 use std::time::{Instant, Duration};
-use std::fmt::Display;
 
-use crate::mpu6050::accel::RawPoint;
+use crate::neo6m::nmea::SentenceSource;
+use crate::timing::calendar::days_from_civil;
+
+pub use gps_spoofing_core::geo::{haversine_distance, GpsCoord};
+pub use gps_spoofing_core::nav::{calc_new_pos, calc_new_vel};
 
 
 // const PORT_NAME: &str = "/dev/ttyS0";
 // const BAUD_RATE: &str = "9600";
 // const GPS_FIX_TIMEOUT: Duration = Duration::from_secs(60); // How long to wait for gps fix before timing out
-const UPDATE_RATE: &str = "1000"; // How often to update gps in milliseconds. Baud rate must change with this
-const EARTH_RAD: f32 = 6371000.0; // radius of earth in km
-
+pub const UPDATE_RATE: u32 = 1000; // Default for how often to update gps in milliseconds. Baud rate must change with this
+const KNOTS_TO_MPS: f32 = 0.514444; // rmc reports speed over ground in knots
+const MAX_INVALID_SENTENCES: u32 = 10; // Invalid sentences in a row before giving up on a fix
 
-/// Struct to hold gps coordinates
-pub struct GpsCoord {
-  lat: f32,
-  lon: f32,
-  alt: f32,
-}
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct GpsData {
   lat: f32,   // rmc, 
   lon: f32,   // rmc, 
   alt: f32,   // gga
   speed: f32, // rmc, 
+  course: f32, // rmc
   time: f64,  // rmc, 
   date: String, // rmc
+  mode: u8,   // gsa (1 = no fix, 2 = 2D, 3 = 3D)
+  pos_prec: f32, // gsa
   hor_prec: f32, // gsa, 
   ver_prec: f32, // gsa, 
+  sats_used: Vec<i32>, // gsa
+  satellites: Vec<Satellite>, // gsv
+  checksum_failures: u32, // sentences skipped while reading this fix
 }
 
+/// Struct to hold a single satellite in view
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Satellite {
+  prn: i32,
+  elevation: f32, // degrees
+  azimuth: f32,   // degrees from true north
+  snr: f32,       // dB-Hz, 0 if not tracking
+  used: bool,     // whether the satellite is part of the current fix
+}
 
-/// setup gps refresh rate and which sentences to output
-pub fn init_gps(gps: &mut Gps) {
-  gps.pmtk_220_set_nmea_updaterate(UPDATE_RATE); // set update rate te 1 second
+
+/// setup gps refresh rate (in milliseconds) and which sentences to output
+pub fn init_gps(gps: &mut Gps, update_rate: u32) {
+  gps.pmtk_220_set_nmea_updaterate(&update_rate.to_string()); // set update rate
   gps.pmtk_314_api_set_nmea_output(
     // every _ updates, give me the sentence (1 = every update, 0 = never)
     NmeaOutput{
@@ -45,7 +60,7 @@ pub fn init_gps(gps: &mut Gps) {
       // information to instantiate a GpsData struct
       gga: 1, 
       gsa: 1, 
-      gsv: 0, 
+      gsv: 1, 
       gll: 0, 
       rmc: 1, 
       vtg: 0, 
@@ -55,18 +70,18 @@ pub fn init_gps(gps: &mut Gps) {
 }
 
 /// waits for gps to get a fix or times out
-pub fn wait_for_fix(gps: &mut Gps, timeout_sec: Duration) -> Option<GpsCoord> {
+pub fn wait_for_fix(gps: &mut impl SentenceSource, timeout_sec: Duration) -> Option<GpsCoord> {
   let start = Instant::now();
   while timeout_sec > (Instant::now() - start) {
-    let sentence = gps.update();
+    let sentence = gps.next_sentence();
     if let GpsSentence::GGA(sen) = sentence {
       if sen.sat_fix != NoFix {
         return Some(
-          GpsCoord {
-            lat: sen.lat.unwrap_or(0.0), // can I get rid of default values here?
-            lon: sen.long.unwrap_or(0.0),
-            alt: sen.msl_alt.unwrap_or(0.0),
-          }
+          GpsCoord::new(
+            sen.lat.unwrap_or(0.0), // can I get rid of default values here?
+            sen.long.unwrap_or(0.0),
+            sen.msl_alt.unwrap_or(0.0),
+          )
         );
       }
     }
@@ -75,108 +90,61 @@ pub fn wait_for_fix(gps: &mut Gps, timeout_sec: Duration) -> Option<GpsCoord> {
 }
 
 /// get gps data or return none if no fix
-pub fn get_gps(gps: &mut Gps) -> Option<GpsData> {
+pub fn get_gps(gps: &mut impl SentenceSource) -> Option<GpsData> {
+  get_gps_timed(gps).map(|(data, _)| data)
+}
+
+/// Same as get_gps, also returning when the fix's RMC sentence arrived. The receiver
+/// sends it a fixed delay after the epoch, so it's used as the fix's time on the host clock.
+pub fn get_gps_timed(gps: &mut impl SentenceSource) -> Option<(GpsData, Instant)> {
   let mut data = GpsData::new();
+  let mut rmc_at = Instant::now();
 
   let mut rmc = false;
   let mut gga = false;
   let mut gsa = false;
 
+  let mut invalid_in_row = 0;
+
   while !rmc || !gga || !gsa {
-    let sentence = gps.update();
+    let sentence = gps.next_sentence();
+    if sentence != GpsSentence::InvalidSentence {
+      invalid_in_row = 0;
+    }
     match sentence {
-      GpsSentence::InvalidSentence => return None, // Invalid checksum
+      GpsSentence::InvalidSentence => {
+        // Invalid checksum, skip the sentence unless the line is garbage
+        data.count_checksum_failure();
+        invalid_in_row += 1;
+        if invalid_in_row >= MAX_INVALID_SENTENCES {
+          return None;
+        }
+      }
       GpsSentence::InvalidBytes    => return None, // Port and gps baud rate don't match
       GpsSentence::NoConnection    => return None, // Gps not connected, not receiving bytes
       GpsSentence::RMC(sen) => {
-        data.lat = sen.latitude.unwrap_or(0.0);
-        data.lon = sen.longitude.unwrap_or(0.0);
-        data.speed = sen.speed.unwrap_or(-1.0);
-        data.time = sen.utc;
-        data.date = sen.date;
+        rmc_at = Instant::now();
+        data.apply_rmc(&sen);
         rmc = true;
       }
       GpsSentence::GGA(sen) => {
-        data.alt = sen.msl_alt.unwrap_or(-1.0);
+        data.apply_gga(&sen);
         gga = true;
       }
       GpsSentence::GSA(sen) => {
-        data.hor_prec = sen.hdop.unwrap_or(-1.0);
-        data.ver_prec = sen.vdop.unwrap_or(-1.0);
+        data.apply_gsa(&sen);
         gsa = true;
       }
+      GpsSentence::GSV(sats) => {
+        data.apply_gsv(&sats); // optional, satellites in view aren't needed for a fix
+      }
       _ => {
         // ignore other sentence types (the data isn't as important for our purposes)
       }   
     }
   }
-  Some(data)
-}
-
-
-/// Predicts the position of the gps by averaging the acceleration over a period of time
-pub fn calc_new_pos(old_pos: &GpsCoord, vel: &RawPoint, accel: &RawPoint, time: &f64) -> GpsCoord {
-  let new_lat = old_pos.lat + vel.x() * (*time as f32) + 0.5 * accel.x() * (*time as f32).powi(2);
-  let new_lon = old_pos.lon + vel.y() * (*time as f32) + 0.5 * accel.y() * (*time as f32).powi(2);
-  let new_alt = old_pos.alt + vel.z() * (*time as f32) + 0.5 * accel.z() * (*time as f32).powi(2);
-  GpsCoord::new(new_lat, new_lon, new_alt)
-}
-
-
-/// Predicts the velocity of the gps by averaging the acceleration over a period of time
-pub fn calc_new_vel(old_vel: &RawPoint, accel: &RawPoint, time: &f64) -> RawPoint {
-  let new_lat = old_vel.x() + accel.x() * (*time as f32);
-  let new_lon = old_vel.y() + accel.y() * (*time as f32);
-  let new_alt = old_vel.z() + accel.z() * (*time as f32);
-  RawPoint::new(new_lat, new_lon, new_alt)
-}
-
-
-fn degrees_to_radians(degrees: f32) -> f32 {
-  degrees * PI / 180.0
-}
-
-/// Computes the distance in meters between two points on earth.
-/// Takes in latitude and longitude of two points, and returns
-/// the distance between them in meters.
-pub fn haversine_distance(lat1: f32, lon1: f32, lat2: f32, lon2: f32) -> f32 {
-  let d_lat = degrees_to_radians(lat2 - lat1);
-  let d_lon = degrees_to_radians(lon2 - lon1);
-
-  let lat1 = degrees_to_radians(lat1);
-  let lat2 = degrees_to_radians(lat2);
-  
-  let a = ((d_lat / 2.0).sin()).powi(2) +
-               ((d_lon / 2.0).sin()).powi(2) * lat1.cos() * lat2.cos();
-  let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
-  c * EARTH_RAD
-}
-
-
-/// GpsCoord implementations
-impl GpsCoord {
-  pub fn new(lat: f32, lon: f32, alt: f32) -> GpsCoord {
-    GpsCoord { lat, lon, alt }
-  }
-
-  pub fn lat(&self) -> f32 {
-    self.lat
-  }
-
-  pub fn lon(&self) -> f32 {
-    self.lon
-  }
-
-  #[allow(dead_code)] // altitude will be taken into account later
-  pub fn alt(&self) -> f32 {
-    self.alt
-  }
-}
-
-impl Display for GpsCoord {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "(lat: {}, lon: {}, alt: {})", self.lat, self.lon, self.alt)
-  }
+  data.mark_used_satellites();
+  Some((data, rmc_at))
 }
 
 
@@ -188,13 +156,24 @@ impl GpsData {
       lon: 0.0,
       alt: 0.0,
       speed: 0.0,
+      course: 0.0,
       time: 0.0,
       date: String::new(),
+      mode: 0,
+      pos_prec: 0.0,
       hor_prec: 0.0,
       ver_prec: 0.0,
+      sats_used: Vec::new(),
+      satellites: Vec::new(),
+      checksum_failures: 0,
     }
   }
 
+  /// Position of the fix
+  pub fn coord(&self) -> GpsCoord {
+    GpsCoord::new(self.lat, self.lon, self.alt)
+  }
+
   #[allow(dead_code)]
   pub fn lat(&self) -> f32 {
     self.lat
@@ -215,6 +194,19 @@ impl GpsData {
     self.speed
   }
 
+  /// Speed over ground in m/s (rmc reports knots)
+  pub fn speed_mps(&self) -> f32 {
+    if self.speed < 0.0 {
+      return self.speed; // keep the "no data" marker
+    }
+    self.speed * KNOTS_TO_MPS
+  }
+
+  /// Course over ground in degrees from true north
+  pub fn course(&self) -> f32 {
+    self.course
+  }
+
   #[allow(dead_code)]
   pub fn time(&self) -> f64 {
     self.time
@@ -233,58 +225,197 @@ impl GpsData {
   pub fn ver_prec(&self) -> f32 {
     self.ver_prec
   }
+
+  pub fn pos_prec(&self) -> f32 {
+    self.pos_prec
+  }
+
+  /// Fix dimension: 1 = no fix, 2 = 2D, 3 = 3D (0 if no gsa has been seen)
+  pub fn mode(&self) -> u8 {
+    self.mode
+  }
+
+  pub fn sats_used(&self) -> &[i32] {
+    &self.sats_used
+  }
+
+  pub fn satellites(&self) -> &[Satellite] {
+    &self.satellites
+  }
+
+  /// Mean signal strength (C/N0 in dB-Hz) of the satellites used in the fix,
+  /// or None if no used satellite is being tracked
+  pub fn mean_snr(&self) -> Option<f32> {
+    let snrs: Vec<f32> = self.satellites.iter()
+      .filter(|sat| sat.used && sat.snr > 0.0)
+      .map(|sat| sat.snr)
+      .collect();
+    if snrs.is_empty() {
+      return None;
+    }
+    Some(snrs.iter().sum::<f32>() / snrs.len() as f32)
+  }
+
+  /// Number of sentences with a bad checksum skipped while reading this fix
+  pub fn checksum_failures(&self) -> u32 {
+    self.checksum_failures
+  }
+
+  /// Returns the fix time as an ISO 8601 UTC timestamp (eg. 2023-11-14T22:13:20.000Z),
+  /// or None if the date or time haven't been received yet
+  pub fn iso_time(&self) -> Option<String> {
+    let (year, month, day) = parse_date(&self.date)?;
+    let (hour, min, sec) = split_utc(self.time)?;
+    Some(format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:06.3}Z", year, month, day, hour, min, sec))
+  }
+
+  /// Returns the fix time as seconds since the Unix epoch, or None if the date or
+  /// time haven't been received yet
+  pub fn utc_seconds(&self) -> Option<f64> {
+    let (year, month, day) = parse_date(&self.date)?;
+    let (hour, min, sec) = split_utc(self.time)?;
+    let days = days_from_civil(year, month, day);
+    Some(days as f64 * 86400.0 + (hour * 3600 + min * 60) as f64 + sec)
+  }
+
+  /// Folds an rmc sentence into the gps data
+  pub fn apply_rmc(&mut self, sen: &RmcData) {
+    self.lat = sen.latitude.unwrap_or(0.0);
+    self.lon = sen.longitude.unwrap_or(0.0);
+    self.speed = sen.speed.unwrap_or(-1.0);
+    self.course = sen.course.unwrap_or(-1.0);
+    self.time = sen.utc;
+    self.date = sen.date.clone();
+  }
+
+  /// Folds a gga sentence into the gps data
+  pub fn apply_gga(&mut self, sen: &GgaData) {
+    self.alt = sen.msl_alt.unwrap_or(-1.0);
+  }
+
+  /// Folds a gsa sentence into the gps data
+  pub fn apply_gsa(&mut self, sen: &GsaData) {
+    self.mode = match sen.dimension_fix {
+      DimensionFix::NotAvailable => 1,
+      DimensionFix::Dimension2d => 2,
+      DimensionFix::Dimension3d => 3,
+    };
+    self.pos_prec = sen.pdop.unwrap_or(-1.0);
+    self.hor_prec = sen.hdop.unwrap_or(-1.0);
+    self.ver_prec = sen.vdop.unwrap_or(-1.0);
+    self.sats_used = [sen.sat1, sen.sat2, sen.sat3, sen.sat4, sen.sat5, sen.sat6,
+                      sen.sat7, sen.sat8, sen.sat9, sen.sat10, sen.sat11, sen.sat12]
+      .iter()
+      .flatten()
+      .copied()
+      .collect();
+  }
+
+  /// Folds the satellites of a (multi-part) gsv sentence into the gps data
+  pub fn apply_gsv(&mut self, sats: &[Satellites]) {
+    self.satellites = sats.iter()
+      .filter_map(|sat| Some(Satellite {
+        prn: sat.id?,
+        elevation: sat.elevation.unwrap_or(0.0),
+        azimuth: sat.azimuth.unwrap_or(0.0),
+        snr: sat.snr.unwrap_or(0.0),
+        used: false,
+      }))
+      .collect();
+  }
+
+  /// Flags the satellites in view that gsa reported as used in the fix
+  pub fn mark_used_satellites(&mut self) {
+    for sat in self.satellites.iter_mut() {
+      sat.used = self.sats_used.contains(&sat.prn);
+    }
+  }
+
+  /// Counts a sentence skipped for a bad checksum while reading this fix
+  pub fn count_checksum_failure(&mut self) {
+    self.checksum_failures += 1;
+  }
 }
 
 
+/// Satellite implementations
+impl Satellite {
+  pub fn prn(&self) -> i32 {
+    self.prn
+  }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+  pub fn elevation(&self) -> f32 {
+    self.elevation
+  }
 
-  #[test]
-  fn test_calc_new_pos() {
-    let old_pos = GpsCoord::new(0.0, 0.0, 0.0);
-    let vel = RawPoint::new(1.0, 1.0, 1.0);
-    let accel = RawPoint::new(1.0, 1.0, 1.0);
-    let time = 1.0;
-    let new_pos = calc_new_pos(&old_pos, &vel, &accel, &time);
-    assert_eq!(new_pos.lat(), 1.5);
-    assert_eq!(new_pos.lon(), 1.5);
-    assert_eq!(new_pos.alt(), 1.5);
+  pub fn azimuth(&self) -> f32 {
+    self.azimuth
   }
 
-  #[test]
-  fn test_calc_new_vel() {
-    let old_vel = RawPoint::new(0.0, 0.0, 0.0);
-    let accel = RawPoint::new(1.0, 1.0, 1.0);
-    let time = 1.0;
-    let new_vel = calc_new_vel(&old_vel, &accel, &time);
-    assert_eq!(new_vel.x(), 1.0);
-    assert_eq!(new_vel.y(), 1.0);
-    assert_eq!(new_vel.z(), 1.0);
+  pub fn snr(&self) -> f32 {
+    self.snr
+  }
+
+  pub fn used(&self) -> bool {
+    self.used
   }
+}
+
+
+/// Splits an rmc date (ddmmyy) into (year, month, day)
+fn parse_date(date: &str) -> Option<(i32, u32, u32)> {
+  if date.len() != 6 || !date.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  let day = date[0..2].parse().ok()?;
+  let month = date[2..4].parse().ok()?;
+  let year: i32 = date[4..6].parse().ok()?;
+  Some((2000 + year, month, day))
+}
+
+/// Splits an rmc utc time (hhmmss.sss) into (hours, minutes, seconds)
+fn split_utc(utc: f64) -> Option<(u32, u32, f64)> {
+  if !(0.0..240000.0).contains(&utc) {
+    return None;
+  }
+  let hour = (utc / 10000.0).floor() as u32;
+  let min = ((utc / 100.0).floor() as u32) % 100;
+  let sec = utc % 100.0;
+  Some((hour, min, sec))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
 
   #[test]
-  fn test_degrees_to_radians() {
-    let deg = 180.0;
-    let rad = degrees_to_radians(deg);
-    assert_eq!(rad, PI);
+  fn test_iso_time() {
+    let mut data = GpsData::new();
+    assert_eq!(data.iso_time(), None);
+
+    data.apply_rmc(&RmcData { utc: 221320.5, date: "141123".to_string(), ..Default::default() });
+    assert_eq!(data.iso_time(), Some("2023-11-14T22:13:20.500Z".to_string()));
+    assert_eq!(data.utc_seconds(), Some(1_700_000_000.5));
   }
 
   #[test]
-  fn test_haversine_distance() {
-    let lat1 = 0.0;
-    let lon1 = 0.0;
-    let lat2 = 0.0;
-    let lon2 = 0.0;
-    let dist = haversine_distance(lat1, lon1, lat2, lon2);
-    assert_eq!(dist, 0.0);
-
-    let lat1 = 0.0;
-    let lon1 = 0.0;
-    let lat2 = 10.0;
-    let lon2 = 10.0;
-    let dist = haversine_distance(lat1, lon1, lat2, lon2);
-    assert_eq!(dist, 1568520.6);
+  fn test_used_satellites() {
+    let mut data = GpsData::new();
+    data.apply_gsa(&GsaData { dimension_fix: DimensionFix::Dimension3d, sat1: Some(4), sat2: Some(9), ..Default::default() });
+    data.apply_gsv(&[
+      Satellites { id: Some(4), elevation: Some(45.0), azimuth: Some(120.0), snr: Some(38.0) },
+      Satellites { id: Some(7), elevation: Some(10.0), azimuth: Some(300.0), snr: None },
+      Satellites { id: None, elevation: None, azimuth: None, snr: None },
+    ]);
+    data.mark_used_satellites();
+
+    assert_eq!(data.mode(), 3);
+    assert_eq!(data.satellites().len(), 2);
+    assert!(data.satellites()[0].used());
+    assert!(!data.satellites()[1].used());
+    assert_eq!(data.satellites()[1].snr(), 0.0);
+    assert_eq!(data.mean_snr(), Some(38.0));
   }
 }
\ No newline at end of file
@@ -1 +1,2 @@
 pub mod gps;
+pub mod nmea;
@@ -0,0 +1,237 @@
+use adafruit_gps::gsv::Satellites;
+use adafruit_gps::{gga, gll, gsa, gsv, rmc, vtg, Gps, GpsSentence};
+use embedded_hal::serial::Read;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const READ_TIMEOUT: Duration = Duration::from_secs(1); // Time without a full line before the gps counts as disconnected
+const MAX_LINE_LEN: usize = 255; // Longest line kept, nmea sentences are at most 82 characters
+const POLL_INTERVAL: Duration = Duration::from_millis(1); // Wait between reads while the port has nothing
+
+
+/// Anything the gps' sentences can be read from, one at a time
+pub trait SentenceSource {
+  /// Reads the next sentence, GpsSentence::NoConnection if nothing arrived in time
+  fn next_sentence(&mut self) -> GpsSentence;
+}
+
+/// Reads nmea sentences from any serial port implementing the embedded-hal serial Read
+/// trait: rppal's Uart on a Pi, linux-embedded-hal's Serial on other Linux boards, or a
+/// mock in tests. Sentences are checked and parsed the same way adafruit_gps does, into
+/// its GpsSentence, so the fix is put together by the same code whichever is used.
+pub struct NmeaReader<S> {
+  serial: S,
+  timeout: Duration,
+  line: Vec<u8>,
+  satellites: Vec<Satellites>, // gsv satellites of the messages read so far
+}
+
+
+impl SentenceSource for Gps {
+  fn next_sentence(&mut self) -> GpsSentence {
+    self.update()
+  }
+}
+
+
+/// NmeaReader implementations
+impl<S: Read<u8>> NmeaReader<S> {
+  pub fn new(serial: S) -> Self {
+    NmeaReader::with_timeout(serial, READ_TIMEOUT)
+  }
+
+  /// Reader that gives up on a line after 'timeout' without one
+  pub fn with_timeout(serial: S, timeout: Duration) -> Self {
+    NmeaReader { serial, timeout, line: Vec::with_capacity(MAX_LINE_LEN), satellites: Vec::new() }
+  }
+
+  /// Gives the port back
+  pub fn release(self) -> S {
+    self.serial
+  }
+
+  /// Reads up to the end of a line (or MAX_LINE_LEN bytes). A '$' starts the line again,
+  /// so a sentence cut short by a read error doesn't swallow the next one. None if the
+  /// line didn't finish within the timeout.
+  fn read_line(&mut self) -> Option<Vec<u8>> {
+    let start = Instant::now();
+    loop {
+      match self.serial.read() {
+        Ok(b'$') => {
+          self.line.clear();
+          self.line.push(b'$');
+        }
+        Ok(b'\n') => return Some(std::mem::take(&mut self.line)),
+        Ok(byte) => {
+          self.line.push(byte);
+          if self.line.len() >= MAX_LINE_LEN {
+            return Some(std::mem::take(&mut self.line));
+          }
+        }
+        Err(nb::Error::Other(_)) => {
+          self.line.clear(); // overrun or framing error, the line is lost
+          if start.elapsed() > self.timeout {
+            return None;
+          }
+        }
+        Err(nb::Error::WouldBlock) => {
+          if start.elapsed() > self.timeout {
+            return None;
+          }
+          thread::sleep(POLL_INTERVAL);
+        }
+      }
+    }
+  }
+}
+
+impl<S: Read<u8>> SentenceSource for NmeaReader<S> {
+  fn next_sentence(&mut self) -> GpsSentence {
+    loop {
+      let Some(line) = self.read_line() else {
+        return GpsSentence::NoConnection;
+      };
+      let Ok(line) = std::str::from_utf8(&line) else {
+        return GpsSentence::InvalidBytes; // usually the port and the gps' baud rates don't match
+      };
+      let Some(fields) = split_sentence(line) else {
+        return GpsSentence::InvalidSentence;
+      };
+      if fields[0].get(3..6) != Some("GSV") {
+        return parse_fields(fields);
+      }
+      if fields.len() < 4 {
+        return GpsSentence::InvalidSentence;
+      }
+
+      // Satellites in view come over several messages, returned together once the last arrives
+      let (Ok(count), Ok(number)) = (fields[1].parse::<u32>(), fields[2].parse::<u32>()) else {
+        return GpsSentence::InvalidSentence;
+      };
+      if number == 1 {
+        self.satellites.clear();
+      }
+      self.satellites.append(&mut gsv::parse_gsv(fields));
+      if number >= count {
+        return GpsSentence::GSV(std::mem::take(&mut self.satellites));
+      }
+    }
+  }
+}
+
+
+/// Checks a line's checksum and splits it into its fields (the first being the header,
+/// eg. "$GPRMC"), without the checksum. None if it isn't a whole, valid sentence.
+pub fn split_sentence(line: &str) -> Option<Vec<&str>> {
+  let line = line.trim();
+  let (body, checksum) = line.strip_prefix('$')?.rsplit_once('*')?;
+  let expected = u8::from_str_radix(checksum, 16).ok()?;
+  if checksum.len() != 2 || body.bytes().fold(0, |sum, byte| sum ^ byte) != expected {
+    return None;
+  }
+  let fields: Vec<&str> = line[..line.len() - 3].split(',').collect();
+  (fields[0].len() >= 6).then_some(fields)
+}
+
+
+/// Parses a single sentence's fields with adafruit_gps' parsers. Those panic on missing
+/// fields and some malformed values, so those are checked first and the sentence is
+/// counted as invalid instead.
+fn parse_fields(fields: Vec<&str>) -> GpsSentence {
+  let position = |degrees: usize, direction: usize| valid_position(fields[degrees], fields[direction]);
+  match fields[0].get(3..6).unwrap_or("") {
+    "RMC" if fields.len() >= 10 && position(3, 4) && position(5, 6) => GpsSentence::RMC(rmc::parse_rmc(fields)),
+    "GGA" if fields.len() >= 14 && position(2, 3) && position(4, 5)
+           && fields[1].parse::<f64>().is_ok() && fields[7].parse::<i32>().is_ok() => GpsSentence::GGA(gga::parse_gga(fields)),
+    "GSA" if fields.len() >= 18 => GpsSentence::GSA(gsa::parse_gsa(fields)),
+    "VTG" if fields.len() >= 8 => GpsSentence::VTG(vtg::parse_vtg(fields)),
+    "GLL" if fields.len() >= 5 && position(1, 2) && position(3, 4) => GpsSentence::GLL(gll::parse_gll(fields)),
+    _ => GpsSentence::InvalidSentence,
+  }
+}
+
+
+/// Whether a latitude or longitude (d)ddmm.mmmm and its hemisphere can be parsed: empty,
+/// as before a fix, or whole
+fn valid_position(degrees: &str, direction: &str) -> bool {
+  if degrees.is_empty() {
+    return true;
+  }
+  let whole = degrees.split('.').next().unwrap_or("");
+  matches!(direction, "N" | "S" | "E" | "W") && (4..=5).contains(&whole.len())
+    && degrees.bytes().all(|byte| byte.is_ascii_digit() || byte == b'.') && degrees.parse::<f32>().is_ok()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::neo6m::gps;
+  use std::collections::VecDeque;
+
+  const RMC: &str = "$GPRMC,123519.00,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*44\r\n";
+  const GGA: &str = "$GPGGA,123519.00,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*69\r\n";
+  const GSA: &str = "$GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39\r\n";
+  const GSV_1: &str = "$GPGSV,2,1,05,04,45,120,40,05,30,200,35,09,60,010,42,12,10,300,20*74\r\n";
+  const GSV_2: &str = "$GPGSV,2,2,05,24,70,090,45*45\r\n";
+
+  /// A port that has received 'bytes' and nothing more
+  struct MockSerial(VecDeque<u8>);
+
+  impl Read<u8> for MockSerial {
+    type Error = ();
+
+    fn read(&mut self) -> nb::Result<u8, ()> {
+      self.0.pop_front().ok_or(nb::Error::WouldBlock)
+    }
+  }
+
+  /// A port whose every read fails, as a disconnected usb adapter's does
+  struct FailingSerial;
+
+  impl Read<u8> for FailingSerial {
+    type Error = ();
+
+    fn read(&mut self) -> nb::Result<u8, ()> {
+      Err(nb::Error::Other(()))
+    }
+  }
+
+  fn reader(text: &[&str]) -> NmeaReader<MockSerial> {
+    let bytes = text.iter().flat_map(|line| line.bytes()).collect();
+    NmeaReader::with_timeout(MockSerial(bytes), Duration::from_millis(10))
+  }
+
+  #[test]
+  fn test_next_sentence() {
+    let mut nmea = reader(&["GA,garbage*00\r\n", GSV_1, GSV_2, &RMC.replace("*44", "*45"), "$GPGGA,,,,,,0,,,,,,,,*66\r\n"]);
+    assert_eq!(nmea.next_sentence(), GpsSentence::InvalidSentence); // the tail of a sentence
+    let GpsSentence::GSV(satellites) = nmea.next_sentence() else {
+      panic!("expected the gsv messages together");
+    };
+    assert_eq!(satellites.iter().map(|sat| sat.id.unwrap()).collect::<Vec<_>>(), [4, 5, 9, 12, 24]);
+    assert_eq!(nmea.next_sentence(), GpsSentence::InvalidSentence); // bad checksum
+    assert_eq!(nmea.next_sentence(), GpsSentence::InvalidSentence); // no time, adafruit_gps would panic
+    assert_eq!(nmea.next_sentence(), GpsSentence::NoConnection);
+
+    let mut nmea = NmeaReader::with_timeout(MockSerial(vec![b'$', 0xFF, b'\n'].into()), Duration::from_millis(10));
+    assert_eq!(nmea.next_sentence(), GpsSentence::InvalidBytes);
+
+    let mut nmea = NmeaReader::with_timeout(FailingSerial, Duration::from_millis(10));
+    assert_eq!(nmea.next_sentence(), GpsSentence::NoConnection); // read errors time out too
+  }
+
+  #[test]
+  fn test_get_gps_timed() {
+    let mut nmea = reader(&[GSA, GSV_1, GSV_2, RMC, GGA]);
+    let (data, _) = gps::get_gps_timed(&mut nmea).unwrap();
+    assert!((data.lat() - 48.1173).abs() < 1e-4);
+    assert!((data.lon() - 11.5167).abs() < 1e-4);
+    assert_eq!(data.alt(), 545.4);
+    assert_eq!(data.mode(), 3);
+    assert_eq!(data.sats_used(), [4, 5, 9, 12, 24]);
+    assert_eq!(data.satellites().iter().filter(|sat| sat.used()).count(), 5);
+    assert!(gps::get_gps_timed(&mut nmea).is_none()); // nothing more was sent
+  }
+}
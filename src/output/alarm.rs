@@ -0,0 +1,174 @@
+use rppal::gpio::{Gpio, InputPin, OutputPin, Trigger};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::detect::verdict::AlertState;
+
+
+/// The gpio lines the alarm needs, so the alarm logic can run without hardware
+pub trait AlarmPins {
+  /// Drives the alarm line (led, buzzer or another controller's input)
+  fn set_alarm(&mut self, on: bool);
+  /// Returns true if the acknowledge input was triggered since the last call
+  fn take_ack(&mut self) -> bool;
+}
+
+/// Hardware alarm that follows the alert state.
+///
+/// The alarm line is driven while the state is spoofed and stays latched
+/// after the state recovers, until the acknowledge input is triggered. An
+/// acknowledge while the state is still spoofed is ignored.
+pub struct Alarm<P: AlarmPins> {
+  pins: P,
+  spoofed: bool,
+  latched: bool,
+  on: bool,
+}
+
+/// Alarm pins on the Raspberry Pi's gpio header
+pub struct RppalAlarmPins {
+  output: OutputPin,
+  active_low: bool,
+  _ack: InputPin, // kept alive so the interrupt stays registered
+  ack_pressed: Arc<AtomicBool>,
+}
+
+/// In memory alarm pins for tests
+#[derive(Debug, Default)]
+pub struct MockAlarmPins {
+  pub alarm_on: bool,
+  pub ack_pending: bool,
+}
+
+
+/// Alarm implementations
+impl<P: AlarmPins> Alarm<P> {
+  pub fn new(mut pins: P) -> Alarm<P> {
+    pins.set_alarm(false);
+    Alarm { pins, spoofed: false, latched: false, on: false }
+  }
+
+  /// Updates the alarm line from the latest alert state and the acknowledge input
+  pub fn update(&mut self, state: AlertState) {
+    let spoofed = state == AlertState::Spoofed;
+    if spoofed && !self.spoofed {
+      self.latched = true; // new alarm
+    }
+    self.spoofed = spoofed;
+
+    if self.pins.take_ack() && !spoofed {
+      self.latched = false;
+    }
+
+    let on = self.latched;
+    if on != self.on {
+      self.pins.set_alarm(on);
+      self.on = on;
+    }
+  }
+
+  /// Whether the alarm line is currently driven
+  pub fn is_on(&self) -> bool {
+    self.on
+  }
+
+  pub fn pins(&self) -> &P {
+    &self.pins
+  }
+
+  pub fn pins_mut(&mut self) -> &mut P {
+    &mut self.pins
+  }
+}
+
+
+/// RppalAlarmPins implementations
+impl RppalAlarmPins {
+  /// Claims 'alarm_pin' as an output and 'ack_pin' as a pulled up input that is
+  /// acknowledged by pulling it low (eg. a button to ground). Pins are BCM numbers.
+  pub fn new(alarm_pin: u8, active_low: bool, ack_pin: u8) -> rppal::gpio::Result<RppalAlarmPins> {
+    let gpio = Gpio::new()?;
+    // start with the line in its off level, so an active low alarm doesn't blip on
+    let pin = gpio.get(alarm_pin)?;
+    let output = if active_low { pin.into_output_high() } else { pin.into_output_low() };
+    let mut ack = gpio.get(ack_pin)?.into_input_pullup();
+
+    let ack_pressed = Arc::new(AtomicBool::new(false));
+    let pressed = Arc::clone(&ack_pressed);
+    ack.set_async_interrupt(Trigger::FallingEdge, move |_| pressed.store(true, Ordering::Relaxed))?;
+
+    Ok(RppalAlarmPins { output, active_low, _ack: ack, ack_pressed })
+  }
+}
+
+impl AlarmPins for RppalAlarmPins {
+  fn set_alarm(&mut self, on: bool) {
+    if on != self.active_low {
+      self.output.set_high();
+    } else {
+      self.output.set_low();
+    }
+  }
+
+  fn take_ack(&mut self) -> bool {
+    self.ack_pressed.swap(false, Ordering::Relaxed)
+  }
+}
+
+
+/// MockAlarmPins implementations
+impl MockAlarmPins {
+  /// Simulates a press of the acknowledge button
+  pub fn press_ack(&mut self) {
+    self.ack_pending = true;
+  }
+}
+
+impl AlarmPins for MockAlarmPins {
+  fn set_alarm(&mut self, on: bool) {
+    self.alarm_on = on;
+  }
+
+  fn take_ack(&mut self) -> bool {
+    std::mem::take(&mut self.ack_pending)
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_alarm_latches_until_ack() {
+    let mut alarm = Alarm::new(MockAlarmPins::default());
+    alarm.update(AlertState::Suspect);
+    assert!(!alarm.pins().alarm_on);
+
+    alarm.update(AlertState::Spoofed);
+    assert!(alarm.pins().alarm_on);
+
+    alarm.update(AlertState::Nominal);
+    assert!(alarm.pins().alarm_on); // still latched
+
+    alarm.pins_mut().press_ack();
+    alarm.update(AlertState::Nominal);
+    assert!(!alarm.pins().alarm_on);
+  }
+
+  #[test]
+  fn test_ack_ignored_while_spoofed() {
+    let mut alarm = Alarm::new(MockAlarmPins::default());
+    alarm.update(AlertState::Spoofed);
+    alarm.pins_mut().press_ack();
+    alarm.update(AlertState::Spoofed);
+    assert!(alarm.is_on());
+
+    alarm.update(AlertState::Nominal);
+    assert!(alarm.is_on()); // the ack was spent while spoofed
+    alarm.pins_mut().press_ack();
+    alarm.update(AlertState::Nominal);
+    assert!(!alarm.is_on());
+  }
+}
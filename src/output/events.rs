@@ -0,0 +1,217 @@
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::detect::verdict::{AlertState, DetectorScore, Verdict};
+use crate::timing::calendar::iso_time;
+
+pub const SCHEMA_VERSION: u32 = 1; // Bump when an event's fields change meaning or are removed
+
+
+/// Typed events written to the event log, one json object per line
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+  FixReceived {
+    fix_time: Option<String>, // utc time reported by the gps
+    lat: f32,
+    lon: f32,
+    alt: f32,
+    hdop: f32,
+    sats_used: usize,
+  },
+  PredictionMade {
+    lat: f32,
+    lon: f32,
+    alt: f32,
+    vel: [f32; 3], // m/s
+    dt: f64,       // seconds integrated over
+  },
+  DetectorVerdict {
+    state: AlertState,
+    scores: Vec<DetectorScore>,
+    residual_m: f32, // distance between the fix and the prediction
+  },
+  AlertTransition {
+    from: Option<AlertState>,
+    to: AlertState,
+  },
+  SensorError {
+    sensor: String,
+    message: String,
+  },
+  OutputError {
+    output: String, // eg. "mqtt"
+    message: String,
+  },
+  CalibrationComplete {
+    sensor: String,
+    accel_offset: [i16; 3],
+    gyro_offset: [i16; 3],
+    duration_s: f64,
+  },
+  CalibrationLoaded {
+    sensor: String,
+    accel_offset: [i16; 3],
+    gyro_offset: [i16; 3],
+    age_s: u64, // time since the saved calibration was taken
+  },
+}
+
+/// An event stamped with the host time and schema version
+#[derive(Serialize)]
+struct Record<'a> {
+  schema: u32,
+  ts: String,
+  #[serde(flatten)]
+  event: &'a Event,
+}
+
+/// Writes events as JSON Lines to stdout or a size rotated file
+pub struct EventLog {
+  sink: Sink,
+  last_state: Option<AlertState>,
+}
+
+enum Sink {
+  Stdout,
+  File(RotatingFile),
+}
+
+/// A log file that is rolled over to path.1, path.2, ... once it reaches 'max_bytes'
+struct RotatingFile {
+  path: PathBuf,
+  max_bytes: u64,
+  max_files: u32, // rotated files kept besides the active one
+  file: File,
+  written: u64,
+}
+
+
+/// EventLog implementations
+impl EventLog {
+  pub fn stdout() -> EventLog {
+    EventLog { sink: Sink::Stdout, last_state: None }
+  }
+
+  /// Appends to the file at 'path', keeping at most 'max_files' rotated files of 'max_bytes' each
+  pub fn rotating_file(path: &str, max_bytes: u64, max_files: u32) -> io::Result<EventLog> {
+    let file = RotatingFile::open(PathBuf::from(path), max_bytes, max_files)?;
+    Ok(EventLog { sink: Sink::File(file), last_state: None })
+  }
+
+  pub fn log(&mut self, event: &Event) -> io::Result<()> {
+    let mut line = to_line(event, SystemTime::now());
+    line.push('\n');
+    match &mut self.sink {
+      Sink::Stdout => {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(line.as_bytes())?;
+        stdout.flush()
+      }
+      Sink::File(file) => file.write_line(line.as_bytes()),
+    }
+  }
+
+  /// Logs a detector verdict, preceded by an alert transition if the state changed
+  pub fn log_verdict(&mut self, verdict: &Verdict, residual_m: f32) -> io::Result<()> {
+    if self.last_state != Some(verdict.state()) {
+      self.log(&Event::AlertTransition { from: self.last_state, to: verdict.state() })?;
+      self.last_state = Some(verdict.state());
+    }
+    self.log(&Event::DetectorVerdict { state: verdict.state(), scores: verdict.scores().to_vec(), residual_m })
+  }
+}
+
+
+/// RotatingFile implementations
+impl RotatingFile {
+  fn open(path: PathBuf, max_bytes: u64, max_files: u32) -> io::Result<RotatingFile> {
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let written = file.metadata()?.len();
+    Ok(RotatingFile { path, max_bytes, max_files, file, written })
+  }
+
+  fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+    if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+      self.rotate()?;
+    }
+    self.file.write_all(line)?;
+    self.written += line.len() as u64;
+    Ok(())
+  }
+
+  /// Shifts path.N-1 -> path.N, ..., path -> path.1 and starts a fresh file
+  fn rotate(&mut self) -> io::Result<()> {
+    if self.max_files == 0 {
+      self.file = File::create(&self.path)?; // nothing to keep, just truncate
+    } else {
+      for i in (1..self.max_files).rev() {
+        let from = self.rotated_path(i);
+        if from.exists() {
+          fs::rename(&from, self.rotated_path(i + 1))?;
+        }
+      }
+      fs::rename(&self.path, self.rotated_path(1))?;
+      self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+    }
+    self.written = 0;
+    Ok(())
+  }
+
+  fn rotated_path(&self, index: u32) -> PathBuf {
+    let mut path = self.path.clone().into_os_string();
+    path.push(format!(".{index}"));
+    PathBuf::from(path)
+  }
+}
+
+
+/// Serializes an event with its schema version and timestamp
+fn to_line(event: &Event, now: SystemTime) -> String {
+  let unix_s = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+  let record = Record { schema: SCHEMA_VERSION, ts: iso_time(unix_s), event };
+  serde_json::to_string(&record).expect("events always serialize")
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::Value;
+  use std::time::Duration;
+
+  #[test]
+  fn test_record_format() {
+    let event = Event::AlertTransition { from: Some(AlertState::Nominal), to: AlertState::Spoofed };
+    let line = to_line(&event, UNIX_EPOCH + Duration::from_millis(1_700_000_000_250));
+    let record: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(record["schema"], SCHEMA_VERSION);
+    assert_eq!(record["ts"], "2023-11-14T22:13:20.250Z");
+    assert_eq!(record["event"], "alert_transition");
+    assert_eq!(record["from"], "nominal");
+    assert_eq!(record["to"], "spoofed");
+  }
+
+  #[test]
+  fn test_rotation() {
+    let dir = std::env::temp_dir().join(format!("events_rotation_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("events.jsonl");
+    let mut log = EventLog::rotating_file(path.to_str().unwrap(), 200, 2).unwrap();
+
+    let event = Event::SensorError { sensor: "gps".to_string(), message: "lost connection".to_string() };
+    for _ in 0..10 {
+      log.log(&event).unwrap();
+    }
+
+    assert!(fs::metadata(&path).unwrap().len() <= 200);
+    assert!(dir.join("events.jsonl.1").exists());
+    assert!(dir.join("events.jsonl.2").exists());
+    assert!(!dir.join("events.jsonl.3").exists());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,417 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::detect::verdict::{AlertState, DetectorScore, Verdict};
+use crate::neo6m::gps::GpsData;
+
+const RELEASE: &str = "3.25"; // gpsd release we model the protocol on
+const PROTO_MAJOR: u8 = 3;
+const PROTO_MINOR: u8 = 14;
+const VENDOR: &str = "gps_spoofing_detection"; // tags the spoofing extension object
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200); // Longest a client may hold up a publish before it's dropped
+
+
+/// Serves the gpsd JSON protocol (VERSION, WATCH, DEVICES, POLL, TPV, SKY) over TCP.
+///
+/// Each published fix is sent to watching clients as a TPV and a SKY object,
+/// followed by a SPOOF object carrying the spoofing state and detector scores.
+/// gpsd clients skip classes they don't know, so they still get positions.
+pub struct GpsdServer {
+  device: String,
+  local_addr: SocketAddr,
+  clients: Arc<Mutex<Vec<Client>>>,
+  last_report: Arc<Mutex<Option<Report>>>,
+}
+
+/// A connected client, shared between the accept thread and its reader thread
+#[derive(Clone)]
+struct Client {
+  stream: Arc<Mutex<TcpStream>>,
+  watching: Arc<AtomicBool>,
+  alive: Arc<AtomicBool>, // cleared by the reader thread once the client disconnects
+}
+
+/// The most recent objects sent to watchers, kept to answer ?POLL
+#[derive(Clone)]
+struct Report {
+  time: Option<String>,
+  tpv: Tpv,
+  sky: Sky,
+}
+
+#[derive(Serialize)]
+struct Version {
+  class: &'static str,
+  release: &'static str,
+  rev: &'static str,
+  proto_major: u8,
+  proto_minor: u8,
+}
+
+#[derive(Serialize)]
+struct Devices<'a> {
+  class: &'static str,
+  devices: Vec<Device<'a>>,
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+  class: &'static str,
+  path: &'a str,
+  driver: &'static str,
+  activated: bool,
+}
+
+#[derive(Serialize)]
+struct Watch {
+  class: &'static str,
+  enable: bool,
+  json: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct Tpv {
+  class: &'static str,
+  device: String,
+  mode: u8,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  time: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  lat: Option<f32>, // left out without a fix, as gpsd does
+  #[serde(skip_serializing_if = "Option::is_none")]
+  lon: Option<f32>,
+  #[serde(rename = "altMSL", skip_serializing_if = "Option::is_none")]
+  alt_msl: Option<f32>, // only with a 3d fix
+  #[serde(skip_serializing_if = "Option::is_none")]
+  speed: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  track: Option<f32>,
+}
+
+#[derive(Clone, Serialize)]
+struct Sky {
+  class: &'static str,
+  device: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  time: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  hdop: Option<f32>, // left out when the receiver didn't report them
+  #[serde(skip_serializing_if = "Option::is_none")]
+  vdop: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pdop: Option<f32>,
+  #[serde(rename = "nSat")]
+  n_sat: usize,
+  #[serde(rename = "uSat")]
+  u_sat: usize,
+  satellites: Vec<SkySatellite>,
+}
+
+#[derive(Clone, Serialize)]
+struct SkySatellite {
+  #[serde(rename = "PRN")]
+  prn: i32,
+  el: f32,
+  az: f32,
+  ss: f32,
+  used: bool,
+}
+
+/// Vendor extension object with the detector output
+#[derive(Serialize)]
+struct Spoof<'a> {
+  class: &'static str,
+  vendor: &'static str,
+  device: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  time: Option<&'a str>,
+  state: AlertState,
+  scores: &'a [DetectorScore],
+}
+
+#[derive(Serialize)]
+struct Error {
+  class: &'static str,
+  message: String,
+}
+
+#[derive(Serialize)]
+struct Poll<'a> {
+  class: &'static str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  time: Option<&'a str>,
+  active: usize,
+  tpv: Vec<&'a Tpv>,
+  sky: Vec<&'a Sky>,
+}
+
+
+/// GpsdServer implementations
+impl GpsdServer {
+  /// Binds the server and starts accepting clients in the background.
+  /// 'device' is the gps port reported to clients (eg. /dev/ttyS0).
+  pub fn bind<A: ToSocketAddrs>(addr: A, device: &str) -> io::Result<GpsdServer> {
+    let listener = TcpListener::bind(addr)?;
+    let server = GpsdServer {
+      device: device.to_string(),
+      local_addr: listener.local_addr()?,
+      clients: Arc::new(Mutex::new(Vec::new())),
+      last_report: Arc::new(Mutex::new(None)),
+    };
+
+    let clients = Arc::clone(&server.clients);
+    let last_report = Arc::clone(&server.last_report);
+    let device = server.device.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        if let Err(e) = accept_client(stream, &device, &clients, &last_report) {
+          eprintln!("gpsd client failed to connect: {e}");
+        }
+      }
+    });
+
+    Ok(server)
+  }
+
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+
+  /// Sends a fix and its verdict to every watching client. Clients that disconnected, or
+  /// didn't take the fix within WRITE_TIMEOUT, are dropped.
+  pub fn publish(&self, data: &GpsData, verdict: &Verdict) {
+    let report = Report::new(&self.device, data);
+    let spoof = Spoof {
+      class: "SPOOF",
+      vendor: VENDOR,
+      device: &self.device,
+      time: report.time.as_deref(),
+      state: verdict.state(),
+      scores: verdict.scores(),
+    };
+    let lines = [to_line(&report.tpv), to_line(&report.sky), to_line(&spoof)].concat();
+
+    let mut clients = self.clients.lock().unwrap();
+    clients.retain(|client| {
+      if !client.alive.load(Ordering::Relaxed) {
+        return false;
+      }
+      !client.watching.load(Ordering::Relaxed) || client.send(&lines).is_ok() // drop clients that went away or fell behind
+    });
+    *self.last_report.lock().unwrap() = Some(report);
+  }
+}
+
+
+/// Greets a new client and starts a thread to answer its commands
+fn accept_client(stream: TcpStream, device: &str, clients: &Arc<Mutex<Vec<Client>>>,
+                 last_report: &Arc<Mutex<Option<Report>>>) -> io::Result<()> {
+  stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+  let reader = BufReader::new(stream.try_clone()?);
+  let client = Client {
+    stream: Arc::new(Mutex::new(stream)),
+    watching: Arc::new(AtomicBool::new(false)),
+    alive: Arc::new(AtomicBool::new(true)),
+  };
+  client.send(&to_line(&version()))?;
+  clients.lock().unwrap().push(client.clone());
+
+  let device = device.to_string();
+  let last_report = Arc::clone(last_report);
+  thread::spawn(move || {
+    'lines: for line in reader.lines() {
+      let Ok(line) = line else { break };
+      for command in line.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+        let response = handle_command(command, &device, &client, &last_report);
+        if client.send(&response).is_err() {
+          break 'lines;
+        }
+      }
+    }
+    client.alive.store(false, Ordering::Relaxed); // eof or an error, the next publish prunes it
+  });
+  Ok(())
+}
+
+/// Answers a single gpsd command (eg. ?WATCH={"enable":true}) with newline separated json objects
+fn handle_command(command: &str, device: &str, client: &Client, last_report: &Mutex<Option<Report>>) -> String {
+  let (name, args) = match command.split_once('=') {
+    Some((name, args)) => (name, serde_json::from_str(args).unwrap_or(Value::Null)),
+    None => (command, Value::Null),
+  };
+
+  match name {
+    "?VERSION" => to_line(&version()),
+    "?DEVICES" => to_line(&devices(device)),
+    "?WATCH" => {
+      // we only speak json, so an enabled watch is a json watch unless told otherwise
+      let enable = args.get("enable").and_then(Value::as_bool).unwrap_or(true);
+      let json = enable && args.get("json").and_then(Value::as_bool).unwrap_or(true);
+      client.watching.store(json, Ordering::Relaxed);
+      [to_line(&devices(device)), to_line(&Watch { class: "WATCH", enable, json })].concat()
+    }
+    "?POLL" => {
+      let last_report = last_report.lock().unwrap();
+      let poll = Poll {
+        class: "POLL",
+        time: last_report.as_ref().and_then(|r| r.time.as_deref()),
+        active: last_report.iter().count(),
+        tpv: last_report.iter().map(|r| &r.tpv).collect(),
+        sky: last_report.iter().map(|r| &r.sky).collect(),
+      };
+      to_line(&poll)
+    }
+    _ => to_line(&Error { class: "ERROR", message: format!("Unrecognized request '{}'", name.trim_start_matches('?')) }),
+  }
+}
+
+fn version() -> Version {
+  Version { class: "VERSION", release: RELEASE, rev: env!("CARGO_PKG_VERSION"), proto_major: PROTO_MAJOR, proto_minor: PROTO_MINOR }
+}
+
+fn devices(device: &str) -> Devices<'_> {
+  Devices { class: "DEVICES", devices: vec![Device { class: "DEVICE", path: device, driver: "NMEA0183", activated: true }] }
+}
+
+/// Serializes a protocol object as a single line
+fn to_line<T: Serialize>(object: &T) -> String {
+  let mut line = serde_json::to_string(object).expect("gpsd objects always serialize");
+  line.push('\n');
+  line
+}
+
+
+/// Client implementations
+impl Client {
+  fn send(&self, lines: &str) -> io::Result<()> {
+    self.stream.lock().unwrap().write_all(lines.as_bytes())
+  }
+}
+
+
+/// Report implementations
+impl Report {
+  fn new(device: &str, data: &GpsData) -> Report {
+    let time = data.iso_time();
+    let (fix_2d, fix_3d) = (data.mode() >= 2, data.mode() >= 3);
+    let dop = |dop: f32| (dop > 0.0).then_some(dop); // 0 before a gsa, -1 when it had none
+    let tpv = Tpv {
+      class: "TPV",
+      device: device.to_string(),
+      mode: data.mode(),
+      time: time.clone(),
+      lat: fix_2d.then(|| data.lat()),
+      lon: fix_2d.then(|| data.lon()),
+      alt_msl: fix_3d.then(|| data.alt()),
+      speed: (data.speed() >= 0.0).then(|| data.speed_mps()),
+      track: (data.course() >= 0.0).then(|| data.course()),
+    };
+    let sky = Sky {
+      class: "SKY",
+      device: device.to_string(),
+      time: time.clone(),
+      hdop: dop(data.hor_prec()),
+      vdop: dop(data.ver_prec()),
+      pdop: dop(data.pos_prec()),
+      n_sat: data.satellites().len(),
+      u_sat: data.sats_used().len(),
+      satellites: data.satellites().iter().map(|sat| SkySatellite {
+        prn: sat.prn(),
+        el: sat.elevation(),
+        az: sat.azimuth(),
+        ss: sat.snr(),
+        used: sat.used(),
+      }).collect(),
+    };
+    Report { time, tpv, sky }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn connect(server: &GpsdServer) -> (TcpStream, BufReader<TcpStream>) {
+    let stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+  }
+
+  fn read_object(reader: &mut BufReader<TcpStream>) -> Value {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    serde_json::from_str(&line).unwrap()
+  }
+
+  #[test]
+  fn test_watch_and_publish() {
+    let server = GpsdServer::bind("127.0.0.1:0", "/dev/ttyS0").unwrap();
+    let (mut stream, mut reader) = connect(&server);
+    assert_eq!(read_object(&mut reader)["class"], "VERSION");
+
+    stream.write_all(b"?WATCH={\"enable\":true,\"json\":true};\n").unwrap();
+    assert_eq!(read_object(&mut reader)["class"], "DEVICES");
+    let watch = read_object(&mut reader);
+    assert_eq!(watch["class"], "WATCH");
+    assert_eq!(watch["json"], true);
+
+    let verdict = Verdict::from_scores(&[DetectorScore::new("position", 1.5)]);
+    server.publish(&GpsData::new(), &verdict);
+
+    let tpv = read_object(&mut reader);
+    assert_eq!(tpv["class"], "TPV");
+    assert_eq!(tpv["device"], "/dev/ttyS0");
+    assert!(tpv.get("lat").is_none()); // no fix, so no position
+    let sky = read_object(&mut reader);
+    assert_eq!(sky["class"], "SKY");
+    assert!(sky.get("hdop").is_none());
+    let spoof = read_object(&mut reader);
+    assert_eq!(spoof["class"], "SPOOF");
+    assert_eq!(spoof["state"], "spoofed");
+    assert_eq!(spoof["scores"][0]["name"], "position");
+  }
+
+  #[test]
+  fn test_poll_and_unknown_command() {
+    let server = GpsdServer::bind("127.0.0.1:0", "/dev/ttyS0").unwrap();
+    server.publish(&GpsData::new(), &Verdict::default());
+    let (mut stream, mut reader) = connect(&server);
+    read_object(&mut reader); // VERSION
+
+    stream.write_all(b"?POLL;?FO\"O\\;\n").unwrap();
+    let poll = read_object(&mut reader);
+    assert_eq!(poll["class"], "POLL");
+    assert_eq!(poll["active"], 1);
+    assert_eq!(poll["tpv"][0]["class"], "TPV");
+    let error = read_object(&mut reader);
+    assert_eq!(error["class"], "ERROR");
+    assert_eq!(error["message"], "Unrecognized request 'FO\"O\\'");
+  }
+
+  #[test]
+  fn test_disconnected_client_pruned() {
+    let server = GpsdServer::bind("127.0.0.1:0", "/dev/ttyS0").unwrap();
+    let (stream, mut reader) = connect(&server);
+    read_object(&mut reader); // VERSION
+    assert_eq!(server.clients.lock().unwrap().len(), 1);
+
+    drop((stream, reader)); // never watched
+    for _ in 0..100 {
+      server.publish(&GpsData::new(), &Verdict::default());
+      if server.clients.lock().unwrap().is_empty() {
+        return;
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+    panic!("the disconnected client was kept");
+  }
+}
@@ -0,0 +1,213 @@
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::detect::verdict::{AlertState, DetectorScore, Verdict};
+use crate::neo6m::gps::GpsData;
+
+const PREFIX: &str = "gps_spoofing"; // Prefix of every exported metric name
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5); // Longest a scraper may take to send its request or take the response
+
+
+/// Serves Prometheus metrics over http at /metrics
+pub struct MetricsServer {
+  local_addr: SocketAddr,
+  values: Arc<Mutex<MetricValues>>,
+}
+
+/// Latest values of every metric
+#[derive(Clone, Debug, Default)]
+struct MetricValues {
+  residual_m: f32,
+  hdop: f32,
+  sats_used: usize,
+  mean_cn0: Option<f32>,
+  imu_rate_hz: f32,
+  scores: Vec<DetectorScore>,
+  state: AlertState,
+  alarms_total: u64,
+  checksum_failures_total: u64,
+  i2c_read_errors_total: u64,
+}
+
+
+/// MetricsServer implementations
+impl MetricsServer {
+  /// Binds the endpoint and starts answering scrapes in the background
+  pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<MetricsServer> {
+    let listener = TcpListener::bind(addr)?;
+    let server = MetricsServer {
+      local_addr: listener.local_addr()?,
+      values: Arc::new(Mutex::new(MetricValues::default())),
+    };
+
+    let values = Arc::clone(&server.values);
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        let _ = handle_scrape(stream, &values); // a scraper hanging up early isn't our problem
+      }
+    });
+
+    Ok(server)
+  }
+
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+
+  /// Records the quality of a new gps fix
+  pub fn update_fix(&self, data: &GpsData) {
+    let mut values = self.values.lock().unwrap();
+    values.hdop = data.hor_prec();
+    values.sats_used = data.sats_used().len();
+    values.mean_cn0 = data.mean_snr();
+    values.checksum_failures_total += data.checksum_failures() as u64;
+  }
+
+  /// Records a verdict, counting an alarm each time the state becomes spoofed
+  pub fn update_verdict(&self, verdict: &Verdict, residual_m: f32) {
+    let mut values = self.values.lock().unwrap();
+    if verdict.is_spoofed() && values.state != AlertState::Spoofed {
+      values.alarms_total += 1;
+    }
+    values.residual_m = residual_m;
+    values.scores = verdict.scores().to_vec();
+    values.state = verdict.state();
+  }
+
+  pub fn set_imu_rate(&self, rate_hz: f32) {
+    self.values.lock().unwrap().imu_rate_hz = rate_hz;
+  }
+
+  /// Sets the total number of failed i2c reads since start up
+  pub fn set_i2c_read_errors(&self, total: u64) {
+    self.values.lock().unwrap().i2c_read_errors_total = total;
+  }
+
+  /// Returns the metrics in the Prometheus text exposition format
+  pub fn render(&self) -> String {
+    self.values.lock().unwrap().render()
+  }
+}
+
+
+/// Answers a single http request, giving up on a scraper that stalls for SCRAPE_TIMEOUT
+/// so it can't hold up the next one
+fn handle_scrape(stream: TcpStream, values: &Mutex<MetricValues>) -> io::Result<()> {
+  stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+  stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  // skip the headers, we don't need any of them
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 2 {
+    header.clear();
+  }
+
+  let mut parts = request_line.split_whitespace();
+  let response = match (parts.next(), parts.next()) {
+    (Some("GET"), Some("/metrics")) => {
+      let body = values.lock().unwrap().render();
+      format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+              body.len(), body)
+    }
+    _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+  };
+  let mut stream = stream;
+  stream.write_all(response.as_bytes())
+}
+
+
+/// MetricValues implementations
+impl MetricValues {
+  fn render(&self) -> String {
+    let mut out = String::new();
+    gauge(&mut out, "position_residual_meters", "Distance between the gps fix and the predicted position", self.residual_m);
+    gauge(&mut out, "gps_hdop", "Horizontal dilution of precision of the last fix", self.hdop);
+    gauge(&mut out, "gps_satellites_used", "Satellites used in the last fix", self.sats_used as f32);
+    if let Some(cn0) = self.mean_cn0 {
+      gauge(&mut out, "gps_mean_cn0_dbhz", "Mean carrier to noise density of the used satellites", cn0);
+    }
+    gauge(&mut out, "imu_sample_rate_hz", "Rate accelerometer samples are being read at", self.imu_rate_hz);
+
+    header(&mut out, "detector_score", "gauge", "Detector score relative to its threshold (1.0 = threshold)");
+    for score in &self.scores {
+      let _ = writeln!(out, "{PREFIX}_detector_score{{detector=\"{}\"}} {}", score.name(), score.score());
+    }
+    let state = match self.state {
+      AlertState::Nominal => 0.0,
+      AlertState::Suspect => 1.0,
+      AlertState::Spoofed => 2.0,
+    };
+    gauge(&mut out, "alert_state", "Alert state (0 = nominal, 1 = suspect, 2 = spoofed)", state);
+
+    counter(&mut out, "alarms_total", "Times the alert state became spoofed", self.alarms_total);
+    counter(&mut out, "nmea_checksum_failures_total", "Nmea sentences dropped for a bad checksum", self.checksum_failures_total);
+    counter(&mut out, "i2c_read_errors_total", "Failed i2c sensor reads", self.i2c_read_errors_total);
+    out
+  }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+  let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+  let _ = writeln!(out, "# TYPE {PREFIX}_{name} {kind}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f32) {
+  header(out, name, "gauge", help);
+  let _ = writeln!(out, "{PREFIX}_{name} {value}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+  header(out, name, "counter", help);
+  let _ = writeln!(out, "{PREFIX}_{name} {value}");
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+
+  fn get(server: &MetricsServer, path: &str) -> String {
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+  }
+
+  #[test]
+  fn test_alarm_counted_on_transition() {
+    let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+    let spoofed = Verdict::from_scores(&[DetectorScore::new("position", 3.0)]);
+    server.update_verdict(&spoofed, 120.0);
+    server.update_verdict(&spoofed, 130.0);
+    server.update_verdict(&Verdict::default(), 1.0);
+    server.update_verdict(&spoofed, 140.0);
+
+    let metrics = server.render();
+    assert!(metrics.contains("gps_spoofing_alarms_total 2\n"));
+    assert!(metrics.contains("gps_spoofing_detector_score{detector=\"position\"} 3\n"));
+    assert!(metrics.contains("gps_spoofing_alert_state 2\n"));
+    assert!(metrics.contains("gps_spoofing_position_residual_meters 140\n"));
+  }
+
+  #[test]
+  fn test_http_endpoint() {
+    let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+    server.set_i2c_read_errors(7);
+
+    let response = get(&server, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("# TYPE gps_spoofing_i2c_read_errors_total counter"));
+    assert!(response.contains("gps_spoofing_i2c_read_errors_total 7\n"));
+
+    assert!(get(&server, "/").starts_with("HTTP/1.1 404"));
+  }
+}
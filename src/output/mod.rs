@@ -0,0 +1,5 @@
+pub mod gpsd;
+pub mod mqtt;
+pub mod events;
+pub mod metrics;
+pub mod alarm;
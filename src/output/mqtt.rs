@@ -0,0 +1,261 @@
+use rumqttc::{Client, ClientError, Event, LastWill, MqttOptions, Packet, QoS, Transport};
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use crate::detect::verdict::{AlertState, Verdict};
+use crate::mpu6050::accel::RawPoint;
+use crate::neo6m::gps::{GpsCoord, GpsData};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30); // Default mqtt keep alive interval
+const QUEUE_CAP: usize = 64; // Messages buffered while the broker is unreachable (newer ones are dropped)
+const RECONNECT_DELAY: Duration = Duration::from_secs(5); // Wait between reconnect attempts
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+
+/// Settings for the mqtt publisher
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+  pub host: String,
+  pub port: u16,
+  pub client_id: String,
+  pub username: Option<String>,
+  pub password: Option<String>,
+  pub qos: u8, // 0, 1 or 2
+  pub keep_alive: Duration,
+  pub topics: MqttTopics,
+  pub tls: Option<MqttTls>,
+}
+
+/// Topics each kind of message is published to
+#[derive(Clone, Debug, PartialEq)]
+pub struct MqttTopics {
+  pub position: String,  // gps fix
+  pub nav: String,       // dead reckoned position and velocity
+  pub detectors: String, // per detector scores
+  pub alert: String,     // alert state transitions
+  pub state: String,     // retained last known state
+  pub status: String,    // retained online/offline (last will)
+}
+
+/// Certificates for a tls connection to the broker (PEM files)
+#[derive(Clone, Debug)]
+pub struct MqttTls {
+  pub ca_path: String,
+  pub client_cert_path: Option<String>,
+  pub client_key_path: Option<String>,
+}
+
+/// A single message ready to be sent to the broker
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+  pub topic: String,
+  pub payload: String,
+  pub retain: bool,
+}
+
+/// Publishes telemetry and alerts to an mqtt broker
+pub struct MqttPublisher {
+  client: Client,
+  topics: MqttTopics,
+  qos: QoS,
+  last_state: Option<AlertState>,
+}
+
+
+/// MqttConfig implementations
+impl MqttConfig {
+  /// Config for a plain tcp broker, with topics under "gps_spoofing/<client_id>"
+  pub fn new(host: &str, port: u16, client_id: &str) -> MqttConfig {
+    MqttConfig {
+      host: host.to_string(),
+      port,
+      client_id: client_id.to_string(),
+      username: None,
+      password: None,
+      qos: 1,
+      keep_alive: KEEP_ALIVE,
+      topics: MqttTopics::with_prefix(&format!("gps_spoofing/{client_id}")),
+      tls: None,
+    }
+  }
+}
+
+
+/// MqttTopics implementations
+impl MqttTopics {
+  pub fn with_prefix(prefix: &str) -> MqttTopics {
+    MqttTopics {
+      position: format!("{prefix}/position"),
+      nav: format!("{prefix}/nav"),
+      detectors: format!("{prefix}/detectors"),
+      alert: format!("{prefix}/alert"),
+      state: format!("{prefix}/state"),
+      status: format!("{prefix}/status"),
+    }
+  }
+}
+
+
+/// MqttPublisher implementations
+impl MqttPublisher {
+  /// Connects to the broker in the background. The broker is told to publish
+  /// "offline" to the status topic if this client disappears.
+  pub fn connect(config: &MqttConfig) -> io::Result<MqttPublisher> {
+    let qos = to_qos(config.qos)?;
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(config.keep_alive);
+    options.set_last_will(LastWill::new(&config.topics.status, OFFLINE, qos, true));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+      options.set_credentials(username, password);
+    }
+    if let Some(tls) = &config.tls {
+      options.set_transport(tls_transport(tls)?);
+    }
+
+    let (client, mut connection) = Client::new(options, QUEUE_CAP);
+
+    // the connection has to be polled for anything to be sent, and reconnects when polled after an error
+    let status = Message { topic: config.topics.status.clone(), payload: ONLINE.to_string(), retain: true };
+    let announce = client.clone();
+    let broker = format!("{}:{}", config.host, config.port);
+    thread::spawn(move || {
+      for event in connection.iter() {
+        match event {
+          Ok(Event::Incoming(Packet::ConnAck(_))) => {
+            let _ = announce.try_publish(&status.topic, qos, status.retain, status.payload.as_str());
+          }
+          Ok(_) => {}
+          Err(e) => {
+            eprintln!("mqtt connection to {broker} failed: {e}");
+            thread::sleep(RECONNECT_DELAY);
+          }
+        }
+      }
+    });
+
+    Ok(MqttPublisher { client, topics: config.topics.clone(), qos, last_state: None })
+  }
+
+  /// Publishes a fix, the navigation state and the verdict. Messages are
+  /// dropped rather than blocking detection if the broker can't keep up; a
+  /// dropped alert is sent again with the next fix.
+  pub fn publish(&mut self, data: &GpsData, predicted: &GpsCoord, velocity: &RawPoint,
+                 verdict: &Verdict) -> Result<(), ClientError> {
+    let messages = build_messages(&self.topics, data, predicted, velocity, verdict, self.last_state);
+    for message in messages {
+      self.client.try_publish(message.topic, self.qos, message.retain, message.payload)?;
+    }
+    self.last_state = Some(verdict.state()); // the alert (always last) was queued
+    Ok(())
+  }
+}
+
+
+/// Builds the messages for a single fix. An alert message is only produced
+/// when the state differs from 'last_state'.
+pub fn build_messages(topics: &MqttTopics, data: &GpsData, predicted: &GpsCoord, velocity: &RawPoint,
+                      verdict: &Verdict, last_state: Option<AlertState>) -> Vec<Message> {
+  let time = data.iso_time();
+  let position = json!({
+    "time": time,
+    "lat": data.lat(),
+    "lon": data.lon(),
+    "alt": data.alt(),
+    "speed": data.speed_mps(),
+    "course": data.course(),
+    "hdop": data.hor_prec(),
+    "sats_used": data.sats_used().len(),
+  });
+  let nav = json!({
+    "time": time,
+    "lat": predicted.lat(),
+    "lon": predicted.lon(),
+    "alt": predicted.alt(),
+    "vel": [velocity.x(), velocity.y(), velocity.z()],
+  });
+  let detectors = json!({ "time": time, "scores": verdict.scores() });
+  let state = json!({ "time": time, "state": verdict.state(), "scores": verdict.scores() });
+
+  let mut messages = vec![
+    Message { topic: topics.position.clone(), payload: position.to_string(), retain: false },
+    Message { topic: topics.nav.clone(), payload: nav.to_string(), retain: false },
+    Message { topic: topics.detectors.clone(), payload: detectors.to_string(), retain: false },
+    Message { topic: topics.state.clone(), payload: state.to_string(), retain: true },
+  ];
+  if last_state != Some(verdict.state()) {
+    let alert = json!({ "time": time, "from": last_state, "to": verdict.state() });
+    messages.push(Message { topic: topics.alert.clone(), payload: alert.to_string(), retain: false });
+  }
+  messages
+}
+
+fn to_qos(qos: u8) -> io::Result<QoS> {
+  match qos {
+    0 => Ok(QoS::AtMostOnce),
+    1 => Ok(QoS::AtLeastOnce),
+    2 => Ok(QoS::ExactlyOnce),
+    _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("mqtt qos must be 0, 1 or 2 (got {qos})"))),
+  }
+}
+
+/// Reads the certificates and builds a tls transport
+fn tls_transport(tls: &MqttTls) -> io::Result<Transport> {
+  let ca = fs::read(&tls.ca_path)?;
+  let client_auth = match (&tls.client_cert_path, &tls.client_key_path) {
+    (Some(cert), Some(key)) => Some((fs::read(cert)?, fs::read(key)?)),
+    (None, None) => None,
+    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "mqtt client cert and key must be given together")),
+  };
+  Ok(Transport::tls(ca, client_auth, None))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::detect::verdict::DetectorScore;
+  use serde_json::Value;
+
+  fn messages(verdict: &Verdict, last_state: Option<AlertState>) -> Vec<Message> {
+    let topics = MqttTopics::with_prefix("test");
+    let predicted = GpsCoord::new(1.0, 2.0, 3.0);
+    let velocity = RawPoint::new(0.5, 0.0, 0.0);
+    build_messages(&topics, &GpsData::new(), &predicted, &velocity, verdict, last_state)
+  }
+
+  #[test]
+  fn test_alert_only_on_transition() {
+    let verdict = Verdict::from_scores(&[DetectorScore::new("position", 2.0)]);
+
+    let first = messages(&verdict, Some(AlertState::Nominal));
+    let alert = first.iter().find(|m| m.topic == "test/alert").unwrap();
+    let alert: Value = serde_json::from_str(&alert.payload).unwrap();
+    assert_eq!(alert["from"], "nominal");
+    assert_eq!(alert["to"], "spoofed");
+
+    let repeat = messages(&verdict, Some(AlertState::Spoofed));
+    assert!(repeat.iter().all(|m| m.topic != "test/alert"));
+  }
+
+  #[test]
+  fn test_state_is_retained() {
+    let all = messages(&Verdict::default(), None);
+    let retained: Vec<_> = all.iter().filter(|m| m.retain).map(|m| m.topic.as_str()).collect();
+    assert_eq!(retained, vec!["test/state"]);
+
+    let nav = all.iter().find(|m| m.topic == "test/nav").unwrap();
+    let nav: Value = serde_json::from_str(&nav.payload).unwrap();
+    assert_eq!(nav["vel"][0], 0.5);
+  }
+
+  #[test]
+  fn test_invalid_qos() {
+    assert!(to_qos(2).is_ok());
+    assert!(to_qos(3).is_err());
+  }
+}
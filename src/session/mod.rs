@@ -0,0 +1,3 @@
+pub mod record;
+pub mod replay;
+pub mod simulate;
@@ -0,0 +1,160 @@
+use adafruit_gps::GpsSentence;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::Instant;
+
+
+/// A single line of a recorded session. 't' is seconds since the recording started.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SessionRecord {
+  Gps {
+    t: f64,
+    sentence: GpsSentence,
+  },
+  Imu {
+    t: f64,
+    accel: [f32; 3], // m/s^2 with the calibration offsets applied
+    #[serde(default)]
+    temperature_c: Option<f32>, // die temperature, missing from older recordings
+    #[serde(default)]
+    gyro: Option<[f32; 3]>, // rad/s with the calibration offsets applied, missing from older recordings
+  },
+  Pressure {
+    t: f64,
+    pressure_pa: f32,
+  },
+  Field {
+    t: f64,
+    field: [f32; 3], // uncalibrated, as read
+  },
+  /// The epoch the next fix made up by the gps records was timed at. The n'th of these
+  /// goes with the n'th fix, older recordings without them time fixes by their last sentence.
+  Epoch {
+    t: f64,
+    pps_t: Option<f64>,  // the pps edge of the epoch, seconds since the recording started
+    host_s: Option<f64>, // the reference clock at the epoch, seconds since the Unix epoch
+  },
+  /// The imu's reading at rest the calibration was taken from (m/s^2), which gives the tilt of the mounting
+  Rest {
+    t: f64,
+    accel: [f32; 3],
+  },
+}
+
+/// Records gps sentences and imu samples as JSON Lines so they can be replayed later
+pub struct SessionWriter {
+  out: BufWriter<File>,
+  start: Instant,
+}
+
+
+/// SessionRecord implementations
+impl SessionRecord {
+  pub fn t(&self) -> f64 {
+    match self {
+      SessionRecord::Gps { t, .. } | SessionRecord::Imu { t, .. } | SessionRecord::Pressure { t, .. }
+      | SessionRecord::Field { t, .. } | SessionRecord::Epoch { t, .. } | SessionRecord::Rest { t, .. } => *t,
+    }
+  }
+}
+
+
+/// SessionWriter implementations
+impl SessionWriter {
+  /// Creates (or truncates) the recording at 'path'
+  pub fn create(path: &str) -> io::Result<SessionWriter> {
+    SessionWriter::create_from(path, Instant::now())
+  }
+
+  /// Same as create for a recording whose times count from 'start', eg. the origin the
+  /// sensor threads time their readings from, so they can be written with their own times
+  pub fn create_from(path: &str, start: Instant) -> io::Result<SessionWriter> {
+    let out = BufWriter::new(File::create(path)?);
+    Ok(SessionWriter { out, start })
+  }
+
+  pub fn write_gps(&mut self, sentence: GpsSentence) -> io::Result<()> {
+    let record = SessionRecord::Gps { t: self.elapsed(), sentence };
+    self.write(&record)
+  }
+
+  pub fn write_imu(&mut self, accel: [f32; 3], temperature_c: Option<f32>, gyro: Option<[f32; 3]>) -> io::Result<()> {
+    self.write_imu_at(Instant::now(), accel, temperature_c, gyro)
+  }
+
+  /// Same as write_imu for a sample taken at 'at' (eg. timed from the fifo)
+  pub fn write_imu_at(&mut self, at: Instant, accel: [f32; 3], temperature_c: Option<f32>, gyro: Option<[f32; 3]>)
+                      -> io::Result<()> {
+    let t = at.saturating_duration_since(self.start).as_secs_f64();
+    self.write(&SessionRecord::Imu { t, accel, temperature_c, gyro })
+  }
+
+  pub fn write(&mut self, record: &SessionRecord) -> io::Result<()> {
+    serde_json::to_writer(&mut self.out, record)?;
+    self.out.write_all(b"\n")
+  }
+
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.out.flush()
+  }
+
+  fn elapsed(&self) -> f64 {
+    self.start.elapsed().as_secs_f64()
+  }
+}
+
+
+/// Reads every record of a session recorded by SessionWriter (or written by the simulator)
+pub fn read_session(path: &str) -> io::Result<Vec<SessionRecord>> {
+  let reader = BufReader::new(File::open(path)?);
+  let mut records = Vec::new();
+  for (i, line) in reader.lines().enumerate() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    let record = serde_json::from_str(&line).map_err(|e| {
+      io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", i + 1))
+    })?;
+    records.push(record);
+  }
+  Ok(records)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use adafruit_gps::rmc::RmcData;
+
+  #[test]
+  fn test_round_trip() {
+    let path = std::env::temp_dir().join(format!("session_test_{}.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let rmc = GpsSentence::RMC(RmcData { latitude: Some(45.0), longitude: Some(-111.0), ..Default::default() });
+    let mut writer = SessionWriter::create(path).unwrap();
+    writer.write_imu([0.1, -0.2, 9.8], Some(31.5), Some([0.01, 0.0, -0.02])).unwrap();
+    writer.write_gps(rmc.clone()).unwrap();
+    writer.flush().unwrap();
+
+    let records = read_session(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(matches!(records[0], SessionRecord::Imu { accel: [0.1, -0.2, 9.8], temperature_c: Some(31.5), gyro: Some(_), .. }));
+    match &records[1] {
+      SessionRecord::Gps { sentence, .. } => assert_eq!(*sentence, rmc),
+      other => panic!("expected a gps record, got {other:?}"),
+    }
+    assert!(records[0].t() <= records[1].t());
+  }
+
+  #[test]
+  fn test_older_recordings() {
+    let record: SessionRecord = serde_json::from_str(r#"{"kind":"imu","t":1.5,"accel":[0.0,0.0,0.1]}"#).unwrap();
+    assert_eq!(record, SessionRecord::Imu { t: 1.5, accel: [0.0, 0.0, 0.1], temperature_c: None, gyro: None });
+  }
+}
@@ -0,0 +1,215 @@
+use adafruit_gps::GpsSentence;
+
+use crate::neo6m::gps::GpsData;
+use crate::session::record::SessionRecord;
+use crate::timing::align::{self, Window, MAX_GAP_PERIODS};
+use crate::timing::ring::TimedRing;
+
+
+/// A complete fix from a recorded session and the epoch it was timed at
+#[derive(Debug)]
+pub struct Epoch {
+  pub fix: GpsData,
+  pub t: f64,              // recording time of the fix's epoch, or of its last sentence in older recordings
+  pub pps_t: Option<f64>,  // the pps edge of the epoch, when it was recorded
+  pub host_s: Option<f64>, // the reference clock at the epoch, when it was recorded
+}
+
+/// The imu, barometer and magnetometer readings of a recorded session, buffered by time
+/// the way the sensor threads buffer them live, so each fix is lined up with them the same way
+#[derive(Debug)]
+pub struct Recorded {
+  imu: TimedRing<[f32; 3]>,
+  gyro: TimedRing<[f32; 3]>, // at the same times as the accelerations, empty for older recordings
+  pressure: TimedRing<f32>,
+  field: TimedRing<[f32; 3]>,
+  rest: Option<[f32; 3]>,    // the imu's reading at rest, which gives the tilt of the mounting
+}
+
+
+/// Groups recorded sentences into fixes the same way get_gps does (a fix is
+/// complete once it has an RMC, GGA and GSA sentence), each timed by its epoch record.
+pub fn epochs(records: &[SessionRecord]) -> Vec<Epoch> {
+  let mut epochs = Vec::new();
+  let mut timings = Vec::new();
+
+  let mut data = GpsData::new();
+  let (mut rmc, mut gga, mut gsa) = (false, false, false);
+
+  for record in records {
+    let (t, sentence) = match record {
+      SessionRecord::Gps { t, sentence } => (*t, sentence),
+      SessionRecord::Epoch { t, pps_t, host_s } => {
+        timings.push((*t, *pps_t, *host_s));
+        continue;
+      }
+      _ => continue,
+    };
+    match sentence {
+      GpsSentence::RMC(sen) => {
+        data.apply_rmc(sen);
+        rmc = true;
+      }
+      GpsSentence::GGA(sen) => {
+        data.apply_gga(sen);
+        gga = true;
+      }
+      GpsSentence::GSA(sen) => {
+        data.apply_gsa(sen);
+        gsa = true;
+      }
+      GpsSentence::GSV(sats) => data.apply_gsv(sats),
+      GpsSentence::InvalidSentence => data.count_checksum_failure(),
+      GpsSentence::InvalidBytes | GpsSentence::NoConnection => {
+        // the gps dropped out, the partial fix can't be trusted
+        data = GpsData::new();
+        (rmc, gga, gsa) = (false, false, false);
+      }
+      _ => {}
+    }
+
+    if rmc && gga && gsa {
+      data.mark_used_satellites();
+      epochs.push(Epoch { fix: std::mem::replace(&mut data, GpsData::new()), t, pps_t: None, host_s: None });
+      (rmc, gga, gsa) = (false, false, false);
+    }
+  }
+
+  // the fixes are recorded as the sentences arrive and the epochs as the sensor threads
+  // time them, so they're paired in order rather than by where they are in the recording
+  for (epoch, (t, pps_t, host_s)) in epochs.iter_mut().zip(timings) {
+    (epoch.t, epoch.pps_t, epoch.host_s) = (t, pps_t, host_s);
+  }
+  epochs
+}
+
+
+/// Recorded implementations
+impl Recorded {
+  pub fn new(records: &[SessionRecord]) -> Recorded {
+    let capacity = records.len();
+    let mut recorded = Recorded {
+      imu: TimedRing::new(capacity),
+      gyro: TimedRing::new(capacity),
+      pressure: TimedRing::new(capacity),
+      field: TimedRing::new(capacity),
+      rest: None,
+    };
+    for record in records {
+      match record {
+        SessionRecord::Imu { t, accel, gyro, .. } => {
+          // pushed or rejected together, so the two stay paired
+          if recorded.imu.push(*t, *accel) {
+            if let Some(gyro) = gyro {
+              recorded.gyro.push(*t, *gyro);
+            }
+          }
+        }
+        SessionRecord::Pressure { t, pressure_pa } => {
+          recorded.pressure.push(*t, *pressure_pa);
+        }
+        SessionRecord::Field { t, field } => {
+          recorded.field.push(*t, *field);
+        }
+        SessionRecord::Rest { accel, .. } => recorded.rest = Some(*accel),
+        SessionRecord::Gps { .. } | SessionRecord::Epoch { .. } => {}
+      }
+    }
+    recorded
+  }
+
+  /// The imu samples between 't0' and 't1' interpolated to both ends, None if they don't
+  /// cover the interval or have a gap of more than a few of their sample periods in it
+  pub fn imu_window(&self, t0: f64, t1: f64) -> Option<Window> {
+    align::window(&self.imu, t0, t1, max_gap(&self.imu))
+  }
+
+  /// The (acceleration, rotation rate) samples between 't0' and 't1', empty for older
+  /// recordings without the rotation rates
+  pub fn imu_samples(&self, t0: f64, t1: f64) -> Vec<([f32; 3], [f32; 3])> {
+    self.imu.between(t0, t1).zip(self.gyro.between(t0, t1))
+      .filter(|((accel_t, _), (gyro_t, _))| accel_t == gyro_t)
+      .map(|((_, accel), (_, gyro))| (*accel, *gyro))
+      .collect()
+  }
+
+  /// The air pressure at 't', interpolated between the barometer readings around it
+  pub fn pressure_at(&self, t: f64) -> Option<f32> {
+    align::interpolate(&self.pressure, t, max_gap(&self.pressure))
+  }
+
+  /// The uncalibrated magnetic field at 't', interpolated the same way
+  pub fn field_at(&self, t: f64) -> Option<[f32; 3]> {
+    align::interpolate(&self.field, t, max_gap(&self.field))
+  }
+
+  /// The imu's reading at rest, if it was recorded
+  pub fn rest(&self) -> Option<[f32; 3]> {
+    self.rest
+  }
+}
+
+
+/// Longest gap between readings interpolated across, a few of their mean sample periods
+fn max_gap<T>(readings: &TimedRing<T>) -> f64 {
+  match (readings.first_t(), readings.last_t()) {
+    (Some(first), Some(last)) if readings.len() > 1 => MAX_GAP_PERIODS * (last - first) / (readings.len() - 1) as f64,
+    _ => 0.0,
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use adafruit_gps::gga::GgaData;
+  use adafruit_gps::gsa::GsaData;
+  use adafruit_gps::rmc::RmcData;
+
+  fn gps(t: f64, sentence: GpsSentence) -> SessionRecord {
+    SessionRecord::Gps { t, sentence }
+  }
+
+  fn imu(t: f64, accel: [f32; 3]) -> SessionRecord {
+    SessionRecord::Imu { t, accel, temperature_c: None, gyro: Some([0.0, 0.0, 0.1]) }
+  }
+
+  #[test]
+  fn test_epochs() {
+    let records = vec![
+      gps(0.9, GpsSentence::GGA(GgaData::default())),
+      gps(0.9, GpsSentence::InvalidSentence),
+      gps(0.9, GpsSentence::GSA(GsaData::default())),
+      gps(1.0, GpsSentence::RMC(RmcData { latitude: Some(45.0), ..Default::default() })),
+      gps(1.9, GpsSentence::GGA(GgaData::default())),
+      SessionRecord::Epoch { t: 0.8, pps_t: Some(0.8), host_s: Some(1.7e9) }, // recorded after the next fix began
+      gps(1.9, GpsSentence::GSA(GsaData::default())),
+      gps(2.0, GpsSentence::RMC(RmcData::default())),
+      gps(2.9, GpsSentence::GGA(GgaData::default())), // incomplete fix at the end
+    ];
+
+    let epochs = epochs(&records);
+    assert_eq!(epochs.len(), 2);
+    assert_eq!(epochs[0].fix.lat(), 45.0);
+    assert_eq!(epochs[0].fix.checksum_failures(), 1);
+    assert_eq!((epochs[0].t, epochs[0].pps_t), (0.8, Some(0.8)));
+    assert_eq!((epochs[1].t, epochs[1].pps_t), (2.0, None)); // no epoch record, timed by its last sentence
+  }
+
+  #[test]
+  fn test_recorded_readings() {
+    let mut records: Vec<SessionRecord> = (0..=20).map(|i| imu(i as f64 * 0.1, [i as f32 * 0.1, 0.0, 0.0])).collect();
+    records.push(SessionRecord::Pressure { t: 0.0, pressure_pa: 101000.0 });
+    records.push(SessionRecord::Pressure { t: 1.0, pressure_pa: 100990.0 });
+    let recorded = Recorded::new(&records);
+
+    let window = recorded.imu_window(0.25, 1.25).unwrap();
+    assert!((window.mean[0] - 0.75).abs() < 1e-5);
+    assert_eq!(recorded.imu_samples(0.25, 1.25).len(), 10);
+    assert_eq!(recorded.imu_samples(0.25, 1.25)[0].1, [0.0, 0.0, 0.1]);
+    assert!((recorded.pressure_at(0.5).unwrap() - 100995.0).abs() < 1e-2);
+    assert!(recorded.field_at(0.5).is_none());
+    assert!(recorded.rest().is_none());
+  }
+}
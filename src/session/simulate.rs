@@ -0,0 +1,192 @@
+use adafruit_gps::gga::{GgaData, SatFix};
+use adafruit_gps::gsa::{DimensionFix, GsaData, Mode};
+use adafruit_gps::gsv::Satellites;
+use adafruit_gps::rmc::RmcData;
+use adafruit_gps::GpsSentence;
+
+use crate::session::record::SessionRecord;
+
+const METERS_PER_DEG_LAT: f32 = 111_320.0; // Close enough for offsets of a few kilometers
+const START_UTC: f64 = 120000.0; // Time of the first simulated fix (hhmmss.sss)
+const DATE: &str = "010124"; // Date of the simulated fixes (ddmmyy)
+const TEMPERATURE_C: f32 = 25.0; // Die temperature of the simulated imu
+const SATELLITES: [(i32, f32, f32, f32); 8] = [ // prn, elevation, azimuth, snr
+  (2, 62.0, 45.0, 42.0), (5, 35.0, 120.0, 38.0), (7, 18.0, 200.0, 31.0), (12, 74.0, 300.0, 45.0),
+  (15, 41.0, 250.0, 40.0), (20, 22.0, 80.0, 33.0), (25, 55.0, 160.0, 43.0), (29, 12.0, 340.0, 28.0),
+];
+
+
+/// A stationary receiver whose fixes can be spoofed part way through
+#[derive(Clone, Debug)]
+pub struct Scenario {
+  pub lat: f32,
+  pub lon: f32,
+  pub alt: f32,
+  pub fixes: u32,               // one fix per second
+  pub imu_rate_hz: u32,
+  pub accel_noise: f32,         // standard deviation of the imu noise in m/s^2
+  pub spoof_after: Option<u32>, // first spoofed fix
+  pub spoof_drift_mps: f32,     // speed the spoofed position is dragged north at
+  pub seed: u64,
+}
+
+
+/// Scenario implementations
+impl Default for Scenario {
+  fn default() -> Self {
+    Scenario {
+      lat: 45.6770,
+      lon: -111.0429,
+      alt: 1461.0,
+      fixes: 60,
+      imu_rate_hz: 100,
+      accel_noise: 0.0,
+      spoof_after: None,
+      spoof_drift_mps: 30.0,
+      seed: 1,
+    }
+  }
+}
+
+
+/// Generates a session as it would have been recorded from the scenario
+pub fn simulate(scenario: &Scenario) -> Vec<SessionRecord> {
+  let mut rng = XorShift(scenario.seed.max(1));
+  let mut records = Vec::new();
+
+  for fix in 0..scenario.fixes {
+    let offset_m = match scenario.spoof_after {
+      Some(start) if fix >= start => (fix - start + 1) as f32 * scenario.spoof_drift_mps,
+      _ => 0.0,
+    };
+    let lat = scenario.lat + offset_m / METERS_PER_DEG_LAT;
+    let t = fix as f64;
+    for sentence in fix_sentences(lat, scenario.lon, scenario.alt, START_UTC + fix as f64) {
+      records.push(SessionRecord::Gps { t, sentence });
+    }
+    records.push(SessionRecord::Epoch { t, pps_t: None, host_s: None });
+
+    // imu samples over the second from the fix, the receiver sits still
+    for i in 0..scenario.imu_rate_hz {
+      let t = fix as f64 + i as f64 / scenario.imu_rate_hz as f64;
+      let accel = [0.0, 0.0, 0.0].map(|a: f32| a + rng.gaussian() * scenario.accel_noise);
+      records.push(SessionRecord::Imu { t, accel, temperature_c: Some(TEMPERATURE_C), gyro: Some([0.0; 3]) });
+    }
+  }
+  records
+}
+
+
+/// The sentences a receiver outputs for a single fix
+fn fix_sentences(lat: f32, lon: f32, alt: f32, utc: f64) -> Vec<GpsSentence> {
+  let used: Vec<i32> = SATELLITES.iter().map(|s| s.0).collect();
+  vec![
+    GpsSentence::GSV(SATELLITES.iter().map(|&(id, elevation, azimuth, snr)| Satellites {
+      id: Some(id),
+      elevation: Some(elevation),
+      azimuth: Some(azimuth),
+      snr: Some(snr),
+    }).collect()),
+    GpsSentence::RMC(RmcData {
+      utc,
+      fix_status: true,
+      latitude: Some(lat),
+      longitude: Some(lon),
+      speed: Some(0.0),
+      course: Some(0.0),
+      date: DATE.to_string(),
+      mag_var: None,
+    }),
+    GpsSentence::GGA(GgaData {
+      utc,
+      lat: Some(lat),
+      long: Some(lon),
+      sat_fix: SatFix::GpsFix,
+      satellites_used: used.len() as i32,
+      hdop: Some(1.0),
+      msl_alt: Some(alt),
+      ..Default::default()
+    }),
+    GpsSentence::GSA(GsaData {
+      mode: Mode::Automatic,
+      dimension_fix: DimensionFix::Dimension3d,
+      sat1: used.first().copied(),
+      sat2: used.get(1).copied(),
+      sat3: used.get(2).copied(),
+      sat4: used.get(3).copied(),
+      sat5: used.get(4).copied(),
+      sat6: used.get(5).copied(),
+      sat7: used.get(6).copied(),
+      sat8: used.get(7).copied(),
+      pdop: Some(1.8),
+      hdop: Some(1.0),
+      vdop: Some(1.5),
+      ..Default::default()
+    }),
+  ]
+}
+
+
+/// Small deterministic generator so simulated sessions are reproducible from a seed
+struct XorShift(u64);
+
+/// XorShift implementations
+impl XorShift {
+  /// Uniform in [0, 1)
+  fn uniform(&mut self) -> f32 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    (self.0 >> 40) as f32 / (1u64 << 24) as f32
+  }
+
+  /// Approximately standard normal (sum of uniforms)
+  fn gaussian(&mut self) -> f32 {
+    (0..12).map(|_| self.uniform()).sum::<f32>() - 6.0
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::detect::engine::Engine;
+  use crate::mpu6050::accel::RawPoint;
+  use crate::session::replay::{epochs, Recorded};
+
+  fn run(scenario: &Scenario) -> Vec<bool> {
+    let records = simulate(scenario);
+    let recorded = Recorded::new(&records);
+    let mut engine = Engine::new(10.0, 0.75);
+    let mut last_t = None;
+    epochs(&records).iter()
+      .filter_map(|epoch| {
+        let window = last_t.replace(epoch.t).and_then(|t0| recorded.imu_window(t0, epoch.t));
+        let [x, y, z] = window.map_or([0.0; 3], |window| window.mean);
+        engine.step(&epoch.fix.coord(), epoch.fix.hor_prec(), &RawPoint::new(x, y, z), 1.0)
+      })
+      .map(|step| step.verdict.is_spoofed())
+      .collect()
+  }
+
+  #[test]
+  fn test_clean_session() {
+    let spoofed = run(&Scenario { fixes: 10, ..Default::default() });
+    assert_eq!(spoofed.len(), 9);
+    assert!(spoofed.iter().all(|s| !s));
+  }
+
+  #[test]
+  fn test_spoofed_session() {
+    let spoofed = run(&Scenario { fixes: 10, spoof_after: Some(5), ..Default::default() });
+    assert!(spoofed[..4].iter().all(|s| !s));
+    assert!(spoofed[4..].iter().all(|s| *s));
+  }
+
+  #[test]
+  fn test_reproducible() {
+    let scenario = Scenario { fixes: 3, ..Default::default() };
+    assert_eq!(simulate(&scenario), simulate(&scenario));
+  }
+}
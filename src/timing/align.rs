@@ -0,0 +1,139 @@
+use crate::timing::ring::TimedRing;
+
+pub const MAX_GAP_PERIODS: f64 = 5.0; // Longest gap between buffered readings, in sample periods, that's interpolated across
+
+
+/// The acceleration over the interval between two gps epochs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Window {
+  pub start: [f32; 3], // interpolated at the first epoch
+  pub end: [f32; 3],   // interpolated at the second epoch
+  pub mean: [f32; 3],  // time weighted mean over the interval
+  pub dt: f64,         // seconds between the epochs
+  pub samples: usize,  // samples inside the interval
+}
+
+
+/// Readings that can be interpolated between two samples
+pub trait Lerp: Copy {
+  /// The reading 'f' of the way from 'self' to 'other'
+  fn lerp(self, other: Self, f: f32) -> Self;
+}
+
+
+/// Reading at 't', linearly interpolated between the samples either side. None if the
+/// buffer doesn't reach 't' on both sides, or the samples either side are more than
+/// 'max_gap' seconds apart (readings were missed around 't').
+pub fn interpolate<T: Lerp>(samples: &TimedRing<T>, t: f64, max_gap: f64) -> Option<T> {
+  let ((t0, a0), (t1, a1)) = samples.bracket(t)?;
+  if t1 == t0 {
+    return Some(*a0);
+  }
+  if t1 - t0 > max_gap {
+    return None;
+  }
+  Some(a0.lerp(*a1, ((t - t0) / (t1 - t0)) as f32))
+}
+
+
+/// The acceleration between the epochs 't0' and 't1', with the samples interpolated to
+/// the epochs themselves and averaged with the trapezoid rule (so unevenly spaced
+/// samples are weighted by the time they cover). None if the buffer doesn't cover the
+/// interval, or any two neighbouring samples over it are more than 'max_gap' seconds apart:
+/// the trapezoid would otherwise draw a straight line over the samples that were missed.
+pub fn window(samples: &TimedRing<[f32; 3]>, t0: f64, t1: f64, max_gap: f64) -> Option<Window> {
+  if t1 <= t0 {
+    return None;
+  }
+  let start = interpolate(samples, t0, max_gap)?;
+  let end = interpolate(samples, t1, max_gap)?;
+
+  let inside: Vec<(f64, [f32; 3])> = samples.between(t0, t1)
+    .filter(|(t, _)| *t > t0 && *t < t1)
+    .copied()
+    .collect();
+  let mut area = [0.0f64; 3];
+  let mut previous = (t0, start);
+  for point in inside.iter().copied().chain([(t1, end)]) {
+    let dt = point.0 - previous.0;
+    if dt > max_gap {
+      return None;
+    }
+    for (i, sum) in area.iter_mut().enumerate() {
+      *sum += (previous.1[i] + point.1[i]) as f64 * 0.5 * dt;
+    }
+    previous = point;
+  }
+
+  let dt = t1 - t0;
+  Some(Window { start, end, mean: area.map(|a| (a / dt) as f32), dt, samples: inside.len() })
+}
+
+
+impl Lerp for f32 {
+  fn lerp(self, other: Self, f: f32) -> Self {
+    self + (other - self) * f
+  }
+}
+
+impl Lerp for [f32; 3] {
+  fn lerp(self, other: Self, f: f32) -> Self {
+    [0, 1, 2].map(|i| self[i].lerp(other[i], f))
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ramp() -> TimedRing<[f32; 3]> {
+    // x = t, y constant, z = 0 sampled every 0.1 s from 0 to 2 s
+    let mut ring = TimedRing::new(100);
+    for i in 0..=20 {
+      let t = i as f64 * 0.1;
+      ring.push(t, [t as f32, 1.0, 0.0]);
+    }
+    ring
+  }
+
+  #[test]
+  fn test_interpolate() {
+    let ring = ramp();
+    let a = interpolate(&ring, 0.55, 0.15).unwrap();
+    assert!((a[0] - 0.55).abs() < 1e-5);
+    assert_eq!(a[1], 1.0);
+    assert!(interpolate(&ring, 2.5, 0.15).is_none());
+    assert!(interpolate(&ring, 0.55, 0.05).is_none()); // samples further apart than allowed
+  }
+
+  #[test]
+  fn test_window_mean() {
+    let ring = ramp();
+    let w = window(&ring, 0.25, 1.25, 0.15).unwrap();
+    assert!((w.mean[0] - 0.75).abs() < 1e-5); // mean of a ramp is its midpoint
+    assert!((w.mean[1] - 1.0).abs() < 1e-6);
+    assert!((w.start[0] - 0.25).abs() < 1e-5);
+    assert!((w.end[0] - 1.25).abs() < 1e-5);
+    assert_eq!(w.samples, 10);
+    assert!((w.dt - 1.0).abs() < 1e-9);
+
+    assert!(window(&ring, 1.5, 2.5, 0.15).is_none()); // not covered yet
+    assert!(window(&ring, 1.0, 1.0, 0.15).is_none());
+  }
+
+  #[test]
+  fn test_window_rejects_gaps() {
+    // samples every 0.1 s with those from 0.6 to 0.9 s missed
+    let mut ring = TimedRing::new(100);
+    for i in (0..=20).filter(|i| !(6..=9).contains(i)) {
+      ring.push(i as f64 * 0.1, [1.0, 0.0, 0.0]);
+    }
+    assert!(window(&ring, 0.1, 0.45, 0.15).is_some());
+    assert!(window(&ring, 0.25, 1.25, 0.15).is_none()); // the gap is inside
+    assert!(window(&ring, 0.7, 1.5, 0.15).is_none()); // the gap is at the start
+    assert!(window(&ring, 0.1, 0.8, 0.15).is_none()); // and at the end
+    assert!(window(&ring, 0.25, 1.25, 0.6).is_some()); // a gap within the allowance
+  }
+}
@@ -0,0 +1,56 @@
+/// Days from 1970-01-01 to a date in the proleptic Gregorian calendar
+pub fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year } as i64;
+  let era = year.div_euclid(400);
+  let year_of_era = year - era * 400;
+  let month = month as i64;
+  let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146097 + day_of_era - 719468
+}
+
+
+/// Date (year, month, day) of a count of days from 1970-01-01
+pub fn civil_from_days(days: i64) -> (i32, u32, u32) {
+  let days = days + 719468;
+  let era = days.div_euclid(146097);
+  let day_of_era = days - era * 146097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+  let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year as i32, month, day)
+}
+
+
+/// Formats seconds since the Unix epoch as an ISO 8601 UTC timestamp (eg. 2023-11-14T22:13:20.000Z)
+pub fn iso_time(unix_s: f64) -> String {
+  let millis = (unix_s * 1000.0).round() as i64;
+  let (year, month, day) = civil_from_days(millis.div_euclid(86_400_000));
+  let millis = millis.rem_euclid(86_400_000);
+  format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+          millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_round_trip() {
+    assert_eq!(days_from_civil(1970, 1, 1), 0);
+    assert_eq!(days_from_civil(2023, 11, 14), 19675);
+    assert_eq!(civil_from_days(19675), (2023, 11, 14));
+    for days in [-1, 59, 60, 11016, 18321, 47540] {
+      let (year, month, day) = civil_from_days(days);
+      assert_eq!(days_from_civil(year, month, day), days);
+    }
+    assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    assert_eq!(iso_time(1_700_000_000.5), "2023-11-14T22:13:20.500Z");
+  }
+}
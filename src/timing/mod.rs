@@ -0,0 +1,4 @@
+pub mod ring;
+pub mod align;
+pub mod pps;
+pub mod calendar;
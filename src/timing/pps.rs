@@ -0,0 +1,208 @@
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::timing::ring::TimedRing;
+
+pub const MAX_LATENCY_S: f64 = 0.9; // Longest an epoch's RMC sentence may arrive after its pps edge
+const EDGES_KEPT: usize = 8; // Pps edges kept for matching with epochs
+const WHOLE_SECOND_S: f64 = 0.001; // Epochs this close to a whole utc second are the ones marked by a pps edge
+const LATENCY_SMOOTHING: f64 = 0.1; // Weight of each new measurement in the average pps to RMC latency
+const KERNEL_POLL: Duration = Duration::from_millis(5); // How often the kernel pps timestamp is checked
+
+
+/// Reasons a pps source couldn't be read
+#[derive(Debug)]
+pub enum PpsError {
+  Gpio(rppal::gpio::Error),
+  Io(String, std::io::Error), // (path, error)
+  Format(String),             // the kernel timestamp couldn't be parsed
+}
+
+/// Waits for the pulse per second edges from the gps, timing them on the host's monotonic clock
+pub enum PpsReader {
+  Gpio(InputPin), // the PPS line wired to a gpio, timed when the edge wakes the thread
+  Kernel {
+    assert_path: PathBuf, // the device's assert timestamp in sysfs, timed by the kernel's interrupt handler
+    sequence: Option<u64>, // sequence number of the last edge seen
+  },
+}
+
+/// When a gps epoch happened, in seconds from the origin of the buffers it's aligned with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EpochTime {
+  pub t: f64,             // the pps edge if there is one, otherwise the RMC arrival less the usual latency
+  pub pps_t: Option<f64>, // the pps edge marking the epoch, for epochs on a whole utc second
+}
+
+/// Matches gps epochs with the pps edges that mark them, and learns the delay
+/// from an edge to its RMC sentence so epochs between edges can be timed too
+#[derive(Clone, Debug)]
+pub struct EpochTimer {
+  edges: TimedRing<()>,
+  latency_s: Option<f64>, // average delay from a pps edge to the RMC sentence of its epoch
+}
+
+
+/// PpsReader implementations
+impl PpsReader {
+  /// Watches for rising edges on gpio 'pin' (BCM numbering)
+  pub fn gpio(pin: u8) -> Result<PpsReader, PpsError> {
+    let mut input = Gpio::new()?.get(pin)?.into_input_pulldown();
+    input.set_interrupt(Trigger::RisingEdge)?;
+    Ok(PpsReader::Gpio(input))
+  }
+
+  /// Watches a Linux pps device (eg. /dev/pps0) through its sysfs timestamp
+  pub fn kernel(device: &str) -> Result<PpsReader, PpsError> {
+    let name = Path::new(device).file_name().and_then(|name| name.to_str()).unwrap_or(device);
+    let assert_path = Path::new("/sys/class/pps").join(name).join("assert");
+    fs::read_to_string(&assert_path).map_err(|e| PpsError::Io(assert_path.display().to_string(), e))?;
+    Ok(PpsReader::Kernel { assert_path, sequence: None })
+  }
+
+  /// Waits up to 'timeout' for the next edge, returning when it happened
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<Instant>, PpsError> {
+    match self {
+      PpsReader::Gpio(input) => Ok(input.poll_interrupt(true, Some(timeout))?.map(|_| Instant::now())),
+      PpsReader::Kernel { assert_path, sequence } => {
+        let deadline = Instant::now() + timeout;
+        loop {
+          let text = fs::read_to_string(&assert_path).map_err(|e| PpsError::Io(assert_path.display().to_string(), e))?;
+          let (stamp, seq) = parse_assert(&text).ok_or_else(|| PpsError::Format(text.trim().to_string()))?;
+          let new_edge = sequence.is_some_and(|last| seq != last);
+          *sequence = Some(seq);
+          if new_edge {
+            return Ok(Some(realtime_to_instant(stamp)));
+          }
+          if Instant::now() >= deadline {
+            return Ok(None);
+          }
+          thread::sleep(KERNEL_POLL);
+        }
+      }
+    }
+  }
+}
+
+
+/// EpochTimer implementations
+impl EpochTimer {
+  pub fn new() -> EpochTimer {
+    EpochTimer { edges: TimedRing::new(EDGES_KEPT), latency_s: None }
+  }
+
+  /// Adds a pps edge seen at 't'
+  pub fn edge(&mut self, t: f64) {
+    self.edges.push(t, ());
+  }
+
+  /// Times the epoch whose RMC sentence arrived at 'arrival_t' reporting 'utc_s' (any
+  /// seconds count, only the fraction is used). An epoch on a whole second is matched with
+  /// the last edge before its RMC sentence; other epochs, or ones with no edge, are timed
+  /// from the RMC sentence less the usual latency.
+  pub fn epoch(&mut self, arrival_t: f64, utc_s: Option<f64>) -> EpochTime {
+    let whole_second = utc_s.is_some_and(on_whole_second);
+    let edge = self.edges.between(arrival_t - MAX_LATENCY_S, arrival_t).last().map(|(t, _)| *t);
+    match edge.filter(|_| whole_second) {
+      Some(pps_t) => {
+        let latency = arrival_t - pps_t;
+        self.latency_s = Some(match self.latency_s {
+          Some(average) => average + (latency - average) * LATENCY_SMOOTHING,
+          None => latency,
+        });
+        EpochTime { t: pps_t, pps_t: Some(pps_t) }
+      }
+      None => EpochTime { t: arrival_t - self.latency_s.unwrap_or(0.0), pps_t: None },
+    }
+  }
+
+  /// Average delay from a pps edge to its RMC sentence, once an edge has been matched
+  pub fn latency_s(&self) -> Option<f64> {
+    self.latency_s
+  }
+}
+
+impl Default for EpochTimer {
+  fn default() -> Self {
+    EpochTimer::new()
+  }
+}
+
+
+impl From<rppal::gpio::Error> for PpsError {
+  fn from(e: rppal::gpio::Error) -> Self {
+    PpsError::Gpio(e)
+  }
+}
+
+impl Display for PpsError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      PpsError::Gpio(e) => write!(f, "gpio error: {e}"),
+      PpsError::Io(path, e) => write!(f, "could not read {path}: {e}"),
+      PpsError::Format(text) => write!(f, "unexpected pps timestamp '{text}'"),
+    }
+  }
+}
+
+impl std::error::Error for PpsError {}
+
+
+/// Whether an epoch at 'utc_s' is on a whole second, so it's marked by a pps edge
+pub fn on_whole_second(utc_s: f64) -> bool {
+  (utc_s - utc_s.round()).abs() < WHOLE_SECOND_S
+}
+
+
+/// Parses a sysfs pps timestamp ("seconds.nanoseconds#sequence") into the
+/// time since the Unix epoch and the sequence number
+pub fn parse_assert(text: &str) -> Option<(Duration, u64)> {
+  let (stamp, sequence) = text.trim().split_once('#')?;
+  let (secs, nanos) = stamp.split_once('.')?;
+  let stamp = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+  Some((stamp, sequence.parse().ok()?))
+}
+
+
+/// Converts a wall clock time to the monotonic clock, by how long ago it was
+fn realtime_to_instant(stamp: Duration) -> Instant {
+  let age = SystemTime::now().duration_since(UNIX_EPOCH + stamp).unwrap_or_default();
+  Instant::now().checked_sub(age).unwrap_or_else(Instant::now)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_assert() {
+    assert_eq!(parse_assert("1700000000.000123456#42\n"), Some((Duration::new(1_700_000_000, 123_456), 42)));
+    assert_eq!(parse_assert("0.000000000#0"), Some((Duration::ZERO, 0)));
+    assert_eq!(parse_assert("garbage"), None);
+  }
+
+  #[test]
+  fn test_epoch_timing() {
+    let mut timer = EpochTimer::new();
+    // no edges yet, timed at the RMC sentence
+    assert_eq!(timer.epoch(0.3, Some(100.0)), EpochTime { t: 0.3, pps_t: None });
+
+    timer.edge(1.0);
+    assert_eq!(timer.epoch(1.3, Some(101.0)), EpochTime { t: 1.0, pps_t: Some(1.0) });
+    assert!((timer.latency_s().unwrap() - 0.3).abs() < 1e-9);
+
+    // an epoch between seconds (5 Hz updates) uses the learnt latency
+    let epoch = timer.epoch(1.5, Some(101.2));
+    assert_eq!(epoch.pps_t, None);
+    assert!((epoch.t - 1.2).abs() < 1e-9);
+
+    // a whole second epoch whose edge is too long ago isn't matched
+    assert_eq!(timer.epoch(2.3, Some(102.0)).pps_t, None);
+  }
+}
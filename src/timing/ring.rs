@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+
+/// A value and its time
+pub type Timed<T> = (f64, T);
+
+
+/// Fixed capacity buffer of values in time order, the oldest overwritten first.
+/// Times are seconds from an origin shared by every buffer being aligned.
+#[derive(Clone, Debug)]
+pub struct TimedRing<T> {
+  items: VecDeque<Timed<T>>,
+  capacity: usize,
+}
+
+
+/// TimedRing implementations
+impl<T> TimedRing<T> {
+  pub fn new(capacity: usize) -> TimedRing<T> {
+    TimedRing { items: VecDeque::with_capacity(capacity), capacity: capacity.max(1) }
+  }
+
+  /// Adds a value, dropping the oldest if full. Values older than the newest
+  /// are rejected (returns false) so the buffer stays in time order.
+  pub fn push(&mut self, t: f64, value: T) -> bool {
+    if self.last_t().is_some_and(|last| t < last) {
+      return false;
+    }
+    if self.items.len() == self.capacity {
+      self.items.pop_front();
+    }
+    self.items.push_back((t, value));
+    true
+  }
+
+  pub fn len(&self) -> usize {
+    self.items.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// Time of the oldest value
+  pub fn first_t(&self) -> Option<f64> {
+    self.items.front().map(|(t, _)| *t)
+  }
+
+  /// Time of the newest value
+  pub fn last_t(&self) -> Option<f64> {
+    self.items.back().map(|(t, _)| *t)
+  }
+
+  pub fn latest(&self) -> Option<&Timed<T>> {
+    self.items.back()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Timed<T>> {
+    self.items.iter()
+  }
+
+  /// Values after 't', oldest first
+  pub fn after(&self, t: f64) -> impl Iterator<Item = &Timed<T>> {
+    let start = self.items.partition_point(|(item_t, _)| *item_t <= t);
+    self.items.range(start..)
+  }
+
+  /// Values from 't0' to 't1' (inclusive), oldest first
+  pub fn between(&self, t0: f64, t1: f64) -> impl Iterator<Item = &Timed<T>> {
+    let start = self.items.partition_point(|(t, _)| *t < t0);
+    let end = self.items.partition_point(|(t, _)| *t <= t1).max(start);
+    self.items.range(start..end)
+  }
+
+  /// The last value at or before 't' and the first at or after it, if the buffer covers 't'
+  pub fn bracket(&self, t: f64) -> Option<(&Timed<T>, &Timed<T>)> {
+    let after = self.items.partition_point(|(item_t, _)| *item_t < t);
+    let at_or_after = self.items.get(after)?;
+    if at_or_after.0 == t {
+      return Some((at_or_after, at_or_after));
+    }
+    Some((self.items.get(after.checked_sub(1)?)?, at_or_after))
+  }
+
+  /// Drops values older than 't'
+  pub fn discard_before(&mut self, t: f64) {
+    while self.items.front().is_some_and(|(item_t, _)| *item_t < t) {
+      self.items.pop_front();
+    }
+  }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_overwrites_oldest() {
+    let mut ring = TimedRing::new(3);
+    for i in 0..5 {
+      assert!(ring.push(i as f64, i));
+    }
+    assert_eq!(ring.len(), 3);
+    assert_eq!(ring.first_t(), Some(2.0));
+    assert_eq!(ring.latest(), Some(&(4.0, 4)));
+    assert!(!ring.push(1.0, 1)); // out of order
+  }
+
+  #[test]
+  fn test_queries() {
+    let mut ring = TimedRing::new(10);
+    for i in 0..5 {
+      ring.push(i as f64, i * 10);
+    }
+    let after: Vec<i32> = ring.after(2.0).map(|(_, v)| *v).collect();
+    assert_eq!(after, [30, 40]);
+    let between: Vec<i32> = ring.between(1.0, 3.0).map(|(_, v)| *v).collect();
+    assert_eq!(between, [10, 20, 30]);
+
+    let (before, after) = ring.bracket(2.5).unwrap();
+    assert_eq!((before.1, after.1), (20, 30));
+    let (before, after) = ring.bracket(3.0).unwrap();
+    assert_eq!((before.1, after.1), (30, 30));
+    assert!(ring.bracket(-1.0).is_none());
+    assert!(ring.bracket(4.5).is_none());
+
+    ring.discard_before(3.0);
+    assert_eq!(ring.first_t(), Some(3.0));
+  }
+}